@@ -0,0 +1,114 @@
+use crate::{RustPackError, TargetInfo, copy_permissions};
+use std::fs;
+use std::path::Path;
+use std::process::Command as ProcessCommand;
+use walkdir::WalkDir;
+
+// Shown when no icon is configured, since appimagetool insists on one.
+const PLACEHOLDER_ICON: &str = r##"<svg xmlns="http://www.w3.org/2000/svg" width="256" height="256"><rect width="256" height="256" rx="32" fill="#b7410e"/></svg>
+"##;
+
+/// Fails with download instructions unless `appimagetool` is on the PATH.
+pub fn check_tool() -> Result<(), Box<dyn std::error::Error>> {
+    match ProcessCommand::new("appimagetool").arg("--version").output() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(RustPackError::ToolMissing("appimagetool").into()),
+    }
+}
+
+/// Builds an AppImage for the Linux binary `target` in `rustpack_dir`: an
+/// AppDir with the binaries in `usr/bin`, bundled libraries in `usr/lib`,
+/// assets in `usr/share/<name>`, the desktop entry and icon at the top and an
+/// `AppRun` that starts the default binary, turned into `output` by `appimagetool`.
+/// `icon` is `(file, name)`; without one a placeholder named `name` is used.
+pub fn write_appimage(
+    rustpack_dir: &Path,
+    target: &TargetInfo,
+    name: &str,
+    desktop_entry: &str,
+    icon: Option<(&Path, &str)>,
+    output: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let arch = match target.arch.as_str() {
+        "x86_64" => "x86_64",
+        "aarch64" => "aarch64",
+        "x86" | "i686" => "i686",
+        "arm" | "armv7" => "armhf",
+        arch => return Err(format!("AppImages aren't supported for {}", arch).into()),
+    };
+    let temp_dir = tempfile::tempdir()?;
+    let app_dir = temp_dir.path().join(format!("{}.AppDir", name));
+
+    let bin_dir = app_dir.join("usr/bin");
+    fs::create_dir_all(&bin_dir)?;
+    for binary_path in target.all_binaries() {
+        let binary = rustpack_dir.join(binary_path);
+        let dest = bin_dir.join(binary.file_name().ok_or("Invalid binary path")?);
+        fs::copy(&binary, &dest)?;
+        copy_permissions(&binary, &dest)?;
+    }
+    let binary_name = Path::new(&target.binary_path)
+        .file_name()
+        .ok_or("Invalid binary path")?
+        .to_string_lossy()
+        .to_string();
+
+    let target_dir = Path::new(&target.binary_path).parent().and_then(|p| p.file_name()).ok_or("Invalid binary path")?;
+    copy_tree(&rustpack_dir.join("lib").join(target_dir), &app_dir.join("usr/lib"))?;
+    copy_tree(&rustpack_dir.join("assets"), &app_dir.join("usr/share").join(name))?;
+
+    fs::write(app_dir.join(format!("{}.desktop", name)), desktop_entry)?;
+    match icon {
+        Some((file, icon_name)) => {
+            let extension = file.extension().map(|e| e.to_string_lossy().to_string()).unwrap_or_else(|| "png".to_string());
+            fs::copy(file, app_dir.join(format!("{}.{}", icon_name, extension)))?;
+        }
+        None => fs::write(app_dir.join(format!("{}.svg", name)), PLACEHOLDER_ICON)?,
+    }
+
+    let mut app_run = String::from("#!/bin/sh\nHERE=$(dirname \"$(readlink -f \"$0\")\")\n");
+    if app_dir.join("usr/lib").is_dir() {
+        app_run.push_str("LD_LIBRARY_PATH=\"$HERE/usr/lib${LD_LIBRARY_PATH:+:$LD_LIBRARY_PATH}\"\nexport LD_LIBRARY_PATH\n");
+    }
+    app_run.push_str(&format!("exec \"$HERE/usr/bin/{}\" \"$@\"\n", binary_name));
+    fs::write(app_dir.join("AppRun"), app_run)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(app_dir.join("AppRun"), fs::Permissions::from_mode(0o755))?;
+    }
+
+    check_tool()?;
+    // Extract-and-run lets appimagetool (itself an AppImage) work without FUSE,
+    // as in most containers.
+    let result = ProcessCommand::new("appimagetool")
+        .env("ARCH", arch)
+        .env("APPIMAGE_EXTRACT_AND_RUN", "1")
+        .arg(&app_dir)
+        .arg(output)
+        .output()?;
+    if !result.status.success() {
+        return Err(format!(
+            "appimagetool failed:\n{}",
+            String::from_utf8_lossy(&result.stderr).trim_end()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn copy_tree(src: &Path, dest: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    if !src.is_dir() {
+        return Ok(());
+    }
+    for entry in WalkDir::new(src).sort_by_file_name().into_iter().filter_map(|e| e.ok()) {
+        let dest_path = dest.join(entry.path().strip_prefix(src)?);
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&dest_path)?;
+        } else {
+            fs::copy(entry.path(), &dest_path)?;
+            copy_permissions(entry.path(), &dest_path)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,101 @@
+//! Extraction stub for self-extracting Windows packages.
+//!
+//! rustpack appends the package payload (a gzip, zstd or plain tar archive)
+//! to a build of this binary, after the same `__PAYLOAD_BEGINS__` marker the
+//! shell bootstrap uses.
+//! At run time it extracts the payload to the temp directory (or to
+//! `RUSTPACK_EXTRACT_DIR` when set), picks the binary matching this machine
+//! from `info.json` and runs it.
+
+use flate2::read::GzDecoder;
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tar::Archive;
+
+const PAYLOAD_MARKER: &[u8] = b"\n__PAYLOAD_BEGINS__\n";
+
+fn main() {
+    match run() {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run() -> Result<i32, Box<dyn std::error::Error>> {
+    let bytes = fs::read(env::current_exe()?)?;
+    // The marker constant is also somewhere in this binary, so take the last
+    // occurrence: the payload follows the stub.
+    let payload_start = bytes
+        .windows(PAYLOAD_MARKER.len())
+        .rposition(|w| w == PAYLOAD_MARKER)
+        .map(|pos| pos + PAYLOAD_MARKER.len())
+        .ok_or("No payload found in this executable")?;
+
+    let (temp_dir, cleanup_dir) = match env::var_os("RUSTPACK_EXTRACT_DIR") {
+        Some(dir) => (PathBuf::from(&dir), PathBuf::from(dir).join("rustpack")),
+        None => {
+            let dir = env::temp_dir().join(format!("rustpack-{}", std::process::id()));
+            (dir.clone(), dir)
+        }
+    };
+    fs::create_dir_all(&temp_dir)?;
+    let payload = &bytes[payload_start..];
+    let decoder: Box<dyn Read> = if payload.starts_with(&[0x1f, 0x8b]) {
+        Box::new(GzDecoder::new(payload))
+    } else if payload.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Box::new(zstd::Decoder::with_buffer(payload)?)
+    } else {
+        Box::new(payload)
+    };
+    Archive::new(decoder).unpack(&temp_dir)?;
+    let rustpack_dir = temp_dir.join("rustpack");
+
+    let info: serde_json::Value = serde_json::from_slice(&fs::read(rustpack_dir.join("info.json"))?)?;
+    let platform = match env::consts::OS {
+        "windows" | "linux" | "macos" | "android" | "freebsd" | "netbsd" => env::consts::OS,
+        _ => "unknown",
+    };
+    let arch_aliases = match env::consts::ARCH {
+        "x86" => vec!["x86", "i586", "i686"],
+        "arm" => vec!["arm", "armv7"],
+        arch => vec![arch],
+    };
+    let target = info["targets"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|t| {
+            t["platform"] == platform
+                && t["arch"].as_str().is_some_and(|a| arch_aliases.contains(&a))
+        })
+        .ok_or_else(|| format!("No compatible binary found for {}-{}", platform, env::consts::ARCH))?;
+    let mut binary_path = target["binary_path"].as_str().ok_or("info.json has no binary_path")?;
+
+    // With several binaries, a first argument naming one of them picks it.
+    let mut args: Vec<_> = env::args_os().skip(1).collect();
+    let binaries: Vec<&str> = target["binaries"].as_array().into_iter().flatten().filter_map(|b| b.as_str()).collect();
+    if binaries.len() > 1
+        && let Some(first) = args.first()
+        && let Some(chosen) = binaries.iter().find(|b| Path::new(b).file_stem() == Some(first.as_os_str()))
+    {
+        binary_path = chosen;
+        args.remove(0);
+    }
+
+    let mut command = Command::new(rustpack_dir.join(binary_path));
+    command.args(args);
+    if rustpack_dir.join("assets").is_dir() {
+        command.env("RUSTPACK_ASSETS_DIR", rustpack_dir.join("assets"));
+    }
+    let status = command.status()?;
+
+    // Windows has no exec, so the stub outlives the app and can clean up.
+    let _ = fs::remove_dir_all(&cleanup_dir);
+    Ok(status.code().unwrap_or(1))
+}
@@ -0,0 +1,47 @@
+use object::{Object, ObjectSymbol, SymbolKind};
+use std::collections::{BTreeMap, HashSet};
+
+/// Bytes of code and data in a binary's symbol table, attributed to the
+/// crate each symbol belongs to, largest first. Symbols that aren't Rust,
+/// such as those of C libraries, count towards `[unknown]`. A stripped
+/// binary has no symbols to attribute and gives an empty list.
+pub fn crate_sizes(data: &[u8]) -> Result<Vec<(String, u64)>, Box<dyn std::error::Error>> {
+    let file = object::File::parse(data)?;
+    let mut sizes: BTreeMap<String, u64> = BTreeMap::new();
+    // Aliases share an address; count each one once.
+    let mut seen = HashSet::new();
+    for symbol in file.symbols() {
+        if symbol.size() == 0 || !matches!(symbol.kind(), SymbolKind::Text | SymbolKind::Data) {
+            continue;
+        }
+        if !seen.insert(symbol.address()) {
+            continue;
+        }
+        let Ok(name) = symbol.name() else {
+            continue;
+        };
+        *sizes.entry(crate_of(name)).or_insert(0) += symbol.size();
+    }
+    let mut sizes: Vec<(String, u64)> = sizes.into_iter().collect();
+    sizes.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    Ok(sizes)
+}
+
+/// The crate a mangled symbol comes from: the first path segment of its
+/// demangled name, or of the self type for `<T as Trait>::method`.
+fn crate_of(symbol: &str) -> String {
+    // Mach-O prefixes every symbol with an underscore.
+    let symbol = symbol.strip_prefix('_').filter(|s| s.starts_with("_ZN") || s.starts_with("_R")).unwrap_or(symbol);
+    let Ok(demangled) = rustc_demangle::try_demangle(symbol) else {
+        return "[unknown]".to_string();
+    };
+    let demangled = format!("{:#}", demangled);
+    let mut path = demangled.trim_start_matches(['<', '&', '*', '[', '(', ' ']);
+    for prefix in ["mut ", "const ", "dyn "] {
+        path = path.strip_prefix(prefix).unwrap_or(path);
+    }
+    match path.find("::") {
+        Some(end) if !path[..end].contains(['<', ' ', '>']) => path[..end].to_string(),
+        _ => "[unknown]".to_string(),
+    }
+}
@@ -0,0 +1,251 @@
+use crate::{PackageInfo, TargetInfo, is_executable, linux_target, source_date_epoch};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use tar::{Builder, EntryType, Header};
+use walkdir::WalkDir;
+
+// -sys crates whose libraries are usually linked dynamically, and the Debian
+// package that provides them. Only a hint: a crate that builds its library
+// statically adds a dependency that isn't needed.
+const SYS_CRATE_PACKAGES: &[(&str, &str)] = &[
+    ("openssl-sys", "libssl3"),
+    ("libz-sys", "zlib1g"),
+    ("libsqlite3-sys", "libsqlite3-0"),
+    ("libdbus-sys", "libdbus-1-3"),
+    ("alsa-sys", "libasound2"),
+    ("libudev-sys", "libudev1"),
+];
+
+/// Writes a Debian package for the single Linux target in `rustpack_dir`.
+/// Its binaries go to `/usr/bin`, assets to `/usr/share/<name>`, the license
+/// to `/usr/share/doc/<name>/copyright` and a `desktop_entry` to
+/// `/usr/share/applications`, with its icon (`(file, name)`) in
+/// `/usr/share/pixmaps`.
+///
+/// A `.deb` is an `ar` archive holding `debian-binary`, `control.tar.gz`
+/// (the control file) and `data.tar.gz` (the installed files).
+pub fn write_deb(
+    rustpack_dir: &Path,
+    package_info: &PackageInfo,
+    maintainer: &str,
+    desktop_entry: Option<&str>,
+    icon: Option<(&Path, &str)>,
+    output: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let target = linux_target(package_info, "A .deb")?;
+    let architecture = debian_architecture(target)?;
+    let name = debian_name(&package_info.name);
+
+    let mut data = Archive::new();
+    data.dir(Path::new("usr/bin"))?;
+    for binary_path in target.all_binaries() {
+        let binary = rustpack_dir.join(binary_path);
+        data.file(&Path::new("usr/bin").join(binary.file_name().ok_or("Invalid binary path")?), &binary)?;
+    }
+    let assets_dir = rustpack_dir.join("assets");
+    if assets_dir.is_dir() {
+        let share = Path::new("usr/share").join(&name);
+        for entry in WalkDir::new(&assets_dir).sort_by_file_name().into_iter().filter_map(|e| e.ok()) {
+            let dest = share.join(entry.path().strip_prefix(&assets_dir)?);
+            if entry.file_type().is_dir() {
+                data.dir(&dest)?;
+            } else {
+                data.file(&dest, entry.path())?;
+            }
+        }
+    }
+    if let Some(desktop_entry) = desktop_entry {
+        let applications = Path::new("usr/share/applications");
+        data.bytes(&applications.join(format!("{}.desktop", name)), desktop_entry.as_bytes(), 0o644)?;
+        if let Some((file, icon_name)) = icon {
+            let extension = file.extension().map(|e| e.to_string_lossy().to_string()).unwrap_or_else(|| "png".to_string());
+            data.file(&Path::new("usr/share/pixmaps").join(format!("{}.{}", icon_name, extension)), file)?;
+        }
+    }
+    let doc = Path::new("usr/share/doc").join(&name);
+    data.dir(&doc)?;
+    data.bytes(&doc.join("copyright"), copyright(package_info, rustpack_dir).as_bytes(), 0o644)?;
+    let installed_size = data.size.div_ceil(1024);
+
+    let mut depends = Vec::new();
+    if let Some(glibc) = target.compatibility.iter().find_map(|c| c.strip_prefix("glibc-"))
+        && !target.binary_path.contains("musl")
+    {
+        depends.push(format!("libc6 (>= {})", glibc));
+    }
+    for (krate, package) in SYS_CRATE_PACKAGES {
+        if package_info.metadata.contains_key(&format!("dependency_{}", krate)) {
+            depends.push(package.to_string());
+        }
+    }
+
+    let description = package_info
+        .description
+        .as_deref()
+        .and_then(|d| d.lines().map(str::trim).find(|l| !l.is_empty()))
+        .unwrap_or(&package_info.name);
+    let mut control = format!(
+        "Package: {}\nVersion: {}\nArchitecture: {}\nMaintainer: {}\nInstalled-Size: {}\n",
+        name,
+        debian_version(&package_info.version),
+        architecture,
+        maintainer,
+        installed_size
+    );
+    if !depends.is_empty() {
+        control.push_str(&format!("Depends: {}\n", depends.join(", ")));
+    }
+    control.push_str(&format!("Section: utils\nPriority: optional\nDescription: {}\n", description));
+    let mut control_tar = Archive::new();
+    control_tar.bytes(Path::new("control"), control.as_bytes(), 0o644)?;
+
+    let mtime = source_date_epoch().unwrap_or(0);
+    let mut ar = io::BufWriter::new(File::create(output)?);
+    ar.write_all(b"!<arch>\n")?;
+    for (member, contents) in [
+        ("debian-binary", b"2.0\n".to_vec()),
+        ("control.tar.gz", control_tar.finish()?),
+        ("data.tar.gz", data.finish()?),
+    ] {
+        writeln!(ar, "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`", member, mtime, 0, 0, 100644, contents.len())?;
+        ar.write_all(&contents)?;
+        if contents.len() % 2 == 1 {
+            ar.write_all(b"\n")?;
+        }
+    }
+    ar.flush()?;
+    Ok(())
+}
+
+/// A gzipped tar archive of root-owned entries, with parent directories
+/// added as needed.
+struct Archive {
+    tar: Builder<GzEncoder<Vec<u8>>>,
+    dirs: Vec<String>,
+    /// Total size of the files, for `Installed-Size`.
+    size: u64,
+}
+
+impl Archive {
+    fn new() -> Archive {
+        Archive {
+            tar: Builder::new(GzEncoder::new(Vec::new(), Compression::default())),
+            dirs: Vec::new(),
+            size: 0,
+        }
+    }
+
+    fn header(entry_type: EntryType, mode: u32, size: u64) -> Header {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_mode(mode);
+        header.set_size(size);
+        header.set_mtime(source_date_epoch().unwrap_or(0));
+        header.set_uid(0);
+        header.set_gid(0);
+        let _ = header.set_username("root");
+        let _ = header.set_groupname("root");
+        header
+    }
+
+    fn dir(&mut self, path: &Path) -> io::Result<()> {
+        let mut current = String::from(".");
+        for component in std::iter::once(None).chain(path.components().map(Some)) {
+            if let Some(component) = component {
+                current.push('/');
+                current.push_str(&component.as_os_str().to_string_lossy());
+            }
+            if !self.dirs.contains(&current) {
+                let mut header = Archive::header(EntryType::Directory, 0o755, 0);
+                self.tar.append_data(&mut header, format!("{}/", current), io::empty())?;
+                self.dirs.push(current.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn file(&mut self, path: &Path, source: &Path) -> io::Result<()> {
+        let file = File::open(source)?;
+        let metadata = file.metadata()?;
+        let mode = if is_executable(&metadata) { 0o755 } else { 0o644 };
+        self.entry(path, metadata.len(), mode, file)
+    }
+
+    fn bytes(&mut self, path: &Path, contents: &[u8], mode: u32) -> io::Result<()> {
+        self.entry(path, contents.len() as u64, mode, contents)
+    }
+
+    fn entry<R: io::Read>(&mut self, path: &Path, size: u64, mode: u32, contents: R) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            self.dir(parent)?;
+        }
+        let mut header = Archive::header(EntryType::Regular, mode, size);
+        self.tar.append_data(&mut header, Path::new(".").join(path), contents)?;
+        self.size += size;
+        Ok(())
+    }
+
+    fn finish(self) -> io::Result<Vec<u8>> {
+        self.tar.into_inner()?.finish()
+    }
+}
+
+fn debian_architecture(target: &TargetInfo) -> Result<&'static str, Box<dyn std::error::Error>> {
+    let triple = &target.binary_path;
+    match target.arch.as_str() {
+        "x86_64" => Ok("amd64"),
+        "aarch64" => Ok("arm64"),
+        "i686" | "i586" | "x86" => Ok("i386"),
+        arch if arch.starts_with("arm") && triple.contains("hf") => Ok("armhf"),
+        arch if arch.starts_with("arm") => Ok("armel"),
+        "riscv64gc" => Ok("riscv64"),
+        "powerpc64le" => Ok("ppc64el"),
+        "s390x" => Ok("s390x"),
+        arch => Err(format!("No Debian architecture for {}", arch).into()),
+    }
+}
+
+/// Package names may only use lowercase letters, digits and `+-.`.
+fn debian_name(name: &str) -> String {
+    name.to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "+-.".contains(c) { c } else { '-' })
+        .collect()
+}
+
+/// Semver pre-releases sort before the release, which Debian spells with `~`.
+fn debian_version(version: &str) -> String {
+    let (version, build) = match version.split_once('+') {
+        Some((version, build)) => (version, Some(build)),
+        None => (version, None),
+    };
+    let mut debian = version.replace('-', "~");
+    if let Some(build) = build {
+        debian.push('+');
+        debian.push_str(build);
+    }
+    debian
+}
+
+/// A machine-readable copyright file naming the license, with the project's
+/// license text if it has one.
+fn copyright(package_info: &PackageInfo, rustpack_dir: &Path) -> String {
+    let license = package_info.metadata.get("license").map_or("unknown", String::as_str);
+    let mut copyright = format!(
+        "Format: https://www.debian.org/doc/packaging-manuals/copyright-format/1.0/\nUpstream-Name: {}\n\nFiles: *\nCopyright: the {} authors\nLicense: {}\n",
+        package_info.name, package_info.name, license
+    );
+    if let Ok(text) = fs::read_to_string(rustpack_dir.join("LICENSE")) {
+        for line in text.lines() {
+            if line.trim().is_empty() {
+                copyright.push_str(" .\n");
+            } else {
+                copyright.push_str(&format!(" {}\n", line));
+            }
+        }
+    }
+    copyright
+}
@@ -0,0 +1,107 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// What went wrong in a rustpack operation. The public functions return it so
+/// callers can tell a failed build from a missing asset or tool; internal
+/// helpers still return `Box<dyn Error>` and box these variants where the
+/// failure happens, and converting back recovers them.
+#[derive(Debug)]
+pub enum RustPackError {
+    /// The project directory has no Cargo.toml.
+    ManifestNotFound(PathBuf),
+    /// cargo (or the configured builder) failed. `output` is its stderr when
+    /// it was captured rather than shown.
+    BuildFailed { target: String, output: String },
+    /// An asset, or an asset pattern that matched nothing.
+    AssetMissing(String),
+    /// An external program rustpack needs isn't installed.
+    ToolMissing(&'static str),
+    /// A patch file that can't be applied.
+    PatchFormat(String),
+    /// The package's signature doesn't match its contents or the key.
+    SignatureMismatch,
+    Io(io::Error),
+    Other(String),
+}
+
+impl fmt::Display for RustPackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RustPackError::ManifestNotFound(path) => write!(f, "No Cargo.toml found in {}", path.display()),
+            RustPackError::BuildFailed { target, output } if output.is_empty() => {
+                write!(f, "Failed to build for target: {}", target)
+            }
+            RustPackError::BuildFailed { target, output } => {
+                write!(f, "Failed to build for target: {}\n{}", target, output)
+            }
+            RustPackError::AssetMissing(asset) => write!(f, "Asset not found: {}", asset),
+            RustPackError::ToolMissing(tool) => f.write_str(match *tool {
+                "cross" => "cross is not installed. Install it with `cargo install cross --git https://github.com/cross-rs/cross` (it also needs Docker or Podman)",
+                "cargo-zigbuild" => "cargo-zigbuild is not installed. Install it with `cargo install cargo-zigbuild` (it also needs Zig, from https://ziglang.org/download/ or `pip install ziglang`)",
+                "appimagetool" => "appimagetool not found. Download it from https://github.com/AppImage/appimagetool/releases, make it executable and put it on your PATH",
+                "hdiutil" => "hdiutil not found; .dmg images can only be built on macOS",
+                "lipo" => "Several macOS targets need lipo (or llvm-lipo) to make a universal binary",
+                "upx" => "upx not found; install it or drop --compress (--skip-missing-tools builds without it)",
+                "strip" => "No strip tool found for a target; install binutils (or LLVM for llvm-strip), set strip_tool, or drop --strip (--skip-missing-tools builds without it)",
+                "aws" => "aws not found; --upload needs the AWS CLI (https://aws.amazon.com/cli/)",
+                "wasm-bindgen" => "wasm-bindgen not found. Install it with `cargo install wasm-bindgen-cli --version <v>`, where <v> is the project's wasm-bindgen version",
+                tool => return write!(f, "{} is not installed", tool),
+            }),
+            RustPackError::PatchFormat(message) | RustPackError::Other(message) => f.write_str(message),
+            RustPackError::SignatureMismatch => f.write_str("Signature does not match the package contents"),
+            RustPackError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for RustPackError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RustPackError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RustPackError {
+    fn from(e: io::Error) -> RustPackError {
+        RustPackError::Io(e)
+    }
+}
+
+impl From<&str> for RustPackError {
+    fn from(message: &str) -> RustPackError {
+        RustPackError::Other(message.to_string())
+    }
+}
+
+impl From<String> for RustPackError {
+    fn from(message: String) -> RustPackError {
+        RustPackError::Other(message)
+    }
+}
+
+impl From<toml::de::Error> for RustPackError {
+    fn from(e: toml::de::Error) -> RustPackError {
+        RustPackError::Other(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for RustPackError {
+    fn from(e: serde_json::Error) -> RustPackError {
+        RustPackError::Other(e.to_string())
+    }
+}
+
+impl From<Box<dyn std::error::Error>> for RustPackError {
+    fn from(e: Box<dyn std::error::Error>) -> RustPackError {
+        match e.downcast::<RustPackError>() {
+            Ok(e) => *e,
+            Err(e) => match e.downcast::<io::Error>() {
+                Ok(e) => RustPackError::Io(*e),
+                Err(e) => RustPackError::Other(e.to_string()),
+            },
+        }
+    }
+}
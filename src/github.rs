@@ -0,0 +1,241 @@
+use std::path::PathBuf;
+
+/// A GitHub release that packages were uploaded to.
+pub struct Release {
+    /// The release's page.
+    pub url: String,
+    /// Download URL of each uploaded asset.
+    pub assets: Vec<String>,
+}
+
+/// The newest release of a repository.
+pub struct Latest {
+    pub tag: String,
+    /// The release's page.
+    pub url: String,
+    /// Name and download URL of each asset.
+    pub assets: Vec<(String, String)>,
+}
+
+/// Owner, repository and tag of an `owner/repo@tag` spec. Without `@tag`,
+/// the tag is `v<version>`.
+pub fn parse_spec(spec: &str, version: &str) -> Result<(String, String, String), Box<dyn std::error::Error>> {
+    let (repository, tag) = match spec.split_once('@') {
+        Some((repository, tag)) => (repository, tag.to_string()),
+        None => (spec, format!("v{}", version)),
+    };
+    match repository.split_once('/') {
+        Some((owner, repo)) if !owner.is_empty() && !repo.is_empty() && !repo.contains('/') && !tag.is_empty() => {
+            Ok((owner.to_string(), repo.to_string(), tag))
+        }
+        _ => Err(format!("--publish-github expects owner/repo or owner/repo@tag, got {}", spec).into()),
+    }
+}
+
+/// The token to authenticate to the GitHub API with.
+pub fn token() -> Result<String, Box<dyn std::error::Error>> {
+    std::env::var("GITHUB_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty())
+        .ok_or_else(|| "--publish-github needs a GITHUB_TOKEN that can write the repository's contents".into())
+}
+
+/// Uploads `files` to the release for the tag in `spec`, creating the
+/// release if the tag has none. An asset of the same name is replaced.
+#[cfg(feature = "github")]
+pub fn publish(spec: &str, version: &str, files: &[PathBuf]) -> Result<Release, Box<dyn std::error::Error>> {
+    api::publish(spec, version, files)
+}
+
+#[cfg(not(feature = "github"))]
+pub fn publish(_spec: &str, _version: &str, _files: &[PathBuf]) -> Result<Release, Box<dyn std::error::Error>> {
+    Err(NOT_BUILT.into())
+}
+
+/// The newest release of `owner/repo` that isn't a draft or prerelease.
+/// `GITHUB_TOKEN` is used when set, for its higher rate limit.
+#[cfg(feature = "github")]
+pub fn latest_release(owner: &str, repo: &str) -> Result<Latest, Box<dyn std::error::Error>> {
+    api::latest_release(owner, repo)
+}
+
+#[cfg(not(feature = "github"))]
+pub fn latest_release(_owner: &str, _repo: &str) -> Result<Latest, Box<dyn std::error::Error>> {
+    Err(NOT_BUILT.into())
+}
+
+/// The contents of a release asset's download URL.
+#[cfg(feature = "github")]
+pub fn download(url: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    api::download(url)
+}
+
+#[cfg(not(feature = "github"))]
+pub fn download(_url: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    Err(NOT_BUILT.into())
+}
+
+/// Fails unless this rustpack can talk to the GitHub API.
+pub fn check_feature() -> Result<(), Box<dyn std::error::Error>> {
+    if cfg!(feature = "github") {
+        Ok(())
+    } else {
+        Err(NOT_BUILT.into())
+    }
+}
+
+const NOT_BUILT: &str = "rustpack was built without the `github` feature; reinstall it with `cargo install rustpack --features github`";
+
+#[cfg(feature = "github")]
+mod api {
+    use super::{Latest, Release};
+    use serde_json::{json, Value};
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    struct Client {
+        agent: ureq::Agent,
+        token: Option<String>,
+        base: String,
+    }
+
+    impl Client {
+        fn new(token: Option<String>) -> Client {
+            Client {
+                agent: ureq::Agent::config_builder()
+                    .http_status_as_error(false)
+                    .user_agent("rustpack")
+                    .timeout_global(Some(Duration::from_secs(600)))
+                    .build()
+                    .into(),
+                token,
+                // GitHub Actions sets this, to a GitHub Enterprise server's API
+                // where there is one.
+                base: std::env::var("GITHUB_API_URL")
+                    .unwrap_or_else(|_| "https://api.github.com".to_string())
+                    .trim_end_matches('/')
+                    .to_string(),
+            }
+        }
+
+        /// Sends a request and returns the status and the parsed JSON body
+        /// (`Null` when there is none).
+        fn request(&self, method: &str, url: &str, body: Option<(&str, Vec<u8>)>) -> Result<(u16, Value), Box<dyn std::error::Error>> {
+            let mut response = match (method, body) {
+                ("POST", Some((content_type, body))) => self.headers(self.agent.post(url)).header("Content-Type", content_type).send(body)?,
+                ("DELETE", _) => self.headers(self.agent.delete(url)).call()?,
+                _ => self.headers(self.agent.get(url)).call()?,
+            };
+            let status = response.status().as_u16();
+            let text = response.body_mut().read_to_string()?;
+            let value = if text.trim().is_empty() { Value::Null } else { serde_json::from_str(&text)? };
+            Ok((status, value))
+        }
+
+        fn headers<B>(&self, request: ureq::RequestBuilder<B>) -> ureq::RequestBuilder<B> {
+            let request = request
+                .header("Accept", "application/vnd.github+json")
+                .header("X-GitHub-Api-Version", "2022-11-28");
+            match &self.token {
+                Some(token) => request.header("Authorization", format!("Bearer {}", token)),
+                None => request,
+            }
+        }
+    }
+
+    /// Fails with GitHub's message unless `status` is one of `expected`.
+    fn expect(action: &str, status: u16, body: &Value, expected: &[u16]) -> Result<(), Box<dyn std::error::Error>> {
+        if expected.contains(&status) {
+            return Ok(());
+        }
+        let message = body["message"].as_str().unwrap_or("no message");
+        Err(format!("GitHub could not {} (HTTP {}: {})", action, status, message).into())
+    }
+
+    pub fn publish(spec: &str, version: &str, files: &[PathBuf]) -> Result<Release, Box<dyn std::error::Error>> {
+        let (owner, repo, tag) = super::parse_spec(spec, version)?;
+        let client = Client::new(Some(super::token()?));
+        let releases = format!("{}/repos/{}/{}/releases", client.base, owner, repo);
+
+        let (status, mut release) = client.request("GET", &format!("{}/tags/{}", releases, encode(&tag)), None)?;
+        if status == 404 {
+            let body = json!({ "tag_name": tag, "name": tag }).to_string();
+            let (status, created) = client.request("POST", &releases, Some(("application/json", body.into_bytes())))?;
+            expect(&format!("create a release for {} in {}/{}", tag, owner, repo), status, &created, &[201])?;
+            release = created;
+        } else {
+            expect(&format!("look up the release for {} in {}/{}", tag, owner, repo), status, &release, &[200])?;
+        }
+
+        // The upload URL is a template like .../assets{?name,label}.
+        let upload_url = release["upload_url"].as_str().unwrap_or_default();
+        let upload_url = upload_url.split('{').next().unwrap_or(upload_url).to_string();
+        let mut assets = Vec::new();
+        for file in files {
+            let name = file
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .ok_or_else(|| format!("{} has no file name to upload under", file.display()))?;
+            let existing = release["assets"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .find(|asset| asset["name"] == name.as_str())
+                .and_then(|asset| asset["id"].as_u64());
+            if let Some(id) = existing {
+                let (status, body) = client.request("DELETE", &format!("{}/assets/{}", releases, id), None)?;
+                expect(&format!("replace the existing asset {}", name), status, &body, &[204])?;
+            }
+            let url = format!("{}?name={}", upload_url, encode(&name));
+            let (status, asset) = client.request("POST", &url, Some(("application/octet-stream", fs::read(file)?)))?;
+            expect(&format!("upload {}", name), status, &asset, &[201])?;
+            assets.push(asset["browser_download_url"].as_str().unwrap_or(&name).to_string());
+        }
+
+        Ok(Release {
+            url: release["html_url"].as_str().unwrap_or_default().to_string(),
+            assets,
+        })
+    }
+
+    pub fn latest_release(owner: &str, repo: &str) -> Result<Latest, Box<dyn std::error::Error>> {
+        let client = Client::new(std::env::var("GITHUB_TOKEN").ok().filter(|token| !token.is_empty()));
+        let url = format!("{}/repos/{}/{}/releases/latest", client.base, owner, repo);
+        let (status, release) = client.request("GET", &url, None)?;
+        expect(&format!("find the latest release of {}/{}", owner, repo), status, &release, &[200])?;
+        let assets = release["assets"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|asset| Some((asset["name"].as_str()?.to_string(), asset["browser_download_url"].as_str()?.to_string())))
+            .collect();
+        Ok(Latest {
+            tag: release["tag_name"].as_str().unwrap_or_default().to_string(),
+            url: release["html_url"].as_str().unwrap_or_default().to_string(),
+            assets,
+        })
+    }
+
+    pub fn download(url: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        // Asset URLs redirect to a CDN and need no API headers.
+        let mut response = Client::new(None).agent.get(url).call()?;
+        let status = response.status().as_u16();
+        if status != 200 {
+            return Err(format!("Downloading {} failed with HTTP {}", url, status).into());
+        }
+        Ok(response.body_mut().with_config().limit(u64::MAX).read_to_vec()?)
+    }
+
+    /// Percent-encodes everything but unreserved characters, for a path
+    /// segment or query value.
+    fn encode(value: &str) -> String {
+        value
+            .bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+                _ => format!("%{:02X}", b),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,6075 @@
+use flate2::read::GzDecoder;
+use flate2::{Compression, GzBuilder};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command as ProcessCommand, Stdio};
+use tar::{Builder, EntryType, Header};
+use walkdir::WalkDir;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use serde::{Deserialize, Serialize};
+use std::env;
+use colored::Colorize;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use chrono::{DateTime, Datelike, SecondsFormat, Timelike, Utc};
+use notify::{DebouncedEvent, Watcher, RecursiveMode, watcher};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use globset::{Glob, GlobBuilder, GlobSetBuilder};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use zip::write::FileOptions;
+use package::{PackageFormat, PayloadCompression};
+use sha2::{Sha256, Digest};
+use hmac::{Hmac, Mac};
+use base64::Engine;
+use object::{Object, ObjectSection};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use ed25519_dalek::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+pub mod package;
+pub mod log;
+pub mod error;
+mod merkle;
+mod deb;
+mod appimage;
+mod macos;
+mod winres;
+mod sbom;
+mod wasm;
+mod bloat;
+mod s3;
+mod github;
+
+pub use error::RustPackError;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// First line of a patch file, followed by the length of the patched output.
+const PATCH_HEADER: &str = "RUSTPACK-PATCH";
+const SIGNATURE_PREFIX: &str = "RUSTPACK-SIGNATURE:";
+// Base64 length of an HMAC-SHA256 digest.
+const SIGNATURE_LEN: usize = 44;
+// Magic number of the zstd skippable frame holding the signature trailer.
+const ZSTD_SKIPPABLE_MAGIC: [u8; 4] = [0x50, 0x2a, 0x4d, 0x18];
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PackageInfo {
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+    pub targets: Vec<TargetInfo>,
+    /// When the package was built, as an ISO 8601 UTC timestamp. Left out
+    /// with `no_timestamp`; older packages record the builder's local time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    pub checksum: String,
+    pub features: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub requires: Vec<String>,
+    pub metadata: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merkle_root: Option<String>,
+    /// Base64 ed25519 public key the package was signed with, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TargetInfo {
+    pub platform: String,
+    pub arch: String,
+    /// The binary that runs by default.
+    pub binary_path: String,
+    /// Every binary packaged for the target, `binary_path` first. Empty in
+    /// packages from before several binaries were supported.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub binaries: Vec<String>,
+    pub checksum: String,
+    /// SHA-256 of each of `binaries`, so the bootstrap can check whichever
+    /// one it runs. Empty in older packages.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub binary_checksums: BTreeMap<String, String>,
+    pub features: Vec<String>,
+    pub optimizations: Option<String>,
+    pub compatibility: Vec<String>,
+    pub signature: Option<String>,
+}
+
+impl TargetInfo {
+    /// `binaries`, or just `binary_path` for older packages.
+    pub fn all_binaries(&self) -> Vec<&str> {
+        if self.binaries.is_empty() {
+            vec![self.binary_path.as_str()]
+        } else {
+            self.binaries.iter().map(String::as_str).collect()
+        }
+    }
+}
+
+pub struct Manifest {
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+    pub license: Option<String>,
+    pub edition: Option<String>,
+    pub authors: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct TargetConfig {
+    pub strip: Option<bool>,
+    pub strip_tool: Option<String>,
+    pub compress: Option<bool>,
+    pub sign: Option<bool>,
+    /// Replaces `max_size` for this target, e.g. `8M`.
+    pub max_size: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BuildConfig {
+    pub strip: bool,
+    pub strip_level: String,
+    /// Strips with this program instead of one picked for each target.
+    pub strip_tool: Option<String>,
+    pub compress: bool,
+    pub strip_rpath: bool,
+    pub bundle_libs: bool,
+    pub lto: Option<String>,
+    pub debug_symbols: bool,
+    pub prefetch_deps: bool,
+    pub profile: String,
+    pub features: Vec<String>,
+    pub no_default_features: bool,
+    /// Extra arguments appended to the `cargo build` command as they are.
+    pub cargo_args: Vec<String>,
+    /// Directory cargo builds into, overriding `CARGO_TARGET_DIR` and the
+    /// workspace's `target/`.
+    pub target_dir: Option<String>,
+    /// Compiler flags added after any `RUSTFLAGS` from the environment.
+    pub rustflags: Vec<String>,
+    pub assets: Vec<String>,
+    pub requires: Vec<String>,
+    /// HMAC-SHA256 key for the legacy signature trailer.
+    pub sign: String,
+    /// ed25519 private key file to write detached signatures with.
+    pub sign_key: Option<String>,
+    pub output_manifest: Option<String>,
+    pub env_file: Option<String>,
+    pub report: Option<String>,
+    /// Also writes the CycloneDX SBOM packaged as `rustpack/sbom.json` here.
+    pub sbom: Option<String>,
+    /// Bundles the license texts of every dependency.
+    pub licenses: bool,
+    pub channel: String,
+    pub channel_manifest: Option<String>,
+    pub max_package_size: Option<u64>,
+    /// Attributes the binaries' size to crates and reports this many of the
+    /// largest.
+    pub bloat: Option<usize>,
+    /// Largest size of each binary once stripped and compressed.
+    pub max_size: Option<u64>,
+    pub update_url: Option<String>,
+    /// Directory to publish the package, its checksum and `version.json`
+    /// to for the updater.
+    pub release_dir: Option<String>,
+    /// Oldest installed version the updater may upgrade from, recorded in
+    /// the release's `version.json`.
+    pub min_update_version: Option<String>,
+    /// Publishes a patch from the previous release in `release_dir` to this
+    /// one, so the updater can download less.
+    pub delta_updates: bool,
+    /// Leaves `created_at` out of `info.json` and the SBOM.
+    pub no_timestamp: bool,
+    /// `s3://bucket/prefix` to upload the packages and their `.sha256` and
+    /// `.sig` files to once they are written.
+    pub upload: Option<String>,
+    /// `owner/repo` or `owner/repo@tag` whose GitHub release gets the
+    /// packages and their checksums as assets.
+    pub publish_github: Option<String>,
+    pub desktop_entry: bool,
+    pub icon: Option<String>,
+    pub categories: Vec<String>,
+    /// The desktop entry's `Comment`, instead of the package description.
+    pub comment: Option<String>,
+    /// A complete desktop entry to bundle instead of the generated one.
+    pub desktop_file: Option<String>,
+    pub bootstrap: String,
+    pub compression: String,
+    /// gzip and ZIP deflate level, 0 (store) to 9 (smallest); flate2's
+    /// default when unset.
+    pub compression_level: Option<u32>,
+    /// Runs the builds: `cargo`, `cross` or `zig` (`cargo zigbuild`).
+    pub builder: String,
+    /// How many targets to build at once.
+    pub jobs: usize,
+    pub windows_stub: Option<String>,
+    pub target_overrides: HashMap<String, TargetConfig>,
+    pub multicall: BTreeMap<String, String>,
+    /// Keys that replace or extend the generated desktop entry.
+    pub desktop: BTreeMap<String, String>,
+    /// Extra entries for the `metadata` in `info.json`, from `--meta` and
+    /// the `[metadata]` table.
+    pub metadata: BTreeMap<String, String>,
+    /// Merges the Intel and Apple Silicon macOS binaries into one universal
+    /// binary.
+    pub universal_macos: bool,
+    /// Links a resource with the icon and version information into Windows
+    /// executables.
+    pub windows_resources: bool,
+    /// `CFBundleIdentifier` of macOS app bundles.
+    pub bundle_id: Option<String>,
+    /// The workspace member to pack, built with `cargo build -p`.
+    pub package: Option<String>,
+    /// The `[[bin]]` targets to package. Empty for the one named after the
+    /// package (or the only one).
+    pub bins: Vec<String>,
+    /// Packages every `[[bin]]` target.
+    pub all_bins: bool,
+    /// Shell commands run in the project directory before building.
+    pub pre_build: Vec<String>,
+    /// Shell commands run after every package was written.
+    pub post_build: Vec<String>,
+    /// Builds without stripping or UPX compression when the tool for it
+    /// isn't installed, instead of failing before the build.
+    pub skip_missing_tools: bool,
+}
+
+// Bump when a field of the build report is renamed, removed or changes
+// meaning; adding fields keeps the version.
+const BUILD_REPORT_SCHEMA_VERSION: u32 = 1;
+
+/// The `--report` JSON document, for CI to read instead of parsing output.
+#[derive(Serialize)]
+struct BuildReport {
+    schema_version: u32,
+    name: String,
+    version: String,
+    outputs: Vec<OutputReport>,
+    targets: Vec<TargetReport>,
+    /// Payload size under each compression, when a self-extracting package
+    /// was written.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    payload_sizes: BTreeMap<String, u64>,
+    /// Where `upload` and `publish_github` put the packages and their
+    /// sidecar files.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    uploads: Vec<String>,
+    duration_secs: f64,
+}
+
+#[derive(Serialize)]
+struct OutputReport {
+    path: String,
+    format: String,
+    size: u64,
+    checksum: String,
+    /// Bytes of files stored once as hard links rather than repeated.
+    deduplicated_bytes: u64,
+}
+
+#[derive(Serialize)]
+struct TargetReport {
+    target: String,
+    platform: String,
+    arch: String,
+    binary_path: String,
+    stripped: bool,
+    compressed: bool,
+    /// Size of the final binary, after stripping and compression.
+    size: u64,
+    sections: BTreeMap<String, usize>,
+    checksum: String,
+    build_secs: f64,
+    /// Shared libraries bundled with `--bundle-libs`.
+    libraries: Vec<String>,
+    /// The largest contributors to the binaries' code and data, with
+    /// `--bloat`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    crates: Vec<CrateSize>,
+}
+
+#[derive(Serialize)]
+struct CrateSize {
+    name: String,
+    size: u64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct ChannelManifest {
+    channels: HashMap<String, ChannelRelease>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChannelRelease {
+    version: String,
+    checksum: String,
+    file: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<String>,
+    /// Installed versions older than this can't update to the release.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min_version: Option<String>,
+    /// The release `patch_file` turns into this one. Kept flat so the
+    /// bootstrap can read it without jq.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    patch_from: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    patch_file: Option<String>,
+}
+
+pub struct ToolRequirement {
+    pub name: &'static str,
+    pub min_version: &'static str,
+    pub reason: &'static str,
+}
+
+pub const TOOL_REQUIREMENTS: &[ToolRequirement] = &[
+    ToolRequirement { name: "cargo", min_version: "1.56.0", reason: "edition 2021 support" },
+    ToolRequirement { name: "rustc", min_version: "1.56.0", reason: "edition 2021 support" },
+    ToolRequirement { name: "strip", min_version: "2.26.0", reason: "--strip-unneeded on modern ELF binaries" },
+    ToolRequirement { name: "upx", min_version: "3.96.0", reason: "fixes for compressing Rust binaries" },
+    ToolRequirement { name: "zstd", min_version: "1.3.2", reason: "long-range mode" },
+];
+
+pub enum ToolStatus {
+    Ok(semver::Version),
+    TooOld(semver::Version),
+    Unknown(String),
+    Missing,
+}
+
+const BOOTSTRAP_SCRIPT: &str = r#"#!/bin/sh
+# Rewritten by rustpack to match the payload; extract_payload reads it back
+# from the package file, so it also works on downloaded updates.
+PAYLOAD_COMPRESSION=gzip
+# SHA-256 of the payload, filled in once it is written. Runs extract to a
+# cache directory named after it, so later launches skip extraction.
+PAYLOAD_CHECKSUM=0000000000000000000000000000000000000000000000000000000000000000
+
+# extract_payload PACKAGE DIR [MEMBER...]
+extract_payload() {
+    PACKAGE="$1"
+    DEST="$2"
+    shift 2
+    PAYLOAD_LINE=$(awk '/^__PAYLOAD_BEGINS__/ { print NR + 1; exit 0; }' "$PACKAGE")
+    COMPRESSION=$(awk '/^PAYLOAD_COMPRESSION=/ { sub(/^[^=]*=/, ""); print; exit } /^__PAYLOAD_BEGINS__/ { exit }' "$PACKAGE")
+    case "$COMPRESSION" in
+        zstd)
+            if command -v zstd > /dev/null; then
+                DECOMPRESS="zstd -dc"
+            elif command -v unzstd > /dev/null; then
+                DECOMPRESS="unzstd -c"
+            else
+                echo "Error: this package is zstd-compressed; install zstd to run it"
+                return 1
+            fi
+            ;;
+        brotli)
+            if command -v brotli > /dev/null; then
+                DECOMPRESS="brotli -dc"
+            else
+                echo "Error: this package is brotli-compressed; install brotli to run it"
+                return 1
+            fi
+            ;;
+        none)
+            DECOMPRESS="cat"
+            ;;
+        *)
+            DECOMPRESS="gzip -dc"
+            ;;
+    esac
+    tail -n+$PAYLOAD_LINE "$PACKAGE" | $DECOMPRESS | tar xf - -C "$DEST" "$@"
+}
+
+# Bootstrap options come first; anything after them is the app's.
+NO_CACHE=""
+CLEANUP=""
+while :; do
+    case "$1" in
+        --no-cache) NO_CACHE=1 ;;
+        --cleanup) CLEANUP=1 ;;
+        *) break ;;
+    esac
+    shift
+done
+
+CACHE_ROOT="${XDG_CACHE_HOME:-$HOME/.cache}/rustpack"
+if [ -n "$RUSTPACK_EXTRACT_DIR" ]; then
+    TEMP_DIR="$RUSTPACK_EXTRACT_DIR"
+    mkdir -p "$TEMP_DIR" || exit 1
+    CLEANUP_DIR="$TEMP_DIR/rustpack"
+    if ! extract_payload "$0" "$TEMP_DIR"; then
+        rm -rf "$CLEANUP_DIR"
+        exit 1
+    fi
+elif [ -z "$NO_CACHE" ] && [ -d "$CACHE_ROOT/$PAYLOAD_CHECKSUM/rustpack" ]; then
+    TEMP_DIR="$CACHE_ROOT/$PAYLOAD_CHECKSUM"
+    CLEANUP_DIR="$TEMP_DIR"
+elif [ -z "$NO_CACHE" ] && [ "$PAYLOAD_CHECKSUM" != "0000000000000000000000000000000000000000000000000000000000000000" ] && mkdir -p "$CACHE_ROOT" 2>/dev/null; then
+    # Extract next to the cache entry and rename it into place, so a
+    # concurrent launch never sees a half-extracted directory.
+    TEMP_DIR="$CACHE_ROOT/$PAYLOAD_CHECKSUM"
+    CLEANUP_DIR="$TEMP_DIR"
+    PARTIAL_DIR=$(mktemp -d "$CACHE_ROOT/.$PAYLOAD_CHECKSUM.XXXXXX") || exit 1
+    if ! extract_payload "$0" "$PARTIAL_DIR"; then
+        rm -rf "$PARTIAL_DIR"
+        exit 1
+    fi
+    if ! mv "$PARTIAL_DIR" "$TEMP_DIR" 2>/dev/null; then
+        rm -rf "$PARTIAL_DIR"
+    fi
+else
+    TEMP_DIR=$(mktemp -d 2>/dev/null || mktemp -d -t rustpack)
+    CLEANUP_DIR="$TEMP_DIR"
+    if ! extract_payload "$0" "$TEMP_DIR"; then
+        rm -rf "$CLEANUP_DIR"
+        exit 1
+    fi
+fi
+RUSTPACK_DIR="$TEMP_DIR/rustpack"
+. "$RUSTPACK_DIR/manifest.env"
+APP_NAME="$PACKAGE_NAME"
+
+KERNEL=$(uname -s | tr '[:upper:]' '[:lower:]')
+ARCH=$(uname -m)
+
+# Android reports a Linux kernel; it can fall back to static musl builds.
+FALLBACK_PLATFORM=""
+if [ "$KERNEL" = "darwin" ]; then
+    PLATFORM="macos"
+elif [ "$KERNEL" = "linux" ] && [ "$(uname -o 2>/dev/null)" = "Android" ]; then
+    PLATFORM="android"
+    FALLBACK_PLATFORM="linux"
+elif [ "$KERNEL" = "linux" ]; then
+    PLATFORM="linux"
+elif [ "$KERNEL" = "freebsd" ] || [ "$KERNEL" = "netbsd" ]; then
+    PLATFORM="$KERNEL"
+elif echo "$KERNEL" | grep -q "mingw\|cygwin\|msys"; then
+    PLATFORM="windows"
+else
+    PLATFORM="unknown"
+fi
+
+if [ "$ARCH" = "x86_64" ] || [ "$ARCH" = "amd64" ]; then
+    ARCH="x86_64"
+elif [ "$ARCH" = "arm64" ] || [ "$ARCH" = "aarch64" ]; then
+    ARCH="aarch64"
+elif [ "$ARCH" = "i386" ] || [ "$ARCH" = "i686" ]; then
+    ARCH="x86"
+elif [ "$ARCH" = "arm" ] || [ "$ARCH" = "armv7l" ]; then
+    ARCH="arm"
+else
+    ARCH="unknown"
+fi
+
+# glibc builds can't run on musl systems such as Alpine.
+LIBC=""
+if [ "$PLATFORM" = "linux" ] && ldd --version 2>&1 | grep -qi musl; then
+    LIBC="musl"
+fi
+
+# select_binary TARGETS: the binary in a PACKAGE_TARGETS list that runs here,
+# preferring a static musl one over a glibc one, falling back to a universal
+# one for the platform, then to a musl one for FALLBACK_PLATFORM.
+select_binary() {
+    echo "$1" | awk -v platform="$PLATFORM" -v arch="$ARCH" -v libc="$LIBC" -v fallback="$FALLBACK_PLATFORM" '
+        $1 == platform && $2 == arch && $3 ~ /-musl/ { print $3; found = 1; exit }
+        $1 == platform && $2 == arch && libc != "musl" && native == "" { native = $3 }
+        $1 == platform && $2 == "universal" && universal == "" { universal = $3 }
+        fallback != "" && $1 == fallback && $2 == arch && $3 ~ /-musl/ && musl == "" { musl = $3 }
+        END {
+            if (found) exit
+            if (native != "") print native
+            else if (universal != "") print universal
+            else if (musl != "") print musl
+        }'
+}
+
+# sha256_of FILE: prints the SHA-256 of FILE, or nothing when neither
+# sha256sum nor shasum is installed.
+sha256_of() {
+    if command -v sha256sum > /dev/null; then
+        sha256sum "$1" | awk '{ print $1 }'
+    elif command -v shasum > /dev/null; then
+        shasum -a 256 "$1" | awk '{ print $1 }'
+    fi
+}
+
+# verify_binary PATH: checks the extracted binary at PATH (relative to the
+# rustpack directory) against its checksum from info.json, so a truncated or
+# altered package refuses to run instead of running whatever it contains.
+# A binary without a recorded checksum doesn't run either.
+verify_binary() {
+    EXPECTED=$(echo "$PACKAGE_CHECKSUMS" | awk -v path="$1" '$1 == path { print $2; exit }')
+    if [ -z "$EXPECTED" ]; then
+        echo "Error: the package records no checksum for $1; refusing to run it"
+        return 1
+    fi
+    ACTUAL=$(sha256_of "$TEMP_DIR/rustpack/$1")
+    if [ -z "$ACTUAL" ]; then
+        echo "Warning: sha256sum and shasum not found; running $1 without verifying its checksum" >&2
+        return 0
+    fi
+    if [ "$ACTUAL" != "$EXPECTED" ]; then
+        echo "Error: $1 does not match the checksum recorded in the package; refusing to run it"
+        return 1
+    fi
+    return 0
+}
+
+channel_field() {
+    if command -v jq > /dev/null; then
+        echo "$VERSION_INFO" | jq -r --arg channel "$1" --arg field "$2" 'if .channels then .channels[$channel][$field] // empty else .[$field] // empty end'
+    else
+        echo "$VERSION_INFO" | awk -v channel="\"$1\":" -v field="\"$2\":" '
+            index($0, "\"channels\":") { has_channels = 1; next }
+            has_channels && !inside && index($0, channel) { inside = 1; next }
+            (inside || !has_channels) && index($0, field) { sub(/^[^:]*: *"/, ""); sub(/".*$/, ""); print; exit }
+            inside && /}/ { exit }'
+    fi
+}
+
+version_at_least() {
+    awk -v have="$1" -v want="$2" 'BEGIN {
+        n = split(have, h, "."); m = split(want, w, ".");
+        for (i = 1; i <= (n > m ? n : m); i++) {
+            if ((h[i] + 0) > (w[i] + 0)) exit 0;
+            if ((h[i] + 0) < (w[i] + 0)) exit 1;
+        }
+        exit 0;
+    }'
+}
+
+# below_min_version: succeeds when the release's min_version is newer than
+# the installed version, which then can't update to it directly.
+below_min_version() {
+    MIN_VERSION=$(channel_field "$CHANNEL" min_version)
+    [ -n "$MIN_VERSION" ] && ! version_at_least "$PACKAGE_VERSION" "$MIN_VERSION"
+}
+
+check_for_updates() {
+    echo "Checking for updates..."
+    CURRENT_VERSION="$PACKAGE_VERSION"
+    CHANNEL="${PACKAGE_CHANNEL:-stable}"
+    UPDATE_URL="$PACKAGE_UPDATE_URL"
+    if [ -z "$UPDATE_URL" ]; then
+        echo "No update URL configured."
+        return 1
+    fi
+    if command -v curl > /dev/null; then
+        VERSION_INFO=$(curl -s "$UPDATE_URL/version.json")
+    elif command -v wget > /dev/null; then
+        VERSION_INFO=$(wget -q -O - "$UPDATE_URL/version.json")
+    else
+        echo "No curl or wget found to check for updates."
+        return 1
+    fi
+    if [ -z "$VERSION_INFO" ]; then
+        echo "Could not fetch version information."
+        return 1
+    fi
+    LATEST_VERSION=$(channel_field "$CHANNEL" version)
+    if [ -z "$LATEST_VERSION" ]; then
+        echo "No release published for the $CHANNEL channel."
+        return 1
+    fi
+    if [ "$CURRENT_VERSION" != "$LATEST_VERSION" ]; then
+        echo "Update available: $LATEST_VERSION (current: $CURRENT_VERSION)"
+        if below_min_version; then
+            echo "It can't be installed over versions before $MIN_VERSION; download it from $UPDATE_URL instead"
+        else
+            echo "Run with --update to download the latest version"
+        fi
+        return 0
+    else
+        echo "You are running the latest version: $CURRENT_VERSION"
+        return 0
+    fi
+}
+
+# download URL FILE: saves URL to FILE, trying up to three times with a
+# growing pause so a flaky connection doesn't fail the update.
+download() {
+    ATTEMPT=1
+    while :; do
+        if command -v curl > /dev/null; then
+            curl -fsSL -o "$2" "$1" && return 0
+        elif command -v wget > /dev/null; then
+            wget -q -O "$2" "$1" && return 0
+        else
+            echo "No curl or wget found to download update."
+            return 1
+        fi
+        if [ "$ATTEMPT" -ge 3 ]; then
+            return 1
+        fi
+        echo "Download of $1 failed, retrying..."
+        sleep $((ATTEMPT * 2))
+        ATTEMPT=$((ATTEMPT + 1))
+    done
+}
+
+# apply_patch FILE PATCH OUT: writes to OUT what a rustpack patch turns
+# FILE into. The patch's header line holds the output's length and the
+# SHA-256s of FILE and OUT, and every other line is an offset:length:base64
+# run of replaced bytes. Fails unless FILE is what the patch was made from
+# and OUT comes out as the patch expects.
+apply_patch() {
+    command -v base64 > /dev/null || return 1
+    read -r MAGIC FINAL FROM TO < "$2"
+    if [ "$MAGIC" != "RUSTPACK-PATCH" ] || [ -z "$TO" ] || [ "$(sha256_of "$1")" != "$FROM" ]; then
+        return 1
+    fi
+    tail -n +2 "$2" | {
+        POS=0
+        while IFS=: read -r OFFSET LENGTH DATA; do
+            if [ "$OFFSET" -gt "$POS" ]; then
+                tail -c +$((POS + 1)) "$1" | head -c $((OFFSET - POS))
+            fi
+            printf '%s' "$DATA" | base64 -d || exit 1
+            POS=$((OFFSET + LENGTH))
+        done
+        if [ "$FINAL" -gt "$POS" ]; then
+            tail -c +$((POS + 1)) "$1" | head -c $((FINAL - POS))
+        fi
+    } > "$3" || return 1
+    [ "$(sha256_of "$3")" = "$TO" ]
+}
+
+perform_update() {
+    echo "Updating to the latest version..."
+    UPDATE_URL="$PACKAGE_UPDATE_URL"
+    if [ -z "$UPDATE_URL" ]; then
+        echo "No update URL configured."
+        return 1
+    fi
+    CHANNEL="${PACKAGE_CHANNEL:-stable}"
+    VERSION_INFO=""
+    if command -v curl > /dev/null; then
+        VERSION_INFO=$(curl -s "$UPDATE_URL/version.json")
+    elif command -v wget > /dev/null; then
+        VERSION_INFO=$(wget -q -O - "$UPDATE_URL/version.json")
+    fi
+    if [ -n "$VERSION_INFO" ] && below_min_version; then
+        echo "Error: the latest release can't be installed over versions before $MIN_VERSION; download it from $UPDATE_URL instead."
+        return 1
+    fi
+    PACKAGE_FILE=$(channel_field "$CHANNEL" file 2>/dev/null)
+    DOWNLOAD_URL="$UPDATE_URL/${PACKAGE_FILE:-latest.rpack}"
+    TEMP_FILE=$(mktemp)
+    SUM_FILE=$(mktemp)
+    PATCHED=""
+    PATCH_FILE=$(channel_field "$CHANNEL" patch_file 2>/dev/null)
+    if [ -n "$PATCH_FILE" ] && [ "$(channel_field "$CHANNEL" patch_from 2>/dev/null)" = "$PACKAGE_VERSION" ]; then
+        PATCH_TEMP=$(mktemp)
+        if download "$UPDATE_URL/$PATCH_FILE" "$PATCH_TEMP" && apply_patch "$0" "$PATCH_TEMP" "$TEMP_FILE"; then
+            echo "Applied the update patch."
+            PATCHED=1
+        else
+            echo "Could not apply the update patch; downloading the whole update instead."
+        fi
+        rm -f "$PATCH_TEMP"
+    fi
+    if [ -z "$PATCHED" ] && ! download "$DOWNLOAD_URL" "$TEMP_FILE"; then
+        echo "Failed to download update; keeping the current version."
+        rm -f "$TEMP_FILE" "$SUM_FILE"
+        return 1
+    fi
+    if ! download "$DOWNLOAD_URL.sha256" "$SUM_FILE"; then
+        echo "Failed to download $DOWNLOAD_URL.sha256; keeping the current version."
+        rm -f "$TEMP_FILE" "$SUM_FILE"
+        return 1
+    fi
+    EXPECTED=$(awk '{ print $1; exit }' "$SUM_FILE")
+    ACTUAL=$(sha256_of "$TEMP_FILE")
+    rm -f "$SUM_FILE"
+    if [ -z "$ACTUAL" ]; then
+        echo "Error: sha256sum and shasum not found, so the update can't be verified; keeping the current version."
+        rm -f "$TEMP_FILE"
+        return 1
+    fi
+    if [ "$ACTUAL" != "$EXPECTED" ]; then
+        echo "Error: the downloaded update does not match its checksum; keeping the current version."
+        rm -f "$TEMP_FILE"
+        return 1
+    fi
+    CHECK_DIR=$(mktemp -d 2>/dev/null || mktemp -d -t rustpack)
+    extract_payload "$TEMP_FILE" "$CHECK_DIR" rustpack/manifest.env > /dev/null 2>&1
+    UPDATE_TARGETS=$(. "$CHECK_DIR/rustpack/manifest.env" 2> /dev/null && echo "$PACKAGE_TARGETS")
+    rm -rf "$CHECK_DIR"
+    if [ -z "$(select_binary "$UPDATE_TARGETS")" ]; then
+        echo "Error: the downloaded update has no binary for $PLATFORM-$ARCH${LIBC:+ ($LIBC)}; keeping the current version."
+        rm -f "$TEMP_FILE"
+        return 1
+    fi
+    # Stage the update next to the current executable, with its permissions,
+    # so the final mv is a rename on the same filesystem that replaces it in
+    # one step.
+    STAGED="$(dirname "$0")/.$(basename "$0").update.$$"
+    if ! cp -p "$0" "$STAGED" || ! cat "$TEMP_FILE" > "$STAGED"; then
+        echo "Error: could not write the update next to $0; keeping the current version."
+        rm -f "$TEMP_FILE" "$STAGED"
+        return 1
+    fi
+    rm -f "$TEMP_FILE"
+    echo "Update verified. Replacing current executable..."
+    if ! mv -f "$STAGED" "$0"; then
+        echo "Error: could not replace $0; keeping the current version."
+        rm -f "$STAGED"
+        return 1
+    fi
+    echo "Update completed successfully!"
+    return 0
+}
+
+if [ "$1" = "--check-updates" ]; then
+    check_for_updates
+    exit $?
+fi
+
+if [ "$1" = "--update" ]; then
+    perform_update
+    exit $?
+fi
+
+# Packages from before updates were verified hand over to the new package
+# to move itself into place.
+if [ "$1" = "--replace-with-update" ]; then
+    if [ -n "$2" ]; then
+        mv "$0" "$2"
+        echo "Update completed successfully!"
+        exit 0
+    else
+        echo "Missing target path for update"
+        exit 1
+    fi
+fi
+
+if [ -d "$TEMP_DIR/rustpack/assets" ]; then
+    export RUSTPACK_ASSETS_DIR="$TEMP_DIR/rustpack/assets"
+fi
+
+check_requirements() {
+    if [ -n "$RUSTPACK_SKIP_REQUIRES" ]; then
+        return 0
+    fi
+    for REQ in $PACKAGE_REQUIRES; do
+        KIND="${REQ%%:*}"
+        VALUE="${REQ#*:}"
+        case "$KIND" in
+            cpu)
+                if [ -r /proc/cpuinfo ] && ! grep -E '^(flags|Features)' /proc/cpuinfo | grep -qw "$VALUE"; then
+                    echo "Error: $APP_NAME requires the CPU feature '$VALUE', which this machine does not support"
+                    return 1
+                fi
+                ;;
+            kernel)
+                KERNEL_VERSION=$(uname -r | sed 's/[^0-9.].*//')
+                if ! version_at_least "$KERNEL_VERSION" "$VALUE"; then
+                    echo "Error: $APP_NAME requires kernel $VALUE or newer (found $KERNEL_VERSION)"
+                    return 1
+                fi
+                ;;
+            *)
+                echo "Warning: Unknown requirement '$REQ', skipping check"
+                ;;
+        esac
+    done
+    return 0
+}
+
+if ! check_requirements; then
+    exit 1
+fi
+
+BINARY_PATH=$(select_binary "$PACKAGE_TARGETS")
+
+if [ -n "$BINARY_PATH" ]; then
+    # With several binaries, a first argument naming one of them picks it.
+    for NAME in $PACKAGE_BINARIES; do
+        if [ "$1" = "$NAME" ]; then
+            case "$BINARY_PATH" in
+                *.exe) NAME="$NAME.exe" ;;
+            esac
+            BINARY_PATH="$(dirname "$BINARY_PATH")/$NAME"
+            shift
+            break
+        fi
+    done
+    if ! verify_binary "$BINARY_PATH"; then
+        exit 1
+    fi
+    chmod +x "$TEMP_DIR/rustpack/$BINARY_PATH"
+    # Shared libraries bundled with --bundle-libs, for binaries whose RPATH couldn't be patched.
+    LIB_DIR="$TEMP_DIR/rustpack/lib/$(basename "$(dirname "$BINARY_PATH")")"
+    if [ -d "$LIB_DIR" ]; then
+        if [ "$PLATFORM" = "macos" ]; then
+            DYLD_LIBRARY_PATH="$LIB_DIR${DYLD_LIBRARY_PATH:+:$DYLD_LIBRARY_PATH}"
+            export DYLD_LIBRARY_PATH
+        else
+            LD_LIBRARY_PATH="$LIB_DIR${LD_LIBRARY_PATH:+:$LD_LIBRARY_PATH}"
+            export LD_LIBRARY_PATH
+        fi
+    fi
+    if [ -n "$CLEANUP" ]; then
+        "$TEMP_DIR/rustpack/$BINARY_PATH" "$@"
+        STATUS=$?
+        rm -rf "$CLEANUP_DIR"
+        exit $STATUS
+    else
+        exec "$TEMP_DIR/rustpack/$BINARY_PATH" "$@"
+    fi
+else
+    echo "Error: No compatible binary found for $PLATFORM-$ARCH${LIBC:+ ($LIBC)}"
+    exit 1
+fi
+exit 0
+__PAYLOAD_BEGINS__
+"#;
+
+fn watch_and_build(
+    project_path: &str, 
+    output_name: &str, 
+    targets: &[String],
+    build_config: &BuildConfig,
+    verbose: bool,
+    open: Option<&[String]>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+    
+    let project_root = fs::canonicalize(project_path)?;
+    let (tx, rx) = channel();
+    let mut watcher = watcher(tx, Duration::from_secs(2))?;
+    watcher.watch(&project_root, RecursiveMode::Recursive)?;
+
+    if !log::quiet() {
+        println!("{} for changes in {}...", "Watching".blue().bold(), project_path);
+    }
+    
+    let report = build_package(project_path, output_name, targets, build_config, verbose, &[PackageFormat::SelfExtracting])?;
+    // The running app is stopped and started again after each successful
+    // rebuild.
+    let mut app = match open {
+        Some(args) => Some(open_package(&report.output, args)?),
+        None => None,
+    };
+
+    let output_path = fs::canonicalize(output_name).unwrap_or_else(|_| PathBuf::from(output_name));
+    let gitignore = load_gitignore(&project_root);
+    
+    let mut last_build = Instant::now();
+    
+    loop {
+        match rx.recv() {
+            Ok(event) => {
+                let Some(changed_path) = watch_event_path(&event) else {
+                    continue;
+                };
+                if is_watch_ignored(&project_root, changed_path, &output_path, &gitignore) {
+                    continue;
+                }
+                if last_build.elapsed() > Duration::from_secs(5) {
+                    let trigger = changed_path.strip_prefix(&project_root).unwrap_or(changed_path);
+                    if !log::quiet() {
+                        println!("{} changes in {}, rebuilding...", "Detected".yellow().bold(), trigger.display());
+                    }
+                    log::info("watch", &format!("Detected changes in {}, rebuilding", trigger.display()));
+                    match build_package(project_path, output_name, targets, build_config, verbose, &[PackageFormat::SelfExtracting]) {
+                        Err(e) => {
+                            log::error("watch", &format!("Build failed: {}", e));
+                            eprintln!("{}: {}", "Build failed".red().bold(), e);
+                        }
+                        Ok(report) => {
+                            if !log::quiet() {
+                                println!("{}", "Rebuild successful".green().bold());
+                            }
+                            if let Some(args) = open {
+                                if let Some(mut previous) = app.take() {
+                                    let _ = previous.kill();
+                                    let _ = previous.wait();
+                                }
+                                match open_package(&report.output, args) {
+                                    Ok(child) => app = Some(child),
+                                    Err(e) => eprintln!("{}: {}", "Could not start the package".red().bold(), e),
+                                }
+                            }
+                        }
+                    }
+                    last_build = Instant::now();
+                }
+            }
+            Err(e) => eprintln!("Watch error: {:?}", e),
+        }
+    }
+}
+
+/// Fails unless `formats` include a self-extracting package and exactly one
+/// of `targets` runs on this machine, so `--open` knows what to run.
+fn check_open(targets: &[String], formats: &[PackageFormat]) -> Result<(), Box<dyn std::error::Error>> {
+    if !formats.contains(&PackageFormat::SelfExtracting) {
+        return Err("--open runs the self-extracting package, but no rpack format was requested".into());
+    }
+    let host = get_current_target();
+    let os = |t: &str| ["linux", "apple", "windows", "android", "freebsd"].into_iter().find(|os| t.contains(os));
+    let runnable: Vec<&String> = targets
+        .iter()
+        .filter(|target| {
+            let triple = split_glibc_version(target).0;
+            triple.split('-').next() == host.split('-').next() && os(triple) == os(&host)
+        })
+        .collect();
+    match runnable.as_slice() {
+        [_] => Ok(()),
+        [] => Err(format!("--open needs a target that runs on this machine ({})", host).into()),
+        _ => Err(format!(
+            "--open needs exactly one target that runs on this machine, but {} all do",
+            runnable.iter().map(|t| t.as_str()).collect::<Vec<_>>().join(", ")
+        )
+        .into()),
+    }
+}
+
+/// Starts the package at `path` with `args`, inheriting stdio.
+fn open_package(path: &Path, args: &[String]) -> Result<std::process::Child, Box<dyn std::error::Error>> {
+    let path = fs::canonicalize(path)?;
+    log::info("open", &format!("Running {}", path.display()));
+    if !log::quiet() {
+        println!("{} {}", "Running".green().bold(), path.display());
+    }
+    ProcessCommand::new(&path)
+        .args(args)
+        .spawn()
+        .map_err(|e| format!("Could not run {}: {}", path.display(), e).into())
+}
+
+fn watch_event_path(event: &DebouncedEvent) -> Option<&Path> {
+    match event {
+        DebouncedEvent::Create(path)
+        | DebouncedEvent::Write(path)
+        | DebouncedEvent::Remove(path)
+        | DebouncedEvent::Chmod(path)
+        | DebouncedEvent::Rename(_, path) => Some(path),
+        _ => None,
+    }
+}
+
+fn load_gitignore(project_root: &Path) -> Gitignore {
+    let mut builder = GitignoreBuilder::new(project_root);
+    builder.add(project_root.join(".gitignore"));
+    builder.build().unwrap_or_else(|_| Gitignore::empty())
+}
+
+/// Build output, VCS metadata and gitignored files must not trigger rebuilds,
+/// otherwise every build would schedule the next one.
+fn is_watch_ignored(project_root: &Path, path: &Path, output_path: &Path, gitignore: &Gitignore) -> bool {
+    if path == output_path {
+        return true;
+    }
+    let Ok(rel_path) = path.strip_prefix(project_root) else {
+        return false;
+    };
+    if rel_path.starts_with("target") || rel_path.components().any(|c| c.as_os_str() == ".git") {
+        return true;
+    }
+    gitignore.matched_path_or_any_parents(path, path.is_dir()).is_ignore()
+}
+
+pub fn parse_manifest(project_path: &str) -> Result<Manifest, RustPackError> {
+    let cargo_toml = Path::new(project_path).join("Cargo.toml");
+    let cargo_content = fs::read_to_string(&cargo_toml).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => RustPackError::ManifestNotFound(PathBuf::from(project_path)),
+        _ => e.into(),
+    })?;
+    let manifest: toml::Value = toml::from_str(&cargo_content)
+        .map_err(|e| format!("Invalid {}: {}", cargo_toml.display(), e))?;
+    let package = manifest
+        .get("package")
+        .and_then(|p| p.as_table())
+        .ok_or_else(|| format!("Could not find a [package] table in {}", cargo_toml.display()))?;
+
+    let mut workspace_package: Option<toml::Value> = None;
+    let mut field = |key: &str| -> Result<Option<String>, Box<dyn std::error::Error>> {
+        match package.get(key) {
+            Some(toml::Value::String(value)) => Ok(Some(value.clone())),
+            Some(toml::Value::Table(table)) if table.get("workspace").and_then(|w| w.as_bool()) == Some(true) => {
+                if workspace_package.is_none() {
+                    workspace_package = Some(find_workspace_package(project_path)?);
+                }
+                Ok(workspace_package
+                    .as_ref()
+                    .and_then(|w| w.get(key))
+                    .and_then(|v| v.as_str())
+                    .map(|v| v.to_string()))
+            }
+            _ => Ok(None),
+        }
+    };
+
+    let name = field("name")?.ok_or("Could not determine project name from Cargo.toml")?;
+    let version = field("version")?.unwrap_or_else(|| "0.1.0".to_string());
+    let description = field("description")?;
+    let license = field("license")?;
+    let edition = field("edition")?;
+    let authors = match package.get("authors") {
+        Some(toml::Value::Table(table)) if table.get("workspace").and_then(|w| w.as_bool()) == Some(true) => {
+            find_workspace_package(project_path)?.get("authors").cloned()
+        }
+        authors => authors.cloned(),
+    };
+    let authors = authors
+        .and_then(|a| a.as_array().cloned())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|a| a.as_str().map(str::to_string))
+        .collect();
+
+    Ok(Manifest {
+        name,
+        version,
+        description,
+        license,
+        edition,
+        authors,
+    })
+}
+
+/// Fails unless `profile` is built in or defined in the workspace's
+/// Cargo.toml, a `.cargo/config.toml` cargo reads for the project or a
+/// `CARGO_PROFILE_<NAME>_*` variable.
+fn check_profile(project_path: &str, profile: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if matches!(profile, "dev" | "release" | "test" | "bench") || manifest_profiles(project_path)?.contains_key(profile) {
+        return Ok(());
+    }
+    let env_prefix = format!("CARGO_PROFILE_{}_", profile.to_uppercase().replace('-', "_"));
+    if env::vars_os().any(|(name, _)| name.to_string_lossy().starts_with(&env_prefix)) {
+        return Ok(());
+    }
+
+    let mut config_dirs: Vec<PathBuf> = fs::canonicalize(project_path)?.ancestors().map(|dir| dir.join(".cargo")).collect();
+    let cargo_home = env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")).map(|home| PathBuf::from(home).join(".cargo")));
+    config_dirs.extend(cargo_home);
+    for dir in config_dirs {
+        for file in ["config.toml", "config"] {
+            if let Ok(content) = fs::read_to_string(dir.join(file))
+                && let Ok(config) = toml::from_str::<toml::Value>(&content)
+                && config.get("profile").and_then(|p| p.get(profile)).is_some()
+            {
+                return Ok(());
+            }
+        }
+    }
+    Err(format!("Unknown profile: {} (define it with a [profile.{}] section in Cargo.toml)", profile, profile).into())
+}
+
+/// The `[profile]` table of the workspace root's Cargo.toml, the only
+/// manifest cargo reads profiles from.
+fn manifest_profiles(project_path: &str) -> Result<toml::Table, Box<dyn std::error::Error>> {
+    let metadata = cargo_metadata(project_path)?;
+    let root = metadata["workspace_root"].as_str().ok_or("cargo metadata did not report a workspace root")?;
+    let cargo_toml = Path::new(root).join("Cargo.toml");
+    let manifest: toml::Value = toml::from_str(&fs::read_to_string(&cargo_toml)?)
+        .map_err(|e| format!("Invalid {}: {}", cargo_toml.display(), e))?;
+    Ok(manifest.get("profile").and_then(|p| p.as_table()).cloned().unwrap_or_default())
+}
+
+/// The cargo arguments that select `profile`. Only `release` has a
+/// shorthand; everything else, including custom profiles, needs `--profile`.
+fn profile_args(profile: &str) -> Vec<String> {
+    match profile {
+        "release" => vec!["--release".to_string()],
+        profile => vec!["--profile".to_string(), profile.to_string()],
+    }
+}
+
+/// The directory under `target/<triple>` that cargo puts `profile`'s
+/// artifacts in.
+fn profile_dir(profile: &str) -> &str {
+    match profile {
+        "dev" | "test" => "debug",
+        "bench" => "release",
+        profile => profile,
+    }
+}
+
+/// Fails, listing the features the project does have, if any of `features`
+/// isn't one of them. Accepts `[features]` entries, optional dependencies
+/// that aren't hidden behind `dep:`, and `dependency/feature`.
+fn check_features(project_path: &str, features: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    // Cargo also accepts space-separated features within one entry.
+    let features: Vec<&str> = features.iter().flat_map(|f| f.split_whitespace()).collect();
+    if features.is_empty() {
+        return Ok(());
+    }
+    let cargo_toml = Path::new(project_path).join("Cargo.toml");
+    let manifest: toml::Value = toml::from_str(&fs::read_to_string(&cargo_toml)?)?;
+
+    let mut dependencies: BTreeMap<String, bool> = BTreeMap::new();
+    let targets = manifest.get("target").and_then(|t| t.as_table());
+    let tables = std::iter::once(&manifest).chain(targets.into_iter().flat_map(|t| t.values()));
+    for table in tables {
+        for (name, spec) in table.get("dependencies").and_then(|d| d.as_table()).into_iter().flatten() {
+            let optional = spec.get("optional").and_then(|o| o.as_bool()).unwrap_or(false);
+            *dependencies.entry(name.clone()).or_default() |= optional;
+        }
+    }
+
+    let declared = manifest.get("features").and_then(|f| f.as_table());
+    let hidden: Vec<&str> = declared
+        .into_iter()
+        .flat_map(|f| f.values())
+        .filter_map(|v| v.as_array())
+        .flatten()
+        .filter_map(|v| v.as_str()?.strip_prefix("dep:"))
+        .collect();
+    let mut available: Vec<String> = declared.into_iter().flat_map(|f| f.keys().cloned()).collect();
+    available.extend(
+        dependencies
+            .iter()
+            .filter(|(name, optional)| **optional && !hidden.contains(&name.as_str()))
+            .map(|(name, _)| name.clone()),
+    );
+    available.sort();
+    available.dedup();
+
+    let unknown: Vec<&str> = features
+        .into_iter()
+        .filter(|feature| match feature.split_once('/') {
+            Some((dependency, _)) => !dependencies.contains_key(dependency.trim_end_matches('?')),
+            None => *feature != "default" && !available.iter().any(|a| a == feature),
+        })
+        .collect();
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    let available = if available.is_empty() {
+        "it doesn't declare any".to_string()
+    } else {
+        format!("available: {}", available.join(", "))
+    };
+    Err(format!("Unknown feature(s) {} in {} ({})", unknown.join(", "), cargo_toml.display(), available).into())
+}
+
+fn find_workspace_package(project_path: &str) -> Result<toml::Value, Box<dyn std::error::Error>> {
+    let project_dir = fs::canonicalize(project_path)?;
+    for dir in project_dir.ancestors() {
+        let cargo_toml = dir.join("Cargo.toml");
+        if !cargo_toml.exists() {
+            continue;
+        }
+        let manifest: toml::Value = toml::from_str(&fs::read_to_string(&cargo_toml)?)?;
+        if let Some(workspace) = manifest.get("workspace") {
+            return workspace
+                .get("package")
+                .cloned()
+                .ok_or_else(|| format!("No [workspace.package] table in {}", cargo_toml.display()).into());
+        }
+    }
+    Err("Could not find the workspace manifest for inherited package fields".into())
+}
+
+/// The directory whose Cargo.toml describes the crate to pack: `project_path`
+/// itself or, with `package`, that member of the workspace at
+/// `project_path`. A workspace without a `[package]` of its own needs one.
+pub fn package_dir(project_path: &str, package: Option<&str>) -> Result<String, RustPackError> {
+    let cargo_toml = Path::new(project_path).join("Cargo.toml");
+    if !cargo_toml.is_file() {
+        return Err(RustPackError::ManifestNotFound(PathBuf::from(project_path)));
+    }
+    let manifest: toml::Value = toml::from_str(&fs::read_to_string(&cargo_toml)?)
+        .map_err(|e| format!("Invalid {}: {}", cargo_toml.display(), e))?;
+    if package.is_none() && (manifest.get("package").is_some() || manifest.get("workspace").is_none()) {
+        return Ok(project_path.to_string());
+    }
+
+    let members = workspace_members(project_path)?;
+    let names = members.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(", ");
+    match package {
+        Some(package) => members
+            .iter()
+            .find(|(name, _)| name == package)
+            .map(|(_, dir)| dir.to_string_lossy().to_string())
+            .ok_or_else(|| format!("No package named {} in the workspace (members: {})", package, names).into()),
+        None => Err(format!("{} is a workspace; choose a member with --package (members: {})", project_path, names).into()),
+    }
+}
+
+/// `cargo metadata` for the workspace packages only.
+fn cargo_metadata(project_path: &str) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let output = ProcessCommand::new("cargo")
+        .current_dir(project_path)
+        .args(["metadata", "--no-deps", "--format-version", "1"])
+        .output()?;
+    if !output.status.success() {
+        return Err(format!("cargo metadata failed:\n{}", String::from_utf8_lossy(&output.stderr).trim_end()).into());
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+/// Where cargo puts the artifacts of the project at `project_path`: the
+/// configured `target_dir`, or else what cargo metadata reports, which
+/// follows `CARGO_TARGET_DIR`, `build.target-dir` and the workspace root.
+fn cargo_target_dir(project_path: &str, build_config: &BuildConfig) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    if let Some(dir) = &build_config.target_dir {
+        return Ok(std::path::absolute(dir)?);
+    }
+    let metadata = cargo_metadata(project_path)?;
+    metadata["target_directory"]
+        .as_str()
+        .map(PathBuf::from)
+        .ok_or_else(|| "cargo metadata did not report a target directory".into())
+}
+
+/// Names and directories of the packages in the workspace at `project_path`.
+fn workspace_members(project_path: &str) -> Result<Vec<(String, PathBuf)>, Box<dyn std::error::Error>> {
+    let metadata = cargo_metadata(project_path)?;
+    let mut members = Vec::new();
+    for package in metadata["packages"].as_array().into_iter().flatten() {
+        if let (Some(name), Some(manifest_path)) = (package["name"].as_str(), package["manifest_path"].as_str()) {
+            let dir = Path::new(manifest_path).parent().unwrap_or(Path::new(".")).to_path_buf();
+            members.push((name.to_string(), dir));
+        }
+    }
+    members.sort();
+    Ok(members)
+}
+
+/// The `[[bin]]` targets to package, the default one first: those in
+/// `bins`, every one with `all_bins`, or else the one named after the
+/// package, or its only one.
+fn select_binaries(manifest_dir: &str, project_name: &str, build_config: &BuildConfig) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let metadata = cargo_metadata(manifest_dir)?;
+    let available: Vec<String> = metadata["packages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|p| p["name"] == project_name)
+        .flat_map(|p| p["targets"].as_array().cloned().unwrap_or_default())
+        .filter(|t| t["kind"].as_array().is_some_and(|kinds| kinds.iter().any(|k| k == "bin")))
+        .filter_map(|t| t["name"].as_str().map(str::to_string))
+        .collect();
+    if available.is_empty() {
+        return Err(format!("{} has no binary targets", project_name).into());
+    }
+
+    let mut selected = if build_config.all_bins {
+        available.clone()
+    } else if !build_config.bins.is_empty() {
+        if let Some(missing) = build_config.bins.iter().find(|b| !available.contains(b)) {
+            return Err(format!("{} has no binary named {} (binaries: {})", project_name, missing, available.join(", ")).into());
+        }
+        let mut bins = build_config.bins.clone();
+        bins.dedup();
+        bins
+    } else if available.iter().any(|b| b == project_name) {
+        vec![project_name.to_string()]
+    } else if let [only] = &available[..] {
+        vec![only.clone()]
+    } else {
+        return Err(format!(
+            "{} has several binaries and none is named after the package; choose with --bin or --bins (binaries: {})",
+            project_name,
+            available.join(", ")
+        )
+        .into());
+    };
+    if let Some(index) = selected.iter().position(|b| b == project_name) {
+        let default = selected.remove(index);
+        selected.insert(0, default);
+    }
+    Ok(selected)
+}
+
+pub fn get_current_target() -> String {
+    let Ok(output) = ProcessCommand::new("rustc").args(["-vV"]).output() else {
+        return "unknown".to_string();
+    };
+
+    let output_str = String::from_utf8_lossy(&output.stdout);
+
+    for line in output_str.lines() {
+        if line.starts_with("host:") {
+            return line.split(':').nth(1).unwrap_or("unknown").trim().to_string();
+        }
+    }
+
+    "unknown".to_string()
+}
+
+/// Splits the glibc version `cargo zigbuild` accepts after a GNU triple, as
+/// in `x86_64-unknown-linux-gnu.2.17`, from the triple itself.
+fn split_glibc_version(target: &str) -> (&str, Option<&str>) {
+    match target.split_once('.') {
+        Some((triple, version))
+            if triple.contains("-linux-gnu")
+                && !version.is_empty()
+                && version.split('.').all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit())) =>
+        {
+            (triple, Some(version))
+        }
+        _ => (target, None),
+    }
+}
+
+/// The glibc version of the build machine, from `ldd --version`, for GNU
+/// targets built natively with cargo, whose binaries link against it. Other
+/// builds use the sysroot of cross or zig, so `None`.
+fn native_glibc_version(target: &str, builder: &str, host: &str) -> Option<String> {
+    if builder != "cargo" || target != host || !target.contains("-linux-gnu") {
+        return None;
+    }
+    let output = ProcessCommand::new("ldd").arg("--version").output().ok()?;
+    // `ldd (GNU libc) 2.39`, or with the distribution's name and release.
+    let version = String::from_utf8_lossy(&output.stdout).lines().next()?.split_whitespace().last()?.to_string();
+    (version.contains('.') && version.split('.').all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit())))
+        .then_some(version)
+}
+
+/// WebAssembly targets build modules, not programs the bootstrap or any of
+/// the other package formats could run.
+fn check_target_platform(target: &str, formats: &[PackageFormat]) -> Result<(), Box<dyn std::error::Error>> {
+    if parse_target(target).0 == "wasm" && formats != [PackageFormat::Wasm] {
+        return Err(format!(
+            "{} builds a WebAssembly module, which has no self-executing package; use --format wasm",
+            target
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// `--format wasm` builds one wasm32 library on its own, as nothing else in
+/// the package could use it.
+fn check_wasm_format(targets: &[String], formats: &[PackageFormat]) -> Result<(), Box<dyn std::error::Error>> {
+    if !formats.contains(&PackageFormat::Wasm) {
+        return Ok(());
+    }
+    if formats.len() > 1 {
+        return Err("--format wasm can't be combined with other formats".into());
+    }
+    match targets {
+        [target] if parse_target(target).0 == "wasm" => Ok(()),
+        _ => Err("--format wasm needs a single WebAssembly target, such as wasm32-unknown-unknown".into()),
+    }
+}
+
+/// Builds the library for `target` and turns it into a web bundle at
+/// `output_name` with `wasm-bindgen`. Nothing self-extracts, so the bootstrap
+/// and the rest of the packaging steps are skipped.
+#[allow(clippy::too_many_arguments)]
+fn build_wasm_package(
+    project_path: &str,
+    manifest_dir: &str,
+    output_name: &str,
+    target: &str,
+    name: &str,
+    version: &str,
+    description: Option<&str>,
+    license: Option<&str>,
+    build_config: &BuildConfig,
+    verbose: bool,
+) -> Result<PackageReport, Box<dyn std::error::Error>> {
+    let start = Instant::now();
+    let mut selection = Vec::new();
+    if let Some(package) = &build_config.package {
+        selection.push("--package".to_string());
+        selection.push(package.clone());
+    }
+    selection.push("--lib".to_string());
+    let mut cargo = cargo_build_command(project_path, target, &selection, &[], build_config, verbose)?;
+    if log::quiet() {
+        run_captured(&mut cargo, target)?;
+    } else if !cargo.status()?.success() {
+        return Err(RustPackError::BuildFailed { target: target.to_string(), output: String::new() }.into());
+    }
+    log::info("build", &format!("Built {}", target));
+    let build_time = start.elapsed();
+
+    let built_dir = cargo_target_dir(project_path, build_config)?.join(target).join(profile_dir(&build_config.profile));
+    let module = wasm::module_path(manifest_dir, name, &built_dir);
+    log::info("archive", &format!("Writing {}", output_name));
+    let archive_start = Instant::now();
+    let module_file = wasm::write_bundle(&module, name, version, description, license, Path::new(output_name))?;
+    let archive_time = archive_start.elapsed();
+    if verbose {
+        println!("{} {} in {:.2}s", "Built".blue(), target, build_time.as_secs_f64());
+    }
+
+    let checksum = calculate_checksum(&Path::new(output_name).join(&module_file))?;
+    let (platform, arch, compatibility) = parse_target(target);
+    let created_at = package_timestamp(build_config)?;
+    let size = output_size(Path::new(output_name))?;
+    Ok(PackageReport {
+        output: PathBuf::from(output_name),
+        size,
+        outputs: vec![PathBuf::from(output_name)],
+        info: PackageInfo {
+            name: name.to_string(),
+            version: version.to_string(),
+            description: description.map(str::to_string),
+            targets: vec![TargetInfo {
+                platform,
+                arch,
+                binary_path: module_file.clone(),
+                binary_checksums: BTreeMap::from([(module_file.clone(), checksum.clone())]),
+                binaries: vec![module_file],
+                checksum: checksum.clone(),
+                features: build_config.features.clone(),
+                optimizations: None,
+                compatibility,
+                signature: None,
+            }],
+            created_at,
+            checksum,
+            features: build_config.features.clone(),
+            requires: Vec::new(),
+            metadata: BTreeMap::new(),
+            merkle_root: None,
+            public_key: None,
+        },
+        compression: PayloadCompression::None,
+        payload_size: size,
+        archive_times: vec![archive_time],
+        exit_code: None,
+    })
+}
+
+fn parse_target(target: &str) -> (String, String, Vec<String>) {
+    let (target, glibc_version) = split_glibc_version(target);
+    let parts: Vec<&str> = target.split('-').collect();
+
+    if parts.len() < 2 {
+        return ("unknown".to_string(), "unknown".to_string(), vec![]);
+    }
+
+    let arch = parts[0].to_string();
+
+    // Android triples also contain "linux", so they are checked first.
+    let platform = if target.contains("windows") {
+        "windows".to_string()
+    } else if target.contains("android") {
+        "android".to_string()
+    } else if target.contains("linux") {
+        "linux".to_string()
+    } else if target.contains("darwin") || target.contains("apple") {
+        "macos".to_string()
+    } else if target.contains("freebsd") {
+        "freebsd".to_string()
+    } else if target.contains("netbsd") {
+        "netbsd".to_string()
+    } else if arch.starts_with("wasm") {
+        "wasm".to_string()
+    } else {
+        "unknown".to_string()
+    };
+
+    // The oldest OS release Rust's standard library supports for each, unless
+    // the triple pins a glibc version. Native builds replace it with the
+    // build machine's glibc.
+    let compatibility = match platform.as_str() {
+        "windows" => vec!["nt6.1".to_string(), "pe".to_string()],
+        // musl targets link statically, so they run on any Linux.
+        "linux" if target.contains("-musl") => vec!["static".to_string(), "elf".to_string()],
+        "linux" => vec![format!("glibc-{}", glibc_version.unwrap_or("2.17")), "elf".to_string()],
+        // Apple Silicon needs macOS 11.
+        "macos" if arch == "aarch64" => vec!["11.0".to_string(), "mach-o".to_string()],
+        "macos" => vec!["10.7".to_string(), "mach-o".to_string()],
+        "android" => vec!["api-21".to_string(), "elf".to_string()],
+        "freebsd" => vec!["12.0".to_string(), "elf".to_string()],
+        "netbsd" => vec!["8.0".to_string(), "elf".to_string()],
+        "wasm" => vec!["wasm".to_string()],
+        _ => vec![],
+    };
+
+    (platform, arch, compatibility)
+}
+
+/// Runs `job` on each item using up to `jobs` threads and returns the results
+/// in item order. Once a job fails no new ones are started, and the error of
+/// the first failed item is returned.
+fn run_jobs<T: Sync, R: Send>(
+    items: &[T],
+    jobs: usize,
+    job: impl Fn(&T) -> Result<R, Box<dyn std::error::Error>> + Sync,
+) -> Result<Vec<R>, Box<dyn std::error::Error>> {
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let results: Mutex<Vec<Option<Result<R, RustPackError>>>> = Mutex::new(items.iter().map(|_| None).collect());
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.max(1) {
+            scope.spawn(|| {
+                while !failed.load(Ordering::SeqCst) {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(item) = items.get(index) else {
+                        break;
+                    };
+                    // Boxed errors aren't Send, so they cross the thread as
+                    // a RustPackError, which keeps the kind of failure.
+                    let result = job(item).map_err(RustPackError::from);
+                    if result.is_err() {
+                        failed.store(true, Ordering::SeqCst);
+                    }
+                    results.lock().unwrap()[index] = Some(result);
+                }
+            });
+        }
+    });
+
+    let mut outputs = Vec::new();
+    for result in results.into_inner().unwrap() {
+        match result {
+            Some(Ok(output)) => outputs.push(output),
+            Some(Err(e)) => return Err(e.into()),
+            None => return Err("Build was interrupted".into()),
+        }
+    }
+    Ok(outputs)
+}
+
+/// Builds and post-processes the binary for `target` in `target_dir`. With
+/// `concurrent`, the target dir is this target's own, so several targets can
+/// build at once; cargo's output is then captured and only shown if the
+/// build fails. `resource` is a compiled Windows resource to link in.
+#[allow(clippy::too_many_arguments)]
+fn build_for_target(
+    project_path: &str,
+    target_dir: &Path,
+    concurrent: bool,
+    bin_dir: &Path,
+    target: &str,
+    binaries: &[String],
+    resource: Option<&Path>,
+    build_config: &BuildConfig,
+    verbose: bool,
+    multi_progress: Option<&MultiProgress>,
+) -> Result<BuiltBinary, Box<dyn std::error::Error>> {
+    // A multi-call wrapper is a crate of its own with a single binary.
+    let mut selection = Vec::new();
+    if build_config.multicall.is_empty() {
+        if let Some(package) = &build_config.package {
+            selection.push("--package".to_string());
+            selection.push(package.clone());
+        }
+        for binary in binaries {
+            selection.push("--bin".to_string());
+            selection.push(binary.clone());
+        }
+    }
+    let link_args: Vec<String> = resource.map(|r| format!("-Clink-arg={}", r.display())).into_iter().collect();
+    let mut cargo = cargo_build_command(project_path, target, &selection, &link_args, build_config, verbose)?;
+
+    let pb = if let Some(multi_progress) = multi_progress {
+        let pb = multi_progress.add(ProgressBar::new_spinner());
+        pb.set_style(ProgressStyle::default_spinner()
+            .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
+            .template("{spinner} {msg}").unwrap());
+        pb.set_message(format!("Building for {}", target));
+        Some(pb)
+    } else {
+        None
+    };
+
+    // Concurrent builds would interleave their output, and --quiet wants
+    // none of it unless the build fails.
+    cargo.env("CARGO_TARGET_DIR", target_dir);
+    if concurrent || log::quiet() {
+        if let Some(pb) = &pb {
+            pb.enable_steady_tick(Duration::from_millis(100));
+        }
+        let built = run_captured(&mut cargo, target);
+        if let Some(pb) = &pb {
+            pb.finish_and_clear();
+        }
+        built?;
+    } else {
+        let status = cargo.status()?;
+        if let Some(ref pb) = pb {
+            pb.finish_and_clear();
+        }
+        if !status.success() {
+            return Err(RustPackError::BuildFailed { target: target.to_string(), output: String::new() }.into());
+        }
+    }
+    log::info("build", &format!("Built {}", target));
+
+    let ext = if target.contains("windows") { ".exe" } else { "" };
+    let built_dir = target_dir
+        .join(split_glibc_version(target).0)
+        .join(profile_dir(&build_config.profile));
+
+    let overrides = build_config.target_overrides.get(target);
+    let strip = overrides.and_then(|o| o.strip).unwrap_or(build_config.strip);
+    let compress = overrides.and_then(|o| o.compress).unwrap_or(build_config.compress);
+    let strip_tool = if strip {
+        let configured = overrides
+            .and_then(|o| o.strip_tool.as_deref())
+            .or(build_config.strip_tool.as_deref());
+        let tool = find_strip_tool(target, configured);
+        if tool.is_none() {
+            let tried = strip_tool_candidates(target, configured).join(", ");
+            let message = format!("No strip tool for {} found (tried {}); set strip_tool to choose one, not stripping", target, tried);
+            log::warning("strip", &message);
+        }
+        tool
+    } else {
+        None
+    };
+    let mut stripped = strip_tool.is_some();
+    let mut compressed = compress;
+    let mut paths = Vec::new();
+    let mut libraries = Vec::new();
+    let mut crates: BTreeMap<String, u64> = BTreeMap::new();
+
+    for binary in binaries {
+        let binary_with_ext = format!("{}{}", binary, ext);
+        let binary_path_with_ext = built_dir.join(&binary_with_ext);
+        let dest_path = bin_dir.join(&binary_with_ext);
+        fs::copy(&binary_path_with_ext, &dest_path)?;
+
+        // Read from cargo's copy, which still has its symbols.
+        if build_config.bloat.is_some() {
+            match bloat::crate_sizes(&fs::read(&binary_path_with_ext)?) {
+                Ok(sizes) if sizes.is_empty() => {
+                    let message = format!("{} has no symbol table, so --bloat can't attribute its size to crates", binary_with_ext);
+                    log::warning("analyze", &message);
+                }
+                Ok(sizes) => {
+                    for (name, size) in sizes {
+                        *crates.entry(name).or_insert(0) += size;
+                    }
+                }
+                Err(e) => log::warning("analyze", &format!("Could not read the symbols of {}: {}", binary_with_ext, e)),
+            }
+        }
+
+        if let Ok(size_info) = analyze_binary_size(&binary_path_with_ext) {
+            log::info("analyze", &format!(
+                "Binary size for {}: {} bytes",
+                target,
+                size_info.get("total").unwrap_or(&0)
+            ));
+        }
+        if verbose
+            && let Ok(size_info) = analyze_binary_size(&binary_path_with_ext)
+        {
+            println!("Binary size analysis for {}:", target);
+            println!("  Total size: {} bytes", size_info.get("total").unwrap_or(&0));
+            for (section, size) in &size_info {
+                if section != "total" {
+                    println!("  {}: {} bytes", section, size);
+                }
+            }
+        }
+
+        if let Some(strip_tool) = &strip_tool {
+            if let Some(pb) = pb.clone() {
+                pb.set_message(format!("Stripping debug symbols for {}", target));
+                pb.enable_steady_tick(Duration::from_millis(100));
+            }
+
+            let strip_args = strip_args(target, strip_tool, &build_config.strip_level)?;
+            log::info("strip", &format!("Running: {} {} {}", strip_tool, strip_args.join(" "), dest_path.display()));
+            let strip_status = ProcessCommand::new(strip_tool)
+                .args(&strip_args)
+                .arg(&dest_path)
+                .status();
+
+            let success = matches!(&strip_status, Ok(status) if status.success());
+            stripped &= success;
+            if success {
+                log::info("strip", &format!("Stripped {}", target));
+            } else {
+                log::warning("strip", &format!("Could not strip the binary for {}", target));
+            }
+            if let Ok(status) = strip_status
+                && verbose
+                && status.success()
+            {
+                println!("Successfully stripped debug symbols with {}", strip_tool);
+            }
+
+            if let Some(pb) = pb.clone() {
+                pb.finish_and_clear();
+            }
+        }
+
+        // Has to happen before UPX, which leaves no dynamic section to edit.
+        if build_config.strip_rpath {
+            if target.contains("linux") || target.contains("android") || target.contains("bsd") {
+                let removed = strip_rpath(&dest_path)?;
+                log::info("rpath", &format!("Removed {} RPATH/RUNPATH entries for {}", removed, target));
+                if verbose {
+                    println!("Removed {} RPATH/RUNPATH entries", removed);
+                }
+            } else {
+                log::warning("rpath", &format!("--strip-rpath only applies to ELF binaries, skipping {}", target));
+            }
+        }
+
+        // patchelf can't edit a UPX-compressed binary either.
+        if build_config.bundle_libs {
+            let lib_dir = bin_dir
+                .ancestors()
+                .nth(2)
+                .ok_or("Invalid binary directory")?
+                .join("lib")
+                .join(target);
+            let bundled = bundle_libraries(&binary_path_with_ext, &dest_path, target, &lib_dir)?;
+            if verbose && !bundled.is_empty() {
+                println!("Bundled shared libraries: {}", bundled.join(", "));
+            }
+            for library in bundled {
+                if !libraries.contains(&library) {
+                    libraries.push(library);
+                }
+            }
+        }
+
+        if compress {
+            if let Some(pb) = pb.clone() {
+                pb.set_message(format!("Compressing binary for {}", target));
+                pb.enable_steady_tick(Duration::from_millis(100));
+            }
+
+            log::info("compress", &format!("Running: upx --best {}", dest_path.display()));
+            let upx_status = ProcessCommand::new("upx")
+                .arg("--best")
+                .arg(&dest_path)
+                .stdout(if log::quiet() { Stdio::null() } else { Stdio::inherit() })
+                .status();
+
+            let success = matches!(&upx_status, Ok(status) if status.success());
+            compressed &= success;
+            if success {
+                log::info("compress", &format!("Compressed {}", target));
+            } else {
+                log::warning("compress", &format!("Could not compress the binary for {}", target));
+            }
+
+            if let Ok(status) = upx_status
+                && verbose
+                && status.success()
+            {
+                println!("Successfully compressed binary with UPX");
+            }
+
+            if let Some(pb) = &pb {
+                pb.finish_and_clear();
+            }
+        }
+
+        paths.push(PathBuf::from("bin").join(target).join(&binary_with_ext));
+    }
+
+    let features = build_config.features.clone();
+    let mut crates: Vec<(String, u64)> = crates.into_iter().collect();
+    crates.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    Ok(BuiltBinary { path: paths[0].clone(), binaries: paths, features, stripped, compressed, libraries, crates })
+}
+
+/// Runs each of `hooks` with the shell in `project_path`, stopping at the
+/// first that fails. Their output goes to the log, and is also shown as it
+/// comes in verbose mode or in the error when a hook fails.
+fn run_hooks(
+    phase: &str,
+    hooks: &[String],
+    project_path: &str,
+    vars: &[(&str, String)],
+    verbose: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for hook in hooks {
+        log::info(phase, &format!("Running: {}", hook));
+        if verbose {
+            println!("{} {} hook: {}", "Running".blue(), phase, hook);
+        }
+        let mut command = if cfg!(windows) {
+            let mut command = ProcessCommand::new("cmd");
+            command.arg("/C");
+            command
+        } else {
+            let mut command = ProcessCommand::new("sh");
+            command.arg("-c");
+            command
+        };
+        let mut child = command
+            .arg(hook)
+            .current_dir(project_path)
+            .envs(vars.iter().map(|(key, value)| (key, value)))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let captured = Mutex::new(String::new());
+        let streams: [Option<Box<dyn Read + Send>>; 2] = [
+            child.stdout.take().map(|s| Box::new(s) as Box<dyn Read + Send>),
+            child.stderr.take().map(|s| Box::new(s) as Box<dyn Read + Send>),
+        ];
+        std::thread::scope(|scope| {
+            for stream in streams.into_iter().flatten() {
+                let captured = &captured;
+                scope.spawn(move || {
+                    for line in BufReader::new(stream).lines().map_while(Result::ok) {
+                        if verbose {
+                            println!("  {}", line);
+                        }
+                        log::info(phase, &line);
+                        let mut captured = captured.lock().unwrap();
+                        captured.push_str(&line);
+                        captured.push('\n');
+                    }
+                });
+            }
+        });
+
+        let status = child.wait()?;
+        if !status.success() {
+            let output = captured.into_inner().unwrap();
+            let mut message = format!("The {} hook `{}` failed ({})", phase, hook, status);
+            if !verbose && !output.is_empty() {
+                message.push_str(&format!(":\n{}", output.trim_end()));
+            }
+            return Err(message.into());
+        }
+    }
+    Ok(())
+}
+
+/// The program and subcommand that build a target with `builder`.
+fn builder_command(builder: &str) -> Result<(&'static str, &'static str), Box<dyn std::error::Error>> {
+    match builder {
+        "cargo" => Ok(("cargo", "build")),
+        "cross" => Ok(("cross", "build")),
+        "zig" => Ok(("cargo", "zigbuild")),
+        _ => Err(format!("Unknown builder: {} (expected cargo, cross or zig)", builder).into()),
+    }
+}
+
+/// Checks that `builder` is known and installed, with instructions for
+/// installing it if not.
+fn check_builder(builder: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let (program, subcommand) = builder_command(builder)?;
+    let mut version = ProcessCommand::new(program);
+    if subcommand != "build" {
+        version.arg(subcommand);
+    }
+    let installed = version
+        .arg("--version")
+        .output()
+        .is_ok_and(|output| output.status.success());
+    match builder {
+        "cross" if !installed => Err(RustPackError::ToolMissing("cross").into()),
+        "zig" if !installed => Err(RustPackError::ToolMissing("cargo-zigbuild").into()),
+        _ => Ok(()),
+    }
+}
+
+/// Runs a cargo build with its output captured, so nothing reaches the
+/// console and a failure reports what cargo printed.
+fn run_captured(cargo: &mut ProcessCommand, target: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let output = cargo.output()?;
+    if !output.status.success() {
+        return Err(RustPackError::BuildFailed {
+            target: target.to_string(),
+            output: String::from_utf8_lossy(&output.stderr).trim_end().to_string(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// The cargo invocation that builds `selection` (`--bin`, `--lib` and
+/// `--package` arguments) for `target` with the configured builder, profile,
+/// features, LTO and extra arguments. `link_args` are passed to rustc on top
+/// of the configured rustflags.
+fn cargo_build_command(
+    project_path: &str,
+    target: &str,
+    selection: &[String],
+    link_args: &[String],
+    build_config: &BuildConfig,
+    verbose: bool,
+) -> Result<ProcessCommand, Box<dyn std::error::Error>> {
+    let (program, subcommand) = builder_command(&build_config.builder)?;
+    let mut cargo_args = vec![subcommand.to_string()];
+    cargo_args.extend(profile_args(&build_config.profile));
+    cargo_args.push("--target".to_string());
+    cargo_args.push(target.to_string());
+    cargo_args.extend(selection.iter().cloned());
+    if !build_config.features.is_empty() {
+        cargo_args.push("--features".to_string());
+        cargo_args.push(build_config.features.join(","));
+    }
+    if build_config.no_default_features {
+        cargo_args.push("--no-default-features".to_string());
+    }
+    cargo_args.extend(build_config.cargo_args.iter().cloned());
+
+    log::info("build", &format!("Running: {} {}", program, cargo_args.join(" ")));
+    if verbose {
+        println!("Running: {} {}", program, cargo_args.join(" "));
+    }
+
+    let mut cargo = ProcessCommand::new(program);
+    cargo.current_dir(project_path).args(&cargo_args);
+    if let Some(dir) = &build_config.target_dir {
+        cargo.env("CARGO_TARGET_DIR", std::path::absolute(dir)?);
+    }
+
+    // Profile overrides go through the environment so the project's own
+    // .cargo/config.toml is never touched.
+    if let Some(lto_type) = &build_config.lto
+        && lto_type != "off"
+    {
+        let profile_env = build_config.profile.to_uppercase().replace('-', "_");
+        cargo
+            .env(format!("CARGO_PROFILE_{}_LTO", profile_env), lto_type)
+            .env(format!("CARGO_PROFILE_{}_CODEGEN_UNITS", profile_env), "1");
+    }
+    if build_config.lto.as_deref().is_some_and(|lto| lto != "off")
+        && build_config.rustflags.iter().any(|f| f.contains("lto") || f.contains("codegen-units"))
+    {
+        let message = "rustflags set LTO or codegen units themselves, overriding --lto";
+        log::warning("build", message);
+    }
+    let mut rustc_args = link_args.to_vec();
+    rustc_args.extend(static_link_args(target, &build_config.rustflags));
+    if let Some((name, value)) = rustflags_env(&build_config.rustflags, &rustc_args) {
+        log::info("build", &format!("{}={}", name, value.replace('\x1f', " ")));
+        if verbose {
+            println!("{}={}", name, value.replace('\x1f', " "));
+        }
+        cargo.env(name, value);
+    }
+    Ok(cargo)
+}
+
+/// `+crt-static` for musl targets, so the binary carries its C library and
+/// runs on any Linux. Left out when the configured rustflags or the
+/// environment already set `crt-static` either way.
+fn static_link_args(target: &str, configured: &[String]) -> Vec<String> {
+    if !target.contains("-linux-musl") {
+        return Vec::new();
+    }
+    let target_env = format!("CARGO_TARGET_{}_RUSTFLAGS", target.to_uppercase().replace(['-', '.'], "_"));
+    let overridden = configured.iter().any(|f| f.contains("crt-static"))
+        || ["RUSTFLAGS", "CARGO_ENCODED_RUSTFLAGS", target_env.as_str()]
+            .iter()
+            .any(|name| env::var(name).is_ok_and(|flags| flags.contains("crt-static")));
+    if overridden {
+        return Vec::new();
+    }
+    vec!["-Ctarget-feature=+crt-static".to_string()]
+}
+
+/// Checks that UPX and a strip tool are installed for the targets that ask
+/// for them, so `--compress` or `--strip` can't quietly do nothing.
+fn check_post_build_tools(targets: &[String], build_config: &BuildConfig) -> Result<(), Box<dyn std::error::Error>> {
+    if build_config.skip_missing_tools {
+        return Ok(());
+    }
+    for target in targets {
+        let overrides = build_config.target_overrides.get(target);
+        if overrides.and_then(|o| o.compress).unwrap_or(build_config.compress)
+            && ProcessCommand::new("upx").arg("--version").output().is_err()
+        {
+            return Err(RustPackError::ToolMissing("upx").into());
+        }
+        let configured = overrides.and_then(|o| o.strip_tool.as_deref()).or(build_config.strip_tool.as_deref());
+        if overrides.and_then(|o| o.strip).unwrap_or(build_config.strip) && find_strip_tool(target, configured).is_none() {
+            let tried = strip_tool_candidates(target, configured).join(", ");
+            log::error("strip", &format!("No strip tool for {} found (tried {})", target, tried));
+            return Err(RustPackError::ToolMissing("strip").into());
+        }
+    }
+    Ok(())
+}
+
+/// Only `cargo zigbuild` understands a glibc version after the triple.
+fn check_target_builder(target: &str, builder: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if builder != "zig" && split_glibc_version(target).1.is_some() {
+        return Err(format!("Target {} pins a glibc version, which needs --builder zig", target).into());
+    }
+    Ok(())
+}
+
+struct BuiltBinary {
+    /// The default binary, relative to the `rustpack/` directory.
+    path: PathBuf,
+    /// Every binary built for the target, `path` first.
+    binaries: Vec<PathBuf>,
+    features: Vec<String>,
+    stripped: bool,
+    compressed: bool,
+    /// File names of the shared libraries copied into `lib/<target>/`.
+    libraries: Vec<String>,
+    /// Bytes each crate contributes to the binaries, largest first, with
+    /// `--bloat`.
+    crates: Vec<(String, u64)>,
+}
+
+/// Writes a wrapper crate under `target/rustpack-multicall` whose binary
+/// (named after the project) dispatches to one of the `tools` entry points
+/// in the project's library, based on the name it was invoked as or, failing
+/// that, its first argument. Each entry point is a `fn(Vec<String>) -> i32`
+/// that receives the tool's arguments and returns its exit code.
+fn generate_multicall_wrapper(
+    project_path: &str,
+    project_name: &str,
+    tools: &BTreeMap<String, String>,
+    build_config: &BuildConfig,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let wrapper_dir = cargo_target_dir(project_path, build_config)?.join("rustpack-multicall");
+    fs::create_dir_all(wrapper_dir.join("src"))?;
+
+    let dependency_path = fs::canonicalize(project_path)?.to_string_lossy().to_string();
+    // Cargo only lets the wrapper enable the project's default features if
+    // the project declares some.
+    let manifest: toml::Value = toml::from_str(&fs::read_to_string(Path::new(project_path).join("Cargo.toml"))?)?;
+    let default_features = if manifest.get("features").and_then(|f| f.get("default")).is_some() {
+        format!("[\"{}/default\"]", project_name)
+    } else {
+        "[]".to_string()
+    };
+    let mut cargo_toml = format!(
+        "[package]\nname = \"{name}-multicall\"\nversion = \"0.0.0\"\nedition = \"2021\"\npublish = false\n\n\
+         [[bin]]\nname = \"{name}\"\npath = \"src/main.rs\"\n\n\
+         [dependencies]\n{name} = {{ path = {path}, default-features = false }}\n\n\
+         [features]\ndefault = {default_features}\n",
+        name = project_name,
+        path = toml::Value::String(dependency_path),
+    );
+    for feature in &build_config.features {
+        cargo_toml.push_str(&format!("{0:?} = [\"{1}/{0}\"]\n", feature, project_name));
+    }
+    // Keep the wrapper out of any workspace the project belongs to, and give
+    // it the project's profiles since it is a workspace of its own.
+    cargo_toml.push_str("\n[workspace]\n");
+    let profiles = manifest_profiles(project_path)?;
+    if !profiles.is_empty() {
+        let mut table = toml::Table::new();
+        table.insert("profile".to_string(), toml::Value::Table(profiles));
+        cargo_toml.push('\n');
+        cargo_toml.push_str(&toml::to_string(&table)?);
+    }
+    fs::write(wrapper_dir.join("Cargo.toml"), cargo_toml)?;
+
+    // The tools are called through the library, which `[lib] name` may
+    // have renamed.
+    let metadata = cargo_metadata(project_path)?;
+    let lib_name = metadata["packages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|p| p["name"] == project_name)
+        .flat_map(|p| p["targets"].as_array().cloned().unwrap_or_default())
+        .find(|t| {
+            t["kind"]
+                .as_array()
+                .is_some_and(|kinds| kinds.iter().any(|k| k == "lib" || k == "rlib" || k == "dylib"))
+        })
+        .and_then(|t| t["name"].as_str().map(str::to_string));
+    let crate_name = lib_name.as_deref().unwrap_or(project_name).replace('-', "_");
+    let names: Vec<&str> = tools.keys().map(|s| s.as_str()).collect();
+    let mut arms = String::new();
+    for (tool, entry) in tools {
+        let entry = entry.strip_prefix(&format!("{}::", crate_name)).unwrap_or(entry);
+        arms.push_str(&format!("        {:?} => {}::{}(args),\n", tool, crate_name, entry));
+    }
+    let main_rs = format!(
+        r#"// Generated by rustpack. Dispatches to a tool based on the name this
+// binary was invoked as, or on its first argument.
+fn main() {{
+    let mut args: Vec<String> = std::env::args().collect();
+    let argv0 = args.remove(0);
+    let invoked = std::path::Path::new(&argv0)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let tool = if TOOLS.contains(&invoked.as_str()) || args.is_empty() {{
+        invoked
+    }} else {{
+        args.remove(0)
+    }};
+    let code = match tool.as_str() {{
+{arms}        other => {{
+            eprintln!("Unknown tool '{{}}'. Available tools: {{}}", other, TOOLS.join(", "));
+            1
+        }}
+    }};
+    std::process::exit(code);
+}}
+
+const TOOLS: &[&str] = &{names:?};
+"#,
+        arms = arms,
+        names = names,
+    );
+    fs::write(wrapper_dir.join("src").join("main.rs"), main_rs)?;
+
+    Ok(wrapper_dir)
+}
+
+/// Removes every DT_RPATH and DT_RUNPATH entry from an ELF binary's dynamic
+/// section in place, so it won't search build-machine library paths at run
+/// time. Later entries move up and the freed slots become DT_NULL. Returns
+/// how many entries were removed.
+fn strip_rpath(path: &Path) -> Result<usize, Box<dyn std::error::Error>> {
+    const PT_DYNAMIC: u32 = 2;
+    const DT_NULL: u64 = 0;
+    const DT_RPATH: u64 = 15;
+    const DT_RUNPATH: u64 = 29;
+
+    let mut data = fs::read(path)?;
+    if !data.starts_with(b"\x7fELF") || data.len() < 64 {
+        return Err(format!("{} is not an ELF binary", path.display()).into());
+    }
+    let is_64 = data[4] == 2;
+    let big_endian = data[5] == 2;
+    let read = |data: &[u8], offset: usize, size: usize| -> Result<u64, Box<dyn std::error::Error>> {
+        let bytes = data.get(offset..offset + size).ok_or("Truncated ELF binary")?;
+        let mut buf = [0u8; 8];
+        if big_endian {
+            buf[8 - size..].copy_from_slice(bytes);
+            Ok(u64::from_be_bytes(buf))
+        } else {
+            buf[..size].copy_from_slice(bytes);
+            Ok(u64::from_le_bytes(buf))
+        }
+    };
+    let word = if is_64 { 8 } else { 4 };
+
+    let (phoff, phentsize, phnum) = if is_64 {
+        (read(&data, 0x20, 8)?, read(&data, 0x36, 2)?, read(&data, 0x38, 2)?)
+    } else {
+        (read(&data, 0x1c, 4)?, read(&data, 0x2a, 2)?, read(&data, 0x2c, 2)?)
+    };
+    let mut dynamic = None;
+    for i in 0..phnum {
+        let header = (phoff + i * phentsize) as usize;
+        if read(&data, header, 4)? as u32 == PT_DYNAMIC {
+            dynamic = Some(if is_64 {
+                (read(&data, header + 8, 8)?, read(&data, header + 32, 8)?)
+            } else {
+                (read(&data, header + 4, 4)?, read(&data, header + 16, 4)?)
+            });
+            break;
+        }
+    }
+    let Some((dyn_offset, dyn_size)) = dynamic else {
+        // Statically linked, nothing to remove.
+        return Ok(0);
+    };
+
+    let entry_size = 2 * word;
+    let mut entries = Vec::new();
+    for i in 0..(dyn_size as usize / entry_size) {
+        let offset = dyn_offset as usize + i * entry_size;
+        let entry = data.get(offset..offset + entry_size).ok_or("Truncated ELF binary")?.to_vec();
+        let tag = read(&entry, 0, word)?;
+        if tag == DT_NULL {
+            break;
+        }
+        entries.push((tag, entry));
+    }
+
+    let total = entries.len();
+    entries.retain(|(tag, _)| *tag != DT_RPATH && *tag != DT_RUNPATH);
+    let removed = total - entries.len();
+    if removed > 0 {
+        let mut offset = dyn_offset as usize;
+        for (_, entry) in &entries {
+            data[offset..offset + entry_size].copy_from_slice(entry);
+            offset += entry_size;
+        }
+        data[offset..offset + removed * entry_size].fill(0);
+        fs::write(path, data)?;
+    }
+    Ok(removed)
+}
+
+/// Copies the shared libraries `built` links against, other than the ones
+/// every system provides, into `lib_dir` and points `binary` (the packaged
+/// copy) at them. Dependencies are listed with `ldd` for Linux targets and
+/// `otool -L` for macOS ones, so this only works for binaries the build
+/// machine can inspect; missing tools are reported and skipped. Returns the
+/// bundled file names.
+fn bundle_libraries(built: &Path, binary: &Path, target: &str, lib_dir: &Path) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let apple = target.contains("apple");
+    if !apple && !target.contains("linux") {
+        log::warning("libs", &format!("--bundle-libs only supports Linux and macOS targets, skipping {}", target));
+        return Ok(Vec::new());
+    }
+    let (tool, args): (&str, &[&str]) = if apple { ("otool", &["-L"]) } else { ("ldd", &[]) };
+    let output = match ProcessCommand::new(tool).args(args).arg(built).output() {
+        Ok(output) => output,
+        Err(_) => {
+            log::warning("libs", &format!("{} not found, not bundling shared libraries for {}", tool, target));
+            return Ok(Vec::new());
+        }
+    };
+    let listing = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() && !listing.contains("not a dynamic executable") {
+        log::warning("libs", &format!("{} could not inspect the binary for {}, not bundling shared libraries", tool, target));
+        return Ok(Vec::new());
+    }
+
+    let mut libraries = Vec::new();
+    for line in listing.lines() {
+        let line = line.trim();
+        let path = if apple {
+            // "/opt/homebrew/lib/libfoo.dylib (compatibility version ...)"
+            line.split(" (").next().filter(|_| line.contains(" (compatibility"))
+        } else if let Some((name, location)) = line.split_once(" => ") {
+            if location.starts_with("not found") {
+                log::warning(
+                    "libs",
+                    &format!("{} links against {}, which wasn't found; add its directory to LD_LIBRARY_PATH to bundle it", target, name),
+                );
+                None
+            } else {
+                location.split(" (").next()
+            }
+        } else {
+            None
+        };
+        let Some(path) = path.map(str::trim).filter(|p| !p.is_empty()) else {
+            continue;
+        };
+        if path.starts_with('@') {
+            log::warning("libs", &format!("Can't resolve {} for {}, leaving it out", path, target));
+            continue;
+        }
+        if is_system_library(path, apple) {
+            continue;
+        }
+        libraries.push(PathBuf::from(path));
+    }
+    if libraries.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    fs::create_dir_all(lib_dir)?;
+    let mut names = Vec::new();
+    for library in &libraries {
+        let name = library.file_name().ok_or("Invalid library path")?;
+        let dest = lib_dir.join(name);
+        fs::copy(library, &dest)?;
+        copy_permissions(library, &dest)?;
+        names.push(name.to_string_lossy().to_string());
+    }
+    log::info("libs", &format!("Bundled {} for {}", names.join(", "), target));
+
+    // The binary lives in bin/<target>/, the libraries in lib/<target>/.
+    let patched = if apple {
+        libraries.iter().zip(&names).all(|(library, name)| {
+            ProcessCommand::new("install_name_tool")
+                .arg("-change")
+                .arg(library)
+                .arg(format!("@executable_path/../../lib/{}/{}", target, name))
+                .arg(binary)
+                .status()
+                .is_ok_and(|status| status.success())
+        })
+    } else {
+        ProcessCommand::new("patchelf")
+            .arg("--set-rpath")
+            .arg(format!("$ORIGIN/../../lib/{}", target))
+            .arg(binary)
+            .status()
+            .is_ok_and(|status| status.success())
+    };
+    if !patched {
+        let patch_tool = if apple { "install_name_tool" } else { "patchelf" };
+        let message = format!(
+            "Could not patch the library path with {} for {}; the bundled libraries are only found through {}",
+            patch_tool,
+            target,
+            if apple { "DYLD_LIBRARY_PATH" } else { "LD_LIBRARY_PATH" }
+        );
+        log::warning("libs", &message);
+    }
+    Ok(names)
+}
+
+/// Libraries that come with the OS and must not be bundled: the C runtime and
+/// anything in the standard system library directories.
+fn is_system_library(path: &str, apple: bool) -> bool {
+    if apple {
+        return path.starts_with("/usr/lib/") || path.starts_with("/System/");
+    }
+    let name = Path::new(path).file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    let runtime = ["ld-linux", "ld-musl", "libc.", "libm.", "libdl.", "libpthread.", "librt.", "libgcc_s.", "libutil.", "libresolv."];
+    runtime.iter().any(|prefix| name.starts_with(prefix))
+        || ["/lib/", "/lib64/", "/lib32/", "/usr/lib/", "/usr/lib64/", "/usr/lib32/"]
+            .iter()
+            .any(|dir| path.starts_with(dir))
+}
+
+fn strip_args(target: &str, tool: &str, level: &str) -> Result<Vec<&'static str>, Box<dyn std::error::Error>> {
+    // Apple's strip removes everything by default and spells the other levels
+    // differently; llvm-strip takes the GNU spelling for every format.
+    let apple_strip = target.contains("apple") && !tool.contains("llvm-strip");
+    let args = match (apple_strip, level) {
+        (true, "all") => vec![],
+        (true, "debug") => vec!["-S"],
+        (true, "unneeded") => vec!["-x"],
+        (false, "all") => vec!["-s"],
+        (false, "debug") => vec!["--strip-debug"],
+        (false, "unneeded") => vec!["--strip-unneeded"],
+        _ => return Err(format!("Unknown strip level: {} (expected all, debug or unneeded)", level).into()),
+    };
+    Ok(args)
+}
+
+/// Strip tools that understand `target`'s binaries, best first: just
+/// `configured` if set, else the host's `strip` when the target has the
+/// host's architecture and OS, the target's binutils (such as
+/// `aarch64-linux-gnu-strip` or `x86_64-w64-mingw32-strip`), and `llvm-strip`,
+/// which handles every format.
+fn strip_tool_candidates(target: &str, configured: Option<&str>) -> Vec<String> {
+    if let Some(tool) = configured {
+        return vec![tool.to_string()];
+    }
+    let triple = split_glibc_version(target).0;
+    let host = get_current_target();
+    let arch = triple.split('-').next().unwrap_or_default();
+    let os = |t: &str| ["linux", "apple", "windows", "android", "freebsd"].into_iter().find(|os| t.contains(os));
+
+    let mut candidates = Vec::new();
+    if host.split('-').next() == Some(arch) && os(&host) == os(triple) {
+        candidates.push("strip".to_string());
+    }
+    let binutils_arch = match arch {
+        a if a.starts_with("armv7") || a.starts_with("thumbv7") => "arm",
+        "riscv64gc" => "riscv64",
+        "i586" => "i686",
+        a => a,
+    };
+    if triple.contains("windows-gnu") {
+        candidates.push(format!("{}-w64-mingw32-strip", binutils_arch));
+    } else if triple.contains("linux") {
+        let env = triple.rsplit('-').next().unwrap_or("gnu");
+        candidates.push(format!("{}-linux-{}-strip", binutils_arch, env));
+    }
+    candidates.push("llvm-strip".to_string());
+    candidates.dedup();
+    candidates
+}
+
+/// The first of `strip_tool_candidates` that is installed.
+fn find_strip_tool(target: &str, configured: Option<&str>) -> Option<String> {
+    strip_tool_candidates(target, configured)
+        .into_iter()
+        .find(|tool| ProcessCommand::new(tool).arg("--version").output().is_ok())
+}
+
+/// SHA-256 of a file. For a directory (an app bundle), the SHA-256 of a
+/// `<hash>  <relative path>` line per file, sorted by path.
+fn calculate_checksum(path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    if path.is_dir() {
+        let mut listing = String::new();
+        for entry in WalkDir::new(path).sort_by_file_name().into_iter().filter_map(|e| e.ok()) {
+            if entry.file_type().is_file() {
+                let rel_path = entry.path().strip_prefix(path)?;
+                listing.push_str(&format!("{}  {}\n", calculate_checksum(entry.path())?, rel_path.to_string_lossy()));
+            }
+        }
+        return Ok(format!("{:x}", Sha256::digest(listing.as_bytes())));
+    }
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    let result = hasher.finalize();
+    
+    Ok(format!("{:x}", result))
+}
+
+/// SHA-256 of each of `binaries`, by their path in the package.
+fn binary_checksums(rustpack_dir: &Path, binaries: &[String]) -> Result<BTreeMap<String, String>, Box<dyn std::error::Error>> {
+    binaries
+        .iter()
+        .map(|binary| Ok((binary.clone(), calculate_checksum(&rustpack_dir.join(binary))?)))
+        .collect()
+}
+
+fn calculate_package_checksum(rustpack_dir: &Path, targets: &[TargetInfo]) -> Result<String, Box<dyn std::error::Error>> {
+    let mut binary_paths: Vec<&str> = targets.iter().flat_map(TargetInfo::all_binaries).collect();
+    binary_paths.sort();
+
+    let mut hasher = Sha256::new();
+    for binary_path in binary_paths {
+        let mut file = File::open(rustpack_dir.join(binary_path))?;
+        io::copy(&mut file, &mut hasher)?;
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn sign_package(path: &Path, key: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let checksum = calculate_checksum(path)?;
+    sign_checksum(&checksum, key)
+}
+
+/// The signature recorded for a binary in info.json: ed25519 when there is a
+/// key file, else HMAC with the `sign` key.
+fn sign_binary(path: &Path, signing_key: Option<&SigningKey>, hmac_key: &str) -> Result<String, Box<dyn std::error::Error>> {
+    match signing_key {
+        Some(key) => Ok(BASE64.encode(key.sign(calculate_checksum(path)?.as_bytes()).to_bytes())),
+        None => sign_package(path, hmac_key),
+    }
+}
+
+/// Reads an ed25519 private key: a PKCS#8 PEM file, as written by
+/// `openssl genpkey -algorithm ed25519`, or the key's 32 raw bytes.
+fn load_signing_key(path: &Path) -> Result<SigningKey, Box<dyn std::error::Error>> {
+    let bytes = fs::read(path).map_err(|e| format!("Cannot read signing key {}: {}", path.display(), e))?;
+    if let Ok(text) = std::str::from_utf8(&bytes)
+        && text.trim_start().starts_with("-----BEGIN")
+    {
+        return SigningKey::from_pkcs8_pem(text)
+            .map_err(|e| format!("Invalid ed25519 private key in {}: {}", path.display(), e).into());
+    }
+    let seed: [u8; 32] = bytes.as_slice().try_into().map_err(|_| {
+        format!("Invalid ed25519 private key in {}: expected a PEM file or 32 raw bytes", path.display())
+    })?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// Reads an ed25519 public key: a PEM file, the key's 32 raw bytes or their
+/// base64 encoding, as stored in info.json.
+fn load_verifying_key(path: &Path) -> Result<VerifyingKey, Box<dyn std::error::Error>> {
+    let bytes = fs::read(path).map_err(|e| format!("Cannot read public key {}: {}", path.display(), e))?;
+    let invalid = |e: String| format!("Invalid ed25519 public key in {}: {}", path.display(), e);
+    match std::str::from_utf8(&bytes) {
+        Ok(text) if text.trim_start().starts_with("-----BEGIN") => {
+            Ok(VerifyingKey::from_public_key_pem(text).map_err(|e| invalid(e.to_string()))?)
+        }
+        _ if bytes.len() == 32 => Ok(VerifyingKey::from_bytes(bytes.as_slice().try_into()?).map_err(|e| invalid(e.to_string()))?),
+        Ok(text) => Ok(decode_public_key(text.trim()).map_err(|e| invalid(e.to_string()))?),
+        Err(_) => Err(invalid("expected a PEM file, 32 raw bytes or base64".to_string()).into()),
+    }
+}
+
+fn decode_public_key(encoded: &str) -> Result<VerifyingKey, Box<dyn std::error::Error>> {
+    let bytes: [u8; 32] = BASE64
+        .decode(encoded)
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .ok_or("expected 32 base64-encoded bytes")?;
+    Ok(VerifyingKey::from_bytes(&bytes)?)
+}
+
+/// Where the detached signature of `output` goes: `<output>.sig`.
+fn signature_path(output: &Path) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".sig");
+    PathBuf::from(path)
+}
+
+/// Writes `<output>.sig`, the base64 ed25519 signature of the hex SHA-256
+/// digest of `output` (of its files, for an app bundle).
+fn write_detached_signature(output: &Path, key: &SigningKey) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let signature = key.sign(calculate_checksum(output)?.as_bytes());
+    let path = signature_path(output);
+    fs::write(&path, format!("{}\n", BASE64.encode(signature.to_bytes())))?;
+    Ok(path)
+}
+
+/// Writes `<output>.sha256` in the format `sha256sum` prints.
+fn write_checksum_file(output: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".sha256");
+    let path = PathBuf::from(path);
+    let name = output.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    write_atomically(&path, format!("{}  {}\n", calculate_checksum(output)?, name).as_bytes())?;
+    Ok(path)
+}
+
+fn sign_checksum(checksum: &str, key: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let mut mac = HmacSha256::new_from_slice(key.as_bytes())?;
+    mac.update(checksum.as_bytes());
+    let result = mac.finalize();
+    let code_bytes = result.into_bytes();
+    
+    Ok(BASE64.encode(code_bytes))
+}
+
+/// Appends a signature trailer covering every byte before it. Self-extracting
+/// packages get the trailer in a form their payload decompressor skips, so the
+/// bootstrap still reads the payload cleanly: an extra, uncompressed gzip
+/// member for gzip, a skippable frame for zstd, and plain text after the end
+/// of a bare tar archive. ZIP packages store it as the archive comment, which
+/// is always the last thing in the file.
+fn sign_output_file(path: &Path, key: &str, compression: PayloadCompression) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+    let mut magic = [0u8; 4];
+    let is_zip = file.read_exact(&mut magic).is_ok() && &magic == b"PK\x03\x04";
+    let format = if is_zip { PackageFormat::Zip } else { PackageFormat::SelfExtracting };
+    if is_zip {
+        let mut eocd = [0u8; 22];
+        if file.metadata()?.len() < 22
+            || file.seek(SeekFrom::End(-22)).is_err()
+            || file.read_exact(&mut eocd).is_err()
+            || &eocd[..4] != b"PK\x05\x06"
+            || eocd[20..] != [0, 0]
+        {
+            return Err("Unexpected ZIP layout, cannot reserve the signature comment".into());
+        }
+        let comment_len = signature_trailer(&"A".repeat(SIGNATURE_LEN), format, compression)?.len() as u16;
+        file.seek(SeekFrom::End(-2))?;
+        file.write_all(&comment_len.to_le_bytes())?;
+    }
+
+    file.seek(SeekFrom::Start(0))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    let signature = sign_checksum(&format!("{:x}", hasher.finalize()), key)?;
+    file.write_all(&signature_trailer(&signature, format, compression)?)?;
+    Ok(())
+}
+
+fn signature_trailer(
+    signature: &str,
+    format: PackageFormat,
+    compression: PayloadCompression,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let text = format!("{}{}\n", SIGNATURE_PREFIX, signature);
+    match (format, compression) {
+        (PackageFormat::Zip, _) | (_, PayloadCompression::None) => Ok(text.into_bytes()),
+        (_, PayloadCompression::Zstd) => {
+            let mut frame = ZSTD_SKIPPABLE_MAGIC.to_vec();
+            frame.extend_from_slice(&(text.len() as u32).to_le_bytes());
+            frame.extend_from_slice(text.as_bytes());
+            Ok(frame)
+        }
+        (_, PayloadCompression::Brotli) => {
+            Err("Brotli payloads can't carry a signature trailer; sign with --sign-key instead".into())
+        }
+        (_, PayloadCompression::Gzip) => {
+            let mut encoder = GzBuilder::new().mtime(0).write(Vec::new(), Compression::none());
+            encoder.write_all(text.as_bytes())?;
+            Ok(encoder.finish()?)
+        }
+    }
+}
+
+pub fn verify_package(package_path: &Path, key: &str) -> Result<(), RustPackError> {
+    let bytes = fs::read(package_path)?;
+    let (format, compression) = if bytes.starts_with(b"PK\x03\x04") {
+        (PackageFormat::Zip, PayloadCompression::None)
+    } else {
+        let payload_start = package::find_payload(&bytes).ok_or("No payload marker found in the package")?;
+        (
+            PackageFormat::SelfExtracting,
+            PayloadCompression::detect_with_stub(&bytes[..payload_start], &bytes[payload_start..]),
+        )
+    };
+    let trailer_len = signature_trailer(&"A".repeat(SIGNATURE_LEN), format, compression)?.len();
+    if bytes.len() < trailer_len {
+        return Err("Package is not signed".into());
+    }
+
+    let (body, trailer) = bytes.split_at(bytes.len() - trailer_len);
+    let text = match (format, compression) {
+        (PackageFormat::Zip, _) | (_, PayloadCompression::None) => String::from_utf8_lossy(trailer).to_string(),
+        (_, PayloadCompression::Zstd) => match trailer.strip_prefix(&ZSTD_SKIPPABLE_MAGIC[..]) {
+            Some(frame) => String::from_utf8_lossy(&frame[4..]).to_string(),
+            None => return Err("Package is not signed".into()),
+        },
+        (_, PayloadCompression::Brotli) => return Err("Package is not signed".into()),
+        (_, PayloadCompression::Gzip) => {
+            let mut text = String::new();
+            if GzDecoder::new(trailer).read_to_string(&mut text).is_err() {
+                return Err("Package is not signed".into());
+            }
+            text
+        }
+    };
+    let signature = text
+        .strip_prefix(SIGNATURE_PREFIX)
+        .ok_or("Package is not signed")?
+        .trim();
+
+    let checksum = format!("{:x}", Sha256::digest(body));
+    let mut mac = HmacSha256::new_from_slice(key.as_bytes()).map_err(|_| "Invalid signing key")?;
+    mac.update(checksum.as_bytes());
+    mac.verify_slice(&BASE64.decode(signature).map_err(|_| "Malformed package signature")?)
+        .map_err(|_| RustPackError::SignatureMismatch)?;
+
+    Ok(())
+}
+
+/// Checks the detached `<package>.sig` written with a `sign_key` against
+/// the ed25519 `public_key` file or, without one, the key embedded in the
+/// package's info.json. The embedded key only shows the package wasn't
+/// changed after signing, since whoever changed it could also replace the key.
+pub fn verify_detached_signature(package_path: &Path, public_key: Option<&Path>) -> Result<(), RustPackError> {
+    let sig_path = signature_path(package_path);
+    let text = fs::read_to_string(&sig_path).map_err(|_| format!("No detached signature found at {}", sig_path.display()))?;
+    let signature = BASE64
+        .decode(text.trim())
+        .ok()
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        .ok_or("Malformed package signature")?;
+    let key = match public_key {
+        Some(path) => load_verifying_key(path)?,
+        None => {
+            let embedded = package::Package::open(package_path)?
+                .info()?
+                .public_key
+                .ok_or("The package has no embedded public key; pass one with --verify-key")?;
+            decode_public_key(&embedded).map_err(|e| format!("Invalid public key in info.json: {}", e))?
+        }
+    };
+    key.verify(calculate_checksum(package_path)?.as_bytes(), &signature)
+        .map_err(|_| RustPackError::SignatureMismatch)?;
+    Ok(())
+}
+
+/// Unpacks a self-extracting package into `dest` without running anything
+/// in it, then checks the binaries against the checksums in its info.json.
+/// Returns the package info.
+pub fn extract_package(package_path: &Path, dest: &Path) -> Result<PackageInfo, RustPackError> {
+    let package = package::Package::open(package_path)?;
+    if package.format() == PackageFormat::Zip {
+        return Err(format!(
+            "{} has no __PAYLOAD_BEGINS__ marker; it is a ZIP package, so unzip it instead",
+            package_path.display()
+        )
+        .into());
+    }
+    let rustpack_dir = dest.join("rustpack");
+    if rustpack_dir.exists() {
+        return Err(format!("{} already exists", rustpack_dir.display()).into());
+    }
+    let info = package.info()?;
+    fs::create_dir_all(dest)?;
+    package.extract(dest)?;
+
+    for target in &info.targets {
+        let expected = std::iter::once((&target.binary_path, &target.checksum)).chain(&target.binary_checksums);
+        for (binary, checksum) in expected {
+            if calculate_checksum(&rustpack_dir.join(binary))? != *checksum {
+                return Err(format!("Checksum mismatch for {} after extraction", binary).into());
+            }
+        }
+    }
+    if calculate_package_checksum(&rustpack_dir, &info.targets)? != info.checksum {
+        return Err("Package checksum mismatch after extraction".into());
+    }
+    Ok(info)
+}
+
+/// Checks `files` (or every file in the package) against the Merkle root
+/// recorded in info.json, returning whether each one is intact.
+pub fn verify_package_files(package_path: &Path, files: Option<&[String]>) -> Result<Vec<(String, bool)>, RustPackError> {
+    let package = package::Package::open(package_path)?;
+    let root = package.info()?.merkle_root.ok_or("Package has no Merkle root")?;
+    let tree_entry = package.entry("rustpack/merkle.json").ok_or("Package does not contain rustpack/merkle.json")?;
+    let tree: merkle::MerkleTree = serde_json::from_slice(&tree_entry.data)?;
+
+    let paths: Vec<String> = match files {
+        Some(files) => files.to_vec(),
+        None => tree.leaves.iter().map(|l| l.path.clone()).collect(),
+    };
+    let mut results = Vec::new();
+    for path in paths {
+        let ok = match (package.entry(&format!("rustpack/{}", path)), tree.proof(&path)) {
+            (Some(entry), Some(proof)) => {
+                let leaf = merkle::leaf_hash(&path, &format!("{:x}", Sha256::digest(&entry.data)));
+                merkle::verify_proof(&leaf, &proof, &root)
+            }
+            _ => false,
+        };
+        results.push((path, ok));
+    }
+    Ok(results)
+}
+
+/// How the sizes of two packages compare, from `diff_packages`.
+#[derive(Serialize)]
+pub struct PackageDiff {
+    /// `name version` of each package.
+    pub old: String,
+    pub new: String,
+    pub package_size: SizeChange,
+    pub targets: Vec<TargetDiff>,
+    pub assets_added: Vec<String>,
+    pub assets_removed: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct TargetDiff {
+    /// `platform-arch`, which is how targets are matched between packages.
+    pub target: String,
+    /// All of the target's binaries together.
+    pub size: SizeChange,
+    /// Section sizes, summed over the binaries. Empty for binaries that
+    /// can't be parsed, such as UPX-compressed ones.
+    pub sections: BTreeMap<String, SizeChange>,
+}
+
+/// A size in bytes before and after; `None` where the package lacks it.
+#[derive(Serialize, Clone, Copy, Default)]
+pub struct SizeChange {
+    pub old: Option<u64>,
+    pub new: Option<u64>,
+}
+
+impl SizeChange {
+    pub fn delta(&self) -> i64 {
+        self.new.unwrap_or(0) as i64 - self.old.unwrap_or(0) as i64
+    }
+}
+
+/// Compares the binaries, their sections and the assets of two packages, to
+/// catch size regressions between builds.
+pub fn diff_packages(old_path: &Path, new_path: &Path) -> Result<PackageDiff, RustPackError> {
+    let old = package::Package::open(old_path)?;
+    let new = package::Package::open(new_path)?;
+    let (old_info, new_info) = (old.info()?, new.info()?);
+
+    // Binary and section sizes of each target, keyed by `platform-arch`.
+    let sizes = |package: &package::Package, info: &PackageInfo| -> BTreeMap<String, (u64, BTreeMap<String, u64>)> {
+        let mut sizes = BTreeMap::new();
+        for target in &info.targets {
+            let binaries = if target.binaries.is_empty() { vec![target.binary_path.clone()] } else { target.binaries.clone() };
+            let mut total = 0;
+            let mut sections = BTreeMap::new();
+            for binary in binaries {
+                let Some(entry) = package.entry(&format!("rustpack/{}", binary)) else {
+                    continue;
+                };
+                total += entry.data.len() as u64;
+                for (name, size) in section_sizes(&entry.data).unwrap_or_default() {
+                    if name != "total" {
+                        *sections.entry(name).or_insert(0) += size as u64;
+                    }
+                }
+            }
+            sizes.insert(format!("{}-{}", target.platform, target.arch), (total, sections));
+        }
+        sizes
+    };
+    let (old_sizes, new_sizes) = (sizes(&old, &old_info), sizes(&new, &new_info));
+
+    let mut targets = Vec::new();
+    let target_names: BTreeSet<&String> = old_sizes.keys().chain(new_sizes.keys()).collect();
+    for target in target_names {
+        let (old_target, new_target) = (old_sizes.get(target), new_sizes.get(target));
+        let mut sections: BTreeMap<String, SizeChange> = BTreeMap::new();
+        for (name, size) in old_target.iter().flat_map(|(_, s)| s) {
+            sections.entry(name.clone()).or_default().old = Some(*size);
+        }
+        for (name, size) in new_target.iter().flat_map(|(_, s)| s) {
+            sections.entry(name.clone()).or_default().new = Some(*size);
+        }
+        targets.push(TargetDiff {
+            target: target.clone(),
+            size: SizeChange { old: old_target.map(|(total, _)| *total), new: new_target.map(|(total, _)| *total) },
+            sections,
+        });
+    }
+
+    let assets = |package: &package::Package| -> BTreeSet<String> {
+        package
+            .entries()
+            .filter(|e| !e.is_dir)
+            .filter_map(|e| e.path.strip_prefix("rustpack/assets/").map(str::to_string))
+            .collect()
+    };
+    let (old_assets, new_assets) = (assets(&old), assets(&new));
+
+    Ok(PackageDiff {
+        old: format!("{} {}", old_info.name, old_info.version),
+        new: format!("{} {}", new_info.name, new_info.version),
+        package_size: SizeChange { old: Some(fs::metadata(old_path)?.len()), new: Some(fs::metadata(new_path)?.len()) },
+        targets,
+        assets_added: new_assets.difference(&old_assets).cloned().collect(),
+        assets_removed: old_assets.difference(&new_assets).cloned().collect(),
+    })
+}
+
+/// Builds packages from a Cargo project. This is what the `rustpack` binary
+/// runs, so build scripts and other tools can package without spawning it.
+pub struct Packer {
+    project_path: String,
+    output: String,
+    targets: Vec<String>,
+    config: BuildConfig,
+    formats: Vec<PackageFormat>,
+    verbose: bool,
+    clean: bool,
+    open: Option<Vec<String>>,
+}
+
+/// What a successful [`Packer::pack`] wrote.
+pub struct PackageReport {
+    /// The first package written, and its size.
+    pub output: PathBuf,
+    pub size: u64,
+    /// Every package written, one per requested format.
+    pub outputs: Vec<PathBuf>,
+    pub info: PackageInfo,
+    /// Compression used for self-extracting outputs.
+    pub compression: PayloadCompression,
+    /// Total size of the packaged files before compression, to compare
+    /// against each output's size.
+    pub payload_size: u64,
+    /// Time spent compressing and writing each output, in `outputs` order.
+    pub archive_times: Vec<Duration>,
+    /// Exit code of the package when it was run with [`Packer::open`].
+    pub exit_code: Option<i32>,
+}
+
+/// What [`Packer::pack`] would do, resolved without running cargo or
+/// writing anything. `problems` lists misconfigurations that would make the
+/// build fail.
+pub struct BuildPlan {
+    pub name: String,
+    pub version: String,
+    pub project_path: String,
+    pub profile: String,
+    pub lto: Option<String>,
+    pub features: Vec<String>,
+    pub no_default_features: bool,
+    pub cargo_args: Vec<String>,
+    /// Where cargo puts the build artifacts.
+    pub target_dir: PathBuf,
+    pub rustflags: Vec<String>,
+    pub outputs: Vec<(String, PackageFormat)>,
+    pub compression: String,
+    pub builder: String,
+    pub windows_bootstrap: bool,
+    pub targets: Vec<TargetPlan>,
+    /// The binaries each target packages, the default one first.
+    pub binaries: Vec<String>,
+    pub assets: Vec<String>,
+    pub jobs: usize,
+    /// What [`Packer::clean`] would remove.
+    pub clean: Vec<PathBuf>,
+    pub problems: Vec<String>,
+}
+
+pub struct TargetPlan {
+    pub target: String,
+    pub platform: String,
+    pub arch: String,
+    pub strip: bool,
+    pub compress: bool,
+    pub sign: bool,
+}
+
+impl Packer {
+    pub fn new(project_path: &str, output: &str, targets: Vec<String>, config: BuildConfig) -> Packer {
+        Packer {
+            project_path: project_path.to_string(),
+            output: output.to_string(),
+            targets,
+            config,
+            formats: vec![PackageFormat::SelfExtracting],
+            verbose: false,
+            clean: false,
+            open: None,
+        }
+    }
+
+    /// Package formats to write from the one build. With more than one, each
+    /// output gets its format's extension in place of the output's own.
+    pub fn formats(mut self, formats: Vec<PackageFormat>) -> Packer {
+        self.formats = formats;
+        self
+    }
+
+    /// Prints build progress to stdout.
+    pub fn verbose(mut self, verbose: bool) -> Packer {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Removes the targets' build directories and the outputs of an earlier
+    /// pack first, so nothing stale ends up in the package.
+    pub fn clean(mut self, clean: bool) -> Packer {
+        self.clean = clean;
+        self
+    }
+
+    /// Runs the self-extracting package with `args` once it is written (and
+    /// after every rebuild in [`Packer::watch`]). Exactly one target must run
+    /// on this machine.
+    pub fn open(mut self, args: Vec<String>) -> Packer {
+        self.open = Some(args);
+        self
+    }
+
+    pub fn pack(&self) -> Result<PackageReport, RustPackError> {
+        if self.open.is_some() {
+            check_open(&self.targets, &self.formats)?;
+        }
+        if self.clean {
+            self.remove_stale()?;
+        }
+        let mut report = build_package(&self.project_path, &self.output, &self.targets, &self.config, self.verbose, &self.formats)?;
+        if let Some(args) = &self.open {
+            let index = self.formats.iter().position(|f| *f == PackageFormat::SelfExtracting).unwrap_or(0);
+            let status = open_package(&report.outputs[index], args)?.wait()?;
+            report.exit_code = Some(status.code().unwrap_or(1));
+        }
+        Ok(report)
+    }
+
+    /// Resolves the build without running it, checking the targets, assets
+    /// and other settings that would otherwise only fail part-way through.
+    pub fn plan(&self) -> Result<BuildPlan, RustPackError> {
+        Ok(self.resolve_plan()?)
+    }
+
+    fn resolve_plan(&self) -> Result<BuildPlan, Box<dyn std::error::Error>> {
+        let config = &self.config;
+        let manifest_dir = package_dir(&self.project_path, config.package.as_deref())?;
+        let manifest = parse_manifest(&manifest_dir)?;
+        let mut problems = Vec::new();
+        if let Err(e) = check_features(&manifest_dir, &config.features) {
+            problems.push(e.to_string());
+        }
+        if let Err(e) = check_profile(&manifest_dir, &config.profile) {
+            problems.push(e.to_string());
+        }
+        if self.open.is_some()
+            && let Err(e) = check_open(&self.targets, &self.formats)
+        {
+            problems.push(e.to_string());
+        }
+        // A wasm bundle is built from the library.
+        let binaries = if self.formats == [PackageFormat::Wasm] {
+            Vec::new()
+        } else if config.multicall.is_empty() {
+            select_binaries(&manifest_dir, &manifest.name, config).unwrap_or_else(|e| {
+                problems.push(e.to_string());
+                Vec::new()
+            })
+        } else {
+            vec![manifest.name.clone()]
+        };
+
+        let known_targets = ProcessCommand::new("rustc")
+            .args(["--print", "target-list"])
+            .output()
+            .ok()
+            .filter(|o| o.status.success())
+            .map(|o| String::from_utf8_lossy(&o.stdout).lines().map(str::to_string).collect::<Vec<_>>());
+        let installed_targets = ProcessCommand::new("rustup")
+            .args(["target", "list", "--installed"])
+            .output()
+            .ok()
+            .filter(|o| o.status.success())
+            .map(|o| String::from_utf8_lossy(&o.stdout).lines().map(str::to_string).collect::<Vec<_>>());
+
+        if self.targets.is_empty() {
+            problems.push("No targets to build".to_string());
+        }
+        let mut targets = Vec::new();
+        for target in &self.targets {
+            let (triple, _) = split_glibc_version(target);
+            let triple = triple.to_string();
+            if known_targets.as_ref().is_some_and(|known| !known.contains(&triple)) {
+                problems.push(format!("Unknown target: {}", target));
+            } else if config.builder != "cross" && installed_targets.as_ref().is_some_and(|installed| !installed.contains(&triple)) {
+                problems.push(format!("Target {} is not installed (rustup target add {})", target, triple));
+            }
+            if let Err(e) = check_target_builder(target, &config.builder) {
+                problems.push(e.to_string());
+            }
+            if let Err(e) = check_target_platform(target, &self.formats) {
+                problems.push(e.to_string());
+            }
+            let overrides = config.target_overrides.get(target);
+            let strip = overrides.and_then(|o| o.strip).unwrap_or(config.strip);
+            if strip && let Err(e) = strip_args(target, "strip", &config.strip_level) {
+                problems.push(e.to_string());
+            }
+            let (platform, arch, _) = parse_target(target);
+            targets.push(TargetPlan {
+                target: target.clone(),
+                platform,
+                arch,
+                strip,
+                compress: overrides.and_then(|o| o.compress).unwrap_or(config.compress),
+                sign: overrides.and_then(|o| o.sign).unwrap_or(true) && (!config.sign.is_empty() || config.sign_key.is_some()),
+            });
+        }
+
+        if let Err(e) = resolve_assets(&self.project_path, &config.assets) {
+            problems.push(e.to_string());
+        }
+        if let Some(icon) = &config.icon
+            && !Path::new(&self.project_path).join(icon).is_file()
+        {
+            problems.push(format!("Icon not found: {}", icon));
+        }
+        if let Err(e) = config.compression.parse::<PayloadCompression>() {
+            problems.push(e);
+        }
+        if let Err(e) = check_builder(&config.builder) {
+            problems.push(e.to_string());
+        }
+        if let Err(e) = check_post_build_tools(&self.targets, config) {
+            problems.push(e.to_string());
+        }
+        for target in &self.targets {
+            if let Err(e) = max_binary_size(target, config) {
+                problems.push(format!("max_size for {}: {}", target, e));
+            }
+        }
+        if let Some(path) = &config.sign_key
+            && let Err(e) = load_signing_key(Path::new(path))
+        {
+            problems.push(e.to_string());
+        }
+        if config.universal_macos
+            && universal_macos_targets(&self.targets)
+            && let Err(e) = macos::check_lipo()
+        {
+            problems.push(e.to_string());
+        }
+        if self.formats.is_empty() {
+            problems.push("No package formats requested".to_string());
+        }
+        let linux_targets = self.targets.iter().filter(|t| parse_target(t).0 == "linux").count();
+        for (format, kind) in [(PackageFormat::Deb, "A .deb"), (PackageFormat::AppImage, "An AppImage")] {
+            if self.formats.contains(&format) && linux_targets != 1 {
+                problems.push(format!("{} holds a single Linux binary, but {} Linux targets would be built", kind, linux_targets));
+            }
+        }
+        if self.formats.contains(&PackageFormat::AppImage)
+            && let Err(e) = appimage::check_tool()
+        {
+            problems.push(e.to_string());
+        }
+        if (self.formats.contains(&PackageFormat::App) || self.formats.contains(&PackageFormat::Dmg))
+            && !self.targets.iter().any(|t| parse_target(t).0 == "macos")
+        {
+            problems.push("App bundles and disk images need a macOS target".to_string());
+        }
+        if self.formats.contains(&PackageFormat::Dmg)
+            && let Err(e) = macos::check_hdiutil()
+        {
+            problems.push(e.to_string());
+        }
+        if let Err(e) = check_wasm_format(&self.targets, &self.formats) {
+            problems.push(e.to_string());
+        }
+        if self.formats.contains(&PackageFormat::Wasm)
+            && let Err(e) = wasm::check_tool()
+        {
+            problems.push(e.to_string());
+        }
+        let windows_bootstrap = match uses_windows_bootstrap(&config.bootstrap, &self.targets) {
+            Ok(windows) => windows,
+            Err(e) => {
+                problems.push(e.to_string());
+                false
+            }
+        };
+        if windows_bootstrap
+            && self.formats.contains(&PackageFormat::SelfExtracting)
+            && let Err(e) = load_windows_stub(config.windows_stub.as_deref())
+        {
+            problems.push(e.to_string());
+        }
+        if self.formats.contains(&PackageFormat::SelfExtracting)
+            && let Ok(compression) = config.compression.parse::<PayloadCompression>()
+            && let Err(e) = check_compression(compression, windows_bootstrap, !config.sign.is_empty())
+        {
+            problems.push(e.to_string());
+        }
+        if let Err(e) = check_release(&self.formats, windows_bootstrap, config) {
+            problems.push(e.to_string());
+        }
+        if let Err(e) = check_upload(config) {
+            problems.push(e.to_string());
+        }
+        if let Err(e) = check_metadata(config) {
+            problems.push(e.to_string());
+        }
+        if let Err(e) = check_source_date_epoch() {
+            problems.push(e.to_string());
+        }
+        if let Err(e) = check_publish_github(config, &manifest.version) {
+            problems.push(e.to_string());
+        }
+
+        let clean = if self.clean {
+            self.stale_paths().unwrap_or_else(|e| {
+                problems.push(e.to_string());
+                Vec::new()
+            })
+        } else {
+            Vec::new()
+        };
+        let outputs = self
+            .formats
+            .iter()
+            .map(|format| {
+                let output = package_output_name(&self.output, *format, self.formats.len() > 1, windows_bootstrap);
+                (output, *format)
+            })
+            .collect();
+
+        Ok(BuildPlan {
+            name: manifest.name,
+            version: manifest.version,
+            project_path: self.project_path.clone(),
+            profile: config.profile.clone(),
+            lto: config.lto.clone(),
+            features: config.features.clone(),
+            no_default_features: config.no_default_features,
+            cargo_args: config.cargo_args.clone(),
+            target_dir: cargo_target_dir(&self.project_path, config)?,
+            rustflags: config.rustflags.clone(),
+            outputs,
+            compression: config.compression.clone(),
+            builder: config.builder.clone(),
+            windows_bootstrap,
+            targets,
+            binaries,
+            assets: config.assets.clone(),
+            jobs: config.jobs.clamp(1, self.targets.len().max(1)),
+            clean,
+            problems,
+        })
+    }
+
+    /// What `clean` removes: each target's directories under the cargo
+    /// target dir and the outputs of an earlier pack, if they exist. Anything
+    /// that resolves to outside the project and target directories is left
+    /// alone.
+    fn stale_paths(&self) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+        let project_dir = fs::canonicalize(&self.project_path)?;
+        let target_dir = cargo_target_dir(&self.project_path, &self.config)?;
+        let target_root = fs::canonicalize(&target_dir).unwrap_or_else(|_| target_dir.clone());
+        let windows_bootstrap = uses_windows_bootstrap(&self.config.bootstrap, &self.targets)?;
+        let mut candidates = Vec::new();
+        for target in &self.targets {
+            candidates.push(target_dir.join(split_glibc_version(target).0));
+            candidates.push(target_dir.join("rustpack-jobs").join(target));
+        }
+        for format in &self.formats {
+            let output = package_output_name(&self.output, *format, self.formats.len() > 1, windows_bootstrap);
+            candidates.push(PathBuf::from(output));
+        }
+
+        let mut paths = Vec::new();
+        for path in candidates {
+            let Ok(resolved) = fs::canonicalize(&path) else {
+                continue;
+            };
+            let inside = |dir: &Path| resolved.starts_with(dir) && resolved != dir;
+            if !inside(&project_dir) && !inside(&target_root) {
+                let message = format!("Not removing {}: it is outside the project and target directories", path.display());
+                log::warning("clean", &message);
+            } else if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+        Ok(paths)
+    }
+
+    fn remove_stale(&self) -> Result<(), Box<dyn std::error::Error>> {
+        for path in self.stale_paths()? {
+            log::info("clean", &format!("Removing {}", path.display()));
+            if self.verbose {
+                println!("{} {}", "Removing".blue(), path.display());
+            }
+            // A symlink is removed itself, never what it points to.
+            if fs::symlink_metadata(&path)?.is_dir() {
+                fs::remove_dir_all(&path)?;
+            } else {
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Packs once, then rebuilds whenever the project changes. Never returns
+    /// unless setting up the watcher or the first build fails.
+    pub fn watch(&self) -> Result<(), RustPackError> {
+        if self.open.is_some() {
+            check_open(&self.targets, &[PackageFormat::SelfExtracting])?;
+        }
+        if self.clean {
+            self.remove_stale()?;
+        }
+        Ok(watch_and_build(&self.project_path, &self.output, &self.targets, &self.config, self.verbose, self.open.as_deref())?)
+    }
+}
+
+fn build_package(
+    project_path: &str, 
+    output_name: &str, 
+    targets: &[String],
+    build_config: &BuildConfig,
+    verbose: bool,
+    formats: &[PackageFormat],
+) -> Result<PackageReport, Box<dyn std::error::Error>> {
+    if formats.is_empty() {
+        return Err("No package formats requested".into());
+    }
+    if formats.contains(&PackageFormat::AppImage) {
+        appimage::check_tool()?;
+    }
+    if formats.contains(&PackageFormat::Dmg) {
+        macos::check_hdiutil()?;
+    }
+    check_wasm_format(targets, formats)?;
+    if formats.contains(&PackageFormat::Wasm) {
+        wasm::check_tool()?;
+    }
+    let compression: PayloadCompression = build_config.compression.parse()?;
+    if formats.contains(&PackageFormat::SelfExtracting) {
+        check_compression(
+            compression,
+            uses_windows_bootstrap(&build_config.bootstrap, targets)?,
+            !build_config.sign.is_empty(),
+        )?;
+    }
+    if build_config.compression_level.is_some()
+        && !formats.contains(&PackageFormat::Zip)
+        && !(formats.contains(&PackageFormat::SelfExtracting) && compression == PayloadCompression::Gzip)
+    {
+        let message = "--compression-level only applies to gzip payloads and ZIP files, ignoring it";
+        log::warning("archive", message);
+    }
+    let start = Instant::now();
+    let temp_dir = tempfile::tempdir()?;
+    let rustpack_dir = temp_dir.path().join("rustpack");
+    fs::create_dir_all(&rustpack_dir)?;
+
+    let mut target_infos = Vec::new();
+    let manifest_dir = package_dir(project_path, build_config.package.as_deref())?;
+    let manifest = parse_manifest(&manifest_dir)?;
+    check_features(&manifest_dir, &build_config.features)?;
+    check_profile(&manifest_dir, &build_config.profile)?;
+    check_builder(&build_config.builder)?;
+    check_post_build_tools(targets, build_config)?;
+    check_release(formats, uses_windows_bootstrap(&build_config.bootstrap, targets)?, build_config)?;
+    check_upload(build_config)?;
+    check_metadata(build_config)?;
+    check_source_date_epoch()?;
+    check_publish_github(build_config, &manifest.version)?;
+    for target in targets {
+        check_target_builder(target, &build_config.builder)?;
+        check_target_platform(target, formats)?;
+    }
+    if build_config.universal_macos && universal_macos_targets(targets) {
+        macos::check_lipo()?;
+    }
+    let signing_key = build_config.sign_key.as_deref().map(|path| load_signing_key(Path::new(path))).transpose()?;
+    let project_name = manifest.name;
+    let version = manifest.version;
+    // A wasm bundle is built from the library.
+    let binaries = if formats == [PackageFormat::Wasm] {
+        Vec::new()
+    } else if build_config.multicall.is_empty() {
+        select_binaries(&manifest_dir, &project_name, build_config)?
+    } else {
+        vec![project_name.clone()]
+    };
+    let description = manifest.description;
+
+    let hook_vars = [
+        ("RUSTPACK_NAME", project_name.clone()),
+        ("RUSTPACK_VERSION", version.clone()),
+        ("RUSTPACK_TARGET", targets.join(",")),
+        (
+            "RUSTPACK_OUTPUT",
+            package_output_name(output_name, formats[0], formats.len() > 1, uses_windows_bootstrap(&build_config.bootstrap, targets)?),
+        ),
+    ];
+    run_hooks("pre-build", &build_config.pre_build, project_path, &hook_vars, verbose)?;
+    if formats == [PackageFormat::Wasm] {
+        let report = build_wasm_package(
+            project_path,
+            &manifest_dir,
+            output_name,
+            &targets[0],
+            &project_name,
+            &version,
+            description.as_deref(),
+            manifest.license.as_deref(),
+            build_config,
+            verbose,
+        )?;
+        run_hooks("post-build", &build_config.post_build, project_path, &hook_vars, verbose)?;
+        return Ok(report);
+    }
+
+    let build_path = if build_config.multicall.is_empty() {
+        PathBuf::from(project_path)
+    } else {
+        log::info("multicall", &format!("Generating multi-call wrapper for {}", build_config.multicall.keys().cloned().collect::<Vec<_>>().join(", ")));
+        if verbose {
+            println!("{} multi-call wrapper", "Generating".blue());
+        }
+        generate_multicall_wrapper(&manifest_dir, &project_name, &build_config.multicall, build_config)?
+    };
+    let build_path = build_path.to_string_lossy();
+
+    let windows_resource = if build_config.windows_resources
+        && targets.iter().any(|t| t.contains("windows"))
+        && !winres::has_own_resources(&manifest_dir)
+    {
+        let icon = build_config.icon.as_ref().and_then(|icon| {
+            let icon = Path::new(project_path).join(icon);
+            let ico = icon.with_extension("ico");
+            ico.is_file().then(|| fs::canonicalize(ico).ok()).flatten()
+        });
+        Some(winres::resource_script(&project_name, &version, description.as_deref(), &manifest.authors, icon.as_deref()))
+    } else {
+        None
+    };
+
+    let mut timings = Vec::new();
+    if build_config.prefetch_deps {
+        log::info("fetch", "Running: cargo fetch");
+        if verbose {
+            println!("{} dependencies", "Fetching".blue());
+        }
+        let fetch_start = Instant::now();
+        let status = ProcessCommand::new("cargo")
+            .current_dir(build_path.as_ref())
+            .arg("fetch")
+            .args(log::quiet().then_some("--quiet"))
+            .status()?;
+        if !status.success() {
+            return Err("Failed to fetch dependencies".into());
+        }
+        timings.push(("fetch".to_string(), fetch_start.elapsed()));
+    }
+
+    // Each target gets its own cargo target dir when building concurrently,
+    // since cargo holds a lock on the shared one for the whole build.
+    let jobs = build_config.jobs.clamp(1, targets.len().max(1));
+    let multi_progress = (!verbose && !log::quiet()).then(MultiProgress::new);
+    let cargo_target = cargo_target_dir(build_path.as_ref(), build_config)?;
+    let builds_start = Instant::now();
+    let host = get_current_target();
+    let built = run_jobs(targets, jobs, |target| {
+        let (platform, arch, mut compatibility) = parse_target(target);
+        if let Some(version) = native_glibc_version(target, &build_config.builder, &host) {
+            compatibility[0] = format!("glibc-{}", version);
+        }
+        let bin_dir = rustpack_dir.join("bin").join(target);
+        fs::create_dir_all(&bin_dir)?;
+
+        log::set_target(target);
+        log::info("build", &format!("Building for {}", target));
+        if verbose {
+            println!("{} for {}", "Building".blue(), target);
+        }
+
+        let target_dir = if jobs > 1 {
+            cargo_target.join("rustpack-jobs").join(target)
+        } else {
+            cargo_target.clone()
+        };
+        let resource = match &windows_resource {
+            Some(script) if platform == "windows" => {
+                let resource = winres::compile_resource(script, target, &target_dir.join("rustpack-resources").join(target))?;
+                if resource.is_none() {
+                    let tried = winres::resource_compilers(target).join(", ");
+                    let message = format!("No resource compiler for {} found (tried {}), building without an icon or version information", target, tried);
+                    log::warning("resources", &message);
+                }
+                resource
+            }
+            _ => None,
+        };
+        let build_start = Instant::now();
+        let binary = build_for_target(
+            &build_path,
+            &target_dir,
+            jobs > 1,
+            &bin_dir,
+            target,
+            &binaries,
+            resource.as_deref(),
+            build_config,
+            verbose,
+            multi_progress.as_ref(),
+        )?;
+        let build_time = build_start.elapsed();
+        let binary_path = &binary.path;
+        if let Some(max_size) = max_binary_size(target, build_config)? {
+            for path in &binary.binaries {
+                check_binary_size(&rustpack_dir.join(path), target, max_size)?;
+            }
+        }
+
+        let binary_checksum = calculate_checksum(&rustpack_dir.join(binary_path))?;
+        let sign_target = build_config.target_overrides.get(target)
+            .and_then(|o| o.sign)
+            .unwrap_or(true);
+        let signature = if sign_target && (signing_key.is_some() || !build_config.sign.is_empty()) {
+            Some(sign_binary(&rustpack_dir.join(binary_path), signing_key.as_ref(), &build_config.sign)?)
+        } else {
+            None
+        };
+
+        let optimizations = build_config
+            .lto
+            .as_deref()
+            .filter(|lto| *lto != "off")
+            .map(|lto| format!("lto-{}", lto));
+
+        let report = TargetReport {
+            target: target.clone(),
+            platform: platform.clone(),
+            arch: arch.clone(),
+            binary_path: binary_path.to_string_lossy().to_string(),
+            stripped: binary.stripped,
+            compressed: binary.compressed,
+            size: fs::metadata(rustpack_dir.join(binary_path))?.len(),
+            sections: analyze_binary_size(&rustpack_dir.join(binary_path))
+                .map(|sizes| sizes.into_iter().filter(|(name, _)| name != "total").collect())
+                .unwrap_or_default(),
+            checksum: binary_checksum.clone(),
+            build_secs: build_time.as_secs_f64(),
+            libraries: binary.libraries,
+            crates: binary
+                .crates
+                .into_iter()
+                .take(build_config.bloat.unwrap_or(0))
+                .map(|(name, size)| CrateSize { name, size })
+                .collect(),
+        };
+
+        let binaries: Vec<String> = binary.binaries.iter().map(|b| b.to_string_lossy().to_string()).collect();
+        Ok((TargetInfo {
+            platform,
+            arch,
+            binary_path: binary_path.to_string_lossy().to_string(),
+            binary_checksums: binary_checksums(&rustpack_dir, &binaries)?,
+            binaries,
+            checksum: binary_checksum,
+            features: binary.features,
+            optimizations,
+            compatibility,
+            signature,
+        }, report, build_time))
+    })?;
+    let mut target_reports = Vec::new();
+    for (target, (target_info, target_report, build_time)) in targets.iter().zip(built) {
+        timings.push((format!("build {}", target), build_time));
+        target_infos.push(target_info);
+        target_reports.push(target_report);
+    }
+    for report in target_reports.iter().filter(|r| !r.crates.is_empty() && !log::quiet()) {
+        println!("{} {} by crate (top {}):", "Size of".blue(), report.target, report.crates.len());
+        for krate in &report.crates {
+            println!("  {:>10}  {}", format_size(krate.size), krate.name);
+        }
+    }
+    if jobs > 1 {
+        timings.push((format!("build (all targets, {} jobs)", jobs), builds_start.elapsed()));
+    }
+
+    for (step, elapsed) in &timings {
+        log::info("timing", &format!("{}: {:.2}s", step, elapsed.as_secs_f64()));
+    }
+    if verbose {
+        println!("{}", "Timing:".blue());
+        for (step, elapsed) in &timings {
+            println!("  {:<40} {:>8.2}s", step, elapsed.as_secs_f64());
+        }
+    }
+
+    if build_config.universal_macos {
+        if universal_macos_targets(targets) {
+            log::info("universal", "Merging the macOS binaries with lipo");
+            if verbose {
+                println!("{} universal macOS binary", "Creating".blue());
+            }
+            merge_universal_macos(&rustpack_dir, &mut target_infos, signing_key.as_ref(), &build_config.sign)?;
+        } else {
+            let message = "A universal macOS binary needs both x86_64-apple-darwin and aarch64-apple-darwin, packaging the targets separately";
+            log::warning("universal", message);
+        }
+    }
+
+    copy_assets(project_path, &rustpack_dir, &build_config.assets, verbose)?;
+    if verbose {
+        println!("{} license file", "Detecting".blue());
+    }
+    log::info("license", "Detecting license file");
+    if let Err(e) = detect_and_embed_license(&manifest_dir, &rustpack_dir) {
+        log::warning("license", &format!("Failed to embed license: {}", e));
+    }
+    if build_config.licenses {
+        if verbose {
+            println!("{} third-party licenses", "Collecting".blue());
+        }
+        let count = collect_third_party_licenses(&manifest_dir, &rustpack_dir, &project_name)
+            .map_err(|e| format!("Could not collect third-party licenses: {}", e))?;
+        log::info("license", &format!("Collected the licenses of {} third-party packages", count));
+    }
+
+    let icon_name = match &build_config.icon {
+        Some(icon) => Some(bundle_icon(project_path, &rustpack_dir, icon)?),
+        None => None,
+    };
+    if build_config.desktop_entry {
+        let desktop_entry = desktop_entry(
+            project_path,
+            build_config,
+            &project_name,
+            &project_name,
+            description.as_deref(),
+            icon_name.as_deref(),
+        )?;
+        fs::write(rustpack_dir.join(format!("{}.desktop", project_name)), desktop_entry)?;
+        log::info("desktop", &format!("Generated {}.desktop", project_name));
+    }
+
+    let mut metadata = BTreeMap::new();
+    metadata.insert("created_with".to_string(), "rustpack".to_string());
+    metadata.insert("rust_version".to_string(), get_rust_version());
+    metadata.insert("build_host".to_string(), host.clone());
+    metadata.extend(git_metadata(project_path));
+    metadata.insert("channel".to_string(), build_config.channel.clone());
+    if !build_config.multicall.is_empty() {
+        metadata.insert("multicall".to_string(), build_config.multicall.keys().cloned().collect::<Vec<_>>().join(","));
+    }
+    if let Some(license) = &manifest.license {
+        metadata.insert("license".to_string(), license.clone());
+    }
+    if let Some(edition) = manifest.edition {
+        metadata.insert("edition".to_string(), edition);
+    }
+    
+    let checksum = calculate_package_checksum(&rustpack_dir, &target_infos)?;
+
+    let enabled_features = vec![
+        "cross_platform".to_string(),
+        "self_extracting".to_string(),
+        "binary_packaging".to_string(),
+        "compression".to_string(),
+        "auto_detection".to_string(),
+    ];
+    
+    let dependencies = match analyze_dependencies(&manifest_dir) {
+        Ok(deps) => {
+            log::info("dependencies", &format!("Dependencies analyzed: {} found", deps.len()));
+            if verbose {
+                println!("{} Dependencies analyzed: {} found", "Info".blue(), deps.len());
+            }
+            deps
+        },
+        Err(e) => {
+            log::warning("dependencies", &format!("Could not analyze dependencies: {}", e));
+            HashMap::new()
+        }
+    };
+    
+    for (name, version) in dependencies {
+        metadata.insert(format!("dependency_{}", name), version);
+    }
+    metadata.extend(build_config.metadata.clone());
+
+    let created_at = package_timestamp(build_config)?;
+    match generate_sbom(&manifest_dir, &project_name, &version, manifest.license.as_deref(), created_at.as_deref()) {
+        Ok(bom) => {
+            let sbom_json = serde_json::to_string_pretty(&bom)?;
+            fs::write(rustpack_dir.join("sbom.json"), &sbom_json)?;
+            if let Some(sbom_path) = &build_config.sbom {
+                fs::write(sbom_path, &sbom_json)?;
+                log::info("sbom", &format!("Wrote {}", sbom_path));
+                if verbose {
+                    println!("{} SBOM to {}", "Wrote".blue(), sbom_path);
+                }
+            }
+        }
+        Err(e) if build_config.sbom.is_some() => return Err(format!("Could not generate the SBOM: {}", e).into()),
+        Err(e) => {
+            log::warning("sbom", &format!("Could not generate the SBOM: {}", e));
+        }
+    }
+
+    let merkle_tree = merkle::MerkleTree::from_dir(&rustpack_dir)?;
+    fs::write(rustpack_dir.join("merkle.json"), serde_json::to_string_pretty(&merkle_tree)?)?;
+
+    let mut package_info = PackageInfo {
+        name: project_name,
+        version,
+        description,
+        targets: target_infos,
+        created_at,
+        checksum,
+        features: enabled_features,
+        requires: build_config.requires.clone(),
+        metadata,
+        merkle_root: Some(merkle_tree.root.clone()),
+        public_key: signing_key.as_ref().map(|key| BASE64.encode(key.verifying_key().to_bytes())),
+    };
+
+    if let Some(update_url) = &build_config.update_url {
+        setup_auto_update(update_url, &mut package_info);
+    }
+
+    let info_json = serde_json::to_string_pretty(&package_info)?;
+    fs::write(rustpack_dir.join("info.json"), &info_json)?;
+    fs::write(rustpack_dir.join("manifest.env"), manifest_env(&package_info))?;
+    if let Some(manifest_path) = &build_config.output_manifest {
+        fs::write(manifest_path, &info_json)?;
+        if verbose {
+            println!("{} manifest to {}", "Wrote".blue(), manifest_path);
+        }
+    }
+
+    let windows_bootstrap = uses_windows_bootstrap(&build_config.bootstrap, targets)?;
+    let payload_size: u64 = package_size_breakdown(&rustpack_dir)?.iter().map(|(_, size)| size).sum();
+    let icon_file = build_config
+        .icon
+        .as_ref()
+        .and_then(|icon| Path::new(icon).file_name())
+        .map(|file| rustpack_dir.join("icons").join(file));
+    let bundle_id = build_config.bundle_id.clone().unwrap_or_else(|| {
+        let name: String = package_info
+            .name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '.' { c } else { '-' })
+            .collect();
+        format!("dev.rustpack.{}", name)
+    });
+    let mut outputs = Vec::new();
+    // Checksum and signature files written next to the outputs.
+    let mut sidecars = Vec::new();
+    let mut deduplicated_bytes = Vec::new();
+    let mut archive_times = Vec::new();
+    for format in formats {
+        let output = package_output_name(output_name, *format, formats.len() > 1, windows_bootstrap);
+        log::info("archive", &format!("Writing {}", output));
+        if let Some(parent) = Path::new(&output).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let archive_start = Instant::now();
+        let mut deduplicated = 0;
+        match format {
+            PackageFormat::Zip => create_zip_package(temp_dir.path(), &output, build_config.compression_level)?,
+            PackageFormat::Deb => {
+                let maintainer = manifest.authors.first().cloned().unwrap_or_else(|| format!("{} developers", package_info.name));
+                let desktop = if build_config.desktop_entry {
+                    let target = linux_target(&package_info, "A .deb")?;
+                    let exec = Path::new(&target.binary_path).file_name().ok_or("Invalid binary path")?.to_string_lossy();
+                    Some(desktop_entry(
+                        project_path,
+                        build_config,
+                        &package_info.name,
+                        &exec,
+                        package_info.description.as_deref(),
+                        icon_name.as_deref(),
+                    )?)
+                } else {
+                    None
+                };
+                deb::write_deb(
+                    &rustpack_dir,
+                    &package_info,
+                    &maintainer,
+                    desktop.as_deref(),
+                    icon_file.as_deref().zip(icon_name.as_deref()),
+                    Path::new(&output),
+                )?;
+            }
+            PackageFormat::AppImage => {
+                let target = linux_target(&package_info, "An AppImage")?;
+                let desktop_entry = desktop_entry(
+                    project_path,
+                    build_config,
+                    &package_info.name,
+                    &package_info.name,
+                    package_info.description.as_deref(),
+                    Some(icon_name.as_deref().unwrap_or(&package_info.name)),
+                )?;
+                appimage::write_appimage(
+                    &rustpack_dir,
+                    target,
+                    &package_info.name,
+                    &desktop_entry,
+                    icon_file.as_deref().zip(icon_name.as_deref()),
+                    Path::new(&output),
+                )?;
+            }
+            PackageFormat::App => {
+                macos::write_app(&rustpack_dir, &package_info, &bundle_id, icon_file.as_deref(), Path::new(&output))?;
+            }
+            PackageFormat::Dmg => {
+                let staging = tempfile::tempdir()?;
+                let app = staging.path().join(format!("{}.app", package_info.name));
+                macos::write_app(&rustpack_dir, &package_info, &bundle_id, icon_file.as_deref(), &app)?;
+                macos::write_dmg(&app, &package_info.name, Path::new(&output))?;
+            }
+            PackageFormat::SelfExtracting if windows_bootstrap => {
+                let stub = load_windows_stub(build_config.windows_stub.as_deref())?;
+                deduplicated = create_self_extracting_package(temp_dir.path(), &output, &stub, compression, build_config.compression_level)?;
+            }
+            PackageFormat::SelfExtracting => {
+                let bootstrap = BOOTSTRAP_SCRIPT.replacen(
+                    "PAYLOAD_COMPRESSION=gzip",
+                    &format!("PAYLOAD_COMPRESSION={}", compression),
+                    1,
+                );
+                deduplicated = create_self_extracting_package(temp_dir.path(), &output, bootstrap.as_bytes(), compression, build_config.compression_level)?;
+            }
+            // Built on its own by build_wasm_package.
+            PackageFormat::Wasm => unreachable!(),
+        }
+        if deduplicated > 0 {
+            log::info("archive", &format!("Stored {} of identical files once", format_size(deduplicated)));
+            if verbose {
+                println!("{} {} of identical files", "Deduplicated".blue(), format_size(deduplicated));
+            }
+        }
+        let archive_time = archive_start.elapsed();
+        let package_size = output_size(Path::new(&output))?;
+        log::info("archive", &format!(
+            "{}: {} of files in {} ({:.1}% of original, {})",
+            output,
+            format_size(payload_size),
+            format_size(package_size),
+            package_size as f64 * 100.0 / payload_size.max(1) as f64,
+            match format {
+                PackageFormat::Zip => match build_config.compression_level {
+                    Some(level) => format!("deflate level {}", level),
+                    None => "deflate".to_string(),
+                },
+                PackageFormat::Deb => "gzip".to_string(),
+                PackageFormat::AppImage => "squashfs".to_string(),
+                PackageFormat::App => "uncompressed".to_string(),
+                PackageFormat::Dmg => "zlib".to_string(),
+                PackageFormat::Wasm => "uncompressed".to_string(),
+                PackageFormat::SelfExtracting => match build_config.compression_level {
+                    Some(level) if compression == PayloadCompression::Gzip => format!("gzip level {}", level),
+                    _ => compression.to_string(),
+                },
+            },
+        ));
+        archive_times.push(archive_time);
+
+        // Checked before signing, so an oversized package is never signed,
+        // and nothing from this run is left behind, not even the formats
+        // that fit.
+        if let Some(max_size) = build_config.max_package_size
+            && package_size > max_size
+        {
+            for path in outputs.iter().chain([&output]) {
+                remove_output(Path::new(path))?;
+            }
+            for path in &sidecars {
+                fs::remove_file(path)?;
+            }
+            if let Some(manifest_path) = &build_config.output_manifest {
+                fs::remove_file(manifest_path)?;
+            }
+            let mut message = format!(
+                "Package size {} exceeds the limit of {}. Largest contributors (uncompressed):",
+                format_size(package_size),
+                format_size(max_size)
+            );
+            for (path, size) in package_size_breakdown(&rustpack_dir)?.iter().take(10) {
+                message.push_str(&format!("\n  {:>10}  {}", format_size(*size), path));
+            }
+            return Err(message.into());
+        }
+
+        if !build_config.sign.is_empty() && matches!(format, PackageFormat::Deb | PackageFormat::AppImage | PackageFormat::App | PackageFormat::Dmg) {
+            log::warning("sign", &format!("Not signing {}: the format has no room for a signature trailer", output));
+        } else if !build_config.sign.is_empty() {
+            log::info("sign", &format!("Signing {}", output));
+            sign_output_file(Path::new(&output), &build_config.sign, compression)?;
+        }
+
+        // The bootstrap's updater checks a download against this before
+        // replacing itself with it.
+        if build_config.update_url.is_some() && *format == PackageFormat::SelfExtracting && !windows_bootstrap {
+            let checksum_path = write_checksum_file(Path::new(&output))?;
+            log::info("update", &format!("Wrote {}", checksum_path.display()));
+            if verbose {
+                println!("{} {}", "Wrote".blue(), checksum_path.display());
+            }
+            sidecars.push(checksum_path);
+        }
+        if let Some(key) = &signing_key {
+            let sig_path = write_detached_signature(Path::new(&output), key)?;
+            log::info("sign", &format!("Wrote {}", sig_path.display()));
+            if verbose {
+                println!("{} {}", "Signed".blue(), sig_path.display());
+            }
+            sidecars.push(sig_path);
+        }
+        outputs.push(output);
+        deduplicated_bytes.push(deduplicated);
+    }
+    let output_name = outputs[0].as_str();
+
+    if let Some(manifest_path) = &build_config.channel_manifest {
+        let file = Path::new(output_name)
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_else(|| output_name.to_string());
+        let release = ChannelRelease {
+            version: package_info.version.clone(),
+            checksum: package_info.checksum.clone(),
+            file,
+            created_at: package_info.created_at.clone(),
+            min_version: build_config.min_update_version.clone(),
+            patch_from: None,
+            patch_file: None,
+        };
+        update_channel_manifest(Path::new(manifest_path), &build_config.channel, release)?;
+        if verbose {
+            println!("{} {} channel in {}", "Updated".blue(), build_config.channel, manifest_path);
+        }
+    }
+
+    if let Some(release_dir) = &build_config.release_dir {
+        let index = formats.iter().position(|f| *f == PackageFormat::SelfExtracting).unwrap_or(0);
+        let published = publish_release(Path::new(release_dir), Path::new(&outputs[index]), &package_info, build_config)?;
+        log::info("update", &format!("Published {}", published.display()));
+        if verbose {
+            println!("{} {} to {}", "Published".blue(), published.display(), release_dir);
+        }
+    }
+
+    let mut uploads = Vec::new();
+    if let Some(url) = &build_config.upload {
+        for path in outputs.iter().map(PathBuf::from).chain(sidecars.iter().cloned()) {
+            log::info("upload", &format!("Uploading {} to {}", path.display(), url));
+            let uploaded = s3::upload(&path, url)?;
+            log::info("upload", &format!("Uploaded {}", uploaded));
+            if !log::quiet() {
+                println!("{} {}", "Uploaded".green(), uploaded);
+            }
+            uploads.push(uploaded);
+        }
+    }
+    if let Some(spec) = &build_config.publish_github {
+        let mut files = Vec::new();
+        for output in &outputs {
+            let path = PathBuf::from(output);
+            if path.is_dir() {
+                log::warning("publish", &format!("{} is a directory, which can't be a release asset; add --format zip to publish it", output));
+                continue;
+            }
+            let checksum_path = PathBuf::from(format!("{}.sha256", output));
+            if !sidecars.contains(&checksum_path) {
+                sidecars.push(write_checksum_file(&path)?);
+            }
+            files.push(path);
+        }
+        files.extend(sidecars.iter().filter(|sidecar| sidecar.is_file()).cloned());
+        log::info("publish", &format!("Publishing {} files to {}", files.len(), spec));
+        let release = github::publish(spec, &package_info.version, &files)?;
+        log::info("publish", &format!("Published {}", release.url));
+        if !log::quiet() {
+            println!("{} {}", "Published".green(), release.url);
+            for asset in &release.assets {
+                println!("  {}", asset);
+            }
+        }
+        uploads.extend(release.assets);
+    }
+
+    if let Some(report_path) = &build_config.report {
+        let report = BuildReport {
+            schema_version: BUILD_REPORT_SCHEMA_VERSION,
+            name: package_info.name.clone(),
+            version: package_info.version.clone(),
+            outputs: outputs
+                .iter()
+                .zip(formats)
+                .zip(&deduplicated_bytes)
+                .map(|((output, format), deduplicated_bytes)| {
+                    Ok(OutputReport {
+                        path: output.clone(),
+                        format: match format {
+                            PackageFormat::Zip => "zip".to_string(),
+                            PackageFormat::Deb => "deb".to_string(),
+                            PackageFormat::AppImage => "appimage".to_string(),
+                            PackageFormat::App => "app".to_string(),
+                            PackageFormat::Dmg => "dmg".to_string(),
+                            PackageFormat::Wasm => "wasm".to_string(),
+                            PackageFormat::SelfExtracting => "rpack".to_string(),
+                        },
+                        size: output_size(Path::new(output))?,
+                        checksum: calculate_checksum(Path::new(output))?,
+                        deduplicated_bytes: *deduplicated_bytes,
+                    })
+                })
+                .collect::<Result<_, Box<dyn std::error::Error>>>()?,
+            targets: target_reports,
+            payload_sizes: if formats.contains(&PackageFormat::SelfExtracting) {
+                payload_sizes(temp_dir.path(), build_config.compression_level)?
+            } else {
+                BTreeMap::new()
+            },
+            uploads,
+            duration_secs: start.elapsed().as_secs_f64(),
+        };
+        fs::write(report_path, serde_json::to_string_pretty(&report)?)?;
+        if verbose {
+            println!("{} build report to {}", "Wrote".blue(), report_path);
+        }
+    }
+
+    if let Some(env_file) = &build_config.env_file {
+        write_env_file(Path::new(env_file), &outputs, &package_info, build_config)?;
+        if verbose {
+            println!("{} build results to {}", "Wrote".blue(), env_file);
+        }
+    }
+
+    run_hooks("post-build", &build_config.post_build, project_path, &hook_vars, verbose)?;
+
+    Ok(PackageReport {
+        output: PathBuf::from(output_name),
+        size: output_size(Path::new(output_name))?,
+        outputs: outputs.iter().map(PathBuf::from).collect(),
+        info: package_info,
+        compression,
+        payload_size,
+        archive_times,
+        exit_code: None,
+    })
+}
+
+/// Removes an output file, or an output directory with everything in it.
+fn remove_output(path: &Path) -> io::Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Size of an output file, or of all the files in an output directory.
+fn output_size(path: &Path) -> io::Result<u64> {
+    if !path.is_dir() {
+        return Ok(fs::metadata(path)?.len());
+    }
+    let mut size = 0;
+    for entry in WalkDir::new(path).into_iter() {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+    Ok(size)
+}
+
+/// The one Linux target of a package, for formats (`kind`) that hold a single
+/// binary.
+fn linux_target<'a>(package_info: &'a PackageInfo, kind: &str) -> Result<&'a TargetInfo, Box<dyn std::error::Error>> {
+    let linux: Vec<&TargetInfo> = package_info.targets.iter().filter(|t| t.platform == "linux").collect();
+    match linux[..] {
+        [target] => Ok(target),
+        _ => Err(format!("{} holds a single Linux binary, but {} Linux targets were built", kind, linux.len()).into()),
+    }
+}
+
+/// The file a package format is written to. A single format uses `output`
+/// as given; with several, the format's extension replaces any package
+/// extension on `output`.
+fn package_output_name(output: &str, format: PackageFormat, multiple: bool, windows_bootstrap: bool) -> String {
+    if !multiple {
+        return output.to_string();
+    }
+    let stem = [".rpack", ".zip", ".exe", ".deb", ".AppImage", ".app", ".dmg"]
+        .iter()
+        .find_map(|ext| output.strip_suffix(ext))
+        .unwrap_or(output);
+    let extension = match format {
+        PackageFormat::Zip => "zip",
+        PackageFormat::Deb => "deb",
+        PackageFormat::AppImage => "AppImage",
+        PackageFormat::App => "app",
+        PackageFormat::Dmg => "dmg",
+        PackageFormat::Wasm => "web",
+        PackageFormat::SelfExtracting if windows_bootstrap => "exe",
+        PackageFormat::SelfExtracting => "rpack",
+    };
+    format!("{}.{}", stem, extension)
+}
+
+/// Writes the pack results as `KEY=VALUE` lines that can be sourced by a
+/// shell or appended to `$GITHUB_ENV`. Values are not quoted, so list
+/// values are comma-separated.
+fn write_env_file(
+    env_path: &Path,
+    outputs: &[String],
+    package_info: &PackageInfo,
+    build_config: &BuildConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let output_name = outputs[0].as_str();
+    let mut artifacts = outputs.to_vec();
+    artifacts.extend(build_config.output_manifest.iter().cloned());
+    artifacts.extend(build_config.channel_manifest.iter().cloned());
+    if let Some(release_dir) = &build_config.release_dir {
+        let release_dir = Path::new(release_dir);
+        artifacts.push(release_dir.join(release_file_name(&build_config.channel)).to_string_lossy().to_string());
+        artifacts.push(release_dir.join("version.json").to_string_lossy().to_string());
+    }
+    let targets: Vec<String> = package_info
+        .targets
+        .iter()
+        .map(|t| format!("{}-{}", t.arch, t.platform))
+        .collect();
+
+    let vars = [
+        ("RUSTPACK_OUTPUT", output_name.to_string()),
+        ("RUSTPACK_NAME", package_info.name.clone()),
+        ("RUSTPACK_VERSION", package_info.version.clone()),
+        ("RUSTPACK_CHECKSUM", calculate_checksum(Path::new(output_name))?),
+        ("RUSTPACK_SIZE", output_size(Path::new(output_name))?.to_string()),
+        ("RUSTPACK_CHANNEL", build_config.channel.clone()),
+        ("RUSTPACK_TARGETS", targets.join(",")),
+        ("RUSTPACK_ARTIFACTS", artifacts.join(",")),
+    ];
+
+    let mut env_file = File::create(env_path)?;
+    for (key, value) in vars {
+        writeln!(env_file, "{}={}", key, value)?;
+    }
+    Ok(())
+}
+
+/// Flattens the parts of info.json the bootstrap script needs into shell
+/// assignments it can source, so running a package doesn't need jq.
+/// `PACKAGE_TARGETS` holds one `platform arch binary_path checksum` line per
+/// target, and `PACKAGE_BINARIES` the names of the binaries when there are
+/// several. Metadata from `--meta` is exported to the app as
+/// `RUSTPACK_META_<KEY>`.
+fn manifest_env(package_info: &PackageInfo) -> String {
+    let targets: Vec<String> = package_info
+        .targets
+        .iter()
+        .map(|t| format!("{} {} {} {}", t.platform, t.arch, t.binary_path, t.checksum))
+        .collect();
+    // Every binary the bootstrap may run, including those picked by name.
+    let checksums: Vec<String> = package_info
+        .targets
+        .iter()
+        .flat_map(|t| {
+            t.all_binaries().into_iter().filter_map(|binary| {
+                let checksum = t.binary_checksums.get(binary).or((binary == t.binary_path).then_some(&t.checksum))?;
+                Some(format!("{} {}", binary, checksum))
+            })
+        })
+        .collect();
+    let binaries: Vec<String> = match package_info.targets.first() {
+        Some(target) if target.binaries.len() > 1 => target
+            .binaries
+            .iter()
+            .filter_map(|b| Path::new(b).file_stem())
+            .map(|n| n.to_string_lossy().to_string())
+            .collect(),
+        _ => Vec::new(),
+    };
+    let metadata = |key: &str| package_info.metadata.get(key).cloned().unwrap_or_default();
+
+    let vars = [
+        ("PACKAGE_NAME", package_info.name.clone()),
+        ("PACKAGE_VERSION", package_info.version.clone()),
+        ("PACKAGE_CHANNEL", metadata("channel")),
+        ("PACKAGE_UPDATE_URL", metadata("update_url")),
+        ("PACKAGE_REQUIRES", package_info.requires.join(" ")),
+        ("PACKAGE_TARGETS", targets.join("\n")),
+        ("PACKAGE_CHECKSUMS", checksums.join("\n")),
+        ("PACKAGE_BINARIES", binaries.join(" ")),
+    ];
+    let quote = |value: &str| format!("'{}'", value.replace('\'', "'\\''"));
+    let mut env: String = vars.iter().map(|(key, value)| format!("{}={}\n", key, quote(value))).collect();
+    for (key, value) in package_info.metadata.iter().filter(|(key, _)| !is_reserved_metadata_key(key)) {
+        env.push_str(&format!("export RUSTPACK_META_{}={}\n", key.to_uppercase(), quote(value)));
+    }
+    env
+}
+
+fn update_channel_manifest(
+    manifest_path: &Path,
+    channel: &str,
+    release: ChannelRelease,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut manifest: ChannelManifest = if manifest_path.exists() {
+        serde_json::from_str(&fs::read_to_string(manifest_path)?)?
+    } else {
+        ChannelManifest::default()
+    };
+
+    manifest.channels.insert(channel.to_string(), release);
+    // Stubs built before channels existed only read the top-level version.
+    manifest.version = manifest.channels.get("stable").map(|r| r.version.clone());
+
+    write_atomically(manifest_path, serde_json::to_string_pretty(&manifest)?.as_bytes())?;
+    Ok(())
+}
+
+/// Writes `data` to `path` through a temporary file beside it that is then
+/// renamed over `path`, so readers see either the old file or all of the
+/// new one. A replaced file's permissions carry over.
+fn write_atomically(path: &Path, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut staged = path.as_os_str().to_owned();
+    staged.push(format!(".{}.tmp", std::process::id()));
+    let staged = PathBuf::from(staged);
+    let result = fs::write(&staged, data)
+        .and_then(|()| match fs::metadata(path) {
+            Ok(metadata) => fs::set_permissions(&staged, metadata.permissions()),
+            Err(_) => Ok(()),
+        })
+        .and_then(|()| fs::rename(&staged, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&staged);
+    }
+    Ok(result?)
+}
+
+/// Fails unless `publish_github` names a repository, a `GITHUB_TOKEN` is
+/// set and this rustpack was built to talk to GitHub.
+fn check_publish_github(build_config: &BuildConfig, version: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(spec) = &build_config.publish_github {
+        github::parse_spec(spec, version)?;
+        github::check_feature()?;
+        github::token()?;
+    }
+    Ok(())
+}
+
+/// `metadata` keys rustpack fills in itself, which `--meta` can't set.
+const RESERVED_METADATA_KEYS: &[&str] = &[
+    "created_with",
+    "rust_version",
+    "build_host",
+    "git_commit",
+    "git_branch",
+    "git_dirty",
+    "channel",
+    "multicall",
+    "license",
+    "edition",
+    "update_url",
+];
+
+/// Whether rustpack sets the `info.json` metadata `key` itself; any other
+/// key came from `--meta`.
+pub fn is_reserved_metadata_key(key: &str) -> bool {
+    RESERVED_METADATA_KEYS.contains(&key) || key.starts_with("dependency_")
+}
+
+/// Fails unless every `metadata` key is a name the bootstrap can export and
+/// not one rustpack sets itself.
+fn check_metadata(build_config: &BuildConfig) -> Result<(), Box<dyn std::error::Error>> {
+    for key in build_config.metadata.keys() {
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("Metadata key {:?} may only contain letters, digits and underscores", key).into());
+        }
+        if is_reserved_metadata_key(key) {
+            return Err(format!("Metadata key {} is set by rustpack itself", key).into());
+        }
+    }
+    Ok(())
+}
+
+/// Fails unless `upload` is an `s3://` URL and the aws CLI is installed.
+fn check_upload(build_config: &BuildConfig) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(url) = &build_config.upload {
+        s3::parse_url(url)?;
+        s3::check_tool()?;
+    }
+    Ok(())
+}
+
+/// The file the updater downloads for `channel`: `latest.rpack` on the
+/// stable channel and `latest-<channel>.rpack` on the others.
+fn release_file_name(channel: &str) -> String {
+    if channel == "stable" {
+        "latest.rpack".to_string()
+    } else {
+        format!("latest-{}.rpack", channel)
+    }
+}
+
+/// Copies the self-extracting `package` into `release_dir` under the name
+/// the updater downloads, with its `.sha256`, and records the release in
+/// the directory's `version.json`. With `delta_updates`, also writes
+/// `patches/<previous>-<version>.patch` from the release it replaces.
+/// Returns the published package's path.
+fn publish_release(
+    release_dir: &Path,
+    package: &Path,
+    package_info: &PackageInfo,
+    build_config: &BuildConfig,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    fs::create_dir_all(release_dir)?;
+    let manifest_path = release_dir.join("version.json");
+    let file = release_file_name(&build_config.channel);
+    let published = release_dir.join(&file);
+
+    let mut patch = None;
+    if build_config.delta_updates && manifest_path.exists() && published.exists() {
+        let manifest: ChannelManifest = serde_json::from_str(&fs::read_to_string(&manifest_path)?)?;
+        if let Some(previous) = manifest.channels.get(&build_config.channel)
+            && previous.version != package_info.version
+        {
+            patch = write_release_patch(release_dir, &published, &previous.version, package, &package_info.version)?
+                .map(|patch_file| (previous.version.clone(), patch_file));
+        }
+    }
+
+    // Clients may be downloading the previous release while it is replaced.
+    write_atomically(&published, &fs::read(package)?)?;
+    fs::set_permissions(&published, fs::metadata(package)?.permissions())?;
+    write_checksum_file(&published)?;
+    let (patch_from, patch_file) = patch.unzip();
+    let release = ChannelRelease {
+        version: package_info.version.clone(),
+        checksum: calculate_checksum(&published)?,
+        file,
+        created_at: package_info.created_at.clone(),
+        min_version: build_config.min_update_version.clone(),
+        patch_from,
+        patch_file,
+    };
+    update_channel_manifest(&release_dir.join("version.json"), &build_config.channel, release)?;
+    Ok(published)
+}
+
+/// Writes the patch from the previous release at `previous` to `package`
+/// into `release_dir/patches` and returns its path relative to
+/// `release_dir`. Patches are positional, so after an early change in a
+/// compressed payload one can come out larger than the package; such a
+/// patch is dropped and the updater downloads the package instead.
+fn write_release_patch(
+    release_dir: &Path,
+    previous: &Path,
+    previous_version: &str,
+    package: &Path,
+    version: &str,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let patch_file = format!("patches/{}-{}.patch", previous_version, version);
+    let patch_path = release_dir.join(&patch_file);
+    fs::create_dir_all(release_dir.join("patches"))?;
+    create_binary_patch(previous, package, &patch_path)?;
+    let patch_size = fs::metadata(&patch_path)?.len();
+    let package_size = fs::metadata(package)?.len();
+    if patch_size >= package_size {
+        fs::remove_file(&patch_path)?;
+        log::warning(
+            "update",
+            &format!(
+                "The patch from {} is {}, no smaller than the package ({}), so it isn't published; --compression none makes patches smaller",
+                previous_version,
+                format_size(patch_size),
+                format_size(package_size)
+            ),
+        );
+        return Ok(None);
+    }
+    log::info("update", &format!("Wrote {} ({})", patch_path.display(), format_size(patch_size)));
+    Ok(Some(patch_file))
+}
+
+/// Fails unless a `release_dir` has what the bootstrap's updater needs: an
+/// update URL and a self-extracting package with the shell bootstrap. A
+/// `min_update_version` has to be a plain version for the bootstrap to
+/// compare against.
+fn check_release(formats: &[PackageFormat], windows_bootstrap: bool, build_config: &BuildConfig) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(version) = &build_config.min_update_version
+        && semver::Version::parse(version).is_err()
+    {
+        return Err(format!("min_update_version {} is not a version like 1.2.0", version).into());
+    }
+    if build_config.release_dir.is_none() {
+        if build_config.delta_updates {
+            return Err("--delta-updates writes its patches to --release-dir, which isn't set".into());
+        }
+        return Ok(());
+    }
+    if build_config.update_url.is_none() {
+        return Err("--release-dir publishes an update, so it needs --update-url".into());
+    }
+    if !formats.contains(&PackageFormat::SelfExtracting) || windows_bootstrap {
+        return Err("--release-dir publishes the self-extracting package for the shell bootstrap's updater, which this build doesn't write".into());
+    }
+    Ok(())
+}
+
+/// The repository rustpack's own releases are published to.
+const SELF_UPDATE_REPOSITORY: (&str, &str) = ("neoapps-dev", "rustpack");
+
+/// What `self_update` found and did.
+#[derive(Debug)]
+pub struct SelfUpdate {
+    /// The running rustpack's version.
+    pub current: String,
+    /// The latest release's version, when it is newer than `current`.
+    pub available: Option<String>,
+    /// The latest release's page, with its changelog.
+    pub release_url: String,
+    /// Whether the running executable was replaced by `available`.
+    pub installed: bool,
+}
+
+/// Looks for a rustpack release newer than this one on GitHub Releases
+/// and, unless `check_only`, replaces the running executable with its
+/// `rustpack-<os>-<arch>` asset. The download has to match the asset's
+/// `.sha256`, in the format rustpack writes for its own packages.
+pub fn self_update(check_only: bool) -> Result<SelfUpdate, RustPackError> {
+    let (owner, repo) = SELF_UPDATE_REPOSITORY;
+    let release = github::latest_release(owner, repo)?;
+    let latest = release.tag.trim_start_matches('v').to_string();
+    let current = env!("CARGO_PKG_VERSION").to_string();
+    let newer = semver::Version::parse(&latest)
+        .map_err(|e| format!("The latest release's tag {} isn't a version: {}", release.tag, e))?
+        > semver::Version::parse(&current).expect("valid package version");
+    let mut update = SelfUpdate {
+        current,
+        available: newer.then(|| latest.clone()),
+        release_url: release.url.clone(),
+        installed: false,
+    };
+    if !newer || check_only {
+        return Ok(update);
+    }
+
+    let name = format!("rustpack-{}-{}{}", env::consts::OS, env::consts::ARCH, env::consts::EXE_SUFFIX);
+    let asset_url = |name: &str| {
+        release
+            .assets
+            .iter()
+            .find(|(asset, _)| asset == name)
+            .map(|(_, url)| url.clone())
+            .ok_or_else(|| format!("The rustpack {} release has no {} asset", latest, name))
+    };
+    let binary_url = asset_url(&name)?;
+    let checksum_url = asset_url(&format!("{}.sha256", name))?;
+    log::info("self-update", &format!("Downloading {}", binary_url));
+    let binary = github::download(&binary_url)?;
+    let checksum_file = github::download(&checksum_url)?;
+    let expected = String::from_utf8_lossy(&checksum_file).split_whitespace().next().unwrap_or_default().to_string();
+    let actual = format!("{:x}", Sha256::digest(&binary));
+    if actual != expected {
+        return Err(format!("{} has checksum {} but {}.sha256 says {}; keeping the installed rustpack", name, actual, name, expected).into());
+    }
+
+    let executable = env::current_exe()?.canonicalize()?;
+    // Windows won't overwrite a running executable, but it will rename one.
+    #[cfg(windows)]
+    let old = {
+        let old = executable.with_extension("old");
+        let _ = fs::remove_file(&old);
+        fs::rename(&executable, &old)?;
+        old
+    };
+    let written = write_atomically(&executable, &binary);
+    // Put the installed rustpack back rather than leave none at all.
+    #[cfg(windows)]
+    if written.is_err() {
+        let _ = fs::rename(&old, &executable);
+    }
+    written?;
+    log::info("self-update", &format!("Replaced {} with rustpack {}", executable.display(), latest));
+    update.installed = true;
+    Ok(update)
+}
+
+/// The size budget for `target`'s binaries: its `[target]` override, or
+/// `max_size`.
+fn max_binary_size(target: &str, build_config: &BuildConfig) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+    match build_config.target_overrides.get(target).and_then(|o| o.max_size.as_deref()) {
+        Some(size) => Ok(Some(parse_size(size)?)),
+        None => Ok(build_config.max_size),
+    }
+}
+
+/// Fails if the binary at `path` is larger than `max_size`, naming its
+/// largest sections.
+fn check_binary_size(path: &Path, target: &str, max_size: u64) -> Result<(), Box<dyn std::error::Error>> {
+    let size = fs::metadata(path)?.len();
+    if size <= max_size {
+        return Ok(());
+    }
+    let mut message = format!(
+        "{} for {} is {} ({} bytes), over the size budget of {} by {}",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        target,
+        format_size(size),
+        size,
+        format_size(max_size),
+        format_size(size - max_size)
+    );
+    let mut sections: Vec<(String, usize)> = analyze_binary_size(path)
+        .map(|sizes| sizes.into_iter().filter(|(name, _)| name != "total").collect())
+        .unwrap_or_default();
+    sections.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    if !sections.is_empty() {
+        message.push_str(". Largest sections:");
+        for (name, size) in sections.iter().take(5) {
+            message.push_str(&format!("\n  {:>10}  {}", format_size(*size as u64), name));
+        }
+    }
+    Err(message.into())
+}
+
+pub fn parse_size(size: &str) -> Result<u64, Box<dyn std::error::Error>> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid size: {}", size))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        _ => return Err(format!("Invalid size unit in: {}", size).into()),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Sums the staged files per binary target, per top-level asset and per
+/// remaining file, largest first.
+fn package_size_breakdown(rustpack_dir: &Path) -> Result<Vec<(String, u64)>, Box<dyn std::error::Error>> {
+    let mut sizes: HashMap<String, u64> = HashMap::new();
+    for entry in WalkDir::new(rustpack_dir).into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let rel_path = entry.path().strip_prefix(rustpack_dir)?;
+        let components: Vec<String> = rel_path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect();
+        let group = match components.first().map(String::as_str) {
+            Some("bin") | Some("assets") if components.len() > 2 => components[..2].join("/"),
+            _ => components.join("/"),
+        };
+        *sizes.entry(group).or_insert(0) += entry.metadata()?.len();
+    }
+
+    let mut breakdown: Vec<(String, u64)> = sizes.into_iter().collect();
+    breakdown.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    Ok(breakdown)
+}
+
+/// Whether a self-extracting package should use the Windows `.exe` stub
+/// rather than the POSIX shell bootstrap.
+pub fn uses_windows_bootstrap(bootstrap: &str, targets: &[String]) -> Result<bool, Box<dyn std::error::Error>> {
+    match bootstrap {
+        "windows" => Ok(true),
+        "posix" => Ok(false),
+        "auto" => Ok(!targets.is_empty() && targets.iter().all(|t| t.contains("windows"))),
+        other => Err(format!("Unknown bootstrap '{}' (expected auto, windows or posix)", other).into()),
+    }
+}
+
+/// Fails for brotli payloads where nothing could read them back: the Windows
+/// stub tells payloads apart by their magic number, which brotli lacks, and
+/// the `sign` trailer would follow the brotli stream, which `brotli -d`
+/// rejects.
+fn check_compression(compression: PayloadCompression, windows_bootstrap: bool, hmac_sign: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if compression != PayloadCompression::Brotli {
+        return Ok(());
+    }
+    if windows_bootstrap {
+        return Err("The Windows stub can't extract brotli payloads; use --compression gzip or zstd".into());
+    }
+    if hmac_sign {
+        return Err("Brotli payloads can't carry a signature trailer; sign with --sign-key instead".into());
+    }
+    Ok(())
+}
+
+fn load_windows_stub(configured: Option<&str>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let stub_path = match configured {
+        Some(path) => PathBuf::from(path),
+        None => env::current_exe()?.with_file_name("rustpack-stub.exe"),
+    };
+    fs::read(&stub_path).map_err(|e| {
+        format!(
+            "Could not read the Windows extraction stub at {} ({}). Build it with \
+             `cargo build --release --bin rustpack-stub --target x86_64-pc-windows-gnu` and pass --windows-stub",
+            stub_path.display(),
+            e
+        )
+        .into()
+    })
+}
+
+/// `git_commit`, `git_dirty` (whether tracked files have uncommitted changes)
+/// and `git_branch` of the repository `project_path` is in. Empty outside a
+/// repository or without git; the branch is left out on a detached HEAD.
+fn git_metadata(project_path: &str) -> BTreeMap<String, String> {
+    let git = |args: &[&str]| -> Option<String> {
+        let output = ProcessCommand::new("git").arg("-C").arg(project_path).args(args).output().ok()?;
+        output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    let mut metadata = BTreeMap::new();
+    let Some(commit) = git(&["rev-parse", "HEAD"]) else {
+        return metadata;
+    };
+    metadata.insert("git_commit".to_string(), commit);
+    // Untracked files are left out, so a package written into the work tree
+    // doesn't make the next build look dirty.
+    if let Some(status) = git(&["status", "--porcelain", "--untracked-files=no"]) {
+        metadata.insert("git_dirty".to_string(), (!status.is_empty()).to_string());
+    }
+    if let Some(branch) = git(&["rev-parse", "--abbrev-ref", "HEAD"]).filter(|b| b != "HEAD") {
+        metadata.insert("git_branch".to_string(), branch);
+    }
+    metadata
+}
+
+/// The package's `created_at` in UTC, such as `2024-05-01T12:00:00Z`:
+/// `SOURCE_DATE_EPOCH` if set, else now. None with `no_timestamp`.
+fn package_timestamp(build_config: &BuildConfig) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    if build_config.no_timestamp {
+        return Ok(None);
+    }
+    let time = match source_date_epoch() {
+        Some(epoch) => DateTime::<Utc>::from_timestamp(epoch as i64, 0).ok_or("SOURCE_DATE_EPOCH is out of range")?,
+        None => Utc::now(),
+    };
+    Ok(Some(time.to_rfc3339_opts(SecondsFormat::Secs, true)))
+}
+
+/// The `SOURCE_DATE_EPOCH` reproducible-builds timestamp, if set.
+fn source_date_epoch() -> Option<u64> {
+    env::var("SOURCE_DATE_EPOCH").ok().and_then(|s| s.trim().parse().ok())
+}
+
+/// Fails on a `SOURCE_DATE_EPOCH` that isn't a Unix timestamp, rather than
+/// quietly using the current time.
+fn check_source_date_epoch() -> Result<(), Box<dyn std::error::Error>> {
+    match env::var("SOURCE_DATE_EPOCH") {
+        Ok(value) if !value.trim().is_empty() && source_date_epoch().is_none() => {
+            Err(format!("SOURCE_DATE_EPOCH must be a Unix timestamp in seconds, got {:?}", value).into())
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &fs::Metadata) -> bool {
+    true
+}
+
+// ZIP timestamps can't go earlier than 1980.
+fn zip_timestamp(epoch: u64) -> zip::DateTime {
+    DateTime::<Utc>::from_timestamp(epoch as i64, 0)
+        .and_then(|t| {
+            zip::DateTime::from_date_and_time(
+                t.year() as u16,
+                t.month() as u8,
+                t.day() as u8,
+                t.hour() as u8,
+                t.minute() as u8,
+                t.second() as u8,
+            )
+            .ok()
+        })
+        .unwrap_or_default()
+}
+
+fn create_self_extracting_package(
+    temp_dir: &Path,
+    output_name: &str,
+    stub: &[u8],
+    compression: PayloadCompression,
+    level: Option<u32>,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let mut output_file = File::create(output_name)?;
+    output_file.write_all(stub)?;
+    if !stub.ends_with(b"__PAYLOAD_BEGINS__\n") {
+        output_file.write_all(b"\n__PAYLOAD_BEGINS__\n")?;
+    }
+
+    // Files are streamed straight into the output, so nothing is held in
+    // memory or staged in a second temp file.
+    let encoder = compression.encoder(io::BufWriter::new(output_file), level)?;
+    let mut tar = Builder::new(encoder);
+    let deduplicated = append_payload(&mut tar, temp_dir)?;
+    tar.into_inner()?.finish()?.flush()?;
+    package::stamp_payload_checksum(Path::new(output_name), stub)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut perms = fs::metadata(output_name)?.permissions();
+        perms.set_mode(0o755);
+        fs::set_permissions(output_name, perms)?;
+    }
+
+    Ok(deduplicated)
+}
+
+/// Writes every file below `temp_dir` to `tar` and returns the bytes saved
+/// by storing identical files once.
+fn append_payload<W: Write>(tar: &mut Builder<W>, temp_dir: &Path) -> Result<u64, Box<dyn std::error::Error>> {
+    let mtime = source_date_epoch().unwrap_or(0);
+    // Files identical to one already written (the same binary built for two
+    // targets, say) are stored as hard links to it.
+    let mut written: HashMap<String, PathBuf> = HashMap::new();
+    let mut deduplicated = 0;
+
+    // Sorted entries with fixed owners, modes and times, so identical inputs
+    // give a byte-identical package (and signature).
+    for entry in WalkDir::new(temp_dir).sort_by_file_name().into_iter().filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path == temp_dir {
+            continue;
+        }
+        let name = path.strip_prefix(temp_dir)?;
+        let mut header = Header::new_gnu();
+        header.set_mtime(mtime);
+        header.set_uid(0);
+        header.set_gid(0);
+        if entry.file_type().is_dir() {
+            header.set_entry_type(EntryType::Directory);
+            header.set_mode(0o755);
+            header.set_size(0);
+            tar.append_data(&mut header, name, io::empty())?;
+        } else {
+            let file = File::open(path)?;
+            let metadata = file.metadata()?;
+            header.set_mode(if is_executable(&metadata) { 0o755 } else { 0o644 });
+            let checksum = if metadata.len() > 0 { Some(calculate_checksum(path)?) } else { None };
+            if let Some(first) = checksum.as_ref().and_then(|c| written.get(c)) {
+                header.set_entry_type(EntryType::Link);
+                header.set_size(0);
+                tar.append_link(&mut header, name, first)?;
+                deduplicated += metadata.len();
+                continue;
+            }
+            header.set_entry_type(EntryType::Regular);
+            header.set_size(metadata.len());
+            tar.append_data(&mut header, name, file)?;
+            if let Some(checksum) = checksum {
+                written.insert(checksum, name.to_path_buf());
+            }
+        }
+    }
+    Ok(deduplicated)
+}
+
+/// The size of the payload under each compression, for the build report, so
+/// `--compression` can be picked by the numbers.
+fn payload_sizes(temp_dir: &Path, level: Option<u32>) -> Result<BTreeMap<String, u64>, Box<dyn std::error::Error>> {
+    struct ByteCount(u64);
+    impl Write for ByteCount {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0 += buf.len() as u64;
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut sizes = BTreeMap::new();
+    for compression in [PayloadCompression::Gzip, PayloadCompression::Zstd, PayloadCompression::Brotli, PayloadCompression::None] {
+        let mut tar = Builder::new(compression.encoder(ByteCount(0), level)?);
+        append_payload(&mut tar, temp_dir)?;
+        sizes.insert(compression.to_string(), tar.into_inner()?.finish()?.0);
+    }
+    Ok(sizes)
+}
+
+/// Copies the icon into `rustpack/icons/` and returns the icon name desktop
+/// entries should reference (the file name without its extension).
+fn bundle_icon(project_path: &str, rustpack_dir: &Path, icon: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let src_path = Path::new(project_path).join(icon);
+    if !src_path.is_file() {
+        return Err(format!("Icon not found: {}", icon).into());
+    }
+    let icons_dir = rustpack_dir.join("icons");
+    fs::create_dir_all(&icons_dir)?;
+    let file_name = src_path.file_name().ok_or("Invalid icon path")?;
+    fs::copy(&src_path, icons_dir.join(file_name))?;
+
+    Ok(src_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| file_name.to_string_lossy().to_string()))
+}
+
+/// The app's desktop entry: the configured `desktop_file` as is, or else
+/// one generated for `name` that runs `exec`, commented with `comment` or
+/// the `description`.
+fn desktop_entry(
+    project_path: &str,
+    build_config: &BuildConfig,
+    name: &str,
+    exec: &str,
+    description: Option<&str>,
+    icon: Option<&str>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if let Some(file) = &build_config.desktop_file {
+        let path = Path::new(project_path).join(file);
+        let entry = fs::read_to_string(&path).map_err(|e| format!("Could not read the desktop file {}: {}", path.display(), e))?;
+        if !entry.lines().any(|line| line.trim() == "[Desktop Entry]") {
+            return Err(format!("{} has no [Desktop Entry] group", path.display()).into());
+        }
+        return Ok(entry);
+    }
+    let comment = build_config.comment.as_deref().or(description);
+    Ok(generate_desktop_entry(name, exec, comment, icon, &build_config.categories, &build_config.desktop))
+}
+
+/// A desktop entry for `name`. `overrides` replace generated keys, and keys
+/// that aren't generated are added at the end.
+fn generate_desktop_entry(
+    name: &str,
+    exec: &str,
+    comment: Option<&str>,
+    icon: Option<&str>,
+    categories: &[String],
+    overrides: &BTreeMap<String, String>,
+) -> String {
+    let mut fields = vec![("Type", "Application".to_string()), ("Name", name.to_string())];
+    if let Some(comment) = comment {
+        fields.push(("Comment", comment.replace('\n', " ")));
+    }
+    fields.push(("Exec", exec.to_string()));
+    if let Some(icon) = icon {
+        fields.push(("Icon", icon.to_string()));
+    }
+    fields.push(("Terminal", "false".to_string()));
+    let categories: Vec<&str> = categories.iter().map(|c| c.as_str()).filter(|c| !c.is_empty()).collect();
+    if !categories.is_empty() {
+        fields.push(("Categories", format!("{};", categories.join(";"))));
+    }
+
+    let mut entry = String::from("[Desktop Entry]\n");
+    for (key, value) in &fields {
+        let value = overrides.get(*key).unwrap_or(value);
+        entry.push_str(&format!("{}={}\n", key, value));
+    }
+    for (key, value) in overrides {
+        if !fields.iter().any(|(k, _)| k == key) {
+            entry.push_str(&format!("{}={}\n", key, value));
+        }
+    }
+    entry
+}
+
+fn copy_assets(
+    project_path: &str,
+    rustpack_dir: &Path,
+    assets: &[String],
+    verbose: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if assets.is_empty() {
+        return Ok(());
+    }
+    
+    let assets_dir = rustpack_dir.join("assets");
+    fs::create_dir_all(&assets_dir)?;
+    
+    log::info("assets", &format!("Copying {} asset entries", assets.len()));
+    if verbose {
+        println!("{} assets", "Copying".blue());
+    }
+    
+    for (src_path, dest) in resolve_assets(project_path, assets)? {
+        let dest_path = assets_dir.join(&dest);
+        if src_path.is_dir() {
+            fs::create_dir_all(&dest_path)?;
+            continue;
+        }
+        if let Some(parent) = dest_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if verbose {
+            println!("  Copying asset: {}", dest.display());
+        }
+        fs::copy(&src_path, &dest_path)?;
+        copy_permissions(&src_path, &dest_path)?;
+    }
+    
+    Ok(())
+}
+
+/// Gives `dest` the permission bits of `src`, so bundled scripts stay
+/// executable even where `fs::copy` doesn't carry the mode over.
+#[cfg(unix)]
+fn copy_permissions(src: &Path, dest: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = fs::metadata(src)?.permissions().mode();
+    fs::set_permissions(dest, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn copy_permissions(_src: &Path, _dest: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Expands asset entries into `(source, destination under assets/)` pairs.
+///
+/// A file is copied to `assets/<file name>` and a directory recursively to
+/// `assets/<dir>`. Entries containing `*`, `?`, `[` or `{` are glob patterns
+/// (`**` crosses directories) matched against paths relative to the project,
+/// and matching files keep that relative path. Entries starting with `!`
+/// exclude matching files, or whole directories, from everything else.
+fn resolve_assets(project_path: &str, assets: &[String]) -> Result<Vec<(PathBuf, PathBuf)>, Box<dyn std::error::Error>> {
+    let project_dir = Path::new(project_path);
+    let mut excludes = GlobSetBuilder::new();
+    for pattern in assets.iter().filter_map(|a| a.strip_prefix('!')) {
+        excludes.add(asset_glob(pattern)?);
+    }
+    let excludes = excludes.build()?;
+    let excluded = |rel_path: &Path| {
+        rel_path
+            .ancestors()
+            .any(|p| !p.as_os_str().is_empty() && excludes.is_match(p))
+    };
+
+    let mut resolved = Vec::new();
+    for asset in assets.iter().filter(|a| !a.starts_with('!')) {
+        if asset.contains(['*', '?', '[', '{']) {
+            let glob = asset_glob(asset)?.compile_matcher();
+            // Only walk below the part of the pattern without wildcards.
+            let base: PathBuf = Path::new(asset.trim_start_matches("./"))
+                .components()
+                .take_while(|c| !c.as_os_str().to_string_lossy().contains(['*', '?', '[', '{']))
+                .collect();
+            let mut matched = false;
+            let walker = WalkDir::new(project_dir.join(&base))
+                .sort_by_file_name()
+                .into_iter()
+                .filter_entry(|e| !matches!(e.path().strip_prefix(project_dir), Ok(p) if p == Path::new("target") || p == Path::new(".git")));
+            for entry in walker.filter_map(|e| e.ok()) {
+                if !entry.file_type().is_file() {
+                    continue;
+                }
+                let rel_path = entry.path().strip_prefix(project_dir)?;
+                if glob.is_match(rel_path) {
+                    matched = true;
+                    if !excluded(rel_path) {
+                        resolved.push((entry.path().to_path_buf(), rel_path.to_path_buf()));
+                    }
+                }
+            }
+            if !matched {
+                return Err(RustPackError::AssetMissing(format!("{} (the pattern matched no files)", asset)).into());
+            }
+            continue;
+        }
+
+        let src_path = project_dir.join(asset);
+        if !src_path.exists() {
+            return Err(RustPackError::AssetMissing(asset.to_string()).into());
+        }
+        if src_path.is_dir() {
+            for entry in WalkDir::new(&src_path).sort_by_file_name().into_iter().filter_map(|e| e.ok()) {
+                let rel_path = Path::new(asset).join(entry.path().strip_prefix(&src_path)?);
+                if !excluded(&rel_path) {
+                    resolved.push((entry.path().to_path_buf(), rel_path));
+                }
+            }
+        } else if !excluded(Path::new(asset)) {
+            let file_name = src_path.file_name().ok_or_else(|| format!("Invalid asset path: {}", asset))?;
+            resolved.push((src_path.clone(), PathBuf::from(file_name)));
+        }
+    }
+    Ok(resolved)
+}
+
+fn asset_glob(pattern: &str) -> Result<Glob, Box<dyn std::error::Error>> {
+    Ok(GlobBuilder::new(pattern.trim_start_matches("./"))
+        .literal_separator(true)
+        .build()?)
+}
+
+fn create_zip_package(temp_dir: &Path, output_name: &str, level: Option<u32>) -> Result<(), Box<dyn std::error::Error>> {
+    let file = File::create(output_name)?;
+    let mut zip = zip::ZipWriter::new(file);
+    let options = FileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .compression_level(level.map(|level| level as i32))
+        .unix_permissions(0o755)
+        .last_modified_time(zip_timestamp(source_date_epoch().unwrap_or(0)));
+
+    for entry in WalkDir::new(temp_dir).sort_by_file_name().into_iter().filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path != temp_dir {
+            let name = path.strip_prefix(temp_dir)?
+                .to_string_lossy()
+                .to_string();
+            
+            if entry.file_type().is_dir() {
+                zip.add_directory(name, options)?;
+            } else {
+                // Same modes as the self-extracting payload.
+                let mode = if is_executable(&entry.metadata()?) { 0o755 } else { 0o644 };
+                zip.start_file(name, options.unix_permissions(mode))?;
+                io::copy(&mut File::open(path)?, &mut zip)?;
+            }
+        }
+    }
+
+    zip.finish()?;
+    Ok(())
+}
+
+pub fn get_rust_version() -> String {
+    let output = ProcessCommand::new("rustc")
+        .args(["--version"])
+        .output();
+    
+    match output {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        Err(_) => "unknown".to_string(),
+    }
+}
+
+pub fn check_tool_version(requirement: &ToolRequirement) -> ToolStatus {
+    let output = match ProcessCommand::new(requirement.name).arg("--version").output() {
+        Ok(output) => output,
+        Err(_) => return ToolStatus::Missing,
+    };
+    let output = String::from_utf8_lossy(&output.stdout).lines().next().unwrap_or("").trim().to_string();
+    let min_version = semver::Version::parse(requirement.min_version).expect("valid minimum version");
+
+    match parse_tool_version(&output) {
+        Some(version) if version < min_version => ToolStatus::TooOld(version),
+        Some(version) => ToolStatus::Ok(version),
+        None => ToolStatus::Unknown(output),
+    }
+}
+
+/// Finds the first dotted version number in a `--version` line, e.g.
+/// `GNU strip (GNU Binutils) 2.40` or `*** Zstandard CLI (64-bit) v1.5.7 ***`.
+fn parse_tool_version(output: &str) -> Option<semver::Version> {
+    output.split_whitespace().find_map(|word| {
+        let word = word.trim_start_matches(|c: char| !c.is_ascii_digit());
+        let version: String = word.chars().take_while(|c| c.is_ascii_digit() || *c == '.').collect();
+        let mut parts: Vec<&str> = version.split('.').filter(|p| !p.is_empty()).collect();
+        if parts.len() < 2 {
+            return None;
+        }
+        parts.resize(3, "0");
+        semver::Version::parse(&parts[..3].join(".")).ok()
+    })
+}
+
+pub fn get_cargo_version() -> String {
+    let output = ProcessCommand::new("cargo")
+        .args(["--version"])
+        .output();
+
+    match output {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        Err(_) => "unknown".to_string(),
+    }
+}
+
+/// Dependency versions for `metadata.dependency_*`: every crate in the
+/// resolved graph from Cargo.lock, or the version requirements of the direct
+/// dependencies in Cargo.toml when there is no lockfile.
+fn analyze_dependencies(project_path: &str) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+    if let Some(lockfile) = find_lockfile(project_path)? {
+        return locked_dependencies(&lockfile, &parse_manifest(project_path)?.name);
+    }
+
+    let cargo_toml = Path::new(project_path).join("Cargo.toml");
+    let cargo_content = fs::read_to_string(cargo_toml)?;
+    let mut dependencies = HashMap::new();
+    let mut in_deps_section = false;
+    for line in cargo_content.lines() {
+        let trimmed = line.trim();
+        
+        if trimmed == "[dependencies]" {
+            in_deps_section = true;
+            continue;
+        } else if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            in_deps_section = false;
+            continue;
+        }
+        
+        if in_deps_section
+            && !trimmed.is_empty()
+            && !trimmed.starts_with('#')
+            && let Some(eq_pos) = trimmed.find('=')
+        {
+            let name = trimmed[..eq_pos].trim().to_string();
+            let version_part = trimmed[eq_pos + 1..].trim();
+            if version_part.starts_with('"') && version_part.ends_with('"') {
+                let version = version_part.trim_matches('"').to_string();
+                dependencies.insert(name, version);
+            } else if version_part.starts_with('{')
+                && let Some(ver_start) = trimmed.find("version")
+                && let Some(eq_start) = trimmed[ver_start..].find('=')
+            {
+                let ver_part = &trimmed[ver_start + eq_start + 1..];
+                if let Some(quote_start) = ver_part.find('"')
+                    && let Some(quote_end) = ver_part[quote_start + 1..].find('"')
+                {
+                    let version = ver_part[quote_start + 1..quote_start + 1 + quote_end].to_string();
+                    dependencies.insert(name, version);
+                }
+            }
+        }
+    }
+    
+    Ok(dependencies)
+}
+
+/// The Cargo.lock for `project_path`. Workspace members share the lockfile
+/// at the workspace root.
+fn find_lockfile(project_path: &str) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+    let project_dir = fs::canonicalize(project_path)?;
+    Ok(project_dir.ancestors().map(|dir| dir.join("Cargo.lock")).find(|lock| lock.is_file()))
+}
+
+/// `cargo metadata` for every package in the resolved dependency graph.
+fn resolved_packages(project_path: &str) -> Result<Vec<serde_json::Value>, Box<dyn std::error::Error>> {
+    let output = ProcessCommand::new("cargo")
+        .current_dir(project_path)
+        .args(["metadata", "--format-version", "1"])
+        .output()?;
+    if !output.status.success() {
+        return Err(format!("cargo metadata failed:\n{}", String::from_utf8_lossy(&output.stderr).trim_end()).into());
+    }
+    let mut metadata: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    Ok(metadata["packages"].as_array_mut().map(std::mem::take).unwrap_or_default())
+}
+
+/// The CycloneDX SBOM for the project at `project_path`, listing the
+/// packages in its Cargo.lock with their licenses.
+fn generate_sbom(
+    project_path: &str,
+    name: &str,
+    version: &str,
+    license: Option<&str>,
+    timestamp: Option<&str>,
+) -> Result<sbom::Bom, Box<dyn std::error::Error>> {
+    let lockfile = find_lockfile(project_path)?.ok_or("no Cargo.lock found")?;
+    let locked = sbom::locked_packages(&lockfile)?;
+    // Licenses only come from the manifests; without them the SBOM still
+    // lists every package.
+    let licenses = match resolved_packages(project_path) {
+        Ok(packages) => packages
+            .iter()
+            .filter_map(|p| {
+                let key = (p["name"].as_str()?.to_string(), p["version"].as_str()?.to_string());
+                Some((key, p["license"].as_str()?.to_string()))
+            })
+            .collect(),
+        Err(e) => {
+            log::warning("sbom", &format!("No licenses in the SBOM: {}", e));
+            BTreeMap::new()
+        }
+    };
+    Ok(sbom::cyclonedx(name, version, license, &locked, &licenses, timestamp.map(str::to_string)))
+}
+
+/// Exact versions of every package in a Cargo.lock except `root` itself.
+/// Crates locked at several versions list them all, comma-separated.
+fn locked_dependencies(lockfile: &Path, root: &str) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+    let lock: toml::Value = toml::from_str(&fs::read_to_string(lockfile)?)?;
+    let mut versions: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for package in lock.get("package").and_then(|p| p.as_array()).into_iter().flatten() {
+        let (Some(name), Some(version)) = (
+            package.get("name").and_then(|n| n.as_str()),
+            package.get("version").and_then(|v| v.as_str()),
+        ) else {
+            continue;
+        };
+        if name == root {
+            continue;
+        }
+        versions.entry(name.to_string()).or_default().push(version.to_string());
+    }
+
+    Ok(versions
+        .into_iter()
+        .map(|(name, mut versions)| {
+            versions.sort();
+            versions.dedup();
+            (name, versions.join(","))
+        })
+        .collect())
+}
+
+fn detect_and_embed_license(project_path: &str, rustpack_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let license_files = [
+        "LICENSE", "LICENSE.txt", "LICENSE.md", 
+        "LICENCE", "LICENCE.txt", "LICENCE.md"
+    ];
+    
+    for license_file in license_files.iter() {
+        let license_path = Path::new(project_path).join(license_file);
+        if license_path.exists() {
+            let dest_path = rustpack_dir.join("LICENSE");
+            fs::copy(&license_path, &dest_path)?;
+            return Ok(());
+        }
+    }
+
+    let placeholder = "No license file detected in the original project.";
+    fs::write(rustpack_dir.join("LICENSE.note"), placeholder)?;
+    
+    Ok(())
+}
+
+/// Copies the license texts of every third-party package in the resolved
+/// dependency graph to `licenses/<crate>-<version>.txt` and lists them all
+/// in `THIRD-PARTY-NOTICES.md`. Packages that ship no license text are
+/// listed with their SPDX expression only. Returns how many were listed.
+fn collect_third_party_licenses(project_path: &str, rustpack_dir: &Path, project_name: &str) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut packages = resolved_packages(project_path)?;
+    // Path dependencies and workspace members are part of the project.
+    packages.retain(|p| !p["source"].is_null());
+    packages.sort_by(|a, b| (a["name"].as_str(), a["version"].as_str()).cmp(&(b["name"].as_str(), b["version"].as_str())));
+    packages.dedup_by(|a, b| a["id"] == b["id"]);
+
+    let licenses_dir = rustpack_dir.join("licenses");
+    fs::create_dir_all(&licenses_dir)?;
+    let mut notices = format!(
+        "# Third-Party Notices\n\n{} includes the following third-party packages.\n\n| Package | Version | License | Text |\n| --- | --- | --- | --- |\n",
+        project_name
+    );
+    for package in &packages {
+        let (Some(name), Some(version)) = (package["name"].as_str(), package["version"].as_str()) else {
+            continue;
+        };
+        let license = package["license"].as_str().unwrap_or("unknown");
+        let crate_dir = package["manifest_path"].as_str().and_then(|m| Path::new(m).parent()).unwrap_or(Path::new(""));
+
+        let mut files: Vec<PathBuf> = fs::read_dir(crate_dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|path| {
+                let file_name = path.file_name().map(|n| n.to_string_lossy().to_uppercase()).unwrap_or_default();
+                path.is_file() && ["LICENSE", "LICENCE", "COPYING", "UNLICENSE", "NOTICE"].iter().any(|p| file_name.starts_with(p))
+            })
+            .collect();
+        if let Some(license_file) = package["license_file"].as_str() {
+            files.push(crate_dir.join(license_file));
+        }
+        files.sort();
+        files.dedup();
+
+        let mut text = String::new();
+        for file in &files {
+            let Ok(contents) = fs::read_to_string(file) else {
+                continue;
+            };
+            if files.len() > 1 {
+                let file_name = file.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+                text.push_str(&format!("----- {} -----\n\n", file_name));
+            }
+            text.push_str(contents.trim_end());
+            text.push_str("\n\n");
+        }
+        let entry = if text.is_empty() {
+            "not included".to_string()
+        } else {
+            let file_name = format!("{}-{}.txt", name, version);
+            fs::write(licenses_dir.join(&file_name), text.trim_end().to_string() + "\n")?;
+            format!("[licenses/{0}](licenses/{0})", file_name)
+        };
+        notices.push_str(&format!("| {} | {} | {} | {} |\n", name, version, license.replace('|', "\\|"), entry));
+    }
+    fs::write(rustpack_dir.join("THIRD-PARTY-NOTICES.md"), notices)?;
+    Ok(packages.len())
+}
+
+/// The variable cargo reads compiler flags from, with `configured` (split
+/// on whitespace, like `RUSTFLAGS`) and then `args` (taken whole) appended to
+/// whatever the environment already sets. Flags given later win, so the
+/// configured ones override the environment's. Cargo ignores `RUSTFLAGS` when
+/// `CARGO_ENCODED_RUSTFLAGS` is set, so that one is extended instead, and is
+/// also used when an argument holds a space, which `RUSTFLAGS` can't express.
+fn rustflags_env(configured: &[String], args: &[String]) -> Option<(&'static str, String)> {
+    let extra: Vec<&str> = configured
+        .iter()
+        .flat_map(|f| f.split_whitespace())
+        .chain(args.iter().map(String::as_str))
+        .collect();
+    if extra.is_empty() {
+        return None;
+    }
+    let encoded = env::var("CARGO_ENCODED_RUSTFLAGS").ok().or_else(|| {
+        args.iter()
+            .any(|a| a.contains(char::is_whitespace))
+            .then(|| env::var("RUSTFLAGS").unwrap_or_default().split_whitespace().collect::<Vec<_>>().join("\x1f"))
+    });
+    if let Some(encoded) = encoded {
+        let flags: Vec<&str> = encoded.split('\x1f').filter(|f| !f.is_empty()).chain(extra).collect();
+        return Some(("CARGO_ENCODED_RUSTFLAGS", flags.join("\x1f")));
+    }
+    let inherited = env::var("RUSTFLAGS").unwrap_or_default();
+    let flags: Vec<&str> = inherited.split_whitespace().chain(extra).collect();
+    Some(("RUSTFLAGS", flags.join(" ")))
+}
+
+/// Whether `targets` include both an Intel and an Apple Silicon macOS target.
+fn universal_macos_targets(targets: &[String]) -> bool {
+    let has = |arch: &str| targets.iter().any(|t| t.starts_with(arch) && t.contains("apple-darwin"));
+    has("x86_64") && has("aarch64")
+}
+
+/// Replaces the Intel and Apple Silicon macOS targets in `target_infos`
+/// with a single `universal` one whose binaries, under
+/// `bin/universal-apple-darwin`, are merged from both with `lipo`.
+fn merge_universal_macos(
+    rustpack_dir: &Path,
+    target_infos: &mut Vec<TargetInfo>,
+    signing_key: Option<&SigningKey>,
+    hmac_key: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let position = |arch: &str| target_infos.iter().position(|t| t.platform == "macos" && t.arch == arch);
+    let (Some(intel), Some(arm)) = (position("x86_64"), position("aarch64")) else {
+        return Err("A universal macOS binary needs both x86_64-apple-darwin and aarch64-apple-darwin".into());
+    };
+
+    let bin_dir = Path::new("bin").join("universal-apple-darwin");
+    fs::create_dir_all(rustpack_dir.join(&bin_dir))?;
+    let mut binaries = Vec::new();
+    for binary in target_infos[intel].all_binaries() {
+        let name = Path::new(binary).file_name().ok_or("Invalid binary path")?;
+        let sources: Vec<PathBuf> = [intel, arm]
+            .iter()
+            .map(|&i| rustpack_dir.join(Path::new(&target_infos[i].binary_path).with_file_name(name)))
+            .collect();
+        let dest = bin_dir.join(name);
+        macos::lipo(&sources, &rustpack_dir.join(&dest))?;
+        copy_permissions(&sources[0], &rustpack_dir.join(&dest))?;
+        binaries.push(dest.to_string_lossy().to_string());
+    }
+    for i in [intel, arm] {
+        if let Some(dir) = Path::new(&target_infos[i].binary_path).parent() {
+            fs::remove_dir_all(rustpack_dir.join(dir))?;
+        }
+    }
+
+    let binary_path = binaries[0].clone();
+    let signed = target_infos[intel].signature.is_some() || target_infos[arm].signature.is_some();
+    let universal = TargetInfo {
+        platform: "macos".to_string(),
+        arch: "universal".to_string(),
+        checksum: calculate_checksum(&rustpack_dir.join(&binary_path))?,
+        binary_checksums: binary_checksums(rustpack_dir, &binaries)?,
+        signature: if signed { Some(sign_binary(&rustpack_dir.join(&binary_path), signing_key, hmac_key)?) } else { None },
+        binary_path,
+        binaries,
+        features: target_infos[intel].features.clone(),
+        optimizations: target_infos[intel].optimizations.clone(),
+        // Runs wherever the Intel binary does, which reaches further back.
+        compatibility: target_infos[intel].compatibility.clone(),
+    };
+    target_infos[intel.min(arm)] = universal;
+    target_infos.remove(intel.max(arm));
+    Ok(())
+}
+
+/// Sizes of the binary's sections, plus the whole file under `total`. Reads
+/// ELF, Mach-O and PE files directly, so it works for any target on any host.
+fn analyze_binary_size(binary_path: &Path) -> Result<HashMap<String, usize>, Box<dyn std::error::Error>> {
+    section_sizes(&fs::read(binary_path)?)
+}
+
+/// `analyze_binary_size` for a binary already in memory.
+fn section_sizes(data: &[u8]) -> Result<HashMap<String, usize>, Box<dyn std::error::Error>> {
+    let mut size_info = HashMap::new();
+    size_info.insert("total".to_string(), data.len());
+
+    let file = object::File::parse(data)?;
+    for section in file.sections() {
+        let Ok(name) = section.name() else {
+            continue;
+        };
+        if name.is_empty() || section.size() == 0 {
+            continue;
+        }
+        // Mach-O repeats section names across segments, so sum them.
+        *size_info.entry(name.to_string()).or_insert(0) += section.size() as usize;
+    }
+
+    Ok(size_info)
+}
+
+fn setup_auto_update(update_url: &str, package_info: &mut PackageInfo) {
+    package_info.metadata.insert("update_url".to_string(), update_url.to_string());
+    package_info.features.push("auto_update".to_string());
+}
+
+/// A size such as `10M` from the environment variable `name`, if it is set.
+fn env_size(name: &str) -> Result<Option<u64>, RustPackError> {
+    match env::var(name) {
+        Ok(value) => match parse_size(&value) {
+            Ok(size) => Ok(Some(size)),
+            Err(_) => Err(RustPackError::Other(format!("{} must be a size such as 10M, not {:?}", name, value))),
+        },
+        Err(_) => Ok(None),
+    }
+}
+
+/// Build settings from the `RUSTPACK_*` environment variables, with the
+/// defaults for anything unset. Library users can start from this and
+/// override individual fields. Fails on a value that can't be parsed.
+pub fn load_env_config() -> Result<BuildConfig, RustPackError> {
+    let strip = env::var("RUSTPACK_STRIP").map(|v| v == "1" || v == "true").unwrap_or(false);
+    let strip_level = env::var("RUSTPACK_STRIP_LEVEL").unwrap_or_else(|_| "all".to_string());
+    let strip_tool = env::var("RUSTPACK_STRIP_TOOL").ok();
+    let compress = env::var("RUSTPACK_COMPRESS").map(|v| v == "1" || v == "true").unwrap_or(false);
+    let strip_rpath = env::var("RUSTPACK_STRIP_RPATH").map(|v| v == "1" || v == "true").unwrap_or(false);
+    let bundle_libs = env::var("RUSTPACK_BUNDLE_LIBS").map(|v| v == "1" || v == "true").unwrap_or(false);
+    let lto = env::var("RUSTPACK_LTO").ok();
+    let debug_symbols = env::var("RUSTPACK_DEBUG_SYMBOLS").map(|v| v == "1" || v == "true").unwrap_or(true);
+    let no_default_features = env::var("RUSTPACK_NO_DEFAULT_FEATURES").map(|v| v == "1" || v == "true").unwrap_or(false);
+    let prefetch_deps = env::var("RUSTPACK_PREFETCH_DEPS").map(|v| v == "1" || v == "true").unwrap_or(false);
+    let profile = env::var("RUSTPACK_PROFILE").unwrap_or_else(|_| "release".to_string());
+    let target_dir = env::var("RUSTPACK_TARGET_DIR").ok();
+    let sign = env::var("RUSTPACK_SIGN").unwrap_or_else(|_| "".to_string());
+    let sign_key = env::var("RUSTPACK_SIGN_KEY").ok();
+    let output_manifest = env::var("RUSTPACK_OUTPUT_MANIFEST").ok();
+    let env_file = env::var("RUSTPACK_ENV_FILE").ok();
+    let report = env::var("RUSTPACK_REPORT").ok();
+    let sbom = env::var("RUSTPACK_SBOM").ok();
+    let licenses = env::var("RUSTPACK_LICENSES").map(|v| v == "1" || v == "true").unwrap_or(false);
+    let channel = env::var("RUSTPACK_CHANNEL").unwrap_or_else(|_| "stable".to_string());
+    let channel_manifest = env::var("RUSTPACK_CHANNEL_MANIFEST").ok();
+    let max_package_size = env_size("RUSTPACK_MAX_PACKAGE_SIZE")?;
+    let bloat = env::var("RUSTPACK_BLOAT").ok().and_then(|n| n.parse().ok());
+    let max_size = env_size("RUSTPACK_MAX_SIZE")?;
+    let update_url = env::var("RUSTPACK_UPDATE_URL").ok();
+    let release_dir = env::var("RUSTPACK_RELEASE_DIR").ok();
+    let min_update_version = env::var("RUSTPACK_MIN_UPDATE_VERSION").ok();
+    let delta_updates = env::var("RUSTPACK_DELTA_UPDATES").map(|v| v == "1" || v == "true").unwrap_or(false);
+    let no_timestamp = env::var("RUSTPACK_NO_TIMESTAMP").map(|v| v == "1" || v == "true").unwrap_or(false);
+    let upload = env::var("RUSTPACK_UPLOAD").ok();
+    let publish_github = env::var("RUSTPACK_PUBLISH_GITHUB").ok();
+    let desktop_entry = env::var("RUSTPACK_DESKTOP_ENTRY").map(|v| v == "1" || v == "true").unwrap_or(false);
+    let icon = env::var("RUSTPACK_ICON").ok();
+    let comment = env::var("RUSTPACK_COMMENT").ok();
+    let bootstrap = env::var("RUSTPACK_BOOTSTRAP").unwrap_or_else(|_| "auto".to_string());
+    let compression = env::var("RUSTPACK_COMPRESSION").unwrap_or_else(|_| "gzip".to_string());
+    let compression_level = match env::var("RUSTPACK_COMPRESSION_LEVEL") {
+        Ok(level) => match level.parse() {
+            Ok(level) if level <= 9 => Some(level),
+            _ => {
+                return Err(RustPackError::Other(format!(
+                    "RUSTPACK_COMPRESSION_LEVEL must be between 0 and 9, not {:?}",
+                    level
+                )));
+            }
+        },
+        Err(_) => None,
+    };
+    let builder = env::var("RUSTPACK_BUILDER").unwrap_or_else(|_| "cargo".to_string());
+    let bundle_id = env::var("RUSTPACK_BUNDLE_ID").ok();
+    let windows_resources = env::var("RUSTPACK_WINDOWS_RESOURCES").map(|v| v == "1" || v == "true").unwrap_or(true);
+    let universal_macos = env::var("RUSTPACK_UNIVERSAL_MACOS").map(|v| v == "1" || v == "true").unwrap_or(false);
+    let package = env::var("RUSTPACK_PACKAGE").ok();
+    let bins = env::var("RUSTPACK_BIN")
+        .map(|b| b.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+    let all_bins = env::var("RUSTPACK_BINS").map(|v| v == "1" || v == "true").unwrap_or(false);
+    let skip_missing_tools = env::var("RUSTPACK_SKIP_MISSING_TOOLS").map(|v| v == "1" || v == "true").unwrap_or(false);
+    let jobs = env::var("RUSTPACK_JOBS")
+        .ok()
+        .and_then(|j| j.parse().ok())
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+    let windows_stub = env::var("RUSTPACK_WINDOWS_STUB").ok();
+    let categories = env::var("RUSTPACK_CATEGORIES")
+        .map(|c| c.split(',').map(|s| s.trim().to_string()).collect())
+        .unwrap_or_else(|_| vec!["Utility".to_string()]);
+    let features = env::var("RUSTPACK_FEATURES")
+        .map(|f| f.split(',').map(|s| s.trim().to_string()).collect())
+        .unwrap_or_else(|_| Vec::new());
+        
+    let assets = env::var("RUSTPACK_ASSETS")
+        .map(|a| a.split(',').map(|s| s.trim().to_string()).collect())
+        .unwrap_or_else(|_| Vec::new());
+
+    let requires = env::var("RUSTPACK_REQUIRES")
+        .map(|r| r.split(',').map(|s| s.trim().to_string()).collect())
+        .unwrap_or_else(|_| Vec::new());
+        
+    Ok(BuildConfig {
+        strip,
+        strip_level,
+        strip_tool,
+        compress,
+        strip_rpath,
+        bundle_libs,
+        lto,
+        debug_symbols,
+        prefetch_deps,
+        profile,
+        features,
+        no_default_features,
+        cargo_args: Vec::new(),
+        target_dir,
+        rustflags: Vec::new(),
+        assets,
+        requires,
+        sign,
+        sign_key,
+        output_manifest,
+        env_file,
+        report,
+        sbom,
+        licenses,
+        channel,
+        channel_manifest,
+        max_package_size,
+        bloat,
+        max_size,
+        update_url,
+        release_dir,
+        min_update_version,
+        delta_updates,
+        no_timestamp,
+        upload,
+        publish_github,
+        desktop_entry,
+        icon,
+        categories,
+        comment,
+        desktop_file: None,
+        bootstrap,
+        compression,
+        compression_level,
+        builder,
+        jobs,
+        windows_stub,
+        target_overrides: HashMap::new(),
+        multicall: BTreeMap::new(),
+        pre_build: Vec::new(),
+        post_build: Vec::new(),
+        desktop: BTreeMap::new(),
+        metadata: BTreeMap::new(),
+        universal_macos,
+        windows_resources,
+        bundle_id,
+        package,
+        bins,
+        all_bins,
+        skip_missing_tools,
+    })
+}
+
+pub fn create_binary_patch(old_path: &Path, new_path: &Path, patch_path: &Path) -> Result<(), RustPackError> {
+    let old_data = fs::read(old_path)?;
+    let new_data = fs::read(new_path)?;
+    fs::write(patch_path, diff_binaries(&old_data, &new_data))?;
+    Ok(())
+}
+
+fn diff_binaries(old_data: &[u8], new_data: &[u8]) -> String {
+    let mut patch_entries = Vec::new();
+    let mut offset = 0;
+    
+    while offset < new_data.len() {
+        let mut diff_start = offset;
+        while diff_start < new_data.len() {
+            if diff_start >= old_data.len() || new_data[diff_start] != old_data[diff_start] {
+                break;
+            }
+            diff_start += 1;
+        }
+        
+        if diff_start >= new_data.len() {
+            break;
+        }
+        let mut diff_end = diff_start + 1;
+        while diff_end < new_data.len() {
+            if diff_end < old_data.len() && new_data[diff_end] == old_data[diff_end] {
+                let mut matches = 1;
+                while matches < 4 && diff_end + matches < new_data.len() && 
+                      diff_end + matches < old_data.len() && 
+                      new_data[diff_end + matches] == old_data[diff_end + matches] {
+                    matches += 1;
+                }
+                
+                if matches >= 4 {
+                    break;
+                }
+            }
+            diff_end += 1;
+        }
+
+        let diff_data = &new_data[diff_start..diff_end];
+        patch_entries.push((diff_start, diff_end - diff_start, diff_data.to_vec()));
+        
+        offset = diff_end;
+    }
+
+    let mut patch = format!(
+        "{} {} {:x} {:x}\n",
+        PATCH_HEADER,
+        new_data.len(),
+        Sha256::digest(old_data),
+        Sha256::digest(new_data)
+    );
+    for (offset, length, data) in patch_entries {
+        patch.push_str(&format!("{}:{}:{}\n", offset, length, BASE64.encode(data)));
+    }
+    patch
+}
+
+pub fn apply_binary_patch(original_path: &Path, patch_path: &Path, output_path: &Path) -> Result<(), RustPackError> {
+    let original_data = fs::read(original_path)?;
+    let patch_content = fs::read_to_string(patch_path)?;
+    let output_data = patch_bytes(&original_data, &patch_content)?;
+
+    let mut output_file = File::create(output_path)?;
+    output_file.write_all(&output_data)?;
+    
+    Ok(())
+}
+
+fn patch_bytes(original_data: &[u8], patch_content: &str) -> Result<Vec<u8>, RustPackError> {
+    let mut output_data = original_data.to_vec();
+    let mut final_length = None;
+    let mut expected_output = None;
+    
+    for line in patch_content.lines() {
+        if let Some(header) = line.strip_prefix(PATCH_HEADER) {
+            let fields: Vec<&str> = header.split_whitespace().collect();
+            final_length = Some(
+                fields
+                    .first()
+                    .and_then(|length| length.parse::<usize>().ok())
+                    .ok_or_else(|| RustPackError::PatchFormat("Malformed patch header".to_string()))?,
+            );
+            if let [_, from, to] = fields[..] {
+                let actual = format!("{:x}", Sha256::digest(original_data));
+                if actual != from {
+                    return Err(RustPackError::PatchFormat(format!("Patch expects input with checksum {} but got {}", from, actual)));
+                }
+                expected_output = Some(to.to_string());
+            }
+            continue;
+        }
+        let parts: Vec<&str> = line.splitn(3, ':').collect();
+        if parts.len() != 3 {
+            continue;
+        }
+        let malformed = || RustPackError::PatchFormat(format!("Malformed patch line: {}", line));
+        let offset = parts[0].parse::<usize>().map_err(|_| malformed())?;
+        let length = parts[1].parse::<usize>().map_err(|_| malformed())?;
+        let data = BASE64.decode(parts[2]).map_err(|_| malformed())?;
+        if offset + length > output_data.len() {
+            output_data.resize(offset + length, 0);
+        }
+        
+        for (i, byte) in data.iter().enumerate() {
+            if offset + i < output_data.len() {
+                output_data[offset + i] = *byte;
+            }
+        }
+    }
+
+    // Patches without a header predate length tracking and can only grow the file.
+    if let Some(final_length) = final_length {
+        output_data.resize(final_length, 0);
+    }
+
+    if let Some(expected) = expected_output {
+        let actual = format!("{:x}", Sha256::digest(&output_data));
+        if actual != expected {
+            return Err(RustPackError::PatchFormat(format!("Patched output has checksum {} but the patch expects {}", actual, expected)));
+        }
+    }
+
+    Ok(output_data)
+}
+
+/// Writes one patch per consecutive pair of `versions` into `patch_dir`,
+/// named `<old>-<new>.patch` after the version file names.
+pub fn create_patch_chain(versions: &[String], patch_dir: &Path) -> Result<Vec<PathBuf>, RustPackError> {
+    if versions.len() < 2 {
+        return Err("A patch chain needs at least two versions".into());
+    }
+    fs::create_dir_all(patch_dir)?;
+
+    let mut patches = Vec::new();
+    for pair in versions.windows(2) {
+        let stem = |p: &str| {
+            Path::new(p)
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_else(|| p.to_string())
+        };
+        let patch_path = patch_dir.join(format!("{}-{}.patch", stem(&pair[0]), stem(&pair[1])));
+        create_binary_patch(Path::new(&pair[0]), Path::new(&pair[1]), &patch_path)?;
+        patches.push(patch_path);
+    }
+    Ok(patches)
+}
+
+pub fn apply_patch_chain(base_path: &Path, patches: &[String], output_path: &Path) -> Result<(), RustPackError> {
+    let mut data = fs::read(base_path)?;
+    for patch in patches {
+        let patch_content = fs::read_to_string(patch)?;
+        data = patch_bytes(&data, &patch_content).map_err(|e| match e {
+            RustPackError::PatchFormat(message) => RustPackError::PatchFormat(format!("{}: {}", patch, message)),
+            e => e,
+        })?;
+    }
+    fs::write(output_path, data)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package_info() -> PackageInfo {
+        PackageInfo {
+            name: "app".to_string(),
+            version: "1.0.0".to_string(),
+            description: None,
+            targets: Vec::new(),
+            created_at: None,
+            checksum: String::new(),
+            features: Vec::new(),
+            requires: Vec::new(),
+            metadata: BTreeMap::new(),
+            merkle_root: None,
+            public_key: None,
+        }
+    }
+
+    #[test]
+    fn requires_is_optional_in_info_json() {
+        let json = serde_json::to_string(&package_info()).unwrap();
+        assert!(!json.contains("requires"));
+        let info: PackageInfo = serde_json::from_str(&json).unwrap();
+        assert!(info.requires.is_empty());
+
+        let mut info = package_info();
+        info.requires = vec!["cpu:avx2".to_string(), "kernel:5.4".to_string()];
+        let json = serde_json::to_string(&info).unwrap();
+        let info: PackageInfo = serde_json::from_str(&json).unwrap();
+        assert_eq!(info.requires, ["cpu:avx2", "kernel:5.4"]);
+    }
+
+    #[test]
+    fn strip_levels_map_to_strip_flags() {
+        let linux = "x86_64-unknown-linux-gnu";
+        assert_eq!(strip_args(linux, "strip", "all").unwrap(), ["-s"]);
+        assert_eq!(strip_args(linux, "strip", "debug").unwrap(), ["--strip-debug"]);
+        assert_eq!(strip_args(linux, "strip", "unneeded").unwrap(), ["--strip-unneeded"]);
+
+        let macos = "aarch64-apple-darwin";
+        assert!(strip_args(macos, "strip", "all").unwrap().is_empty());
+        assert_eq!(strip_args(macos, "strip", "debug").unwrap(), ["-S"]);
+        assert_eq!(strip_args(macos, "strip", "unneeded").unwrap(), ["-x"]);
+        assert_eq!(strip_args(macos, "llvm-strip", "debug").unwrap(), ["--strip-debug"]);
+
+        assert!(strip_args(linux, "strip", "symbols").is_err());
+    }
+
+    /// A staging directory laid out like build_package's, with a binary, an
+    /// executable script and a plain asset.
+    fn staged_package() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let rustpack_dir = dir.path().join("rustpack");
+        fs::create_dir_all(rustpack_dir.join("bin/x86_64-unknown-linux-gnu")).unwrap();
+        fs::create_dir_all(rustpack_dir.join("assets")).unwrap();
+        fs::write(rustpack_dir.join("info.json"), serde_json::to_string_pretty(&package_info()).unwrap()).unwrap();
+        fs::write(rustpack_dir.join("bin/x86_64-unknown-linux-gnu/app"), b"\x7fELF not really").unwrap();
+        fs::write(rustpack_dir.join("assets/run.sh"), "#!/bin/sh\necho hi\n").unwrap();
+        fs::write(rustpack_dir.join("assets/readme.txt"), "hello\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            for (path, mode) in [("bin/x86_64-unknown-linux-gnu/app", 0o755), ("assets/run.sh", 0o755), ("assets/readme.txt", 0o644)] {
+                fs::set_permissions(rustpack_dir.join(path), fs::Permissions::from_mode(mode)).unwrap();
+            }
+        }
+        dir
+    }
+
+    #[test]
+    fn changing_any_stub_byte_invalidates_signature() {
+        let staged = staged_package();
+        let output = staged.path().join("app.rpack");
+        let output_name = output.to_str().unwrap();
+        create_self_extracting_package(staged.path(), output_name, BOOTSTRAP_SCRIPT.as_bytes(), PayloadCompression::Gzip, None).unwrap();
+        sign_output_file(&output, "secret", PayloadCompression::Gzip).unwrap();
+        verify_package(&output, "secret").unwrap();
+
+        let signed = fs::read(&output).unwrap();
+        let stub_len = package::find_payload(&signed).unwrap();
+        let tampered = staged.path().join("tampered.rpack");
+        for offset in (0..stub_len).step_by(13).chain([stub_len - 1]) {
+            let mut bytes = signed.clone();
+            bytes[offset] ^= 0x20;
+            fs::write(&tampered, &bytes).unwrap();
+            // Bytes of the payload marker make the package unreadable
+            // instead; either way it fails to verify.
+            assert!(verify_package(&tampered, "secret").is_err(), "changing byte {} of the stub went unnoticed", offset);
+        }
+    }
+
+    #[test]
+    fn desktop_entry_has_name_and_exec() {
+        let categories = vec!["Development".to_string(), "Utility".to_string()];
+        let entry = generate_desktop_entry("My App", "my-app", Some("Does\nthings"), Some("my-app"), &categories, &BTreeMap::new());
+        let lines: Vec<&str> = entry.lines().collect();
+        assert_eq!(lines[0], "[Desktop Entry]");
+        assert!(lines.contains(&"Name=My App"));
+        assert!(lines.contains(&"Exec=my-app"));
+        assert!(lines.contains(&"Comment=Does things"));
+        assert!(lines.contains(&"Icon=my-app"));
+        assert!(lines.contains(&"Categories=Development;Utility;"));
+
+        let overrides = BTreeMap::from([("Exec".to_string(), "my-app %F".to_string()), ("StartupWMClass".to_string(), "MyApp".to_string())]);
+        let entry = generate_desktop_entry("My App", "my-app", None, None, &[], &overrides);
+        assert!(entry.lines().any(|line| line == "Exec=my-app %F"));
+        assert!(entry.ends_with("StartupWMClass=MyApp\n"));
+    }
+
+    #[test]
+    fn tool_versions_are_parsed_from_version_lines() {
+        let version = |line: &str| parse_tool_version(line).map(|v| v.to_string());
+        assert_eq!(version("GNU strip (GNU Binutils) 2.40").as_deref(), Some("2.40.0"));
+        assert_eq!(version("*** Zstandard CLI (64-bit) v1.5.7, by Yann Collet ***").as_deref(), Some("1.5.7"));
+        assert_eq!(version("upx 4.2.2").as_deref(), Some("4.2.2"));
+        assert_eq!(version("cargo 1.80.0 (376290515 2024-07-16)").as_deref(), Some("1.80.0"));
+        assert_eq!(version("strip version 7"), None);
+    }
+
+    #[test]
+    fn patch_truncates_shorter_new_file() {
+        let old: Vec<u8> = (0..4096u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut new = old[..1000].to_vec();
+        new[10] ^= 0xff;
+        let patched = patch_bytes(&old, &diff_binaries(&old, &new)).unwrap();
+        assert_eq!(patched, new);
+
+        let dir = tempfile::tempdir().unwrap();
+        let (old_path, new_path, patch_path, output_path) =
+            (dir.path().join("old"), dir.path().join("new"), dir.path().join("patch"), dir.path().join("output"));
+        fs::write(&old_path, &old).unwrap();
+        fs::write(&new_path, &new).unwrap();
+        create_binary_patch(&old_path, &new_path, &patch_path).unwrap();
+        apply_binary_patch(&old_path, &patch_path, &output_path).unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), new);
+    }
+
+    #[test]
+    fn patch_chain_rebuilds_latest_from_base() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut versions = Vec::new();
+        let mut data: Vec<u8> = (0..2048u32).map(|i| (i % 256) as u8).collect();
+        for version in 0..4 {
+            match version {
+                1 => data[100..120].fill(0xaa),
+                2 => data.extend_from_slice(b"a longer tail"),
+                3 => data.truncate(1500),
+                _ => {}
+            }
+            let path = dir.path().join(format!("v{}", version));
+            fs::write(&path, &data).unwrap();
+            versions.push(path.to_string_lossy().to_string());
+        }
+
+        let patches = create_patch_chain(&versions, &dir.path().join("patches")).unwrap();
+        assert_eq!(patches.len(), 3);
+        let patches: Vec<String> = patches.iter().map(|p| p.to_string_lossy().to_string()).collect();
+        let output = dir.path().join("output");
+        apply_patch_chain(Path::new(&versions[0]), &patches, &output).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), data);
+
+        // Each patch checks it is applied to the version it was made from.
+        let skipped = [patches[0].clone(), patches[2].clone()];
+        assert!(matches!(apply_patch_chain(Path::new(&versions[0]), &skipped, &output), Err(RustPackError::PatchFormat(_))));
+    }
+
+    /// `readelf -d` lines naming RPATH or RUNPATH, or None without readelf.
+    fn rpath_entries(binary: &Path) -> Option<Vec<String>> {
+        let output = ProcessCommand::new("readelf").arg("-d").arg(binary).output().ok()?;
+        let dynamic = String::from_utf8_lossy(&output.stdout).into_owned();
+        Some(dynamic.lines().filter(|l| l.contains("(RPATH)") || l.contains("(RUNPATH)")).map(str::to_string).collect())
+    }
+
+    #[test]
+    fn strip_rpath_removes_rpath_and_runpath() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("main.c");
+        fs::write(&source, "int main(void) { return 0; }\n").unwrap();
+        for (name, dtags) in [("rpath", "-Wl,--disable-new-dtags"), ("runpath", "-Wl,--enable-new-dtags")] {
+            let binary = dir.path().join(name);
+            let Ok(status) = ProcessCommand::new("gcc")
+                .arg(&source)
+                .args(["-o", binary.to_str().unwrap(), "-Wl,-rpath,/build/machine/lib", dtags])
+                .status()
+            else {
+                eprintln!("gcc not found, skipping");
+                return;
+            };
+            assert!(status.success());
+            assert!(rpath_entries(&binary).is_none_or(|entries| !entries.is_empty()), "{} was linked without one", name);
+
+            assert_eq!(strip_rpath(&binary).unwrap(), 1);
+            if let Some(entries) = rpath_entries(&binary) {
+                assert!(entries.is_empty(), "{:?}", entries);
+            }
+            assert!(ProcessCommand::new(&binary).status().unwrap().success());
+            assert_eq!(strip_rpath(&binary).unwrap(), 0);
+        }
+        assert!(strip_rpath(&source).is_err());
+    }
+
+    /// Peak resident memory of this process so far, in bytes.
+    #[cfg(target_os = "linux")]
+    fn peak_memory() -> u64 {
+        let status = fs::read_to_string("/proc/self/status").unwrap();
+        let line = status.lines().find(|l| l.starts_with("VmHWM:")).unwrap();
+        line.split_whitespace().nth(1).unwrap().parse::<u64>().unwrap() * 1024
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn large_files_are_streamed_into_archives() {
+        const SIZE: u64 = 200 << 20;
+        let staged = staged_package();
+        File::create(staged.path().join("rustpack/assets/large.bin")).unwrap().set_len(SIZE).unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let zip_path = out.path().join("app.zip");
+        let rpack_path = out.path().join("app.rpack");
+
+        let before = peak_memory();
+        create_zip_package(staged.path(), zip_path.to_str().unwrap(), Some(1)).unwrap();
+        create_self_extracting_package(staged.path(), rpack_path.to_str().unwrap(), BOOTSTRAP_SCRIPT.as_bytes(), PayloadCompression::None, None).unwrap();
+        let growth = peak_memory().saturating_sub(before);
+        assert!(growth < SIZE / 4, "packing grew memory by {} bytes", growth);
+
+        let mut zip = zip::ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+        assert_eq!(zip.by_name("rustpack/assets/large.bin").unwrap().size(), SIZE);
+        assert!(fs::metadata(&rpack_path).unwrap().len() > SIZE);
+    }
+
+    fn object_file(format: object::BinaryFormat, arch: object::Architecture) -> Vec<u8> {
+        use object::write::{Object, StandardSection};
+        let mut file = Object::new(format, arch, object::Endianness::Little);
+        let text = file.section_id(StandardSection::Text);
+        file.append_section_data(text, &[0x90; 48], 16);
+        let data = file.section_id(StandardSection::Data);
+        file.append_section_data(data, &[1; 20], 8);
+        file.write().unwrap()
+    }
+
+    #[test]
+    fn section_sizes_read_elf() {
+        let data = object_file(object::BinaryFormat::Elf, object::Architecture::X86_64);
+        let sizes = section_sizes(&data).unwrap();
+        assert_eq!(sizes["total"], data.len());
+        assert_eq!(sizes[".text"], 48);
+        assert_eq!(sizes[".data"], 20);
+    }
+
+    #[test]
+    fn section_sizes_read_macho() {
+        let data = object_file(object::BinaryFormat::MachO, object::Architecture::Aarch64);
+        let sizes = section_sizes(&data).unwrap();
+        assert_eq!(sizes["total"], data.len());
+        assert_eq!(sizes["__text"], 48);
+        assert_eq!(sizes["__data"], 20);
+    }
+
+    #[test]
+    fn section_sizes_read_pe() {
+        use object::pe;
+        use object::write::pe::{NtHeaders, Writer};
+        let mut data = Vec::new();
+        let mut writer = Writer::new(true, 0x1000, 0x200, &mut data);
+        writer.reserve_dos_header_and_stub();
+        writer.reserve_nt_headers(pe::IMAGE_NUMBEROF_DIRECTORY_ENTRIES);
+        writer.reserve_section_headers(2);
+        let text = writer.reserve_text_section(48);
+        let rdata = writer.reserve_rdata_section(20);
+        writer.write_dos_header_and_stub().unwrap();
+        writer.write_nt_headers(NtHeaders {
+            machine: pe::IMAGE_FILE_MACHINE_AMD64,
+            time_date_stamp: 0,
+            characteristics: pe::IMAGE_FILE_EXECUTABLE_IMAGE | pe::IMAGE_FILE_LARGE_ADDRESS_AWARE,
+            major_linker_version: 14,
+            minor_linker_version: 0,
+            address_of_entry_point: text.virtual_address,
+            image_base: 0x1_4000_0000,
+            major_operating_system_version: 6,
+            minor_operating_system_version: 0,
+            major_image_version: 0,
+            minor_image_version: 0,
+            major_subsystem_version: 6,
+            minor_subsystem_version: 0,
+            subsystem: pe::IMAGE_SUBSYSTEM_WINDOWS_CUI,
+            dll_characteristics: 0,
+            size_of_stack_reserve: 0x10_0000,
+            size_of_stack_commit: 0x1000,
+            size_of_heap_reserve: 0x10_0000,
+            size_of_heap_commit: 0x1000,
+        });
+        writer.write_section_headers();
+        writer.write_section(text.file_offset, &[0xc3; 48]);
+        writer.write_section(rdata.file_offset, &[1; 20]);
+
+        let sizes = section_sizes(&data).unwrap();
+        assert_eq!(sizes["total"], data.len());
+        assert_eq!(sizes[".text"], 48);
+        assert_eq!(sizes[".rdata"], 20);
+    }
+
+    #[test]
+    fn section_sizes_reject_non_binaries() {
+        assert!(section_sizes(b"#!/bin/sh\necho hello\n").is_err());
+    }
+
+    #[test]
+    fn asset_globs_match_nested_files_and_honor_excludes() {
+        let project = tempfile::tempdir().unwrap();
+        for file in [
+            "static/logo.png",
+            "static/icons/app.png",
+            "static/icons/notes.txt",
+            "static/dev/debug.png",
+            "static/dev/more/trace.png",
+            "docs/guide.md",
+            "docs/draft.md",
+        ] {
+            let path = project.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, file).unwrap();
+        }
+        let resolve = |assets: &[&str]| {
+            let assets: Vec<String> = assets.iter().map(|a| a.to_string()).collect();
+            let resolved = resolve_assets(project.path().to_str().unwrap(), &assets).unwrap();
+            resolved
+                .iter()
+                .filter(|(source, _)| source.is_file())
+                .map(|(_, dest)| dest.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            resolve(&["static/**/*.png"]),
+            ["static/dev/debug.png", "static/dev/more/trace.png", "static/icons/app.png", "static/logo.png"]
+        );
+        // Excluding a directory excludes everything below it.
+        assert_eq!(resolve(&["static/**/*.png", "!static/dev/*"]), ["static/icons/app.png", "static/logo.png"]);
+        assert_eq!(resolve(&["docs", "!docs/draft.md"]), ["docs/guide.md"]);
+        let missing = resolve_assets(project.path().to_str().unwrap(), &["static/**/*.gif".to_string()]);
+        assert!(missing.unwrap_err().to_string().contains("matched no files"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn archives_keep_source_file_modes() {
+        use std::os::unix::fs::PermissionsExt;
+        let staged = staged_package();
+        let out = tempfile::tempdir().unwrap();
+        let zip_path = out.path().join("app.zip");
+        let rpack_path = out.path().join("app.rpack");
+        create_zip_package(staged.path(), zip_path.to_str().unwrap(), None).unwrap();
+        create_self_extracting_package(staged.path(), rpack_path.to_str().unwrap(), BOOTSTRAP_SCRIPT.as_bytes(), PayloadCompression::Gzip, None)
+            .unwrap();
+
+        for package in [zip_path, rpack_path] {
+            let extracted = tempfile::tempdir().unwrap();
+            package::Package::open(&package).unwrap().extract(extracted.path()).unwrap();
+            for (path, mode) in [("bin/x86_64-unknown-linux-gnu/app", 0o755), ("assets/run.sh", 0o755), ("assets/readme.txt", 0o644)] {
+                let metadata = fs::metadata(extracted.path().join("rustpack").join(path)).unwrap();
+                assert_eq!(metadata.permissions().mode() & 0o777, mode, "{} in {}", path, package.display());
+            }
+        }
+    }
+
+    #[test]
+    fn parse_target_knows_each_platform() {
+        let platform = |target: &str| {
+            let (platform, arch, compatibility) = parse_target(target);
+            (platform, arch, compatibility.join(" "))
+        };
+        let expected = |platform: &str, arch: &str, compatibility: &str| (platform.to_string(), arch.to_string(), compatibility.to_string());
+
+        assert_eq!(platform("x86_64-pc-windows-msvc"), expected("windows", "x86_64", "nt6.1 pe"));
+        assert_eq!(platform("x86_64-unknown-linux-gnu"), expected("linux", "x86_64", "glibc-2.17 elf"));
+        assert_eq!(platform("aarch64-apple-darwin"), expected("macos", "aarch64", "11.0 mach-o"));
+        assert_eq!(platform("x86_64-apple-darwin"), expected("macos", "x86_64", "10.7 mach-o"));
+        assert_eq!(platform("aarch64-linux-android"), expected("android", "aarch64", "api-21 elf"));
+        assert_eq!(platform("x86_64-unknown-freebsd"), expected("freebsd", "x86_64", "12.0 elf"));
+        assert_eq!(platform("x86_64-unknown-netbsd"), expected("netbsd", "x86_64", "8.0 elf"));
+        assert_eq!(platform("wasm32-unknown-unknown"), expected("wasm", "wasm32", "wasm"));
+        assert_eq!(platform("wasm32-wasip1"), expected("wasm", "wasm32", "wasm"));
+        assert_eq!(platform("riscv64gc-unknown-none-elf"), expected("unknown", "riscv64gc", ""));
+
+        let error = check_target_platform("wasm32-unknown-unknown", &[PackageFormat::SelfExtracting]).unwrap_err();
+        assert!(error.to_string().contains("use --format wasm"));
+        check_target_platform("wasm32-unknown-unknown", &[PackageFormat::Wasm]).unwrap();
+    }
+
+    #[test]
+    fn zig_glibc_suffix_sets_compatibility() {
+        assert_eq!(split_glibc_version("x86_64-unknown-linux-gnu.2.31"), ("x86_64-unknown-linux-gnu", Some("2.31")));
+        assert_eq!(split_glibc_version("x86_64-unknown-linux-gnu"), ("x86_64-unknown-linux-gnu", None));
+        assert_eq!(split_glibc_version("x86_64-unknown-linux-gnu.2."), ("x86_64-unknown-linux-gnu.2.", None));
+        assert_eq!(split_glibc_version("x86_64-unknown-linux-musl.1.2"), ("x86_64-unknown-linux-musl.1.2", None));
+
+        let (platform, arch, compatibility) = parse_target("x86_64-unknown-linux-gnu.2.31");
+        assert_eq!((platform.as_str(), arch.as_str()), ("linux", "x86_64"));
+        assert_eq!(compatibility, ["glibc-2.31", "elf"]);
+        // Only native cargo builds link against the build machine's glibc.
+        assert_eq!(native_glibc_version("x86_64-unknown-linux-gnu.2.31", "zig", "x86_64-unknown-linux-gnu"), None);
+    }
+
+    #[test]
+    fn musl_targets_link_statically_unless_configured() {
+        assert_eq!(static_link_args("x86_64-unknown-linux-gnu", &[]), Vec::<String>::new());
+        let configured = ["-C".to_string(), "target-feature=-crt-static".to_string()];
+        assert_eq!(static_link_args("x86_64-unknown-linux-musl", &configured), Vec::<String>::new());
+        if env::var_os("RUSTFLAGS").is_none() && env::var_os("CARGO_ENCODED_RUSTFLAGS").is_none() {
+            assert_eq!(static_link_args("aarch64-unknown-linux-musl", &[]), ["-Ctarget-feature=+crt-static"]);
+        }
+    }
+}
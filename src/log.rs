@@ -0,0 +1,252 @@
+use chrono::Utc;
+use colored::Colorize;
+use serde::Serialize;
+use std::fs::File;
+use std::io::Write;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+static LOG_FILE: OnceLock<Mutex<File>> = OnceLock::new();
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+/// How much is printed to the console. The log file and webhook receive
+/// every event regardless.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Errors only.
+    Quiet,
+    /// Warnings, progress and the build summary.
+    Normal,
+    /// Everything, including cargo's output and each step of the build.
+    Verbose,
+}
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        1 => Verbosity::Normal,
+        _ => Verbosity::Verbose,
+    }
+}
+
+pub fn quiet() -> bool {
+    verbosity() == Verbosity::Quiet
+}
+
+#[derive(Serialize)]
+struct LogEntry<'a> {
+    timestamp: String,
+    level: &'a str,
+    phase: &'a str,
+    message: &'a str,
+}
+
+/// Starts writing every log event to `path` as JSON lines, independent of
+/// how much is printed to the console.
+pub fn init_file(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let file = File::create(path)?;
+    LOG_FILE
+        .set(Mutex::new(file))
+        .map_err(|_| "Log file is already initialized")?;
+    Ok(())
+}
+
+/// POSTs every log event to `url` as JSON, plus a heartbeat carrying the
+/// latest status every `interval` so long cargo builds still show progress.
+#[cfg(feature = "webhook")]
+pub fn init_webhook(url: &str, interval: Duration) -> Result<(), Box<dyn std::error::Error>> {
+    webhook::init(url, interval)
+}
+
+#[cfg(not(feature = "webhook"))]
+pub fn init_webhook(_url: &str, _interval: Duration) -> Result<(), Box<dyn std::error::Error>> {
+    Err("rustpack was built without the `webhook` feature".into())
+}
+
+/// Sets the target included in progress updates until the next call.
+pub fn set_target(_target: &str) {
+    #[cfg(feature = "webhook")]
+    webhook::set_target(_target);
+}
+
+pub fn event(level: &str, phase: &str, message: &str) {
+    #[cfg(feature = "webhook")]
+    webhook::event("progress", level, phase, message);
+    write_entry(level, phase, message);
+}
+
+/// Logs how the build ended. With a progress webhook, this is posted as a
+/// `completed` or `failed` update, and the call waits until every pending
+/// update has been sent, so call it before exiting.
+pub fn finish(success: bool, message: &str) {
+    let level = if success { "info" } else { "error" };
+    #[cfg(feature = "webhook")]
+    webhook::finish(if success { "completed" } else { "failed" }, level, message);
+    write_entry(level, "package", message);
+}
+
+fn write_entry(level: &str, phase: &str, message: &str) {
+    let Some(file) = LOG_FILE.get() else {
+        return;
+    };
+    let entry = LogEntry {
+        timestamp: Utc::now().to_rfc3339(),
+        level,
+        phase,
+        message,
+    };
+    if let (Ok(mut file), Ok(line)) = (file.lock(), serde_json::to_string(&entry)) {
+        let _ = writeln!(file, "{}", line);
+    }
+}
+
+pub fn info(phase: &str, message: &str) {
+    event("info", phase, message);
+}
+
+/// Logs a warning and prints it to stderr unless the console is quiet.
+/// Anything worth a warning is worth showing; use `info` for the rest.
+pub fn warning(phase: &str, message: &str) {
+    event("warn", phase, message);
+    if !quiet() {
+        eprintln!("{} {}", "Warning:".yellow(), message);
+    }
+}
+
+pub fn error(phase: &str, message: &str) {
+    event("error", phase, message);
+}
+
+#[cfg(feature = "webhook")]
+mod webhook {
+    use chrono::Utc;
+    use serde::Serialize;
+    use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+    use std::sync::{Mutex, OnceLock};
+    use std::time::{Duration, Instant};
+
+    // Updates go to a background thread that does all the posting, so the
+    // build never waits on the network.
+    static SENDER: OnceLock<Sender<Message>> = OnceLock::new();
+    static START: OnceLock<Instant> = OnceLock::new();
+    static STATUS: Mutex<Status> = Mutex::new(Status {
+        target: String::new(),
+        phase: String::new(),
+        message: String::new(),
+    });
+
+    struct Status {
+        target: String,
+        phase: String,
+        message: String,
+    }
+
+    enum Message {
+        Post(ProgressUpdate),
+        /// Answered once every update sent before it has been posted.
+        Flush(Sender<()>),
+    }
+
+    #[derive(Serialize)]
+    struct ProgressUpdate {
+        event: &'static str,
+        timestamp: String,
+        level: String,
+        target: String,
+        phase: String,
+        message: String,
+        elapsed_secs: f64,
+    }
+
+    pub fn init(url: &str, interval: Duration) -> Result<(), Box<dyn std::error::Error>> {
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .timeout_global(Some(Duration::from_secs(5)))
+            .build()
+            .into();
+        let (sender, receiver) = mpsc::channel();
+        SENDER
+            .set(sender)
+            .map_err(|_| "Progress webhook is already initialized")?;
+        START.get_or_init(Instant::now);
+
+        let url = url.to_string();
+        std::thread::spawn(move || loop {
+            match receiver.recv_timeout(interval) {
+                Ok(Message::Post(update)) => post(&agent, &url, &update),
+                Ok(Message::Flush(done)) => {
+                    let _ = done.send(());
+                }
+                // Nothing happened for a whole interval, as during a long
+                // cargo build, so repeat the latest status.
+                Err(RecvTimeoutError::Timeout) => {
+                    let Ok(status) = STATUS.lock() else {
+                        continue;
+                    };
+                    let update = update("heartbeat", "info", &status.target, &status.phase, &status.message);
+                    drop(status);
+                    post(&agent, &url, &update);
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        });
+        Ok(())
+    }
+
+    pub fn set_target(target: &str) {
+        if let Ok(mut status) = STATUS.lock() {
+            status.target = target.to_string();
+        }
+    }
+
+    pub fn event(event: &'static str, level: &str, phase: &str, message: &str) {
+        let Ok(mut status) = STATUS.lock() else {
+            return;
+        };
+        status.phase = phase.to_string();
+        status.message = message.to_string();
+        let update = update(event, level, &status.target, phase, message);
+        drop(status);
+        send(Message::Post(update));
+    }
+
+    /// Sends the final update and waits, for a bounded time, until it and
+    /// everything before it have been posted.
+    pub fn finish(event: &'static str, level: &str, message: &str) {
+        self::event(event, level, "package", message);
+        let (done, flushed) = mpsc::channel();
+        if send(Message::Flush(done)) {
+            let _ = flushed.recv_timeout(Duration::from_secs(30));
+        }
+    }
+
+    fn send(message: Message) -> bool {
+        SENDER.get().is_some_and(|sender| sender.send(message).is_ok())
+    }
+
+    fn update(event: &'static str, level: &str, target: &str, phase: &str, message: &str) -> ProgressUpdate {
+        ProgressUpdate {
+            event,
+            timestamp: Utc::now().to_rfc3339(),
+            level: level.to_string(),
+            target: target.to_string(),
+            phase: phase.to_string(),
+            message: message.to_string(),
+            elapsed_secs: START.get().map_or(0.0, |start| start.elapsed().as_secs_f64()),
+        }
+    }
+
+    fn post(agent: &ureq::Agent, url: &str, update: &ProgressUpdate) {
+        if let Ok(body) = serde_json::to_string(update) {
+            // Progress reporting must never fail the build.
+            let _ = agent
+                .post(url)
+                .header("Content-Type", "application/json")
+                .send(&body);
+        }
+    }
+}
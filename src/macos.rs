@@ -0,0 +1,175 @@
+use crate::{PackageInfo, RustPackError, TargetInfo, copy_permissions};
+use std::fs;
+use std::path::Path;
+use std::process::Command as ProcessCommand;
+use walkdir::WalkDir;
+
+/// Fails unless `hdiutil`, which only ships with macOS, is available.
+pub fn check_hdiutil() -> Result<(), Box<dyn std::error::Error>> {
+    match ProcessCommand::new("hdiutil").arg("help").output() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(RustPackError::ToolMissing("hdiutil").into()),
+    }
+}
+
+/// Fails unless `lipo` or `llvm-lipo` is available to merge binaries.
+pub fn check_lipo() -> Result<(), Box<dyn std::error::Error>> {
+    if ["lipo", "llvm-lipo"].iter().any(|tool| ProcessCommand::new(tool).arg("-info").output().is_ok()) {
+        Ok(())
+    } else {
+        Err(RustPackError::ToolMissing("lipo").into())
+    }
+}
+
+/// Writes `<output>` as an application bundle: the macOS binaries in
+/// `Contents/MacOS`, the icon and assets in `Contents/Resources` and an
+/// `Info.plist` from the package metadata. With both an Intel and an Apple
+/// Silicon target each binary is merged into a universal one with `lipo`.
+/// `icon` is the bundled icon file, if any.
+pub fn write_app(
+    rustpack_dir: &Path,
+    package_info: &PackageInfo,
+    bundle_id: &str,
+    icon: Option<&Path>,
+    output: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let targets: Vec<&TargetInfo> = package_info.targets.iter().filter(|t| t.platform == "macos").collect();
+    let Some(first) = targets.first() else {
+        return Err("An .app bundle needs a macOS target".into());
+    };
+    if output.exists() && !output.join("Contents/Info.plist").is_file() {
+        return Err(format!("{} exists and isn't an app bundle, not replacing it", output.display()).into());
+    }
+
+    // Built next to the output and moved into place once complete, so a
+    // failed build never leaves half a bundle behind.
+    let parent = output.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let staging = tempfile::Builder::new().prefix(".rustpack-app").tempdir_in(parent)?;
+    let bundle = staging.path().join("bundle");
+    let contents = bundle.join("Contents");
+    let macos_dir = contents.join("MacOS");
+    let resources = contents.join("Resources");
+    fs::create_dir_all(&macos_dir)?;
+    fs::create_dir_all(&resources)?;
+
+    let executable = Path::new(&first.binary_path)
+        .file_name()
+        .ok_or("Invalid binary path")?
+        .to_string_lossy()
+        .to_string();
+    for binary in first.all_binaries() {
+        let name = Path::new(binary).file_name().ok_or("Invalid binary path")?;
+        let dest = macos_dir.join(name);
+        let sources: Vec<_> = targets
+            .iter()
+            .map(|t| rustpack_dir.join(Path::new(&t.binary_path).with_file_name(name)))
+            .collect();
+        if let [source] = &sources[..] {
+            fs::copy(source, &dest)?;
+            copy_permissions(source, &dest)?;
+        } else {
+            lipo(&sources, &dest)?;
+        }
+    }
+
+    let icon_file = match icon {
+        Some(icon) => {
+            let file_name = icon.file_name().ok_or("Invalid icon path")?.to_string_lossy().to_string();
+            fs::copy(icon, resources.join(&file_name))?;
+            Some(file_name)
+        }
+        None => None,
+    };
+    let assets_dir = rustpack_dir.join("assets");
+    if assets_dir.is_dir() {
+        for entry in WalkDir::new(&assets_dir).sort_by_file_name().into_iter().filter_map(|e| e.ok()) {
+            let dest_path = resources.join("assets").join(entry.path().strip_prefix(&assets_dir)?);
+            if entry.file_type().is_dir() {
+                fs::create_dir_all(&dest_path)?;
+            } else {
+                fs::copy(entry.path(), &dest_path)?;
+                copy_permissions(entry.path(), &dest_path)?;
+            }
+        }
+    }
+
+    // The oldest release any of the binaries supports.
+    let minimum_version = targets
+        .iter()
+        .filter_map(|t| t.compatibility.first())
+        .min_by_key(|v| v.split('.').map(|p| p.parse::<u32>().unwrap_or(0)).collect::<Vec<_>>())
+        .cloned()
+        .unwrap_or_else(|| "10.7".to_string());
+    let mut plist = vec![
+        ("CFBundleDevelopmentRegion", "en".to_string()),
+        ("CFBundleExecutable", executable),
+        ("CFBundleIdentifier", bundle_id.to_string()),
+        ("CFBundleInfoDictionaryVersion", "6.0".to_string()),
+        ("CFBundleName", package_info.name.clone()),
+        ("CFBundleDisplayName", package_info.name.clone()),
+        ("CFBundlePackageType", "APPL".to_string()),
+        ("CFBundleShortVersionString", package_info.version.clone()),
+        ("CFBundleVersion", package_info.version.clone()),
+        ("LSMinimumSystemVersion", minimum_version),
+    ];
+    if let Some(icon_file) = icon_file {
+        plist.push(("CFBundleIconFile", icon_file));
+    }
+    fs::write(contents.join("Info.plist"), info_plist(&plist))?;
+    fs::write(contents.join("PkgInfo"), "APPL????")?;
+
+    if output.exists() {
+        fs::remove_dir_all(output)?;
+    }
+    fs::rename(&bundle, output)?;
+    Ok(())
+}
+
+/// Wraps the bundle at `app` in a compressed disk image with a link to
+/// `/Applications` to drag it onto.
+pub fn write_dmg(app: &Path, volume_name: &str, output: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    check_hdiutil()?;
+    let staging = app.parent().ok_or("Invalid app bundle path")?;
+    #[cfg(unix)]
+    std::os::unix::fs::symlink("/Applications", staging.join("Applications"))?;
+    let result = ProcessCommand::new("hdiutil")
+        .args(["create", "-volname", volume_name, "-format", "UDZO", "-ov", "-srcfolder"])
+        .arg(staging)
+        .arg(output)
+        .output()?;
+    if !result.status.success() {
+        return Err(format!("hdiutil failed:\n{}", String::from_utf8_lossy(&result.stderr).trim_end()).into());
+    }
+    Ok(())
+}
+
+/// Merges `binaries`, each built for a different architecture, into the
+/// universal binary `output`.
+pub fn lipo(binaries: &[std::path::PathBuf], output: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    for tool in ["lipo", "llvm-lipo"] {
+        if let Ok(status) = ProcessCommand::new(tool).arg("-create").args(binaries).arg("-output").arg(output).status() {
+            if status.success() {
+                return Ok(());
+            }
+            return Err(format!("{} could not create a universal binary", tool).into());
+        }
+    }
+    Err(RustPackError::ToolMissing("lipo").into())
+}
+
+fn info_plist(entries: &[(&str, String)]) -> String {
+    let mut plist = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n<dict>\n",
+    );
+    for (key, value) in entries {
+        plist.push_str(&format!("    <key>{}</key>\n    <string>{}</string>\n", key, xml_escape(value)));
+    }
+    plist.push_str("    <key>NSHighResolutionCapable</key>\n    <true/>\n</dict>\n</plist>\n");
+    plist
+}
+
+fn xml_escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
@@ -1,62 +1,27 @@
 use clap::{Command, Arg, ArgAction};
-use flate2::write::GzEncoder;
-use flate2::Compression;
-use std::fs::{self, File};
-use std::io::{self, Read, Write};
-use std::path::{Path, PathBuf};
-use std::process::Command as ProcessCommand;
-use tar::Builder;
-use walkdir::WalkDir;
-use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use serde::{Deserialize, Serialize};
-use std::env;
 use colored::Colorize;
-use indicatif::{ProgressBar, ProgressStyle};
-use rand::Rng;
-use chrono::Local;
-use notify::{Watcher, RecursiveMode, watcher};
-use std::sync::mpsc::channel;
-use std::time::{Duration, Instant};
-use zip::write::FileOptions;
-use sha2::{Sha256, Digest};
-use hmac::{Hmac, Mac};
-use base64::encode;
-use toml;
-
-type HmacSha256 = Hmac<Sha256>;
-
-#[derive(Serialize, Deserialize, Clone)]
-struct PackageInfo {
-    name: String,
-    version: String,
-    description: Option<String>,
-    targets: Vec<TargetInfo>,
-    created_at: String,
-    checksum: String,
-    features: Vec<String>,
-    metadata: HashMap<String, String>,
-}
+use chrono::{DateTime, Utc};
+use rustpack::log;
+use rustpack::package::{Package, PackageFormat};
+use rustpack::{
+    apply_binary_patch, apply_patch_chain, check_tool_version, create_binary_patch, create_patch_chain, diff_packages, extract_package,
+    get_cargo_version, get_current_target, get_rust_version, is_reserved_metadata_key, load_env_config, package_dir, parse_manifest, parse_size, self_update,
+    uses_windows_bootstrap, verify_detached_signature, verify_package, verify_package_files, BuildConfig, BuildPlan, PackageInfo, Packer, RustPackError, SizeChange, TargetConfig, ToolStatus,
+    TOOL_REQUIREMENTS,
+};
 
+/// `desktop` in RustPack.toml: a `[desktop]` table of keys for the generated
+/// desktop entry, or the path of a complete desktop file.
 #[derive(Serialize, Deserialize, Clone)]
-struct TargetInfo {
-    platform: String,
-    arch: String,
-    binary_path: String,
-    features: Vec<String>,
-    optimizations: Option<String>,
-    compatibility: Vec<String>,
-}
-
-#[derive(Serialize, Deserialize)]
-struct BuildConfig {
-    strip: bool,
-    compress: bool,
-    lto: Option<String>,
-    debug_symbols: bool,
-    profile: String,
-    features: Vec<String>,
-    assets: Vec<String>,
-    sign: String,
+#[serde(untagged)]
+enum DesktopConfig {
+    Keys(BTreeMap<String, String>),
+    File(String),
 }
 
 #[derive(Serialize, Deserialize, Default)]
@@ -65,155 +30,75 @@ struct RustPackConfig {
     output: Option<String>,
     targets: Option<Vec<String>>,
     strip: Option<bool>,
+    strip_level: Option<String>,
+    strip_tool: Option<String>,
     compress: Option<bool>,
+    strip_rpath: Option<bool>,
+    bundle_libs: Option<bool>,
     lto: Option<String>,
+    prefetch_deps: Option<bool>,
     profile: Option<String>,
     features: Option<Vec<String>>,
     assets: Option<Vec<String>>,
+    requires: Option<Vec<String>>,
     zip: Option<bool>,
+    format: Option<Vec<String>>,
     no_default_features: Option<bool>,
+    cargo_args: Option<Vec<String>>,
+    target_dir: Option<String>,
+    rustflags: Option<Vec<String>>,
     watch: Option<bool>,
     sign: Option<String>,
+    sign_key: Option<String>,
+    output_manifest: Option<String>,
+    env_file: Option<String>,
+    report: Option<String>,
+    sbom: Option<String>,
+    licenses: Option<bool>,
+    channel: Option<String>,
+    channel_manifest: Option<String>,
+    max_package_size: Option<String>,
+    max_size: Option<String>,
+    bloat: Option<usize>,
+    update_url: Option<String>,
+    release_dir: Option<String>,
+    min_update_version: Option<String>,
+    delta_updates: Option<bool>,
+    no_timestamp: Option<bool>,
+    upload: Option<String>,
+    publish_github: Option<String>,
+    log_file: Option<String>,
+    progress_webhook: Option<String>,
+    desktop_entry: Option<bool>,
+    icon: Option<String>,
+    categories: Option<Vec<String>>,
+    bootstrap: Option<String>,
+    compression: Option<String>,
+    compression_level: Option<u32>,
+    builder: Option<String>,
+    jobs: Option<usize>,
+    windows_stub: Option<String>,
     verbose: Option<bool>,
+    quiet: Option<bool>,
+    target: Option<HashMap<String, TargetConfig>>,
+    multicall: Option<BTreeMap<String, String>>,
+    desktop: Option<DesktopConfig>,
+    comment: Option<String>,
+    metadata: Option<BTreeMap<String, String>>,
+    bundle_id: Option<String>,
+    universal_macos: Option<bool>,
+    skip_missing_tools: Option<bool>,
+    windows_resources: Option<bool>,
+    package: Option<String>,
+    bin: Option<Vec<String>>,
+    bins: Option<bool>,
+    pre_build: Option<Vec<String>>,
+    post_build: Option<Vec<String>>,
 }
 
-// TODO: add windows bootstrap code or choose another lang (windows can use sh)
-const BOOTSTRAP_SCRIPT: &str = r#"#!/bin/sh
-PAYLOAD_LINE=$(awk '/^__PAYLOAD_BEGINS__/ { print NR + 1; exit 0; }' $0)
-TEMP_DIR=$(mktemp -d 2>/dev/null || mktemp -d -t rustpack)
-tail -n+$PAYLOAD_LINE $0 | tar xzf - -C "$TEMP_DIR"
-APP_NAME=$(jq -r '.name' "$TEMP_DIR/rustpack/info.json")
-
-KERNEL=$(uname -s | tr '[:upper:]' '[:lower:]')
-ARCH=$(uname -m)
-
-if [ "$KERNEL" = "darwin" ]; then
-    PLATFORM="macos"
-elif [ "$KERNEL" = "linux" ]; then
-    PLATFORM="linux"
-elif echo "$KERNEL" | grep -q "mingw\|cygwin\|msys"; then
-    PLATFORM="windows"
-else
-    PLATFORM="unknown"
-fi
-
-if [ "$ARCH" = "x86_64" ] || [ "$ARCH" = "amd64" ]; then
-    ARCH="x86_64"
-elif [ "$ARCH" = "arm64" ] || [ "$ARCH" = "aarch64" ]; then
-    ARCH="aarch64"
-elif [ "$ARCH" = "i386" ] || [ "$ARCH" = "i686" ]; then
-    ARCH="x86"
-elif [ "$ARCH" = "arm" ] || [ "$ARCH" = "armv7l" ]; then
-    ARCH="arm"
-else
-    ARCH="unknown"
-fi
-
-if [ -d "$TEMP_DIR/rustpack/assets" ]; then
-    export RUSTPACK_ASSETS_DIR="$TEMP_DIR/rustpack/assets"
-fi
-
-BINARY_PATH=$(jq -r --arg platform "$PLATFORM" --arg arch "$ARCH" '.targets[] | select(.platform == $platform and .arch == $arch) | .binary_path' "$TEMP_DIR/rustpack/info.json")
-
-if [ -n "$BINARY_PATH" ]; then
-    chmod +x "$TEMP_DIR/rustpack/$BINARY_PATH"
-    CLEANUP_OPT="--cleanup"
-    if echo "$*" | grep -q -- "$CLEANUP_OPT"; then
-        ARGS=$(echo "$*" | sed "s/$CLEANUP_OPT//")
-        exec "$TEMP_DIR/rustpack/$BINARY_PATH" $ARGS
-        trap "rm -rf $TEMP_DIR" EXIT
-    else
-        exec "$TEMP_DIR/rustpack/$BINARY_PATH" "$@"
-    fi
-else
-    echo "Error: No compatible binary found for $PLATFORM-$ARCH"
-    exit 1
-fi
-
-check_for_updates() {
-    echo "Checking for updates..."
-    CURRENT_VERSION=$(jq -r '.version' "$RUSTPACK_DIR/info.json")
-    UPDATE_URL=$(jq -r '.metadata.update_url // empty' "$RUSTPACK_DIR/info.json")
-    if [ -z "$UPDATE_URL" ]; then
-        echo "No update URL configured."
-        return 1
-    fi
-    if command -v curl > /dev/null; then
-        VERSION_INFO=$(curl -s "$UPDATE_URL/version.json")
-    elif command -v wget > /dev/null; then
-        VERSION_INFO=$(wget -q -O - "$UPDATE_URL/version.json")
-    else
-        echo "No curl or wget found to check for updates."
-        return 1
-    fi
-    if [ -z "$VERSION_INFO" ]; then
-        echo "Could not fetch version information."
-        return 1
-    fi
-    LATEST_VERSION=$(echo "$VERSION_INFO" | jq -r '.version')
-    if [ "$CURRENT_VERSION" != "$LATEST_VERSION" ]; then
-        echo "Update available: $LATEST_VERSION (current: $CURRENT_VERSION)"
-        echo "Run with --update to download the latest version"
-        return 0
-    else
-        echo "You are running the latest version: $CURRENT_VERSION"
-        return 0
-    fi
-}
-
-perform_update() {
-    echo "Updating to the latest version..."
-    UPDATE_URL=$(jq -r '.metadata.update_url // empty' "$RUSTPACK_DIR/info.json")
-    if [ -z "$UPDATE_URL" ]; then
-        echo "No update URL configured."
-        return 1
-    fi
-    DOWNLOAD_URL="$UPDATE_URL/latest.rpack"
-    TEMP_FILE=$(mktemp)
-    if command -v curl > /dev/null; then
-        curl -L -o "$TEMP_FILE" "$DOWNLOAD_URL"
-    elif command -v wget > /dev/null; then
-        wget -O "$TEMP_FILE" "$DOWNLOAD_URL"
-    else
-        echo "No curl or wget found to download update."
-        return 1
-    fi
-    if [ $? -ne 0 ]; then
-        echo "Failed to download update."
-        return 1
-    fi
-    chmod +x "$TEMP_FILE"
-    echo "Update downloaded. Replacing current executable..."
-    "$TEMP_FILE" --replace-with-update "$0"
-    exit $?
-}
-
-if [ "$1" = "--check-updates" ]; then
-    check_for_updates
-    exit $?
-fi
-
-if [ "$1" = "--update" ]; then
-    perform_update
-    exit $?
-fi
-
-if [ "$1" = "--replace-with-update" ]; then
-    if [ -n "$2" ]; then
-        mv "$0" "$2"
-        echo "Update completed successfully!"
-        exit 0
-    else
-        echo "Missing target path for update"
-        exit 1
-    fi
-fi
-exit 0
-__PAYLOAD_BEGINS__
-"#;
-
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let matches = Command::new("RustPack")
+/// The command-line interface, shared by argument parsing and `rustpack man`.
+fn cli() -> Command {
+    Command::new("RustPack")
         .version("0.2.0")
         .about("Bundle Rust applications for cross-platform execution")
         .arg(
@@ -242,11 +127,46 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("Strip debug symbols from binaries")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("strip-level")
+                .long("strip-level")
+                .help("How much to strip (all, debug, unneeded); implies --strip")
+                .value_parser(["all", "debug", "unneeded"]),
+        )
+        .arg(
+            Arg::new("strip-tool")
+                .long("strip-tool")
+                .help("Strip program to use instead of one picked for each target"),
+        )
+        .arg(
+            Arg::new("strip-rpath")
+                .long("strip-rpath")
+                .help("Remove RPATH/RUNPATH entries from ELF binaries")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("bundle-libs")
+                .long("bundle-libs")
+                .help("Bundle non-system shared libraries the binaries link against")
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("lto")
                 .long("lto")
-                .help("Enable Link Time Optimization (thin, fat, off)")
-                .default_value("off"),
+                .help("Enable Link Time Optimization (thin, fat, off; default off)"),
+        )
+        .arg(
+            Arg::new("jobs")
+                .short('j')
+                .long("jobs")
+                .help("Number of targets to build at once (defaults to the number of CPUs)")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("prefetch-deps")
+                .long("prefetch-deps")
+                .help("Run cargo fetch before building so download time is reported separately")
+                .action(ArgAction::SetTrue),
         )
         .arg(
             Arg::new("compress")
@@ -254,6 +174,30 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("Compress binaries with UPX if available")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("dry-run")
+                .long("dry-run")
+                .help("Print what would be built and check the configuration, without running cargo or writing files")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("pre-build")
+                .long("pre-build")
+                .help("Shell command to run in the project directory before building (repeatable)")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("post-build")
+                .long("post-build")
+                .help("Shell command to run in the project directory after a successful build (repeatable)")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("clean")
+                .long("clean")
+                .help("Remove the targets' build directories and the previous output before building")
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("watch")
                 .long("watch")
@@ -261,9 +205,106 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .action(ArgAction::SetTrue),
         )
         .arg(
-            Arg::new("sign")
-                .long("sign")
-                .help("Sign the package with a key"),
+            Arg::new("open")
+                .long("open")
+                .help("Run the package after building it (and after each rebuild with --watch), passing the arguments after --")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("open-args")
+                .help("Arguments for the package run by --open")
+                .num_args(0..)
+                .last(true)
+                .allow_hyphen_values(true),
+        )
+        .arg(
+            Arg::new("sign-key")
+                .long("sign-key")
+                .help("Sign the package with an ed25519 private key file (PEM or raw), writing <output>.sig"),
+        )
+        .arg(
+            Arg::new("sign-hmac")
+                .long("sign-hmac")
+                .alias("sign")
+                .help("Sign the package with a shared HMAC key (legacy)"),
+        )
+        .arg(
+            Arg::new("verify")
+                .long("verify")
+                .help("Verify the signature of an existing package: <package>.sig with --verify-key or the embedded public key, or the HMAC trailer with --sign-hmac"),
+        )
+        .arg(
+            Arg::new("verify-key")
+                .long("verify-key")
+                .help("ed25519 public key file (PEM, raw or base64) to check --verify against"),
+        )
+        .arg(
+            Arg::new("verify-files")
+                .long("verify-files")
+                .help("Check files of an existing package against the Merkle root in its info.json"),
+        )
+        .arg(
+            Arg::new("files")
+                .long("files")
+                .help("Files to check with --verify-files, relative to rustpack/ (comma-separated; default all)"),
+        )
+        .arg(
+            Arg::new("list-contents")
+                .long("list-contents")
+                .help("List the files inside an existing package without extracting it"),
+        )
+        .arg(
+            Arg::new("diff")
+                .long("diff")
+                .num_args(2)
+                .value_names(["OLD", "NEW"])
+                .help("Compare the binary, section and asset sizes of two packages"),
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .help("Print --diff as JSON")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("extract")
+                .long("extract")
+                .help("Unpack a self-extracting package into the --output directory without running it"),
+        )
+        .arg(
+            Arg::new("list")
+                .long("list")
+                .help("Show the name, version, targets, features, assets and checksums of an existing package"),
+        )
+        .arg(
+            Arg::new("raw")
+                .long("raw")
+                .help("Print the package's info.json as-is with --list")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("long")
+                .long("long")
+                .help("Also show modes and modification times with --list-contents")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("init")
+                .long("init")
+                .help("Write a commented RustPack.toml for the project in --input")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("force")
+                .long("force")
+                .help("Overwrite an existing RustPack.toml with --init")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("doctor")
+                .long("doctor")
+                .help("Check that the external tools rustpack uses are installed and recent enough")
+                .action(ArgAction::SetTrue),
         )
         .arg(
             Arg::new("features")
@@ -273,8 +314,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .arg(
             Arg::new("profile")
                 .long("profile")
-                .help("Build profile (dev, release)")
-                .default_value("release"),
+                .help("Cargo profile to build with: dev, release or one from Cargo.toml (default: release)"),
         )
         .arg(
             Arg::new("no-default-features")
@@ -282,12 +322,65 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("Disable default features")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("cargo-arg")
+                .long("cargo-arg")
+                .help("Argument to append to the cargo build command as is (repeatable)")
+                .allow_hyphen_values(true)
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("meta")
+                .long("meta")
+                .value_name("KEY=VALUE")
+                .help("Add an entry to the package's info.json metadata, exported to the app as RUSTPACK_META_<KEY> (repeatable)")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("target-dir")
+                .long("target-dir")
+                .help("Directory for cargo's build artifacts (defaults to CARGO_TARGET_DIR or the workspace's target/)"),
+        )
         .arg(
             Arg::new("zip")
                 .long("zip")
                 .help("Create a ZIP archive instead of a self-extracting executable")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("bootstrap")
+                .long("bootstrap")
+                .help("Self-extracting bootstrap to emit (auto picks windows when every target is Windows)")
+                .value_parser(["auto", "windows", "posix"]),
+        )
+        .arg(
+            Arg::new("compression")
+                .long("compression")
+                .help("Compression for the self-extracting payload (zstd and brotli need the matching tool on the target machine)")
+                .value_parser(["gzip", "zstd", "brotli", "none"]),
+        )
+        .arg(
+            Arg::new("compression-level")
+                .long("compression-level")
+                .help("gzip and ZIP compression level, from 0 (fastest) to 9 (smallest)")
+                .value_parser(clap::value_parser!(u32).range(0..=9)),
+        )
+        .arg(
+            Arg::new("builder")
+                .long("builder")
+                .help("Tool that runs the builds (cross uses Docker toolchains, zig runs cargo zigbuild)")
+                .value_parser(["cargo", "cross", "zig"]),
+        )
+        .arg(
+            Arg::new("windows-stub")
+                .long("windows-stub")
+                .help("Path to a Windows build of rustpack-stub (defaults to rustpack-stub.exe next to rustpack)"),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .help("Package formats to write from one build (comma-separated: rpack, zip, deb, appimage, app, dmg, wasm)"),
+        )
         .arg(
             Arg::new("verbose")
                 .short('v')
@@ -295,6 +388,84 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("Enable verbose output")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .help("Only print errors")
+                .conflicts_with("verbose")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("log-file")
+                .long("log-file")
+                .help("Write a detailed JSON-lines build log to this file"),
+        )
+        .arg(
+            Arg::new("progress-webhook")
+                .long("progress-webhook")
+                .help("POST JSON progress updates to this URL (needs the webhook feature)"),
+        )
+        .arg(
+            Arg::new("desktop-entry")
+                .long("desktop-entry")
+                .help("Generate and bundle a freedesktop .desktop file for Linux GUI apps")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("icon")
+                .long("icon")
+                .help("Application icon to bundle with the package"),
+        )
+        .arg(
+            Arg::new("package")
+                .long("package")
+                .help("Workspace member to pack"),
+        )
+        .arg(
+            Arg::new("bin")
+                .long("bin")
+                .help("Binaries to package (comma-separated, the first runs by default)"),
+        )
+        .arg(
+            Arg::new("bins")
+                .long("bins")
+                .help("Package every binary of the crate")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("bundle-id")
+                .long("bundle-id")
+                .help("Bundle identifier of macOS app bundles (default dev.rustpack.<name>)"),
+        )
+        .arg(
+            Arg::new("no-windows-resources")
+                .long("no-windows-resources")
+                .help("Don't link the icon and version information into Windows executables")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("skip-missing-tools")
+                .long("skip-missing-tools")
+                .help("Build without stripping or UPX compression when strip or upx isn't installed, instead of failing")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("universal-macos")
+                .long("universal-macos")
+                .help("Merge the x86_64 and aarch64 macOS builds into one universal binary with lipo")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("categories")
+                .long("categories")
+                .help("Desktop entry categories (comma-separated, default Utility)"),
+        )
+        .arg(
+            Arg::new("comment")
+                .long("comment")
+                .help("Desktop entry comment (defaults to the package description)"),
+        )
         .arg(
             Arg::new("name")
                 .long("name")
@@ -305,11 +476,102 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .long("assets")
                 .help("Assets to include in the package (comma-separated)")
         )
+        .arg(
+            Arg::new("requires")
+                .long("requires")
+                .help("Runtime capabilities the package requires, e.g. cpu:avx2,kernel:5.4 (comma-separated)"),
+        )
+        .arg(
+            Arg::new("output-manifest")
+                .long("output-manifest")
+                .help("Also write the generated info.json to this path"),
+        )
+        .arg(
+            Arg::new("env-file")
+                .long("env-file")
+                .help("Write the pack results as KEY=VALUE lines (e.g. for $GITHUB_ENV)"),
+        )
+        .arg(
+            Arg::new("report")
+                .long("report")
+                .help("Write a versioned JSON build report (targets, sizes, sections, checksums, timing) to this path"),
+        )
+        .arg(
+            Arg::new("licenses")
+                .long("licenses")
+                .help("Bundle the license texts of every dependency, with a THIRD-PARTY-NOTICES.md summary")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("sbom")
+                .long("sbom")
+                .help("Also write the package's CycloneDX SBOM to this path"),
+        )
+        .arg(
+            Arg::new("channel")
+                .long("channel")
+                .help("Release channel the package is published on (e.g. stable, beta)"),
+        )
+        .arg(
+            Arg::new("channel-manifest")
+                .long("channel-manifest")
+                .help("Add this build to a channel-aware update manifest (version.json)"),
+        )
+        .arg(
+            Arg::new("max-package-size")
+                .long("max-package-size")
+                .help("Abort if the finished package is larger than this (e.g. 50M, 1.5G)"),
+        )
+        .arg(
+            Arg::new("bloat")
+                .long("bloat")
+                .num_args(0..=1)
+                .default_missing_value("10")
+                .value_parser(clap::value_parser!(usize))
+                .help("Show the N crates (default 10) that contribute the most to each target's binaries, also in --report"),
+        )
+        .arg(
+            Arg::new("max-size")
+                .long("max-size")
+                .help("Fail the build if a binary is larger than this once stripped and compressed (e.g. 5000000, 8M)"),
+        )
         .arg(
             Arg::new("update-url")
                 .long("update-url")
                 .help("URL for checking and downloading updates"),
         )
+        .arg(
+            Arg::new("release-dir")
+                .long("release-dir")
+                .help("Directory to publish latest.rpack, its checksum and version.json to for the updater (needs --update-url)"),
+        )
+        .arg(
+            Arg::new("min-update-version")
+                .long("min-update-version")
+                .help("Oldest installed version that may update to this release"),
+        )
+        .arg(
+            Arg::new("delta-updates")
+                .long("delta-updates")
+                .help("Also publish a patch from the previous release in --release-dir for the updater")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no-timestamp")
+                .long("no-timestamp")
+                .help("Leave the build time out of info.json and the SBOM")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("upload")
+                .long("upload")
+                .help("Upload the packages and their checksum and signature files to s3://bucket/prefix with the aws CLI"),
+        )
+        .arg(
+            Arg::new("publish-github")
+                .long("publish-github")
+                .help("Upload the packages and their checksums to the GitHub release owner/repo@tag (tag defaults to v<version>; needs GITHUB_TOKEN)"),
+        )
         .arg(
             Arg::new("create-patch")
                 .long("create-patch")
@@ -337,16 +599,95 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             .long("patch-file")
             .help("Path to the patch file to apply"),
         )
-        .get_matches();
+        .arg(
+            Arg::new("create-patch-chain")
+                .long("create-patch-chain")
+                .help("Create patches between each consecutive pair of --versions into --patch-output")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("versions")
+                .long("versions")
+                .help("Binaries of successive versions, oldest first (comma-separated)"),
+        )
+        .arg(
+            Arg::new("apply-patch-chain")
+                .long("apply-patch-chain")
+                .help("Apply --patch-files in order to the input binary, verifying each step")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("patch-files")
+                .long("patch-files")
+                .help("Patch files to apply in order (comma-separated)"),
+        )
+        .disable_help_subcommand(true)
+        .subcommand(
+            Command::new("man")
+                .about("Print the man page as roff to stdout (view it with `man -l -`)"),
+        )
+        .subcommand(
+            Command::new("self-update")
+                .about("Replace this rustpack with the latest release from GitHub, if it is newer")
+                .arg(
+                    Arg::new("check-only")
+                        .long("check-only")
+                        .help("Only report whether a newer release is available")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // https://no-color.org: a non-empty NO_COLOR turns off colored output.
+    if std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+        colored::control::set_override(false);
+    }
+    // clap exits with 2 on usage errors, which is the code for a failed build.
+    let matches = cli().try_get_matches().unwrap_or_else(|e| {
+        let _ = e.print();
+        std::process::exit(if e.use_stderr() { 1 } else { 0 });
+    });
+
+    if matches.subcommand_name() == Some("man") {
+        clap_mangen::Man::new(cli().name("rustpack")).render(&mut std::io::stdout())?;
+        return Ok(());
+    }
+    if let Some(("self-update", args)) = matches.subcommand() {
+        match self_update(args.get_flag("check-only")) {
+            Ok(update) => match &update.available {
+                None => println!("rustpack {} is the latest version", update.current),
+                Some(version) => {
+                    if update.installed {
+                        println!("{} rustpack {} to {}", "Updated".green(), update.current, version);
+                    } else {
+                        println!("rustpack {} is available (this is {}); run `rustpack self-update` to install it", version, update.current);
+                    }
+                    println!("Changelog: {}", update.release_url);
+                }
+            },
+            Err(e) => {
+                eprintln!("Self-update failed: {}", e);
+                std::process::exit(exit_code(&e));
+            }
+        }
+        return Ok(());
+    }
         
-    let env_config = load_env_config();
+    let env_config = match load_env_config() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}: {}", "Invalid configuration".red().bold(), e);
+            std::process::exit(exit_code(&e));
+        }
+    };
     
 if matches.get_flag("create-patch") {
     if let (Some(old_version), Some(patch_output)) = (
         matches.get_one::<String>("old-version"),
         matches.get_one::<String>("patch-output")
     ) {
-        let new_version = matches.get_one::<String>("input").unwrap();
+        let new_version = matches.get_one::<String>("input").map_or(".", String::as_str);
         println!("Creating binary patch from {} to {}", old_version, new_version);
         
         if let Err(e) = create_binary_patch(
@@ -355,7 +696,7 @@ if matches.get_flag("create-patch") {
             Path::new(patch_output)
         ) {
             eprintln!("Failed to create patch: {}", e);
-            std::process::exit(1);
+            std::process::exit(exit_code(&e));
         }
         
         println!("Patch created successfully: {}", patch_output);
@@ -371,7 +712,7 @@ if matches.get_flag("apply-patch") {
         matches.get_one::<String>("patch-file"),
         matches.get_one::<String>("output")
     ) {
-        let input = matches.get_one::<String>("input").unwrap();
+        let input = matches.get_one::<String>("input").map_or(".", String::as_str);
         println!("Applying patch {} to {} and saving as {}", patch_file, input, output);
         
         if let Err(e) = apply_binary_patch(
@@ -380,7 +721,7 @@ if matches.get_flag("apply-patch") {
             Path::new(output)
         ) {
             eprintln!("Failed to apply patch: {}", e);
-            std::process::exit(1);
+            std::process::exit(exit_code(&e));
         }
         
         println!("Patch applied successfully: {}", output);
@@ -391,854 +732,1119 @@ if matches.get_flag("apply-patch") {
     }
 }
 
-    let project_path = matches.get_one::<String>("input").unwrap();
-    let config = read_config_file(project_path)?;
-    let project_name = matches.get_one::<String>("name")
-        .map(|s| s.to_string())
-        .or_else(|| config.name.clone())
-        .unwrap_or_else(|| get_project_name(project_path).unwrap_or_else(|_| "unknown".to_string()));
-    
-    let projectname = format!("{}.rpack", project_name);
-    let output_name = matches
-        .get_one::<String>("output")
-        .map(|s| s.to_string())
-        .or_else(|| config.output.clone())
-        .unwrap_or(projectname);
+if matches.get_flag("create-patch-chain") {
+    if let (Some(versions), Some(patch_output)) = (
+        matches.get_one::<String>("versions"),
+        matches.get_one::<String>("patch-output")
+    ) {
+        let versions: Vec<String> = versions.split(',').map(|s| s.trim().to_string()).collect();
+        match create_patch_chain(&versions, Path::new(patch_output)) {
+            Ok(patches) => {
+                for patch in patches {
+                    println!("Patch created successfully: {}", patch.display());
+                }
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("Failed to create patch chain: {}", e);
+                std::process::exit(exit_code(&e));
+            }
+        }
+    } else {
+        eprintln!("When using --create-patch-chain, both --versions and --patch-output are required");
+        std::process::exit(1);
+    }
+}
 
-    let targets = matches
-        .get_one::<String>("targets")
-        .map(|t| t.split(',').map(|s| s.trim().to_string()).collect::<Vec<_>>())
-        .or_else(|| config.assets.clone())
-        .unwrap_or_else(|| vec![get_current_target()]);
-        
-    let assets = matches
-        .get_one::<String>("assets")
-        .map(|a| a.split(',').map(|s| s.trim().to_string()).collect())
-        .or_else(|| config.assets.clone())
-        .unwrap_or_else(Vec::new);
-let build_config = BuildConfig {
-    strip: matches.get_flag("strip") || config.strip.unwrap_or(env_config.strip),
-    compress: matches.get_flag("compress") || config.compress.unwrap_or(env_config.compress),
-    lto: Some(matches.get_one::<String>("lto").unwrap_or(&config.lto.clone().unwrap_or(env_config.lto.unwrap_or_else(|| "off".to_string()))).clone()),
-    debug_symbols: !(matches.get_flag("strip") || config.strip.unwrap_or(env_config.strip)),
-    sign: matches
-        .get_one::<String>("sign")
-        .map(|s| s.to_string())
-        .or_else(|| config.sign.clone())
-        .unwrap_or(env_config.sign),
-    profile: matches
-        .get_one::<String>("profile")
-        .map(|s| s.to_string())
-        .or_else(|| config.profile.clone())
-        .unwrap_or(env_config.profile),
-    features: matches
-        .get_one::<String>("features")
-        .map(|f| f.split(',').map(|s| s.trim().to_string()).collect())
-        .or_else(|| config.features.clone())
-        .unwrap_or(env_config.features),
-    assets: matches
-        .get_one::<String>("assets")
-        .map(|a| a.split(',').map(|s| s.trim().to_string()).collect())
-        .or_else(|| config.assets.clone())
-        .unwrap_or(env_config.assets),
-};
+if matches.get_flag("apply-patch-chain") {
+    if let (Some(patch_files), Some(output)) = (
+        matches.get_one::<String>("patch-files"),
+        matches.get_one::<String>("output")
+    ) {
+        let input = matches.get_one::<String>("input").map_or(".", String::as_str);
+        let patches: Vec<String> = patch_files.split(',').map(|s| s.trim().to_string()).collect();
+        println!("Applying {} patches to {} and saving as {}", patches.len(), input, output);
 
-    let verbose = matches.get_flag("verbose") || config.verbose.unwrap_or(false);
-    let create_zip = matches.get_flag("zip") || config.zip.unwrap_or(false);
-    let watch_mode = matches.get_flag("watch") || config.watch.unwrap_or(false);
-    
-    if verbose {
-        println!("{} Rust project: {}", "Packing".green(), project_path);
-        println!("{} for targets: {:?}", "Building".green(), targets);
-    }
+        if let Err(e) = apply_patch_chain(Path::new(input), &patches, Path::new(output)) {
+            eprintln!("Failed to apply patch chain: {}", e);
+            std::process::exit(exit_code(&e));
+        }
 
-    if watch_mode {
-        watch_and_build(project_path, &output_name, &targets, &build_config, verbose)?;
+        println!("Patch chain applied successfully: {}", output);
+        return Ok(());
     } else {
-        build_package(project_path, &output_name, &targets, &build_config, verbose, create_zip)?;
-    }
-
-    if verbose {
-        println!("{} created successfully: {}", "Package".green().bold(), output_name);
+        eprintln!("When using --apply-patch-chain, both --patch-files and --output are required");
+        std::process::exit(1);
     }
-    
-    Ok(())
 }
 
-fn watch_and_build(
-    project_path: &str, 
-    output_name: &str, 
-    targets: &[String],
-    build_config: &BuildConfig,
-    verbose: bool,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-    
-    let (tx, rx) = channel();
-    let mut watcher = watcher(tx, Duration::from_secs(2))?;
-    watcher.watch(project_path, RecursiveMode::Recursive)?;
-
-    println!("{} for changes in {}...", "Watching".blue().bold(), project_path);
-    
-    build_package(project_path, output_name, targets, build_config, verbose, false)?;
-    
-    let mut last_build = Instant::now();
-    
-    loop {
-        match rx.recv() {
-            Ok(_) => {
-                if last_build.elapsed() > Duration::from_secs(5) {
-                    println!("{} changes, rebuilding...", "Detected".yellow().bold());
-                    if let Err(e) = build_package(project_path, output_name, targets, build_config, verbose, false) {
-                        println!("{}: {}", "Build failed".red().bold(), e);
-                    } else {
-                        println!("{}", "Rebuild successful".green().bold());
-                    }
-                    last_build = Instant::now();
-                }
+if matches.get_flag("doctor") {
+    let mut outdated = false;
+    for requirement in TOOL_REQUIREMENTS {
+        match check_tool_version(requirement) {
+            ToolStatus::Ok(version) => println!("{} {} {}", "ok".green().bold(), requirement.name, version),
+            ToolStatus::TooOld(version) => {
+                outdated = true;
+                println!(
+                    "{} {} {} (need {} or newer for {})",
+                    "outdated".red().bold(),
+                    requirement.name,
+                    version,
+                    requirement.min_version,
+                    requirement.reason
+                );
             }
-            Err(e) => println!("Watch error: {:?}", e),
+            ToolStatus::Unknown(output) => println!("{} {} (could not parse version from {:?})", "unknown".yellow().bold(), requirement.name, output),
+            ToolStatus::Missing => println!("{} {}", "missing".yellow().bold(), requirement.name),
         }
     }
+    if outdated {
+        std::process::exit(1);
+    }
+    return Ok(());
 }
 
-fn get_project_name(project_path: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let cargo_toml = Path::new(project_path).join("Cargo.toml");
-    let cargo_content = fs::read_to_string(cargo_toml)?;
-
-    cargo_content
-        .lines()
-        .find_map(|line| {
-            if line.trim().starts_with("name =") {
-                let parts: Vec<&str> = line.split('=').collect();
-                if parts.len() >= 2 {
-                    return Some(parts[1].trim().trim_matches('"').to_string());
-                }
-            }
-            None
-        })
-        .ok_or_else(|| "Could not determine project name from Cargo.toml".into())
+if matches.get_flag("init") {
+    let project_path = matches.get_one::<String>("input").map_or(".", String::as_str);
+    match init_config(project_path, matches.get_flag("force")) {
+        Ok(path) => println!("{} {}; run `rustpack --dry-run` to see what it would build", "Created".green(), path.display()),
+        Err(e) => {
+            let e = RustPackError::from(e);
+            eprintln!("{}: {}", "Failed to create RustPack.toml".red().bold(), e);
+            std::process::exit(exit_code(&e));
+        }
+    }
+    return Ok(());
 }
 
-fn get_project_version(project_path: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let cargo_toml = Path::new(project_path).join("Cargo.toml");
-    let cargo_content = fs::read_to_string(cargo_toml)?;
-
-    cargo_content
-        .lines()
-        .find_map(|line| {
-            if line.trim().starts_with("version =") {
-                let parts: Vec<&str> = line.split('=').collect();
-                if parts.len() >= 2 {
-                    return Some(parts[1].trim().trim_matches('"').to_string());
-                }
-            }
-            None
-        })
-        .ok_or_else(|| "Could not determine project version from Cargo.toml".into())
+if let Some(package_path) = matches.get_one::<String>("extract") {
+    let Some(dest) = matches.get_one::<String>("output") else {
+        eprintln!("--extract needs --output <dir>");
+        std::process::exit(1);
+    };
+    match extract_package(Path::new(package_path), Path::new(dest)) {
+        Ok(info) => {
+            println!(
+                "{} {} {} to {} (checksums verified)",
+                "Extracted".green(),
+                info.name,
+                info.version,
+                Path::new(dest).join("rustpack").display()
+            );
+        }
+        Err(e) => {
+            eprintln!("Failed to extract {}: {}", package_path, e);
+            std::process::exit(exit_code(&e));
+        }
+    }
+    return Ok(());
 }
 
-fn get_project_description(project_path: &str) -> Option<String> {
-    let cargo_toml = Path::new(project_path).join("Cargo.toml");
-    if let Ok(cargo_content) = fs::read_to_string(cargo_toml) {
-        for line in cargo_content.lines() {
-            if line.trim().starts_with("description =") {
-                let parts: Vec<&str> = line.split('=').collect();
-                if parts.len() >= 2 {
-                    return Some(parts[1].trim().trim_matches('"').to_string());
-                }
+if let Some(package_path) = matches.get_one::<String>("list") {
+    let package = match Package::open(Path::new(package_path)) {
+        Ok(package) => package,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", package_path, e);
+            std::process::exit(1);
+        }
+    };
+    if matches.get_flag("raw") {
+        match package.entry("rustpack/info.json") {
+            Some(entry) => println!("{}", String::from_utf8_lossy(&entry.data).trim_end()),
+            None => {
+                eprintln!("{} does not contain rustpack/info.json", package_path);
+                std::process::exit(1);
             }
         }
+        return Ok(());
+    }
+    match package.info() {
+        Ok(info) => print_package_info(&package, &info),
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", package_path, e);
+            std::process::exit(1);
+        }
     }
-    None
+    return Ok(());
 }
 
-fn get_current_target() -> String {
-    let output = ProcessCommand::new("rustc")
-        .args(&["-vV"])
-        .output()
-        .expect("Failed to execute rustc");
-
-    let output_str = String::from_utf8_lossy(&output.stdout);
+if let Some(package_path) = matches.get_one::<String>("list-contents") {
+    let package = match Package::open(Path::new(package_path)) {
+        Ok(package) => package,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", package_path, e);
+            std::process::exit(1);
+        }
+    };
+    let long = matches.get_flag("long");
+    for entry in package.entries() {
+        let path = if entry.is_dir { format!("{}/", entry.path) } else { entry.path.clone() };
+        if long {
+            let mtime = DateTime::<Utc>::from_timestamp(entry.mtime as i64, 0)
+                .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default();
+            println!("{} {:>10} {} {}", format_mode(entry.mode, entry.is_dir), entry.data.len(), mtime, path);
+        } else {
+            println!("{:>10} {}", entry.data.len(), path);
+        }
+    }
+    return Ok(());
+}
 
-    for line in output_str.lines() {
-        if line.starts_with("host:") {
-            return line.split(':').nth(1).unwrap_or("unknown").trim().to_string();
+if let Some(mut paths) = matches.get_many::<String>("diff") {
+    let (old_path, new_path) = (paths.next().unwrap(), paths.next().unwrap());
+    let diff = match diff_packages(Path::new(old_path), Path::new(new_path)) {
+        Ok(diff) => diff,
+        Err(e) => {
+            eprintln!("Failed to compare {} and {}: {}", old_path, new_path, e);
+            std::process::exit(exit_code(&e));
         }
+    };
+    if matches.get_flag("json") {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+        return Ok(());
     }
 
-    "unknown".to_string()
+    println!("{} {} -> {}", "Comparing".blue(), diff.old, diff.new);
+    println!("  {:<24} {:>12} {:>12} {:>18}", "", "OLD", "NEW", "CHANGE");
+    print_size_change("package", &diff.package_size);
+    for target in &diff.targets {
+        println!("  {}", target.target.bold());
+        print_size_change("  total", &target.size);
+        // Largest changes first; unchanged sections would only be noise.
+        let mut sections: Vec<_> = target
+            .sections
+            .iter()
+            .filter(|(_, change)| change.delta() != 0 || change.old.is_none() != change.new.is_none())
+            .collect();
+        sections.sort_by_key(|(_, change)| std::cmp::Reverse(change.delta().abs()));
+        for (name, change) in &sections {
+            print_size_change(&format!("  {}", name), change);
+        }
+        let unchanged = target.sections.len() - sections.len();
+        if unchanged > 0 {
+            println!("    {} unchanged sections", unchanged);
+        }
+    }
+    if !diff.assets_added.is_empty() || !diff.assets_removed.is_empty() {
+        println!("  {}", "assets".bold());
+        for asset in &diff.assets_added {
+            println!("    {} {}", "+".green(), asset);
+        }
+        for asset in &diff.assets_removed {
+            println!("    {} {}", "-".red(), asset);
+        }
+    }
+    return Ok(());
 }
 
-fn parse_target(target: &str) -> (String, String, Vec<String>) {
-    let parts: Vec<&str> = target.split('-').collect();
-
-    if parts.len() < 2 {
-        return ("unknown".to_string(), "unknown".to_string(), vec![]);
+if let Some(package_path) = matches.get_one::<String>("verify-files") {
+    let files: Option<Vec<String>> = matches
+        .get_one::<String>("files")
+        .map(|f| f.split(',').map(|s| s.trim().to_string()).collect());
+    match verify_package_files(Path::new(package_path), files.as_deref()) {
+        Ok(results) => {
+            let mut failed = false;
+            for (path, ok) in results {
+                if ok {
+                    println!("{} {}", "ok".green().bold(), path);
+                } else {
+                    failed = true;
+                    println!("{} {}", "FAILED".red().bold(), path);
+                }
+            }
+            if failed {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Err(e) => {
+            eprintln!("{} {}: {}", "Verification failed".red().bold(), package_path, e);
+            std::process::exit(exit_code(&e));
+        }
     }
+}
 
-    let arch = parts[0].to_string();
+if let Some(package_path) = matches.get_one::<String>("verify") {
+    let hmac_key = matches
+        .get_one::<String>("sign-hmac")
+        .map(|s| s.to_string())
+        .unwrap_or(env_config.sign);
+    let verify_key = matches.get_one::<String>("verify-key");
 
-    let platform = if target.contains("windows") {
-        "windows".to_string()
-    } else if target.contains("linux") {
-        "linux".to_string()
-    } else if target.contains("darwin") || target.contains("apple") {
-        "macos".to_string()
+    let result = if verify_key.is_none() && !hmac_key.is_empty() {
+        verify_package(Path::new(package_path), &hmac_key)
     } else {
-        "unknown".to_string()
-    };
-
-    let compatibility = match platform.as_str() {
-        "windows" => vec!["nt6.1".to_string(), "pe".to_string()],
-        "linux" => vec!["glibc-2.17".to_string(), "elf".to_string()],
-        "macos" => vec!["10.7".to_string(), "mach-o".to_string()],
-        _ => vec![],
+        verify_detached_signature(Path::new(package_path), verify_key.map(Path::new))
     };
+    if let Err(e) = result {
+        eprintln!("{} {}: {}", "Verification failed".red().bold(), package_path, e);
+        std::process::exit(exit_code(&e));
+    }
 
-    (platform, arch, compatibility)
+    println!("{} {}", "Signature OK:".green().bold(), package_path);
+    if verify_key.is_none() && hmac_key.is_empty() {
+        println!("Checked against the public key inside the package, which shows it is intact but not who signed it; pass --verify-key to check the signer.");
+    }
+    return Ok(());
 }
 
-fn build_for_target(
-    project_path: &str, 
-    bin_dir: &Path, 
-    target: &str, 
-    project_name: &str, 
-    build_config: &BuildConfig,
-    verbose: bool,
-) -> Result<(PathBuf, Vec<String>), Box<dyn std::error::Error>> {
-    let features_args = if build_config.features.is_empty() {
-        vec![]
-    } else {
-        vec!["--features".to_string(), build_config.features.join(",")]
+    let project_path = matches.get_one::<String>("input").map_or(".", String::as_str);
+    let config = match read_config_file(project_path) {
+        Ok(config) if config.compression_level.is_some_and(|level| level > 9) => {
+            eprintln!("{}: compression_level must be between 0 and 9", "Invalid configuration".red().bold());
+            std::process::exit(1);
+        }
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}: {}", "Invalid configuration".red().bold(), e);
+            std::process::exit(1);
+        }
     };
-
-    let mut cargo_args = vec![
-        "build".to_string(),
-        format!("--{}", build_config.profile),
-        "--target".to_string(), 
-        target.to_string(),
-    ];
-
-    cargo_args.extend(features_args);
-
-    if verbose {
-        println!("Running: cargo {}", cargo_args.join(" "));
-    }
-
-    let pb = if !verbose {
-        let pb = ProgressBar::new_spinner();
-        pb.set_style(ProgressStyle::default_spinner()
-            .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
-            .template("{spinner} {msg}").unwrap());
-        pb.set_message(format!("Building for {}", target));
-        Some(pb)
-    } else {
-        None
+    let package = matches
+        .get_one::<String>("package")
+        .map(|s| s.to_string())
+        .or_else(|| config.package.clone())
+        .or(env_config.package.clone());
+    let project_name = matches.get_one::<String>("name")
+        .map(|s| s.to_string())
+        .or_else(|| config.name.clone())
+        .unwrap_or_else(|| {
+            package_dir(project_path, package.as_deref())
+                .and_then(|dir| parse_manifest(&dir))
+                .map(|m| m.name)
+                .unwrap_or_else(|_| "unknown".to_string())
+        });
+    
+    let create_zip = matches.get_flag("zip") || config.zip.unwrap_or(false);
+    let formats = match matches
+        .get_one::<String>("format")
+        .map(|f| f.split(',').map(|s| s.trim().to_string()).collect::<Vec<_>>())
+        .or_else(|| config.format.clone())
+    {
+        Some(formats) => match formats.iter().map(|f| f.parse()).collect::<Result<Vec<PackageFormat>, _>>() {
+            Ok(formats) => formats,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        None if create_zip => vec![PackageFormat::Zip],
+        None => vec![PackageFormat::SelfExtracting],
     };
+    // A wasm bundle can't hold a native binary, so it builds for wasm32 by
+    // default.
+    let wasm_only = formats == [PackageFormat::Wasm];
+    let targets = matches
+        .get_one::<String>("targets")
+        .map(|t| t.split(',').map(|s| s.trim().to_string()).collect::<Vec<_>>())
+        .or_else(|| config.targets.clone())
+        .unwrap_or_else(|| vec![if wasm_only { "wasm32-unknown-unknown".to_string() } else { get_current_target() }]);
 
-    if let Some(lto_type) = &build_config.lto {
-        if lto_type != "off" {
-            fs::create_dir_all(Path::new(project_path).join(".cargo"))?;
-            let config_content = format!(r#"
-[profile.release]
-lto = "{}"
-codegen-units = 1
-"#, lto_type);
-            fs::write(Path::new(project_path).join(".cargo").join("config.toml"), config_content)?;
+    let bootstrap = matches
+        .get_one::<String>("bootstrap")
+        .map(|s| s.to_string())
+        .or_else(|| config.bootstrap.clone())
+        .unwrap_or(env_config.bootstrap);
+    let windows_bootstrap = match uses_windows_bootstrap(&bootstrap, &targets) {
+        Ok(windows) => windows,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
         }
-    }
+    };
 
-    let status = ProcessCommand::new("cargo")
-        .current_dir(project_path)
-        .args(&cargo_args)
-        .status()?;
+    let extension = if windows_bootstrap { "exe" } else { "rpack" };
+    let projectname = if wasm_only { format!("{}-wasm", project_name) } else { format!("{}.{}", project_name, extension) };
+    let output_name = matches
+        .get_one::<String>("output")
+        .map(|s| s.to_string())
+        .or_else(|| config.output.clone())
+        .unwrap_or(projectname);
 
-    if let Some(ref pb) = pb {
-        pb.finish_and_clear();
+    let mut metadata = config.metadata.clone().unwrap_or_default();
+    for pair in matches.get_many::<String>("meta").into_iter().flatten() {
+        let Some((key, value)) = pair.split_once('=') else {
+            eprintln!("--meta expects key=value, got {}", pair);
+            std::process::exit(1);
+        };
+        metadata.insert(key.to_string(), value.to_string());
     }
 
-    if !status.success() {
-        return Err(format!("Failed to build for target: {}", target).into());
-    }
+let build_config = BuildConfig {
+    strip: matches.get_flag("strip") || matches.contains_id("strip-level") || config.strip.unwrap_or(env_config.strip),
+    strip_level: matches
+        .get_one::<String>("strip-level")
+        .map(|s| s.to_string())
+        .or_else(|| config.strip_level.clone())
+        .unwrap_or(env_config.strip_level),
+    strip_tool: matches
+        .get_one::<String>("strip-tool")
+        .map(|s| s.to_string())
+        .or_else(|| config.strip_tool.clone())
+        .or(env_config.strip_tool),
+    compress: matches.get_flag("compress") || config.compress.unwrap_or(env_config.compress),
+    strip_rpath: matches.get_flag("strip-rpath") || config.strip_rpath.unwrap_or(env_config.strip_rpath),
+    bundle_libs: matches.get_flag("bundle-libs") || config.bundle_libs.unwrap_or(env_config.bundle_libs),
+    lto: matches
+        .get_one::<String>("lto")
+        .cloned()
+        .or_else(|| config.lto.clone())
+        .or(env_config.lto),
+    prefetch_deps: matches.get_flag("prefetch-deps") || config.prefetch_deps.unwrap_or(env_config.prefetch_deps),
+    debug_symbols: !(matches.get_flag("strip") || matches.contains_id("strip-level") || config.strip.unwrap_or(env_config.strip)),
+    sign: matches
+        .get_one::<String>("sign-hmac")
+        .map(|s| s.to_string())
+        .or_else(|| config.sign.clone())
+        .unwrap_or(env_config.sign),
+    sign_key: matches
+        .get_one::<String>("sign-key")
+        .map(|s| s.to_string())
+        .or_else(|| config.sign_key.clone())
+        .or(env_config.sign_key),
+    profile: matches
+        .get_one::<String>("profile")
+        .map(|s| s.to_string())
+        .or_else(|| config.profile.clone())
+        .unwrap_or(env_config.profile),
+    features: matches
+        .get_one::<String>("features")
+        .map(|f| f.split(',').map(|s| s.trim().to_string()).collect())
+        .or_else(|| config.features.clone())
+        .unwrap_or(env_config.features),
+    no_default_features: matches.get_flag("no-default-features") || config.no_default_features.unwrap_or(env_config.no_default_features),
+    cargo_args: matches
+        .get_many::<String>("cargo-arg")
+        .map(|args| args.cloned().collect())
+        .or_else(|| config.cargo_args.clone())
+        .unwrap_or(env_config.cargo_args),
+    target_dir: matches
+        .get_one::<String>("target-dir")
+        .cloned()
+        .or_else(|| config.target_dir.clone())
+        .or(env_config.target_dir),
+    rustflags: config.rustflags.clone().unwrap_or(env_config.rustflags),
+    assets: matches
+        .get_one::<String>("assets")
+        .map(|a| a.split(',').map(|s| s.trim().to_string()).collect())
+        .or_else(|| config.assets.clone())
+        .unwrap_or(env_config.assets),
+    requires: matches
+        .get_one::<String>("requires")
+        .map(|r| r.split(',').map(|s| s.trim().to_string()).collect())
+        .or_else(|| config.requires.clone())
+        .unwrap_or(env_config.requires),
+    output_manifest: matches
+        .get_one::<String>("output-manifest")
+        .map(|s| s.to_string())
+        .or_else(|| config.output_manifest.clone())
+        .or(env_config.output_manifest),
+    env_file: matches
+        .get_one::<String>("env-file")
+        .map(|s| s.to_string())
+        .or_else(|| config.env_file.clone())
+        .or(env_config.env_file),
+    report: matches
+        .get_one::<String>("report")
+        .map(|s| s.to_string())
+        .or_else(|| config.report.clone())
+        .or(env_config.report),
+    licenses: matches.get_flag("licenses") || config.licenses.unwrap_or(env_config.licenses),
+    sbom: matches
+        .get_one::<String>("sbom")
+        .map(|s| s.to_string())
+        .or_else(|| config.sbom.clone())
+        .or(env_config.sbom),
+    channel: matches
+        .get_one::<String>("channel")
+        .map(|s| s.to_string())
+        .or_else(|| config.channel.clone())
+        .unwrap_or(env_config.channel),
+    channel_manifest: matches
+        .get_one::<String>("channel-manifest")
+        .map(|s| s.to_string())
+        .or_else(|| config.channel_manifest.clone())
+        .or(env_config.channel_manifest),
+    max_package_size: match matches
+        .get_one::<String>("max-package-size")
+        .or(config.max_package_size.as_ref())
+    {
+        Some(size) => Some(parse_size(size)?),
+        None => env_config.max_package_size,
+    },
+    bloat: matches.get_one::<usize>("bloat").copied().or(config.bloat).or(env_config.bloat),
+    max_size: match matches.get_one::<String>("max-size").or(config.max_size.as_ref()) {
+        Some(size) => Some(parse_size(size)?),
+        None => env_config.max_size,
+    },
+    update_url: matches
+        .get_one::<String>("update-url")
+        .map(|s| s.to_string())
+        .or_else(|| config.update_url.clone())
+        .or(env_config.update_url),
+    release_dir: matches
+        .get_one::<String>("release-dir")
+        .cloned()
+        .or_else(|| config.release_dir.clone())
+        .or(env_config.release_dir),
+    min_update_version: matches
+        .get_one::<String>("min-update-version")
+        .cloned()
+        .or_else(|| config.min_update_version.clone())
+        .or(env_config.min_update_version),
+    delta_updates: matches.get_flag("delta-updates") || config.delta_updates.unwrap_or(env_config.delta_updates),
+    no_timestamp: matches.get_flag("no-timestamp") || config.no_timestamp.unwrap_or(env_config.no_timestamp),
+    upload: matches
+        .get_one::<String>("upload")
+        .cloned()
+        .or_else(|| config.upload.clone())
+        .or(env_config.upload),
+    publish_github: matches
+        .get_one::<String>("publish-github")
+        .cloned()
+        .or_else(|| config.publish_github.clone())
+        .or(env_config.publish_github),
+    desktop_entry: matches.get_flag("desktop-entry") || config.desktop_entry.unwrap_or(env_config.desktop_entry),
+    icon: matches
+        .get_one::<String>("icon")
+        .map(|s| s.to_string())
+        .or_else(|| config.icon.clone())
+        .or(env_config.icon),
+    categories: matches
+        .get_one::<String>("categories")
+        .map(|c| c.split(',').map(|s| s.trim().to_string()).collect())
+        .or_else(|| config.categories.clone())
+        .unwrap_or(env_config.categories),
+    bootstrap,
+    compression: matches
+        .get_one::<String>("compression")
+        .map(|s| s.to_string())
+        .or_else(|| config.compression.clone())
+        .unwrap_or(env_config.compression),
+    compression_level: matches
+        .get_one::<u32>("compression-level")
+        .copied()
+        .or(config.compression_level)
+        .or(env_config.compression_level),
+    builder: matches
+        .get_one::<String>("builder")
+        .map(|s| s.to_string())
+        .or_else(|| config.builder.clone())
+        .unwrap_or(env_config.builder),
+    jobs: matches
+        .get_one::<usize>("jobs")
+        .copied()
+        .or(config.jobs)
+        .unwrap_or(env_config.jobs),
+    windows_stub: matches
+        .get_one::<String>("windows-stub")
+        .map(|s| s.to_string())
+        .or_else(|| config.windows_stub.clone())
+        .or(env_config.windows_stub),
+    target_overrides: config.target.clone().unwrap_or_default(),
+    multicall: config.multicall.clone().unwrap_or_default(),
+    desktop: match &config.desktop {
+        Some(DesktopConfig::Keys(keys)) => keys.clone(),
+        _ => BTreeMap::new(),
+    },
+    desktop_file: match &config.desktop {
+        Some(DesktopConfig::File(file)) => Some(file.clone()),
+        _ => None,
+    },
+    comment: matches
+        .get_one::<String>("comment")
+        .cloned()
+        .or_else(|| config.comment.clone())
+        .or(env_config.comment),
+    metadata,
+    universal_macos: matches.get_flag("universal-macos") || config.universal_macos.unwrap_or(env_config.universal_macos),
+    windows_resources: !matches.get_flag("no-windows-resources") && config.windows_resources.unwrap_or(env_config.windows_resources),
+    bundle_id: matches
+        .get_one::<String>("bundle-id")
+        .map(|s| s.to_string())
+        .or_else(|| config.bundle_id.clone())
+        .or(env_config.bundle_id),
+    package,
+    bins: matches
+        .get_one::<String>("bin")
+        .map(|b| b.split(',').map(|s| s.trim().to_string()).collect())
+        .or_else(|| config.bin.clone())
+        .unwrap_or(env_config.bins),
+    all_bins: matches.get_flag("bins") || config.bins.unwrap_or(env_config.all_bins),
+    pre_build: matches
+        .get_many::<String>("pre-build")
+        .map(|hooks| hooks.cloned().collect())
+        .or_else(|| config.pre_build.clone())
+        .unwrap_or(env_config.pre_build),
+    post_build: matches
+        .get_many::<String>("post-build")
+        .map(|hooks| hooks.cloned().collect())
+        .or_else(|| config.post_build.clone())
+        .unwrap_or(env_config.post_build),
+    skip_missing_tools: matches.get_flag("skip-missing-tools")
+        || config.skip_missing_tools.unwrap_or(env_config.skip_missing_tools),
+};
 
-    let binary_path = Path::new(project_path)
-        .join("target")
-        .join(target)
-        .join(&build_config.profile)
-        .join(project_name);
+    let quiet = matches.get_flag("quiet") || (!matches.get_flag("verbose") && config.quiet.unwrap_or(false));
+    let verbose = !quiet && (matches.get_flag("verbose") || config.verbose.unwrap_or(false));
+    log::set_verbosity(if quiet {
+        log::Verbosity::Quiet
+    } else if verbose {
+        log::Verbosity::Verbose
+    } else {
+        log::Verbosity::Normal
+    });
+    let watch_mode = matches.get_flag("watch") || config.watch.unwrap_or(false);
 
-    let ext = if target.contains("windows") { ".exe" } else { "" };
-    let binary_with_ext = format!("{}{}", project_name, ext);
-    let binary_path_with_ext = Path::new(project_path)
-        .join("target")
-        .join(target)
-        .join(&build_config.profile)
-        .join(format!("{}{}", project_name, ext));
-    
-    let dest_path = bin_dir.join(&binary_with_ext);
-    fs::copy(&binary_path_with_ext, &dest_path)?;
+    if let Some(log_file) = matches
+        .get_one::<String>("log-file")
+        .or(config.log_file.as_ref())
+        && let Err(e) = log::init_file(log_file)
+    {
+        eprintln!("Could not open log file {}: {}", log_file, e);
+        std::process::exit(1);
+    }
+    if let Some(url) = matches
+        .get_one::<String>("progress-webhook")
+        .or(config.progress_webhook.as_ref())
+        && let Err(e) = log::init_webhook(url, Duration::from_secs(30))
+    {
+        eprintln!("Could not set up the progress webhook: {}", e);
+        std::process::exit(1);
+    }
+    log::info("setup", &format!("Packing Rust project: {}", project_path));
+    log::info("setup", &format!("Targets: {}", targets.join(", ")));
+    log::info("setup", &format!("Tool version: {}", get_rust_version()));
+    log::info("setup", &format!("Tool version: {}", get_cargo_version()));
     
     if verbose {
-        if let Ok(size_info) = analyze_binary_size(&binary_path_with_ext) {
-            println!("Binary size analysis for {}:", target);
-            println!("  Total size: {} bytes", size_info.get("total").unwrap_or(&0));
-            for (section, size) in &size_info {
-                if section != "total" {
-                    println!("  {}: {} bytes", section, size);
-                }
-            }
-        }
+        println!("{} Rust project: {}", "Packing".green(), project_path);
+        println!("{} for targets: {:?}", "Building".green(), targets);
     }
 
+    let mut required_tools = vec!["cargo", "rustc"];
     if build_config.strip {
-        if let Some(pb) = pb.clone() {
-            pb.set_message(format!("Stripping debug symbols for {}", target));
-            pb.enable_steady_tick(Duration::from_millis(100));
+        required_tools.push("strip");
+    }
+    if build_config.compress {
+        required_tools.push("upx");
+    }
+    for requirement in TOOL_REQUIREMENTS.iter().filter(|r| required_tools.contains(&r.name)) {
+        let status = check_tool_version(requirement);
+        if let ToolStatus::TooOld(version) = &status {
+            let message = format!(
+                "{} {} is older than the required {} ({})",
+                requirement.name, version, requirement.min_version, requirement.reason
+            );
+            log::warning("setup", &message);
         }
-        
-        let strip_tool = match target {
-            t if t.contains("windows") => "strip",
-            t if t.contains("apple") => "strip",
-            _ => "strip",
-        };
-
-        let strip_status = ProcessCommand::new(strip_tool)
-            .arg(&dest_path)
-            .status();
-
-        if let Ok(status) = strip_status {
-            if verbose && status.success() {
-                println!("Successfully stripped debug symbols");
+        if verbose {
+            match status {
+                ToolStatus::Ok(version) | ToolStatus::TooOld(version) => println!("{} {} {}", "Using".blue(), requirement.name, version),
+                ToolStatus::Unknown(output) => println!("{} {} ({})", "Using".blue(), requirement.name, output),
+                ToolStatus::Missing => {}
             }
         }
-        
-        if let Some(pb) = pb.clone() {
-            pb.finish_and_clear();
-        }
     }
 
-    if build_config.compress {
-        if let Some(pb) = pb.clone() {
-            pb.set_message(format!("Compressing binary for {}", target));
-            pb.enable_steady_tick(Duration::from_millis(100));
-        }
-        
-        let upx_status = ProcessCommand::new("upx")
-            .arg("--best")
-            .arg(&dest_path)
-            .status();
-
-        if let Ok(status) = upx_status {
-            if verbose && status.success() {
-                println!("Successfully compressed binary with UPX");
+    let compression_level = build_config.compression_level;
+    let mut packer = Packer::new(project_path, &output_name, targets, build_config)
+        .formats(formats)
+        .verbose(verbose)
+        .clean(matches.get_flag("clean"));
+    if matches.get_flag("open") {
+        packer = packer.open(matches.get_many::<String>("open-args").into_iter().flatten().cloned().collect());
+    }
+    if matches.get_flag("dry-run") {
+        let plan = match packer.plan() {
+            Ok(plan) => plan,
+            Err(e) => {
+                eprintln!("{}: {}", "Dry run failed".red().bold(), e);
+                std::process::exit(exit_code(&e));
             }
+        };
+        print_plan(&plan);
+        if !plan.problems.is_empty() {
+            std::process::exit(1);
         }
-        
-        if let Some(pb) = pb {
-            pb.finish_and_clear();
+        return Ok(());
+    }
+    let mut app_exit_code = None;
+    if watch_mode {
+        if let Err(e) = packer.watch() {
+            log::finish(false, &format!("Build failed: {}", e));
+            eprintln!("{}: {}", "Build failed".red().bold(), e);
+            std::process::exit(exit_code(&e));
+        }
+    } else {
+        match packer.pack() {
+            // The sizes also show with --compression-level, to tune it.
+            Ok(report) if verbose || (compression_level.is_some() && !quiet) => {
+                app_exit_code = report.exit_code;
+                for (output, elapsed) in report.outputs.iter().zip(&report.archive_times) {
+                    let size = fs::metadata(output).map(|m| m.len()).unwrap_or(0);
+                    println!(
+                        "{} {}: {} of files in {} bytes ({:.1}%), written in {:.2}s",
+                        "Archived".blue(),
+                        output.display(),
+                        report.payload_size,
+                        size,
+                        size as f64 * 100.0 / report.payload_size.max(1) as f64,
+                        elapsed.as_secs_f64()
+                    );
+                }
+            }
+            Ok(report) => app_exit_code = report.exit_code,
+            Err(e) => {
+                log::finish(false, &format!("Build failed: {}", e));
+                eprintln!("{}: {}", "Build failed".red().bold(), e);
+                std::process::exit(exit_code(&e));
+            }
         }
     }
+    log::finish(true, &format!("Package created: {}", output_name));
 
-    let features = build_config.features.clone();
-    
-    let rel_path = PathBuf::from("bin")
-        .join(target)
-        .join(&binary_with_ext);
-
-    Ok((rel_path, features))
-}
-
-fn calculate_checksum(path: &Path) -> Result<String, Box<dyn std::error::Error>> {
-    let mut file = File::open(path)?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
-    
-    let mut hasher = Sha256::new();
-    hasher.update(&buffer);
-    let result = hasher.finalize();
+    if verbose {
+        println!("{} created successfully: {}", "Package".green().bold(), output_name);
+    }
+    if let Some(code) = app_exit_code {
+        std::process::exit(code);
+    }
     
-    Ok(format!("{:x}", result))
+    Ok(())
 }
 
-fn sign_package(path: &Path, key: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let checksum = calculate_checksum(path)?;
-    
-    let mut mac = HmacSha256::new_from_slice(key.as_bytes())?;
-    mac.update(checksum.as_bytes());
-    let result = mac.finalize();
-    let code_bytes = result.into_bytes();
-    
-    Ok(encode(&code_bytes))
+/// The exit status for each kind of failure, so scripts can tell them apart.
+/// These are documented in the README and must not be renumbered.
+fn exit_code(error: &RustPackError) -> i32 {
+    match error {
+        RustPackError::BuildFailed { .. } => 2,
+        RustPackError::AssetMissing(_) => 3,
+        RustPackError::ToolMissing(_) => 4,
+        RustPackError::SignatureMismatch => 5,
+        RustPackError::ManifestNotFound(_) => 6,
+        RustPackError::PatchFormat(_) => 7,
+        RustPackError::Io(_) | RustPackError::Other(_) => 1,
+    }
 }
 
-fn build_package(
-    project_path: &str, 
-    output_name: &str, 
-    targets: &[String],
-    build_config: &BuildConfig,
-    verbose: bool,
-    create_zip: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let temp_dir = tempfile::tempdir()?;
-    let rustpack_dir = temp_dir.path().join("rustpack");
-    fs::create_dir_all(&rustpack_dir)?;
-
-    let mut target_infos = Vec::new();
-    let project_name = get_project_name(project_path)?;
-    let version = get_project_version(project_path).unwrap_or_else(|_| "0.1.0".to_string());
-    let description = get_project_description(project_path);
-
-    for target in targets {
-        let (platform, arch, compatibility) = parse_target(target);
-        let bin_dir = rustpack_dir.join("bin").join(target);
-        fs::create_dir_all(&bin_dir)?;
-
-        if verbose {
-            println!("{} for {}", "Building".blue(), target);
-        }
-        
-        let (binary_path, features) = build_for_target(
-            project_path, 
-            &bin_dir, 
-            target, 
-            &project_name, 
-            build_config,
-            verbose,
-        )?;
-
-        let optimizations = if build_config.lto.as_deref() != Some("off") {
-            Some(format!("lto-{}", build_config.lto.as_deref().unwrap_or("off")))
-        } else {
-            None
+fn print_plan(plan: &BuildPlan) {
+    let yes_no = |value: bool| if value { "yes" } else { "no" };
+    println!("{} nothing will be built or written", "Dry run:".blue().bold());
+    println!("  Project:   {} {} ({})", plan.name, plan.version, plan.project_path);
+    println!("  Profile:   {} (lto {}, built with {})", plan.profile, plan.lto.as_deref().unwrap_or("off"), plan.builder);
+    let features = if plan.features.is_empty() { "none".to_string() } else { plan.features.join(", ") };
+    let defaults = if plan.no_default_features { "without" } else { "with" };
+    println!("  Features:  {} ({} default features)", features, defaults);
+    if !plan.cargo_args.is_empty() {
+        println!("  Cargo:     {}", plan.cargo_args.join(" "));
+    }
+    println!("  Build dir: {}", plan.target_dir.display());
+    if !plan.rustflags.is_empty() {
+        println!("  Rustflags: {}", plan.rustflags.join(" "));
+    }
+    if plan.binaries.len() > 1 {
+        println!("  Binaries:  {} (runs {} by default)", plan.binaries.join(", "), plan.binaries[0]);
+    }
+    for (output, format) in &plan.outputs {
+        let kind = match format {
+            PackageFormat::Zip => "zip".to_string(),
+            PackageFormat::Deb => "deb".to_string(),
+            PackageFormat::AppImage => "appimage".to_string(),
+            PackageFormat::App => "macos app bundle".to_string(),
+            PackageFormat::Dmg => "macos disk image".to_string(),
+            PackageFormat::Wasm => "wasm bundle".to_string(),
+            PackageFormat::SelfExtracting if plan.windows_bootstrap => format!("windows exe, {}", plan.compression),
+            PackageFormat::SelfExtracting => format!("self-extracting, {}", plan.compression),
         };
-
-        target_infos.push(TargetInfo {
-            platform,
-            arch,
-            binary_path: binary_path.to_string_lossy().to_string(),
-            features,
-            optimizations,
-            compatibility,
-        });
+        println!("  Output:    {} ({})", output, kind);
     }
-    
-    copy_assets(project_path, &rustpack_dir, &build_config.assets, verbose)?;    
-    if verbose {
-        println!("{} license file", "Detecting".blue());
+    println!("  Targets:   {} ({} at a time)", plan.targets.len(), plan.jobs);
+    for target in &plan.targets {
+        println!(
+            "    {:<32} {:<16} strip: {:<3} upx: {:<3} sign: {}",
+            target.target,
+            format!("{}-{}", target.platform, target.arch),
+            yes_no(target.strip),
+            yes_no(target.compress),
+            yes_no(target.sign)
+        );
     }
-    if let Err(e) = detect_and_embed_license(project_path, &rustpack_dir) {
-        if verbose {
-            println!("{} Failed to embed license: {}", "Warning".yellow(), e);
-        }
+    if !plan.assets.is_empty() {
+        println!("  Assets:    {}", plan.assets.join(", "));
     }
-
-    let mut metadata = HashMap::new();
-    metadata.insert("created_with".to_string(), "rustpack".to_string());
-    metadata.insert("rust_version".to_string(), get_rust_version());
-    
-    let checksum = rand::thread_rng()
-        .sample_iter(&rand::distributions::Alphanumeric)
-        .take(16)
-        .map(char::from)
-        .collect::<String>();
-
-    let enabled_features = vec![
-        "cross_platform".to_string(),
-        "self_extracting".to_string(),
-        "binary_packaging".to_string(),
-        "compression".to_string(),
-        "auto_detection".to_string(),
-    ];
-    
-    let dependencies = match analyze_dependencies(project_path) {
-        Ok(deps) => {
-            if verbose {
-                println!("{} Dependencies analyzed: {} found", "Info".blue(), deps.len());
-            }
-            deps
-        },
-        Err(e) => {
-            if verbose {
-                println!("{} Could not analyze dependencies: {}", "Warning".yellow(), e);
-            }
-            HashMap::new()
+    if !plan.clean.is_empty() {
+        println!("  Clean:     would remove {} paths", plan.clean.len());
+        for path in &plan.clean {
+            println!("    {}", path.display());
         }
-    };
-    
-    for (name, version) in dependencies {
-        metadata.insert(format!("dependency_{}", name), version);
     }
 
-    let package_info = PackageInfo {
-        name: project_name,
-        version,
-        description,
-        targets: target_infos,
-        created_at: Local::now().to_rfc3339(),
-        checksum,
-        features: enabled_features,
-        metadata,
-    };
-
-    let info_json = serde_json::to_string_pretty(&package_info)?;
-    fs::write(rustpack_dir.join("info.json"), info_json)?;
-
-    if create_zip {
-        create_zip_package(&temp_dir.path(), output_name)?;  
+    if plan.problems.is_empty() {
+        println!("{} configuration looks good", "OK:".green().bold());
     } else {
-        create_self_extracting_package(&temp_dir.path(), output_name)?;
-        sign_package(Path::new(output_name), &build_config.sign)?;
-    }
-
-    Ok(())
-}
-
-fn create_self_extracting_package(temp_dir: &Path, output_name: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let temp_archive = tempfile::NamedTempFile::new()?;
-
-    let tar_gz = GzEncoder::new(temp_archive.reopen()?, Compression::default());
-    let mut tar = Builder::new(tar_gz);
-
-    for entry in WalkDir::new(temp_dir).into_iter().filter_map(|e| e.ok()) {
-        let path = entry.path();
-        if path != temp_dir {
-            let name = path.strip_prefix(temp_dir)?;
-            if entry.file_type().is_dir() {
-                tar.append_dir(name, path)?;
-            } else {
-                tar.append_path_with_name(path, name)?;
-            }
+        println!("{}", "Problems:".red().bold());
+        for problem in &plan.problems {
+            println!("  {}", problem);
         }
     }
-
-    let tar_gz = tar.into_inner()?;
-    tar_gz.finish()?;
-
-    let mut output_file = File::create(output_name)?;
-    output_file.write_all(BOOTSTRAP_SCRIPT.as_bytes())?;
-    io::copy(&mut File::open(temp_archive.path())?, &mut output_file)?;
-
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let mut perms = fs::metadata(output_name)?.permissions();
-        perms.set_mode(0o755);
-        fs::set_permissions(output_name, perms)?;
-    }
-
-    Ok(())
 }
 
-fn copy_assets(
-    project_path: &str,
-    rustpack_dir: &Path,
-    assets: &[String],
-    verbose: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    if assets.is_empty() {
-        return Ok(());
+fn print_package_info(package: &Package, info: &PackageInfo) {
+    println!("  Name:      {} {}", info.name, info.version);
+    if let Some(description) = &info.description {
+        println!("  About:     {}", description);
     }
-    
-    let assets_dir = rustpack_dir.join("assets");
-    fs::create_dir_all(&assets_dir)?;
-    
-    if verbose {
-        println!("{} assets", "Copying".blue());
+    let format = match package.format() {
+        PackageFormat::Zip => "zip".to_string(),
+        _ => format!("self-extracting, {}", package.compression()),
+    };
+    println!("  Format:    {}", format);
+    if let Some(created_at) = &info.created_at {
+        // Older packages recorded the builder's local time.
+        let created_at = DateTime::parse_from_rfc3339(created_at)
+            .map(|t| t.with_timezone(&Utc).format("%Y-%m-%d %H:%M:%S UTC").to_string())
+            .unwrap_or_else(|_| created_at.clone());
+        println!("  Created:   {}", created_at);
     }
-    
-    for asset in assets {
-        let src_path = Path::new(project_path).join(asset);
-        if !src_path.exists() {
-            return Err(format!("Asset not found: {}", asset).into());
+    if let Some(commit) = info.metadata.get("git_commit") {
+        let mut details: Vec<&str> = info.metadata.get("git_branch").map(String::as_str).into_iter().collect();
+        if info.metadata.get("git_dirty").is_some_and(|dirty| dirty == "true") {
+            details.push("uncommitted changes");
         }
-        
-        if src_path.is_dir() {
-            let dest_dir = assets_dir.join(asset);
-            fs::create_dir_all(&dest_dir)?;
-            
-            for entry in WalkDir::new(&src_path).into_iter().filter_map(|e| e.ok()) {
-                let rel_path = entry.path().strip_prefix(&src_path)?;
-                let dest_path = dest_dir.join(rel_path);
-                
-                if entry.file_type().is_dir() {
-                    fs::create_dir_all(&dest_path)?;
-                } else {
-                    if verbose {
-                        println!("  Copying asset: {}", rel_path.display());
-                    }
-                    fs::copy(entry.path(), &dest_path)?;
-                }
-            }
+        if details.is_empty() {
+            println!("  Commit:    {}", commit);
         } else {
-            let file_name = src_path.file_name().unwrap();
-            let dest_path = assets_dir.join(file_name);
-            
-            if verbose {
-                println!("  Copying asset: {}", file_name.to_string_lossy());
-            }
-            fs::copy(&src_path, &dest_path)?;
+            println!("  Commit:    {} ({})", commit, details.join(", "));
         }
     }
-    
-    Ok(())
-}
+    if let (Some(host), Some(rust_version)) = (info.metadata.get("build_host"), info.metadata.get("rust_version")) {
+        println!("  Built on:  {} with {}", host, rust_version);
+    }
+    if let Some(channel) = info.metadata.get("channel") {
+        println!("  Channel:   {}", channel);
+    }
+    let custom: Vec<String> = info
+        .metadata
+        .iter()
+        .filter(|(key, _)| !is_reserved_metadata_key(key))
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    if !custom.is_empty() {
+        println!("  Metadata:  {}", custom.join(", "));
+    }
+    println!("  Checksum:  {}", info.checksum);
+    let features: Vec<&str> = info
+        .targets
+        .iter()
+        .flat_map(|t| &t.features)
+        .map(String::as_str)
+        .filter(|f| !f.is_empty())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    println!("  Features:  {}", if features.is_empty() { "none".to_string() } else { features.join(", ") });
+    if !info.requires.is_empty() {
+        println!("  Requires:  {}", info.requires.join(", "));
+    }
+    if let Some(target) = info.targets.iter().find(|t| t.binaries.len() > 1) {
+        let names: Vec<_> = target
+            .binaries
+            .iter()
+            .filter_map(|b| Path::new(b).file_stem())
+            .map(|n| n.to_string_lossy())
+            .collect();
+        println!("  Binaries:  {} (runs {} by default)", names.join(", "), names[0]);
+    }
 
-fn create_zip_package(temp_dir: &Path, output_name: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let file = File::create(output_name)?;
-    let mut zip = zip::ZipWriter::new(file);
-    let options = FileOptions::default()
-        .compression_method(zip::CompressionMethod::Deflated)
-        .unix_permissions(0o755);
+    println!("  Targets:   {}", info.targets.len());
+    println!("    {:<16} {:<48} {:<64} SIGNED", "PLATFORM", "BINARY", "SHA-256");
+    for target in &info.targets {
+        println!(
+            "    {:<16} {:<48} {:<64} {}",
+            format!("{}-{}", target.platform, target.arch),
+            target.binary_path,
+            target.checksum,
+            if target.signature.is_some() { "yes" } else { "no" }
+        );
+    }
 
-    for entry in WalkDir::new(temp_dir).into_iter().filter_map(|e| e.ok()) {
-        let path = entry.path();
-        if path != temp_dir {
-            let name = path.strip_prefix(temp_dir)?
-                .to_string_lossy()
-                .to_string();
-            
-            if entry.file_type().is_dir() {
-                zip.add_directory(name, options)?;
-            } else {
-                zip.start_file(name, options)?;
-                let mut f = File::open(path)?;
-                let mut buffer = Vec::new();
-                f.read_to_end(&mut buffer)?;
-                zip.write_all(&buffer)?;
-            }
+    let assets: Vec<_> = package
+        .entries()
+        .filter(|e| !e.is_dir)
+        .filter_map(|e| Some((e.path.strip_prefix("rustpack/assets/")?, e.data.len())))
+        .collect();
+    if !assets.is_empty() {
+        println!("  Assets:    {}", assets.len());
+        for (path, size) in assets {
+            println!("    {:>10}  {}", size, path);
         }
     }
+}
 
-    zip.finish()?;
-    Ok(())
+/// A `--diff` table row: both sizes and the change, red when it grew.
+fn print_size_change(label: &str, change: &SizeChange) {
+    let size = |size: Option<u64>| size.map_or("-".to_string(), |s| s.to_string());
+    let delta = change.delta();
+    let percent = match change.old {
+        Some(old) if old > 0 => format!(" ({:+.1}%)", delta as f64 * 100.0 / old as f64),
+        _ => String::new(),
+    };
+    let text = format!("{:+}{}", delta, percent);
+    let text = match delta {
+        0 => text.normal(),
+        d if d > 0 => text.red(),
+        _ => text.green(),
+    };
+    println!("  {:<24} {:>12} {:>12} {:>18}", label, size(change.old), size(change.new), text);
 }
 
-fn read_config_file(project_path: &str) -> Result<RustPackConfig, Box<dyn std::error::Error>> {
-    let config_path = Path::new(project_path).join("RustPack.toml");
-    if !config_path.exists() {
-        return Ok(RustPackConfig::default());
+/// `ls -l` style permissions, e.g. `drwxr-xr-x`.
+fn format_mode(mode: u32, is_dir: bool) -> String {
+    let mut text = String::from(if is_dir { "d" } else { "-" });
+    for shift in [6, 3, 0] {
+        let bits = (mode >> shift) & 0o7;
+        text.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        text.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        text.push(if bits & 0o1 != 0 { 'x' } else { '-' });
     }
-    
-    let config_content = fs::read_to_string(config_path)?;
-    let config: RustPackConfig = toml::from_str(&config_content)?;
-    Ok(config)
+    text
 }
 
-fn get_rust_version() -> String {
-    let output = ProcessCommand::new("rustc")
-        .args(&["--version"])
-        .output();
-    
-    match output {
-        Ok(output) => String::from_utf8_lossy(&output.stdout).trim().to_string(),
-        Err(_) => "unknown".to_string(),
-    }
+fn read_config_file(project_path: &str) -> Result<RustPackConfig, Box<dyn std::error::Error>> {
+    let found: Vec<&str> = CONFIG_FILES
+        .iter()
+        .copied()
+        .filter(|name| Path::new(project_path).join(name).exists())
+        .collect();
+    let config_file = match found.as_slice() {
+        [] => return Ok(RustPackConfig::default()),
+        [config_file] => *config_file,
+        _ => return Err(format!("found {}; keep only one of them", found.join(" and ")).into()),
+    };
+
+    let config_content = fs::read_to_string(Path::new(project_path).join(config_file))?;
+    let parse = || -> Result<RustPackConfig, Box<dyn std::error::Error>> {
+        // Every format is read into a TOML value, so they share the
+        // variable expansion and deserialize into the config the same way.
+        let mut value: toml::Value = match Path::new(config_file).extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::from_str(&config_content)?,
+            Some("yaml" | "yml") => serde_yaml::from_str(&config_content)?,
+            _ => toml::from_str(&config_content)?,
+        };
+        expand_env_values(&mut value, "")?;
+        Ok(value.try_into()?)
+    };
+    parse().map_err(|e| format!("{}: {}", config_file, e).into())
 }
 
-fn analyze_dependencies(project_path: &str) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
-    let cargo_toml = Path::new(project_path).join("Cargo.toml");
-    let cargo_content = fs::read_to_string(cargo_toml)?;
-    let mut dependencies = HashMap::new();
-    let mut in_deps_section = false;
-    for line in cargo_content.lines() {
-        let trimmed = line.trim();
-        
-        if trimmed == "[dependencies]" {
-            in_deps_section = true;
-            continue;
-        } else if trimmed.starts_with('[') && trimmed.ends_with(']') {
-            in_deps_section = false;
+/// Writes a starting `RustPack.toml` for the crate in `project_path`, filled
+/// in from its manifest and layout, and checks that it loads.
+fn init_config(project_path: &str, force: bool) -> Result<std::path::PathBuf, Box<dyn std::error::Error>> {
+    let config_path = Path::new(project_path).join("RustPack.toml");
+    for name in CONFIG_FILES {
+        if !Path::new(project_path).join(name).exists() {
             continue;
         }
-        
-        if in_deps_section && !trimmed.is_empty() && !trimmed.starts_with('#') {
-            if let Some(eq_pos) = trimmed.find('=') {
-                let name = trimmed[..eq_pos].trim().to_string();
-                let version_part = trimmed[eq_pos + 1..].trim();
-                if version_part.starts_with('"') && version_part.ends_with('"') {
-                    let version = version_part.trim_matches('"').to_string();
-                    dependencies.insert(name, version);
-                } 
-                else if version_part.starts_with('{') {
-                    if let Some(ver_start) = trimmed.find("version") {
-                        if let Some(eq_start) = trimmed[ver_start..].find('=') {
-                            let ver_part = &trimmed[ver_start + eq_start + 1..];
-                            if let Some(quote_start) = ver_part.find('"') {
-                                if let Some(quote_end) = ver_part[quote_start + 1..].find('"') {
-                                    let version = ver_part[quote_start + 1..quote_start + 1 + quote_end].to_string();
-                                    dependencies.insert(name, version);
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+        if *name != "RustPack.toml" {
+            return Err(format!("{} already configures this project", name).into());
         }
-    }
-    
-    Ok(dependencies)
-}
-
-fn detect_and_embed_license(project_path: &str, rustpack_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    let license_files = [
-        "LICENSE", "LICENSE.txt", "LICENSE.md", 
-        "LICENCE", "LICENCE.txt", "LICENCE.md"
-    ];
-    
-    for license_file in license_files.iter() {
-        let license_path = Path::new(project_path).join(license_file);
-        if license_path.exists() {
-            let dest_path = rustpack_dir.join("LICENSE");
-            fs::copy(&license_path, &dest_path)?;
-            return Ok(());
+        if !force {
+            return Err("RustPack.toml already exists; pass --force to overwrite it".into());
         }
     }
 
-    let placeholder = "No license file detected in the original project.";
-    fs::write(rustpack_dir.join("LICENSE.note"), placeholder)?;
-    
-    Ok(())
-}
-
-fn analyze_binary_size(binary_path: &Path) -> Result<HashMap<String, usize>, Box<dyn std::error::Error>> {
-    let mut size_info = HashMap::new();
-    let metadata = fs::metadata(binary_path)?;
-    size_info.insert("total".to_string(), metadata.len() as usize);
-    let objdump_output = ProcessCommand::new("objdump")
-        .args(&["-h", &binary_path.to_string_lossy()])
-        .output();
-        
-    if let Ok(output) = objdump_output {
-        if output.status.success() {
-            let output_str = String::from_utf8_lossy(&output.stdout);
-            for line in output_str.lines() {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 4 && parts[0].starts_with(".") {
-                    if let Ok(size) = usize::from_str_radix(parts[2], 16) {
-                        size_info.insert(parts[0].to_string(), size);
-                    }
-                }
-            }
+    let manifest = parse_manifest(project_path)?;
+    let quote = |value: &str| toml::Value::String(value.to_string()).to_string();
+    let mut content = format!("# RustPack configuration for {} {}", manifest.name, manifest.version);
+    if let Some(license) = &manifest.license {
+        content.push_str(&format!(", licensed {}", license));
+    }
+    content.push_str(
+        ".\n# Command-line flags override these settings, and they override RUSTPACK_*\n# variables. Run `rustpack --dry-run` to check them.\n\n",
+    );
+    content.push_str(&format!("output = {}\n", quote(&format!("{}.rpack", manifest.name))));
+    content.push_str(&format!("targets = [{}]\n", quote(&get_current_target())));
+    content.push_str("#targets = [\"x86_64-unknown-linux-gnu\", \"aarch64-apple-darwin\", \"x86_64-pc-windows-msvc\"]\n");
+    content.push_str("profile = \"release\"\n");
+    content.push_str("strip = true\n");
+    content.push_str("#compress = true  # shrink binaries with UPX\n");
+    content.push_str("#features = [\"feature1\"]\n");
+    content.push_str("#format = [\"rpack\", \"zip\"]  # also deb, appimage, app, dmg, wasm\n");
+    content.push_str("#compression = \"zstd\"  # or \"brotli\", \"none\"; gzip by default\n");
+    if Path::new(project_path).join("assets").is_dir() {
+        content.push_str("assets = [\"assets/\"]\n");
+    } else {
+        content.push_str("#assets = [\"assets/\"]\n");
+    }
+    if manifest.license.is_some() {
+        content.push_str("#licenses = true  # bundle the license texts of every dependency\n");
+    }
+    content.push_str("#max_size = \"8M\"  # fail the build if a binary is larger\n");
+    content.push_str("#sign_key = \"rustpack-key.pem\"\n");
+
+    let previous = fs::read(&config_path).ok();
+    fs::write(&config_path, &content)?;
+    if let Err(e) = read_config_file(project_path) {
+        match previous {
+            Some(previous) => fs::write(&config_path, previous)?,
+            None => fs::remove_file(&config_path)?,
         }
+        return Err(format!("the generated config doesn't load ({}); this is a bug", e).into());
     }
-    
-    Ok(size_info)
+    Ok(config_path)
 }
 
-fn setup_auto_update(update_url: &str, package_info: &mut PackageInfo) {
-    package_info.metadata.insert("update_url".to_string(), update_url.to_string());
-    package_info.features.push("auto_update".to_string());
-}
+/// The config files looked for in the project directory. Only one of them
+/// may exist.
+const CONFIG_FILES: &[&str] = &["RustPack.toml", "RustPack.json", "RustPack.yaml", "RustPack.yml"];
 
-fn load_env_config() -> BuildConfig {
-    let strip = env::var("RUSTPACK_STRIP").map(|v| v == "1" || v == "true").unwrap_or(false);
-    let compress = env::var("RUSTPACK_COMPRESS").map(|v| v == "1" || v == "true").unwrap_or(false);
-    let lto = env::var("RUSTPACK_LTO").ok();
-    let debug_symbols = env::var("RUSTPACK_DEBUG_SYMBOLS").map(|v| v == "1" || v == "true").unwrap_or(true);
-    let profile = env::var("RUSTPACK_PROFILE").unwrap_or_else(|_| "release".to_string());
-    let sign = env::var("RUSTPACK_SIGN").unwrap_or_else(|_| "".to_string());
-    let features = env::var("RUSTPACK_FEATURES")
-        .map(|f| f.split(',').map(|s| s.trim().to_string()).collect())
-        .unwrap_or_else(|_| Vec::new());
-        
-    let assets = env::var("RUSTPACK_ASSETS")
-        .map(|a| a.split(',').map(|s| s.trim().to_string()).collect())
-        .unwrap_or_else(|_| Vec::new());
-        
-    BuildConfig {
-        strip,
-        compress,
-        lto,
-        debug_symbols,
-        profile,
-        features,
-        assets,
-        sign,
-    }
-}
+const HOOK_KEYS: &[&str] = &["pre_build", "post_build"];
 
-fn create_binary_patch(old_path: &Path, new_path: &Path, patch_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    let mut old_file = File::open(old_path)?;
-    let mut new_file = File::open(new_path)?;
-    let mut old_data = Vec::new();
-    let mut new_data = Vec::new();
-    old_file.read_to_end(&mut old_data)?;
-    new_file.read_to_end(&mut new_data)?;
-    let mut patch_entries = Vec::new();
-    let mut offset = 0;
-    
-    while offset < new_data.len() {
-        let mut diff_start = offset;
-        while diff_start < new_data.len() {
-            if diff_start >= old_data.len() || new_data[diff_start] != old_data[diff_start] {
-                break;
-            }
-            diff_start += 1;
+/// Expands environment variables in every string of a parsed config, with
+/// `key` naming the value in errors.
+fn expand_env_values(value: &mut toml::Value, key: &str) -> Result<(), Box<dyn std::error::Error>> {
+    match value {
+        toml::Value::String(text) => {
+            *text = expand_env(text).map_err(|e| format!("{} in {}", e, key))?;
         }
-        
-        if diff_start >= new_data.len() {
-            break;
+        toml::Value::Array(items) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                expand_env_values(item, &format!("{}[{}]", key, index))?;
+            }
         }
-        let mut diff_end = diff_start + 1;
-        while diff_end < new_data.len() {
-            if diff_end < old_data.len() && new_data[diff_end] == old_data[diff_end] {
-                let mut matches = 1;
-                while matches < 4 && diff_end + matches < new_data.len() && 
-                      diff_end + matches < old_data.len() && 
-                      new_data[diff_end + matches] == old_data[diff_end + matches] {
-                    matches += 1;
-                }
-                
-                if matches >= 4 {
-                    break;
+        toml::Value::Table(table) => {
+            for (name, item) in table.iter_mut() {
+                // Hooks run in a shell, which expands them itself once
+                // RUSTPACK_OUTPUT and the like are set.
+                if key.is_empty() && HOOK_KEYS.contains(&name.as_str()) {
+                    continue;
                 }
+                let key = if key.is_empty() { name.clone() } else { format!("{}.{}", key, name) };
+                expand_env_values(item, &key)?;
             }
-            diff_end += 1;
         }
-
-        let diff_data = &new_data[diff_start..diff_end];
-        patch_entries.push((diff_start, diff_end - diff_start, diff_data.to_vec()));
-        
-        offset = diff_end;
-    }
-    let mut patch_file = File::create(patch_path)?;
-    for (offset, length, data) in patch_entries {
-        writeln!(patch_file, "{}:{}:{}", offset, length, 
-                base64::encode(data))?;
+        _ => {}
     }
-    
     Ok(())
 }
 
-fn apply_binary_patch(original_path: &Path, patch_path: &Path, output_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    let mut original_file = File::open(original_path)?;
-    let mut original_data = Vec::new();
-    original_file.read_to_end(&mut original_data)?;
-    let patch_content = fs::read_to_string(patch_path)?;
-    let mut output_data = original_data.clone();
-    
-    for line in patch_content.lines() {
-        let parts: Vec<&str> = line.splitn(3, ':').collect();
-        if parts.len() != 3 {
+/// Replaces `${VAR}` and `$VAR` in `text` with the variable's value, and
+/// `${VAR:-default}` with `default` when `VAR` is unset or empty. `$$` is a
+/// literal `$`. Fails on an unset variable without a default.
+fn expand_env(text: &str) -> Result<String, String> {
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let (name, default) = if let Some(after) = rest.strip_prefix('$') {
+            expanded.push('$');
+            rest = after;
             continue;
+        } else if let Some(braced) = rest.strip_prefix('{') {
+            let end = braced.find('}').ok_or_else(|| format!("unterminated ${{ in \"{}\"", text))?;
+            rest = &braced[end + 1..];
+            match braced[..end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&braced[..end], None),
+            }
+        } else {
+            let end = rest.find(|c: char| !is_name(c)).unwrap_or(rest.len());
+            if end == 0 {
+                expanded.push('$');
+                continue;
+            }
+            let name = &rest[..end];
+            rest = &rest[end..];
+            (name, None)
+        };
+        if name.is_empty() || !name.chars().all(is_name) {
+            return Err(format!("invalid variable name \"{}\"", name));
         }
-        let offset = parts[0].parse::<usize>()?;
-        let length = parts[1].parse::<usize>()?;
-        let data = base64::decode(parts[2])?;
-        if offset + length > output_data.len() {
-            output_data.resize(offset + length, 0);
-        }
-        
-        for (i, byte) in data.iter().enumerate() {
-            if offset + i < output_data.len() {
-                output_data[offset + i] = *byte;
+        match (std::env::var(name).ok().filter(|v| !v.is_empty() || default.is_none()), default) {
+            (Some(value), _) => expanded.push_str(&value),
+            (None, Some(default)) => expanded.push_str(default),
+            (None, None) => {
+                return Err(format!("environment variable {} is not set (use ${{{}:-default}} to give it a default)", name, name));
             }
         }
     }
-
-    let mut output_file = File::create(output_path)?;
-    output_file.write_all(&output_data)?;
-    
-    Ok(())
+    expanded.push_str(rest);
+    Ok(expanded)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_env_substitutes_variables() {
+        let path = std::env::var("PATH").unwrap();
+        assert_eq!(expand_env("${PATH}").unwrap(), path);
+        assert_eq!(expand_env("bin:$PATH/x").unwrap(), format!("bin:{}/x", path));
+        assert_eq!(expand_env("costs $$5 and $ more").unwrap(), "costs $5 and $ more");
+        assert_eq!(expand_env("no variables").unwrap(), "no variables");
+    }
+
+    #[test]
+    fn expand_env_uses_defaults_for_unset_variables() {
+        assert_eq!(
+            expand_env("dist/${RUSTPACK_TEST_UNSET_VERSION:-dev}/app.rpack").unwrap(),
+            "dist/dev/app.rpack"
+        );
+        assert_eq!(expand_env("${RUSTPACK_TEST_UNSET_VERSION:-}").unwrap(), "");
+        assert_eq!(expand_env("${PATH:-unused}").unwrap(), std::env::var("PATH").unwrap());
+    }
+
+    #[test]
+    fn expand_env_fails_on_missing_variables() {
+        let error = expand_env("dist/${RUSTPACK_TEST_UNSET_VERSION}/app").unwrap_err();
+        assert!(error.contains("RUSTPACK_TEST_UNSET_VERSION is not set"), "{}", error);
+        assert!(expand_env("$RUSTPACK_TEST_UNSET_VERSION").is_err());
+        assert!(expand_env("${RUSTPACK_TEST_UNSET_VERSION").unwrap_err().contains("unterminated"));
+        assert!(expand_env("${NOT-A-NAME}").unwrap_err().contains("invalid variable name"));
+
+        let mut config: toml::Value =
+            toml::from_str("pre_build = [\"echo $RUSTPACK_TEST_UNSET_VERSION\"]\n[targets_config]\nout = [\"a\", \"$RUSTPACK_TEST_UNSET_VERSION\"]\n")
+                .unwrap();
+        let error = expand_env_values(&mut config, "").unwrap_err().to_string();
+        assert!(error.ends_with("in targets_config.out[1]"), "{}", error);
+    }
+
+    #[test]
+    fn config_is_read_from_each_format() {
+        for (name, content) in [
+            ("RustPack.toml", "output = \"app.rpack\"\ntargets = [\"x86_64-unknown-linux-gnu\"]\nstrip = true\n"),
+            ("RustPack.json", r#"{"output": "app.rpack", "targets": ["x86_64-unknown-linux-gnu"], "strip": true}"#),
+            ("RustPack.yaml", "output: app.rpack\ntargets:\n  - x86_64-unknown-linux-gnu\nstrip: true\n"),
+            ("RustPack.yml", "output: app.rpack\ntargets: [x86_64-unknown-linux-gnu]\nstrip: true\n"),
+        ] {
+            let project = tempfile::tempdir().unwrap();
+            fs::write(project.path().join(name), content).unwrap();
+            let config = read_config_file(project.path().to_str().unwrap()).unwrap();
+            assert_eq!(config.output.as_deref(), Some("app.rpack"), "{}", name);
+            assert_eq!(config.targets, Some(vec!["x86_64-unknown-linux-gnu".to_string()]), "{}", name);
+            assert_eq!(config.strip, Some(true), "{}", name);
+        }
+    }
+
+    #[test]
+    fn config_errors_name_the_file() {
+        let project = tempfile::tempdir().unwrap();
+        let dir = project.path().to_str().unwrap();
+        let error = || match read_config_file(dir) {
+            Ok(_) => panic!("the config loaded"),
+            Err(e) => e.to_string(),
+        };
+        assert!(read_config_file(dir).unwrap().output.is_none());
+
+        fs::write(project.path().join("RustPack.json"), "{\"strip\": \"yes\"}").unwrap();
+        assert!(error().starts_with("RustPack.json: "), "{}", error());
+        fs::write(project.path().join("RustPack.json"), "{\"strip\": true}").unwrap();
+        fs::write(project.path().join("RustPack.toml"), "strip = true\n").unwrap();
+        assert_eq!(error(), "found RustPack.toml and RustPack.json; keep only one of them");
+    }
+}
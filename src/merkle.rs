@@ -0,0 +1,177 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use walkdir::WalkDir;
+
+// Files derived from the package contents, so they can't be part of the tree.
+const EXCLUDED: &[&str] = &["info.json", "manifest.env", "merkle.json"];
+
+/// A Merkle tree over every file in the `rustpack/` directory, so a client
+/// can check any subset of files against the root in info.json without
+/// hashing the whole package.
+///
+/// Leaves are `SHA-256(0x00 || path || 0x00 || SHA-256(contents))`, sorted by
+/// path, and inner nodes are `SHA-256(0x01 || left || right)`. A node without
+/// a sibling is carried up to the next level unchanged.
+#[derive(Serialize, Deserialize)]
+pub struct MerkleTree {
+    pub root: String,
+    pub leaves: Vec<MerkleLeaf>,
+    /// Every level of the tree, from the leaf hashes up to the root.
+    pub levels: Vec<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MerkleLeaf {
+    pub path: String,
+    pub hash: String,
+}
+
+pub struct ProofStep {
+    pub hash: String,
+    /// Whether the sibling hash goes on the left when combining.
+    pub left: bool,
+}
+
+impl MerkleTree {
+    pub fn from_dir(rustpack_dir: &Path) -> Result<MerkleTree, Box<dyn std::error::Error>> {
+        let mut files = Vec::new();
+        for entry in WalkDir::new(rustpack_dir).into_iter().filter_map(|e| e.ok()) {
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let path = entry.path().strip_prefix(rustpack_dir)?.to_string_lossy().replace('\\', "/");
+            let mut hasher = Sha256::new();
+            io::copy(&mut File::open(entry.path())?, &mut hasher)?;
+            files.push((path, format!("{:x}", hasher.finalize())));
+        }
+        Ok(MerkleTree::from_hashes(files))
+    }
+
+    /// Builds the tree from `(path, hex SHA-256 of contents)` pairs, with
+    /// paths relative to the `rustpack/` directory.
+    pub fn from_hashes(files: Vec<(String, String)>) -> MerkleTree {
+        let mut leaves: Vec<MerkleLeaf> = files
+            .into_iter()
+            .filter(|(path, _)| !EXCLUDED.contains(&path.as_str()))
+            .map(|(path, content_hash)| MerkleLeaf {
+                hash: leaf_hash(&path, &content_hash),
+                path,
+            })
+            .collect();
+        leaves.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let mut levels = vec![leaves.iter().map(|l| l.hash.clone()).collect::<Vec<_>>()];
+        while levels.last().map_or(0, |l| l.len()) > 1 {
+            let next = levels
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => node_hash(left, right),
+                    [single] => single.clone(),
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+
+        let root = levels
+            .last()
+            .and_then(|l| l.first().cloned())
+            .unwrap_or_else(|| format!("{:x}", Sha256::digest(b"")));
+        MerkleTree { root, leaves, levels }
+    }
+
+    /// Sibling hashes needed to recompute the root from the leaf at `path`.
+    pub fn proof(&self, path: &str) -> Option<Vec<ProofStep>> {
+        let mut index = self.leaves.iter().position(|l| l.path == path)?;
+        let mut proof = Vec::new();
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = index ^ 1;
+            if let Some(hash) = level.get(sibling) {
+                proof.push(ProofStep {
+                    hash: hash.clone(),
+                    left: sibling < index,
+                });
+            }
+            index /= 2;
+        }
+        Some(proof)
+    }
+}
+
+pub fn leaf_hash(path: &str, content_hash: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update([0u8]);
+    hasher.update(path.as_bytes());
+    hasher.update([0u8]);
+    hasher.update(content_hash.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+fn node_hash(left: &str, right: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(left.as_bytes());
+    hasher.update(right.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+pub fn verify_proof(leaf: &str, proof: &[ProofStep], root: &str) -> bool {
+    let computed = proof.iter().fold(leaf.to_string(), |hash, step| {
+        if step.left {
+            node_hash(&step.hash, &hash)
+        } else {
+            node_hash(&hash, &step.hash)
+        }
+    });
+    computed == root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn content_hash(path: &Path) -> String {
+        format!("{:x}", Sha256::digest(fs::read(path).unwrap()))
+    }
+
+    #[test]
+    fn proof_verifies_file_and_detects_tampering() {
+        let dir = tempfile::tempdir().unwrap();
+        for (path, contents) in [
+            ("bin/x86_64-unknown-linux-gnu/app", "binary"),
+            ("assets/a.txt", "a"),
+            ("assets/b.txt", "b"),
+            ("assets/nested/c.txt", "c"),
+            ("LICENSE", "license"),
+            ("info.json", "{}"),
+        ] {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        let tree = MerkleTree::from_dir(dir.path()).unwrap();
+        assert_eq!(tree.leaves.len(), 5);
+        assert!(tree.proof("info.json").is_none());
+
+        for leaf in &tree.leaves {
+            let proof = tree.proof(&leaf.path).unwrap();
+            let hash = leaf_hash(&leaf.path, &content_hash(&dir.path().join(&leaf.path)));
+            assert!(verify_proof(&hash, &proof, &tree.root), "{} did not verify", leaf.path);
+        }
+
+        let tampered = dir.path().join("assets/b.txt");
+        fs::write(&tampered, "B").unwrap();
+        let proof = tree.proof("assets/b.txt").unwrap();
+        assert!(!verify_proof(&leaf_hash("assets/b.txt", &content_hash(&tampered)), &proof, &tree.root));
+        // A file's contents can't be passed off under another path.
+        let proof = tree.proof("assets/a.txt").unwrap();
+        assert!(!verify_proof(&leaf_hash("assets/a.txt", &content_hash(&dir.path().join("assets/nested/c.txt"))), &proof, &tree.root));
+        assert_ne!(MerkleTree::from_dir(dir.path()).unwrap().root, tree.root);
+    }
+}
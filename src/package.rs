@@ -0,0 +1,539 @@
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{self, File};
+use std::io::{Cursor, Read, Write};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tar::{Archive, Builder, EntryType, Header};
+use zip::write::FileOptions;
+
+use crate::merkle::MerkleTree;
+use crate::{manifest_env, sign_output_file, sign_package, source_date_epoch, zip_timestamp, PackageInfo};
+
+const PAYLOAD_MARKER: &[u8] = b"\n__PAYLOAD_BEGINS__\n";
+const PAYLOAD_CHECKSUM_KEY: &[u8] = b"\nPAYLOAD_CHECKSUM=";
+const INFO_PATH: &str = "rustpack/info.json";
+const MANIFEST_ENV_PATH: &str = "rustpack/manifest.env";
+const MERKLE_PATH: &str = "rustpack/merkle.json";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PackageFormat {
+    SelfExtracting,
+    Zip,
+    /// A Debian package for a single Linux target. Written only; `Package`
+    /// can't open it.
+    Deb,
+    /// An AppImage for a single Linux target, built with `appimagetool`.
+    /// Written only, like `Deb`.
+    AppImage,
+    /// A macOS `.app` bundle directory. Written only.
+    App,
+    /// A disk image holding the `.app` bundle, built with `hdiutil`.
+    /// Written only.
+    Dmg,
+    /// A directory with a WebAssembly module, its `wasm-bindgen` JS bindings,
+    /// a loader page and a `package.json`. Written only.
+    Wasm,
+}
+
+impl std::str::FromStr for PackageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<PackageFormat, String> {
+        match s {
+            "rpack" => Ok(PackageFormat::SelfExtracting),
+            "zip" => Ok(PackageFormat::Zip),
+            "deb" => Ok(PackageFormat::Deb),
+            "appimage" => Ok(PackageFormat::AppImage),
+            "app" => Ok(PackageFormat::App),
+            "dmg" => Ok(PackageFormat::Dmg),
+            "wasm" => Ok(PackageFormat::Wasm),
+            other => Err(format!("Unknown package format '{}' (expected rpack, zip, deb, appimage, app, dmg or wasm)", other)),
+        }
+    }
+}
+
+/// How the tar payload of a self-extracting package is compressed. ZIP
+/// packages always use Deflate.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PayloadCompression {
+    Gzip,
+    Zstd,
+    /// Has no magic number, so packages are identified as brotli by the
+    /// `PAYLOAD_COMPRESSION` line in their bootstrap.
+    Brotli,
+    None,
+}
+
+impl std::str::FromStr for PayloadCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<PayloadCompression, String> {
+        match s {
+            "gzip" => Ok(PayloadCompression::Gzip),
+            "zstd" => Ok(PayloadCompression::Zstd),
+            "brotli" => Ok(PayloadCompression::Brotli),
+            "none" => Ok(PayloadCompression::None),
+            other => Err(format!("Unknown compression '{}' (expected gzip, zstd, brotli or none)", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for PayloadCompression {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            PayloadCompression::Gzip => "gzip",
+            PayloadCompression::Zstd => "zstd",
+            PayloadCompression::Brotli => "brotli",
+            PayloadCompression::None => "none",
+        })
+    }
+}
+
+impl PayloadCompression {
+    /// Identifies the compression from the first bytes of a payload. Anything
+    /// that isn't gzip or zstd is taken to be a plain tar archive.
+    pub fn detect(payload: &[u8]) -> PayloadCompression {
+        if payload.starts_with(&[0x1f, 0x8b]) {
+            PayloadCompression::Gzip
+        } else if payload.starts_with(&ZSTD_MAGIC) {
+            PayloadCompression::Zstd
+        } else {
+            PayloadCompression::None
+        }
+    }
+
+    /// Like `detect`, but recognizes brotli payloads from the bootstrap in
+    /// `stub`, the part of the package before the payload.
+    pub fn detect_with_stub(stub: &[u8], payload: &[u8]) -> PayloadCompression {
+        const BROTLI_LINE: &[u8] = b"\nPAYLOAD_COMPRESSION=brotli\n";
+        if stub.windows(BROTLI_LINE.len()).any(|w| w == BROTLI_LINE) {
+            PayloadCompression::Brotli
+        } else {
+            PayloadCompression::detect(payload)
+        }
+    }
+
+    /// `level` is the gzip level, 0 to 9, or flate2's default. The other
+    /// compressions always use their own fixed level.
+    pub(crate) fn encoder<W: Write>(self, writer: W, level: Option<u32>) -> std::io::Result<PayloadEncoder<W>> {
+        Ok(match self {
+            PayloadCompression::Gzip => {
+                PayloadEncoder::Gzip(GzEncoder::new(writer, level.map_or_else(Compression::default, Compression::new)))
+            }
+            PayloadCompression::Zstd => PayloadEncoder::Zstd(zstd::Encoder::new(writer, ZSTD_LEVEL)?),
+            PayloadCompression::Brotli => {
+                PayloadEncoder::Brotli(Box::new(brotli::CompressorWriter::new(writer, 64 * 1024, BROTLI_QUALITY, BROTLI_WINDOW)))
+            }
+            PayloadCompression::None => PayloadEncoder::None(writer),
+        })
+    }
+
+    pub(crate) fn decoder<'a>(self, payload: &'a [u8]) -> std::io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            PayloadCompression::Gzip => Box::new(GzDecoder::new(payload)),
+            PayloadCompression::Zstd => Box::new(zstd::Decoder::with_buffer(payload)?),
+            PayloadCompression::Brotli => Box::new(brotli::Decompressor::new(payload, 64 * 1024)),
+            PayloadCompression::None => Box::new(payload),
+        })
+    }
+}
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+// Packages are compressed once and extracted on every run, so trade pack
+// time for size; decompression speed barely depends on the level.
+const ZSTD_LEVEL: i32 = 19;
+// The highest quality, and the largest window the `brotli` tool decodes
+// without being told to.
+const BROTLI_QUALITY: u32 = 11;
+const BROTLI_WINDOW: u32 = 24;
+
+pub(crate) enum PayloadEncoder<W: Write> {
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+    Brotli(Box<brotli::CompressorWriter<W>>),
+    None(W),
+}
+
+impl<W: Write> PayloadEncoder<W> {
+    pub(crate) fn finish(self) -> std::io::Result<W> {
+        match self {
+            PayloadEncoder::Gzip(encoder) => encoder.finish(),
+            PayloadEncoder::Zstd(encoder) => encoder.finish(),
+            PayloadEncoder::Brotli(mut encoder) => {
+                // into_inner ends the stream but drops write errors, which
+                // flushing first surfaces.
+                encoder.flush()?;
+                Ok(encoder.into_inner())
+            }
+            PayloadEncoder::None(writer) => Ok(writer),
+        }
+    }
+}
+
+impl<W: Write> Write for PayloadEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            PayloadEncoder::Gzip(encoder) => encoder.write(buf),
+            PayloadEncoder::Zstd(encoder) => encoder.write(buf),
+            PayloadEncoder::Brotli(encoder) => encoder.write(buf),
+            PayloadEncoder::None(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            PayloadEncoder::Gzip(encoder) => encoder.flush(),
+            PayloadEncoder::Zstd(encoder) => encoder.flush(),
+            PayloadEncoder::Brotli(encoder) => encoder.flush(),
+            PayloadEncoder::None(writer) => writer.flush(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PackageEntry {
+    pub path: String,
+    pub data: Vec<u8>,
+    pub mode: u32,
+    pub mtime: u64,
+    pub is_dir: bool,
+}
+
+/// An existing `.rpack` or `.zip` package loaded into memory so its
+/// entries can be inspected, replaced and written back out.
+pub struct Package {
+    format: PackageFormat,
+    compression: PayloadCompression,
+    stub: Vec<u8>,
+    entries: Vec<PackageEntry>,
+}
+
+impl Package {
+    pub fn open(path: &Path) -> Result<Package, Box<dyn std::error::Error>> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+
+        if bytes.starts_with(b"PK\x03\x04") {
+            return Ok(Package {
+                format: PackageFormat::Zip,
+                compression: PayloadCompression::None,
+                stub: Vec::new(),
+                entries: read_zip_entries(&bytes)?,
+            });
+        }
+
+        let payload_start = find_payload(&bytes)
+            .ok_or_else(|| format!("No payload marker found in {}", path.display()))?;
+        let compression = PayloadCompression::detect_with_stub(&bytes[..payload_start], &bytes[payload_start..]);
+        Ok(Package {
+            format: PackageFormat::SelfExtracting,
+            compression,
+            stub: bytes[..payload_start].to_vec(),
+            entries: read_tar_entries(&bytes[payload_start..], compression)?,
+        })
+    }
+
+    pub fn format(&self) -> PackageFormat {
+        self.format
+    }
+
+    /// The payload compression of a self-extracting package, kept when it
+    /// is written back out.
+    pub fn compression(&self) -> PayloadCompression {
+        self.compression
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &PackageEntry> {
+        self.entries.iter()
+    }
+
+    pub fn entry(&self, path: &str) -> Option<&PackageEntry> {
+        self.entries.iter().find(|e| e.path == path)
+    }
+
+    pub fn info(&self) -> Result<PackageInfo, Box<dyn std::error::Error>> {
+        let entry = self.entry(INFO_PATH).ok_or("Package does not contain rustpack/info.json")?;
+        Ok(serde_json::from_slice(&entry.data)?)
+    }
+
+    pub fn set_info(&mut self, info: &PackageInfo) -> Result<(), Box<dyn std::error::Error>> {
+        let info_json = serde_json::to_string_pretty(info)?;
+        self.replace_entry(INFO_PATH, info_json.into_bytes());
+        self.replace_entry(MANIFEST_ENV_PATH, manifest_env(info).into_bytes());
+        Ok(())
+    }
+
+    /// Writes every entry below `dest`. Fails on entries with absolute paths
+    /// or `..` components, which would land outside it.
+    pub fn extract(&self, dest: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        for entry in &self.entries {
+            let rel_path = Path::new(&entry.path);
+            if !rel_path.components().all(|c| matches!(c, std::path::Component::Normal(_) | std::path::Component::CurDir)) {
+                return Err(format!("Refusing to extract {}: it points outside the destination", entry.path).into());
+            }
+        }
+        for entry in &self.entries {
+            let dest_path = dest.join(&entry.path);
+            if entry.is_dir {
+                fs::create_dir_all(&dest_path)?;
+                continue;
+            }
+            if let Some(parent) = dest_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&dest_path, &entry.data)?;
+
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&dest_path, fs::Permissions::from_mode(entry.mode))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the contents of `path`, adding it (and any missing parent
+    /// directories) when the package doesn't contain it yet.
+    pub fn replace_entry(&mut self, path: &str, data: Vec<u8>) {
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.path == path && !e.is_dir) {
+            entry.data = data;
+            return;
+        }
+
+        let mut parent = String::new();
+        for component in path.split('/').take(path.split('/').count() - 1) {
+            if !parent.is_empty() {
+                parent.push('/');
+            }
+            parent.push_str(component);
+            if !self.entries.iter().any(|e| e.is_dir && e.path == parent) {
+                self.entries.push(PackageEntry {
+                    path: parent.clone(),
+                    data: Vec::new(),
+                    mode: 0o755,
+                    mtime: source_date_epoch().unwrap_or(0),
+                    is_dir: true,
+                });
+            }
+        }
+        self.entries.push(PackageEntry {
+            path: path.to_string(),
+            data,
+            mode: 0o644,
+            mtime: source_date_epoch().unwrap_or(0),
+            is_dir: false,
+        });
+    }
+
+    pub fn replace_asset(&mut self, name: &str, data: Vec<u8>) {
+        self.replace_entry(&format!("rustpack/assets/{}", name), data);
+    }
+
+    pub fn replace_binary(&mut self, target: &str, data: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+        let info = self.info()?;
+        let target_info = info
+            .targets
+            .iter()
+            .find(|t| t.binary_path.starts_with(&format!("bin/{}/", target)))
+            .ok_or_else(|| format!("Package has no binary for target {}", target))?;
+        self.replace_entry(&format!("rustpack/{}", target_info.binary_path), data);
+        Ok(())
+    }
+
+    /// Writes the package to `path` in its original format. The Merkle tree,
+    /// if the package has one, is rebuilt from the current entries. When a
+    /// signing key is given, the per-target binary signatures in info.json
+    /// are recomputed and the written file gets a fresh signature trailer.
+    pub fn write(&mut self, path: &Path, sign_key: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+        if self.entry(MERKLE_PATH).is_some() {
+            self.refresh_merkle()?;
+        }
+        if let Some(key) = sign_key {
+            self.resign(key)?;
+        }
+
+        match self.format {
+            PackageFormat::Zip => self.write_zip(path)?,
+            PackageFormat::SelfExtracting => self.write_self_extracting(path)?,
+            PackageFormat::Deb | PackageFormat::AppImage | PackageFormat::App | PackageFormat::Dmg | PackageFormat::Wasm => {
+                return Err(format!("Opened packages can't be written as {:?}", self.format).into());
+            }
+        }
+
+        if let Some(key) = sign_key {
+            sign_output_file(path, key, self.compression)?;
+        }
+        Ok(())
+    }
+
+    fn refresh_merkle(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let files = self
+            .entries
+            .iter()
+            .filter(|e| !e.is_dir)
+            .filter_map(|e| {
+                let path = e.path.strip_prefix("rustpack/")?;
+                Some((path.to_string(), format!("{:x}", Sha256::digest(&e.data))))
+            })
+            .collect();
+        let tree = MerkleTree::from_hashes(files);
+
+        let mut info = self.info()?;
+        info.merkle_root = Some(tree.root.clone());
+        self.replace_entry(MERKLE_PATH, serde_json::to_string_pretty(&tree)?.into_bytes());
+        self.set_info(&info)
+    }
+
+    fn resign(&mut self, key: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let mut info = self.info()?;
+        let staging = tempfile::tempdir()?;
+        for target in info.targets.iter_mut().filter(|t| t.signature.is_some()) {
+            let entry = self
+                .entry(&format!("rustpack/{}", target.binary_path))
+                .ok_or_else(|| format!("Package is missing {}", target.binary_path))?;
+            let binary = staging.path().join("binary");
+            fs::write(&binary, &entry.data)?;
+            target.signature = Some(sign_package(&binary, key)?);
+        }
+        self.set_info(&info)
+    }
+
+    fn write_self_extracting(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let mut output_file = File::create(path)?;
+        output_file.write_all(&self.stub)?;
+
+        let mut tar = Builder::new(self.compression.encoder(output_file, None)?);
+        for entry in &self.entries {
+            let mut header = Header::new_gnu();
+            header.set_mode(entry.mode);
+            header.set_mtime(entry.mtime);
+            if entry.is_dir {
+                header.set_entry_type(EntryType::Directory);
+                header.set_size(0);
+                tar.append_data(&mut header, &entry.path, std::io::empty())?;
+            } else {
+                header.set_entry_type(EntryType::Regular);
+                header.set_size(entry.data.len() as u64);
+                tar.append_data(&mut header, &entry.path, entry.data.as_slice())?;
+            }
+        }
+        tar.into_inner()?.finish()?;
+        stamp_payload_checksum(path, &self.stub)?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+        }
+        Ok(())
+    }
+
+    fn write_zip(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let mut zip = zip::ZipWriter::new(File::create(path)?);
+        for entry in &self.entries {
+            let options = FileOptions::default()
+                .compression_method(zip::CompressionMethod::Deflated)
+                .unix_permissions(entry.mode)
+                .last_modified_time(zip_timestamp(entry.mtime));
+            if entry.is_dir {
+                zip.add_directory(entry.path.as_str(), options)?;
+            } else {
+                zip.start_file(entry.path.as_str(), options)?;
+                zip.write_all(&entry.data)?;
+            }
+        }
+        zip.finish()?;
+        Ok(())
+    }
+}
+
+// Windows stubs contain the marker themselves, so the payload starts after
+// the last occurrence.
+pub(crate) fn find_payload(bytes: &[u8]) -> Option<usize> {
+    bytes
+        .windows(PAYLOAD_MARKER.len())
+        .rposition(|w| w == PAYLOAD_MARKER)
+        .map(|pos| pos + PAYLOAD_MARKER.len())
+}
+
+/// Fills in the bootstrap's `PAYLOAD_CHECKSUM` with the SHA-256 of the
+/// payload written after `stub`, which names the package's extraction cache
+/// directory. Stubs without the line (Windows, older packages) are left as
+/// they are.
+pub(crate) fn stamp_payload_checksum(path: &Path, stub: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::{Seek, SeekFrom};
+
+    let Some(offset) = stub
+        .windows(PAYLOAD_CHECKSUM_KEY.len())
+        .position(|w| w == PAYLOAD_CHECKSUM_KEY)
+        .map(|pos| pos + PAYLOAD_CHECKSUM_KEY.len())
+    else {
+        return Ok(());
+    };
+    let payload_start = find_payload(stub).unwrap_or(stub.len() + PAYLOAD_MARKER.len());
+
+    let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+    file.seek(SeekFrom::Start(payload_start as u64))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    file.seek(SeekFrom::Start(offset as u64))?;
+    file.write_all(format!("{:x}", hasher.finalize()).as_bytes())?;
+    Ok(())
+}
+
+fn read_tar_entries(payload: &[u8], compression: PayloadCompression) -> Result<Vec<PackageEntry>, Box<dyn std::error::Error>> {
+    let mut archive = Archive::new(compression.decoder(payload)?);
+    let mut entries = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().trim_end_matches('/').to_string();
+        let is_dir = entry.header().entry_type().is_dir();
+        let mode = entry.header().mode()?;
+        let mtime = entry.header().mtime()?;
+        let mut data = Vec::new();
+        if entry.header().entry_type().is_hard_link() {
+            // Identical files are stored once; later copies link to the first.
+            let target = entry
+                .link_name()?
+                .map(|p| p.to_string_lossy().to_string())
+                .ok_or_else(|| format!("Hard link {} has no target", path))?;
+            data = entries
+                .iter()
+                .find(|e: &&PackageEntry| e.path == target)
+                .map(|e| e.data.clone())
+                .ok_or_else(|| format!("Hard link {} points to missing {}", path, target))?;
+        } else if !is_dir {
+            entry.read_to_end(&mut data)?;
+        }
+        entries.push(PackageEntry { path, data, mode, mtime, is_dir });
+    }
+    Ok(entries)
+}
+
+fn read_zip_entries(bytes: &[u8]) -> Result<Vec<PackageEntry>, Box<dyn std::error::Error>> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+    let mut entries = Vec::new();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        let path = file.name().trim_end_matches('/').to_string();
+        let is_dir = file.is_dir();
+        let mode = file.unix_mode().unwrap_or(if is_dir { 0o755 } else { 0o644 });
+        let mut data = Vec::new();
+        if !is_dir {
+            file.read_to_end(&mut data)?;
+        }
+        let mtime = zip_mtime(file.last_modified());
+        entries.push(PackageEntry { path, data, mode, mtime, is_dir });
+    }
+    Ok(entries)
+}
+
+// ZIP stores local time without a zone; treat it as UTC, which is what
+// rustpack writes.
+fn zip_mtime(time: zip::DateTime) -> u64 {
+    chrono::NaiveDate::from_ymd_opt(time.year() as i32, time.month() as u32, time.day() as u32)
+        .and_then(|date| date.and_hms_opt(time.hour() as u32, time.minute() as u32, time.second() as u32))
+        .map_or(0, |t| t.and_utc().timestamp().max(0) as u64)
+}
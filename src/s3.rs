@@ -0,0 +1,62 @@
+use crate::RustPackError;
+use std::path::Path;
+use std::process::Command as ProcessCommand;
+
+/// Fails unless the `aws` CLI is available to upload with.
+pub fn check_tool() -> Result<(), Box<dyn std::error::Error>> {
+    match ProcessCommand::new("aws").arg("--version").output() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(RustPackError::ToolMissing("aws").into()),
+    }
+}
+
+/// The bucket and key prefix of an `s3://bucket/prefix` URL. The prefix has
+/// no trailing slash and may be empty.
+pub fn parse_url(url: &str) -> Result<(&str, &str), Box<dyn std::error::Error>> {
+    let rest = url
+        .strip_prefix("s3://")
+        .ok_or_else(|| format!("--upload expects an s3://bucket/prefix URL, got {}", url))?;
+    let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+    if bucket.is_empty() {
+        return Err(format!("--upload URL {} has no bucket", url).into());
+    }
+    Ok((bucket, prefix.trim_end_matches('/')))
+}
+
+/// Copies `path` (recursively, for an app bundle) to `url` under its file
+/// name and returns the `s3://` URL it ended up at. The aws CLI finds the
+/// credentials and region itself: environment variables, its config and
+/// credentials files, SSO or an instance role.
+pub fn upload(path: &Path, url: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let (bucket, prefix) = parse_url(url)?;
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .ok_or_else(|| format!("{} has no file name to upload under", path.display()))?;
+    let destination = if prefix.is_empty() {
+        format!("s3://{}/{}", bucket, name)
+    } else {
+        format!("s3://{}/{}/{}", bucket, prefix, name)
+    };
+
+    let mut command = ProcessCommand::new("aws");
+    command.args(["s3", "cp", "--only-show-errors"]);
+    if path.is_dir() {
+        command.arg("--recursive");
+    }
+    let output = command
+        .arg(path)
+        .arg(&destination)
+        .output()
+        .map_err(|_| RustPackError::ToolMissing("aws"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "Uploading {} to {} failed:\n{}",
+            path.display(),
+            destination,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        )
+        .into());
+    }
+    Ok(destination)
+}
@@ -0,0 +1,120 @@
+use crate::RustPackError;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command as ProcessCommand;
+
+/// Fails unless `wasm-bindgen` is available to generate the JS bindings.
+pub fn check_tool() -> Result<(), Box<dyn std::error::Error>> {
+    match ProcessCommand::new("wasm-bindgen").arg("--version").output() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(RustPackError::ToolMissing("wasm-bindgen").into()),
+    }
+}
+
+/// The module cargo writes for the library of the package in `manifest_dir`
+/// under `built_dir`: `[lib] name`, or the package name with dashes
+/// replaced by underscores.
+pub fn module_path(manifest_dir: &str, package_name: &str, built_dir: &Path) -> PathBuf {
+    let lib_name = fs::read_to_string(Path::new(manifest_dir).join("Cargo.toml"))
+        .ok()
+        .and_then(|content| toml::from_str::<toml::Value>(&content).ok())
+        .and_then(|manifest| manifest.get("lib")?.get("name")?.as_str().map(str::to_string))
+        .unwrap_or_else(|| package_name.replace('-', "_"));
+    built_dir.join(format!("{}.wasm", lib_name))
+}
+
+/// Writes `output` as a directory a web server can serve: the bindings
+/// `wasm-bindgen --target web` generates for `module` (`<stem>.js`,
+/// `<stem>_bg.wasm` and TypeScript declarations), an `index.html` that loads
+/// them, and a `package.json` for publishing to npm. Returns the file name
+/// of the processed module.
+pub fn write_bundle(
+    module: &Path,
+    name: &str,
+    version: &str,
+    description: Option<&str>,
+    license: Option<&str>,
+    output: &Path,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if !module.is_file() {
+        return Err(format!(
+            "No WebAssembly module at {}; add `crate-type = [\"cdylib\"]` to the [lib] section of Cargo.toml",
+            module.display()
+        )
+        .into());
+    }
+    let stem = module.file_stem().ok_or("Invalid module path")?.to_string_lossy().to_string();
+
+    if output.exists() {
+        fs::remove_dir_all(output)?;
+    }
+    let result = ProcessCommand::new("wasm-bindgen")
+        .args(["--target", "web", "--out-dir"])
+        .arg(output)
+        .args(["--out-name", &stem])
+        .arg(module)
+        .output()?;
+    if !result.status.success() {
+        return Err(format!("wasm-bindgen failed:\n{}", String::from_utf8_lossy(&result.stderr).trim_end()).into());
+    }
+
+    fs::write(output.join("index.html"), loader_page(name, &stem))?;
+
+    let mut files: Vec<String> = fs::read_dir(output)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|file| file != "index.html")
+        .collect();
+    files.sort();
+    let package = NpmPackage {
+        name,
+        version,
+        description,
+        license,
+        kind: "module",
+        main: format!("{}.js", stem),
+        types: format!("{}.d.ts", stem),
+        files,
+    };
+    fs::write(output.join("package.json"), serde_json::to_string_pretty(&package)? + "\n")?;
+
+    Ok(format!("{}_bg.wasm", stem))
+}
+
+#[derive(Serialize)]
+struct NpmPackage<'a> {
+    name: &'a str,
+    version: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    license: Option<&'a str>,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    main: String,
+    types: String,
+    files: Vec<String>,
+}
+
+/// A page that instantiates the module. Exported functions are reachable as
+/// `window.wasm` from the browser console.
+fn loader_page(name: &str, stem: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <title>{name}</title>
+</head>
+<body>
+    <script type="module">
+        import init, * as wasm from "./{stem}.js";
+        await init();
+        window.wasm = wasm;
+    </script>
+</body>
+</html>
+"#
+    )
+}
@@ -0,0 +1,123 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command as ProcessCommand;
+
+// Build dependencies that embed a resource of their own, which would clash
+// with ours when linked.
+const RESOURCE_CRATES: &[&str] = &["winres", "winresource", "embed-resource", "tauri-build"];
+
+/// Whether the project at `manifest_dir` already embeds Windows resources
+/// from its build script.
+pub fn has_own_resources(manifest_dir: &str) -> bool {
+    let Ok(content) = fs::read_to_string(Path::new(manifest_dir).join("Cargo.toml")) else {
+        return false;
+    };
+    let Ok(manifest) = toml::from_str::<toml::Value>(&content) else {
+        return false;
+    };
+    let targets = manifest.get("target").and_then(|t| t.as_table());
+    std::iter::once(&manifest)
+        .chain(targets.into_iter().flat_map(|t| t.values()))
+        .filter_map(|table| table.get("build-dependencies").and_then(|d| d.as_table()))
+        .any(|deps| RESOURCE_CRATES.iter().any(|name| deps.contains_key(*name)))
+}
+
+/// A resource script with a `VERSIONINFO` block, which fills in the Details
+/// tab of the executable's properties, and `icon` as the icon Explorer shows.
+pub fn resource_script(name: &str, version: &str, description: Option<&str>, authors: &[String], icon: Option<&Path>) -> String {
+    let numeric = numeric_version(version);
+    let mut script = String::from("#pragma code_page(65001)\n");
+    if let Some(icon) = icon {
+        script.push_str(&format!("1 ICON \"{}\"\n", rc_escape(&icon.to_string_lossy())));
+    }
+    script.push_str(&format!(
+        "1 VERSIONINFO\nFILEVERSION {0}\nPRODUCTVERSION {0}\nFILEOS 0x40004\nFILETYPE 0x1\nBEGIN\n    BLOCK \"StringFileInfo\"\n    BEGIN\n        BLOCK \"040904B0\"\n        BEGIN\n",
+        numeric
+    ));
+    // Authors are usually `Name <email>`; the name is enough here.
+    let company = authors.first().map(|a| a.split('<').next().unwrap_or(a).trim().to_string());
+    let description = description
+        .and_then(|d| d.lines().map(str::trim).find(|l| !l.is_empty()))
+        .unwrap_or(name);
+    let mut values = vec![
+        ("FileDescription", description.to_string()),
+        ("FileVersion", version.to_string()),
+        ("InternalName", name.to_string()),
+        ("ProductName", name.to_string()),
+        ("ProductVersion", version.to_string()),
+    ];
+    if let Some(company) = company.filter(|c| !c.is_empty()) {
+        values.push(("LegalCopyright", format!("Copyright (c) {}", company)));
+        values.push(("CompanyName", company));
+    }
+    for (key, value) in values {
+        script.push_str(&format!("            VALUE \"{}\", \"{}\"\n", key, rc_escape(&value)));
+    }
+    script.push_str(
+        "        END\n    END\n    BLOCK \"VarFileInfo\"\n    BEGIN\n        VALUE \"Translation\", 0x409, 1200\n    END\nEND\n",
+    );
+    script
+}
+
+/// Compiles `script` into `out_dir` with a resource compiler that produces
+/// what `target`'s linker takes: a COFF object from `windres` for GNU
+/// targets, a `.res` file from `rc` or `llvm-rc` for MSVC. Returns `None` if
+/// none of them is installed.
+pub fn compile_resource(script: &str, target: &str, out_dir: &Path) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+    fs::create_dir_all(out_dir)?;
+    let out_dir = fs::canonicalize(out_dir)?;
+    let rc_file = out_dir.join("resource.rc");
+    fs::write(&rc_file, script)?;
+
+    let msvc = target.ends_with("-msvc");
+    let Some(tool) = resource_compilers(target)
+        .into_iter()
+        .find(|tool| ProcessCommand::new(tool).arg("--version").output().is_ok())
+    else {
+        return Ok(None);
+    };
+    let (output, result) = if msvc {
+        let output = out_dir.join("resource.res");
+        let result = ProcessCommand::new(&tool).arg("/fo").arg(&output).arg(&rc_file).output()?;
+        (output, result)
+    } else {
+        let output = out_dir.join("resource.o");
+        let result = ProcessCommand::new(&tool)
+            .arg("--input")
+            .arg(&rc_file)
+            .arg("--output")
+            .arg(&output)
+            .arg("--output-format=coff")
+            .output()?;
+        (output, result)
+    };
+    if !result.status.success() {
+        return Err(format!("{} failed:\n{}", tool, String::from_utf8_lossy(&result.stderr).trim_end()).into());
+    }
+    Ok(Some(output))
+}
+
+/// The resource compilers to try for `target`, in order.
+pub fn resource_compilers(target: &str) -> Vec<String> {
+    if target.ends_with("-msvc") {
+        return vec!["llvm-rc".to_string(), "rc".to_string()];
+    }
+    let arch = match target.split('-').next().unwrap_or_default() {
+        "i586" | "i686" => "i686",
+        arch => arch,
+    };
+    vec![format!("{}-w64-mingw32-windres", arch), "windres".to_string(), "llvm-windres".to_string()]
+}
+
+/// `major,minor,patch,0` for `FILEVERSION`, which only takes numbers.
+fn numeric_version(version: &str) -> String {
+    let core = version.split(['-', '+']).next().unwrap_or(version);
+    let mut parts: Vec<u16> = core.split('.').map(|p| p.parse().unwrap_or(0)).take(3).collect();
+    parts.resize(4, 0);
+    parts.iter().map(u16::to_string).collect::<Vec<_>>().join(",")
+}
+
+/// Strings in resource scripts escape backslashes and double their quotes.
+fn rc_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\"\"")
+}
@@ -0,0 +1,254 @@
+//! Runs packages built from the fixture crate to check the bootstrap script.
+mod common;
+
+use common::{Fixture, assert_success, stdout};
+use sha2::Digest;
+
+#[test]
+fn missing_capability_refuses_to_run() {
+    let fixture = Fixture::new();
+    let package = fixture.pack("app.rpack", &["--requires", "cpu:rustpack_no_such_flag"]);
+
+    let result = fixture.run(&package).output().unwrap();
+    assert_eq!(result.status.code(), Some(1));
+    assert!(stdout(&result).contains("requires the CPU feature 'rustpack_no_such_flag'"), "{}", stdout(&result));
+    assert!(!stdout(&result).contains("Hello, world!"));
+
+    let result = fixture.run(&package).env("RUSTPACK_SKIP_REQUIRES", "1").output().unwrap();
+    assert_success(&result);
+    assert!(stdout(&result).contains("Hello, world!"));
+}
+
+#[test]
+fn beta_package_checks_beta_channel() {
+    let fixture = Fixture::new();
+    let server = tempfile::tempdir().unwrap();
+    let update_url = format!("file://{}", server.path().display());
+    let package = fixture.pack("app.rpack", &["--channel", "beta", "--update-url", &update_url]);
+    let check_updates = |stable: &str, beta: &str| {
+        let version_json = format!(
+            r#"{{"channels": {{"stable": {{"version": "{}", "checksum": "", "file": "latest.rpack"}}, "beta": {{"version": "{}", "checksum": "", "file": "latest-beta.rpack"}}}}, "version": "{}"}}"#,
+            stable, beta, stable
+        );
+        std::fs::write(server.path().join("version.json"), version_json).unwrap();
+        let result = fixture.run(&package).arg("--check-updates").output().unwrap();
+        assert_success(&result);
+        stdout(&result)
+    };
+
+    let output = check_updates("9.9.9", "0.1.0");
+    assert!(output.contains("You are running the latest version: 0.1.0"), "{}", output);
+    let output = check_updates("0.1.0", "0.2.0");
+    assert!(output.contains("Update available: 0.2.0"), "{}", output);
+}
+
+#[test]
+fn multicall_binary_dispatches_on_invocation_name() {
+    let fixture = Fixture::new();
+    fixture.write(
+        "src/lib.rs",
+        "pub fn foo(args: Vec<String>) -> i32 {\n    println!(\"foo {:?}\", args);\n    0\n}\n\n\
+         pub fn bar(args: Vec<String>) -> i32 {\n    println!(\"bar {:?}\", args);\n    3\n}\n",
+    );
+    fixture.write("RustPack.toml", "[multicall]\nfoo = \"hello_world_test::foo\"\nbar = \"hello_world_test::bar\"\n");
+    let package = fixture.pack("tools.rpack", &[]);
+
+    let extracted = tempfile::tempdir().unwrap();
+    rustpack::extract_package(&package, extracted.path()).unwrap();
+    let binary = extracted.path().join(format!("rustpack/bin/{}/hello-world-test", rustpack::get_current_target()));
+    for tool in ["foo", "bar"] {
+        std::os::unix::fs::symlink(&binary, extracted.path().join(tool)).unwrap();
+    }
+    let result = std::process::Command::new(extracted.path().join("foo")).arg("-x").output().unwrap();
+    assert_success(&result);
+    assert_eq!(stdout(&result), "foo [\"-x\"]\n");
+    let result = std::process::Command::new(extracted.path().join("bar")).output().unwrap();
+    assert_eq!(result.status.code(), Some(3));
+    assert_eq!(stdout(&result), "bar []\n");
+
+    // The package itself is invoked under its own name, so the first
+    // argument picks the tool.
+    let result = fixture.run(&package).args(["bar", "y"]).output().unwrap();
+    assert_eq!(result.status.code(), Some(3));
+    assert_eq!(stdout(&result), "bar [\"y\"]\n");
+}
+
+#[test]
+fn multicall_uses_renamed_lib_crate() {
+    let fixture = Fixture::new();
+    let manifest = std::fs::read_to_string(fixture.path().join("Cargo.toml")).unwrap();
+    fixture.write("Cargo.toml", format!("{}\n[lib]\nname = \"greet\"\n", manifest));
+    fixture.write("src/lib.rs", "pub fn hi(_args: Vec<String>) -> i32 {\n    println!(\"hi\");\n    0\n}\n");
+    fixture.write("RustPack.toml", "[multicall]\nhi = \"greet::hi\"\n");
+    let package = fixture.pack("tools.rpack", &[]);
+    let result = fixture.run(&package).arg("hi").output().unwrap();
+    assert_success(&result);
+    assert_eq!(stdout(&result), "hi\n");
+}
+
+#[test]
+fn cleanup_removes_extracted_files() {
+    let fixture = Fixture::new();
+    fixture.write(
+        "src/main.rs",
+        "fn main() {\n    let args: Vec<String> = std::env::args().skip(1).collect();\n    println!(\"Hello, world! {:?}\", args);\n    \
+         std::process::exit(if args.iter().any(|a| a == \"fail\") { 7 } else { 0 });\n}\n",
+    );
+    let package = fixture.pack("app.rpack", &[]);
+    let tmp = fixture.path().join("tmp");
+    std::fs::create_dir(&tmp).unwrap();
+    let entries = |dir: &std::path::Path| std::fs::read_dir(dir).map(|d| d.count()).unwrap_or(0);
+
+    let result = fixture.run(&package).args(["--no-cache", "--cleanup", "fail"]).env("TMPDIR", &tmp).output().unwrap();
+    assert_eq!(result.status.code(), Some(7));
+    assert!(stdout(&result).contains("Hello, world!"));
+    assert_eq!(entries(&tmp), 0);
+
+    // Without --cleanup the app is exec'd and its files stay.
+    let result = fixture.run(&package).arg("--no-cache").env("TMPDIR", &tmp).output().unwrap();
+    assert_success(&result);
+    assert_eq!(entries(&tmp), 1);
+    // Either order works, and a --no-cache after the app's own arguments
+    // is passed on to it.
+    let result = fixture.run(&package).args(["--cleanup", "--no-cache", "x", "--no-cache"]).env("TMPDIR", &tmp).output().unwrap();
+    assert_success(&result);
+    assert!(stdout(&result).contains("[\"x\", \"--no-cache\"]"), "{}", stdout(&result));
+    assert_eq!(entries(&tmp), 1);
+
+    let cache = fixture.path().join("cache/rustpack");
+    assert_success(&fixture.run(&package).output().unwrap());
+    assert_eq!(entries(&cache), 1);
+    // After the app's own arguments, --cleanup is passed on to it.
+    let result = fixture.run(&package).args(["fail", "--cleanup"]).output().unwrap();
+    assert_eq!(result.status.code(), Some(7));
+    assert!(stdout(&result).contains("[\"fail\", \"--cleanup\"]"), "{}", stdout(&result));
+    assert_eq!(entries(&cache), 1);
+    let result = fixture.run(&package).arg("--cleanup").output().unwrap();
+    assert_success(&result);
+    assert_eq!(entries(&cache), 0);
+}
+
+#[test]
+fn no_default_features_leaves_out_default_feature() {
+    let fixture = Fixture::new();
+    fixture.write(
+        "Cargo.toml",
+        "[package]\nname = \"hello-world-test\"\nversion = \"0.1.0\"\nedition = \"2024\"\n\n[features]\ndefault = [\"greeting\"]\ngreeting = []\nextra = []\n",
+    );
+    fixture.write(
+        "src/main.rs",
+        "fn main() {\n    #[cfg(feature = \"greeting\")]\n    println!(\"default feature on\");\n    #[cfg(feature = \"extra\")]\n    println!(\"extra feature on\");\n}\n",
+    );
+    let run = |output: &str, args: &[&str]| stdout(&fixture.run(&fixture.pack(output, args)).output().unwrap());
+
+    assert_eq!(run("default.rpack", &[]), "default feature on\n");
+    assert_eq!(run("none.rpack", &["--no-default-features"]), "");
+    assert_eq!(run("extra.rpack", &["--no-default-features", "--features", "extra"]), "extra feature on\n");
+}
+
+#[test]
+fn extracts_to_rustpack_extract_dir() {
+    let fixture = Fixture::new();
+    let package = fixture.pack("app.rpack", &[]);
+    let extract_dir = fixture.path().join("scratch/app");
+
+    let result = fixture.run(&package).env("RUSTPACK_EXTRACT_DIR", &extract_dir).output().unwrap();
+    assert_success(&result);
+    assert!(stdout(&result).contains("Hello, world!"));
+    assert!(extract_dir.join("rustpack/info.json").is_file());
+    let binary = extract_dir.join(format!("rustpack/bin/{}/hello-world-test", rustpack::get_current_target()));
+    assert!(binary.is_file());
+    assert!(!fixture.path().join("cache").exists());
+}
+
+#[test]
+fn update_without_host_binary_is_refused() {
+    let fixture = Fixture::new();
+    let server = tempfile::tempdir().unwrap();
+    let update_url = format!("file://{}", server.path().display());
+    let package = fixture.pack("app.rpack", &["--update-url", &update_url]);
+    let installed = std::fs::read(&package).unwrap();
+    std::fs::write(
+        server.path().join("version.json"),
+        r#"{"channels": {"stable": {"version": "0.2.0", "checksum": "", "file": "latest.rpack"}}, "version": "0.2.0"}"#,
+    )
+    .unwrap();
+    let publish = |update: &std::path::Path| {
+        let published = server.path().join("latest.rpack");
+        std::fs::copy(update, &published).unwrap();
+        let checksum = format!("{:x}", sha2::Sha256::digest(std::fs::read(&published).unwrap()));
+        std::fs::write(server.path().join("latest.rpack.sha256"), format!("{}  latest.rpack\n", checksum)).unwrap();
+    };
+
+    // The same build, but claiming its only binary is for another platform.
+    let mut update = rustpack::package::Package::open(&fixture.pack("update.rpack", &[])).unwrap();
+    let mut info = update.info().unwrap();
+    for target in &mut info.targets {
+        target.platform = "macos".to_string();
+        target.arch = "aarch64".to_string();
+    }
+    update.set_info(&info).unwrap();
+    let foreign = fixture.path().join("foreign.rpack");
+    update.write(&foreign, None).unwrap();
+    publish(&foreign);
+
+    let result = fixture.run(&package).arg("--update").output().unwrap();
+    assert_eq!(result.status.code(), Some(1));
+    assert!(stdout(&result).contains("the downloaded update has no binary for linux-x86_64"), "{}", stdout(&result));
+    assert_eq!(std::fs::read(&package).unwrap(), installed);
+
+    publish(&fixture.path().join("update.rpack"));
+    let result = fixture.run(&package).arg("--update").output().unwrap();
+    assert_success(&result);
+    assert!(stdout(&result).contains("Update completed successfully!"));
+}
+
+#[test]
+fn bundled_script_stays_executable() {
+    use std::os::unix::fs::PermissionsExt;
+    let fixture = Fixture::new();
+    let script = fixture.write("helper.sh", "#!/bin/sh\necho helper ran\n");
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let package = fixture.pack("app.rpack", &["--assets", "helper.sh"]);
+    let extract_dir = fixture.path().join("extracted");
+
+    assert_success(&fixture.run(&package).env("RUSTPACK_EXTRACT_DIR", &extract_dir).output().unwrap());
+    let helper = extract_dir.join("rustpack/assets/helper.sh");
+    assert_eq!(std::fs::metadata(&helper).unwrap().permissions().mode() & 0o777, 0o755);
+    let result = std::process::Command::new(&helper).output().unwrap();
+    assert_eq!(stdout(&result), "helper ran\n");
+}
+
+#[test]
+fn tampered_secondary_binary_is_refused() {
+    let fixture = Fixture::new();
+    fixture.write("src/bin/helper.rs", "fn main() {\n    println!(\"helper ran\");\n}\n");
+    let package = fixture.pack("tools.rpack", &["--bins"]);
+    let result = fixture.run(&package).arg("helper").output().unwrap();
+    assert_success(&result);
+    assert_eq!(stdout(&result), "helper ran\n");
+
+    let helper = format!("rustpack/bin/{}/helper", rustpack::get_current_target());
+    let mut tampered = rustpack::package::Package::open(&package).unwrap();
+    let mut binary = tampered.entry(&helper).unwrap().data.clone();
+    binary.extend_from_slice(b"tampered");
+    tampered.replace_entry(&helper, binary);
+    let tampered_path = fixture.path().join("tampered.rpack");
+    tampered.write(&tampered_path, None).unwrap();
+    let result = fixture.run(&tampered_path).arg("helper").output().unwrap();
+    assert_eq!(result.status.code(), Some(1));
+    assert!(stdout(&result).contains("does not match the checksum recorded in the package"), "{}", stdout(&result));
+    assert!(!stdout(&result).contains("helper ran"));
+
+    // A binary without a recorded checksum doesn't run either.
+    let mut unrecorded = rustpack::package::Package::open(&package).unwrap();
+    let mut info = unrecorded.info().unwrap();
+    info.targets[0].binary_checksums.clear();
+    unrecorded.set_info(&info).unwrap();
+    let unrecorded_path = fixture.path().join("unrecorded.rpack");
+    unrecorded.write(&unrecorded_path, None).unwrap();
+    let result = fixture.run(&unrecorded_path).arg("helper").output().unwrap();
+    assert_eq!(result.status.code(), Some(1));
+    assert!(stdout(&result).contains("records no checksum for"), "{}", stdout(&result));
+    assert_eq!(stdout(&fixture.run(&unrecorded_path).output().unwrap()), "Hello, world!\n");
+}
@@ -0,0 +1,698 @@
+//! Runs rustpack on the fixture crate and checks what it writes.
+mod common;
+
+use common::Fixture;
+use rustpack::package::Package;
+use std::fs;
+
+#[test]
+fn output_manifest_matches_embedded_info() {
+    let fixture = Fixture::new();
+    let package = fixture.pack("app.rpack", &["--output-manifest", "version.json"]);
+
+    let external = fs::read(fixture.path().join("version.json")).unwrap();
+    let package = Package::open(&package).unwrap();
+    let embedded = &package.entry("rustpack/info.json").unwrap().data;
+    assert_eq!(&external, embedded);
+    assert_eq!(package.info().unwrap().name, "hello-world-test");
+}
+
+/// The `strip`, `upx` and `sign` columns of each target in `--dry-run`.
+fn planned_toggles(output: &str, target: &str) -> Vec<String> {
+    let line = output
+        .lines()
+        .find(|line| line.trim_start().starts_with(target))
+        .unwrap_or_else(|| panic!("no plan line for {} in:\n{}", target, output));
+    line.split_whitespace().skip(2).filter(|word| !word.ends_with(':')).map(str::to_string).collect()
+}
+
+#[test]
+fn target_sections_override_sign_compress_and_strip() {
+    let fixture = Fixture::new();
+    fixture.write(
+        "RustPack.toml",
+        r#"targets = ["x86_64-unknown-linux-gnu", "aarch64-unknown-linux-gnu"]
+strip = true
+
+[target.aarch64-unknown-linux-gnu]
+strip = false
+compress = true
+sign = false
+"#,
+    );
+    let result = fixture.rustpack().args(["--dry-run", "--sign-hmac", "secret"]).output().unwrap();
+    let output = common::stdout(&result);
+    assert_eq!(planned_toggles(&output, "x86_64-unknown-linux-gnu"), ["yes", "no", "yes"]);
+    assert_eq!(planned_toggles(&output, "aarch64-unknown-linux-gnu"), ["no", "yes", "no"]);
+
+    // The same overrides apply to a real build.
+    fixture.write("RustPack.toml", "[target.x86_64-unknown-linux-gnu]\nsign = false\n");
+    let package = fixture.pack("unsigned.rpack", &["--sign-hmac", "secret"]);
+    let info = Package::open(&package).unwrap().info().unwrap();
+    assert!(info.targets[0].signature.is_none());
+    fs::remove_file(fixture.path().join("RustPack.toml")).unwrap();
+    let package = fixture.pack("signed.rpack", &["--sign-hmac", "secret"]);
+    let info = Package::open(&package).unwrap().info().unwrap();
+    assert!(info.targets[0].signature.is_some());
+}
+
+#[test]
+fn lto_comes_from_flag_then_config_then_environment() {
+    let fixture = Fixture::new();
+    let planned_lto = |args: &[&str], env: Option<&str>| {
+        let mut command = fixture.rustpack();
+        command.arg("--dry-run").args(args).env_remove("RUSTPACK_LTO");
+        if let Some(lto) = env {
+            command.env("RUSTPACK_LTO", lto);
+        }
+        let output = common::stdout(&command.output().unwrap());
+        let start = output.find("(lto ").unwrap() + "(lto ".len();
+        output[start..].split(',').next().unwrap().to_string()
+    };
+
+    assert_eq!(planned_lto(&[], None), "off");
+    assert_eq!(planned_lto(&[], Some("fat")), "fat");
+    fixture.write("RustPack.toml", "lto = \"thin\"\n");
+    assert_eq!(planned_lto(&[], Some("fat")), "thin");
+    assert_eq!(planned_lto(&["--lto", "off"], Some("fat")), "off");
+}
+
+/// The targets listed by `--dry-run`.
+fn planned_targets(fixture: &Fixture) -> Vec<String> {
+    let output = common::stdout(&fixture.rustpack().arg("--dry-run").output().unwrap());
+    let lines: Vec<&str> = output.lines().collect();
+    let start = lines.iter().position(|line| line.trim_start().starts_with("Targets:")).unwrap();
+    lines[start + 1..]
+        .iter()
+        .take_while(|line| line.starts_with("    "))
+        .map(|line| line.split_whitespace().next().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn targets_come_from_config_file() {
+    let fixture = Fixture::new();
+    assert_eq!(planned_targets(&fixture), [rustpack::get_current_target()]);
+
+    fixture.write("RustPack.toml", "targets = [\"x86_64-unknown-linux-gnu\"]\n");
+    assert_eq!(planned_targets(&fixture), ["x86_64-unknown-linux-gnu"]);
+
+    fixture.write("config.txt", "");
+    fixture.write(
+        "RustPack.toml",
+        "targets = [\"aarch64-unknown-linux-gnu\", \"x86_64-unknown-linux-gnu\"]\nassets = [\"config.txt\"]\n",
+    );
+    assert_eq!(planned_targets(&fixture), ["aarch64-unknown-linux-gnu", "x86_64-unknown-linux-gnu"]);
+}
+
+#[test]
+fn signed_package_verifies_with_same_key() {
+    let fixture = Fixture::new();
+    fixture.pack("app.rpack", &["--sign-hmac", "secret"]);
+
+    let result = fixture.rustpack().args(["--verify", "app.rpack", "--sign-hmac", "secret"]).output().unwrap();
+    common::assert_success(&result);
+    assert!(common::stdout(&result).contains("Signature OK"));
+
+    let result = fixture.rustpack().args(["--verify", "app.rpack", "--sign-hmac", "other"]).output().unwrap();
+    assert_eq!(result.status.code(), Some(5));
+}
+
+#[test]
+fn oversized_package_aborts_and_leaves_nothing_behind() {
+    let fixture = Fixture::new();
+    fixture.write("assets/big.bin", vec![0u8; 4 << 20]);
+    fixture.write("signing.key", [7u8; 32]);
+    let before: Vec<_> = fs::read_dir(fixture.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+
+    // The ZIP deflates the asset to well under the limit and is signed
+    // before the uncompressed self-extracting package fails it.
+    let result = fixture
+        .rustpack()
+        .args(["-o", "app.rpack", "--format", "zip,rpack", "--compression", "none", "--assets", "assets/"])
+        .args(["--sign-key", "signing.key", "--output-manifest", "version.json", "--max-package-size", "1M"])
+        .output()
+        .unwrap();
+    assert!(!result.status.success());
+    let stderr = common::stderr(&result);
+    assert!(stderr.contains("exceeds the limit of"), "{}", stderr);
+    assert!(stderr.contains("assets/assets"), "{}", stderr);
+
+    let after: Vec<_> = fs::read_dir(fixture.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+    let left: Vec<_> = after.iter().filter(|name| !before.contains(name) && *name != "target" && *name != "Cargo.lock").collect();
+    assert!(left.is_empty(), "left behind: {:?}", left);
+}
+
+#[test]
+fn update_url_is_recorded_only_when_given() {
+    let fixture = Fixture::new();
+    let package = fixture.pack("plain.rpack", &[]);
+    let info = Package::open(&package).unwrap().info().unwrap();
+    assert!(!info.metadata.contains_key("update_url"));
+
+    let package = fixture.pack("updating.rpack", &["--update-url", "https://example.com/app"]);
+    let info = Package::open(&package).unwrap().info().unwrap();
+    assert_eq!(info.metadata.get("update_url").map(String::as_str), Some("https://example.com/app"));
+    assert!(info.features.iter().any(|feature| feature == "auto_update"));
+}
+
+#[test]
+fn log_file_records_phases_when_quiet() {
+    let fixture = Fixture::new();
+    let result = fixture
+        .rustpack()
+        .args(["-o", "app.rpack", "--quiet", "--log-file", "build.jsonl"])
+        .output()
+        .unwrap();
+    common::assert_success(&result);
+    assert!(result.stdout.is_empty(), "{}", common::stdout(&result));
+
+    let log = fs::read_to_string(fixture.path().join("build.jsonl")).unwrap();
+    let entries: Vec<serde_json::Value> = log.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    for phase in ["setup", "build", "archive", "package"] {
+        assert!(entries.iter().any(|entry| entry["phase"] == phase), "no {} entries in:\n{}", phase, log);
+    }
+    assert!(entries.iter().all(|entry| entry["timestamp"].is_string() && entry["level"].is_string()));
+}
+
+#[test]
+fn warnings_reach_console_and_log_file() {
+    let fixture = Fixture::new();
+    let result = fixture
+        .rustpack()
+        .args(["-o", "app.deb", "--format", "deb", "--sign-hmac", "secret", "--log-file", "build.jsonl"])
+        .output()
+        .unwrap();
+    common::assert_success(&result);
+    assert!(common::stderr(&result).contains("Warning: Not signing app.deb"), "{}", common::stderr(&result));
+    let log = fs::read_to_string(fixture.path().join("build.jsonl")).unwrap();
+    assert!(log.lines().any(|line| line.contains("\"warn\"") && line.contains("Not signing app.deb")), "{}", log);
+}
+
+#[test]
+fn desktop_entry_is_bundled() {
+    let fixture = Fixture::new();
+    let package = fixture.pack("app.rpack", &["--desktop-entry"]);
+    let package = Package::open(&package).unwrap();
+    let entry = String::from_utf8(package.entry("rustpack/hello-world-test.desktop").unwrap().data.clone()).unwrap();
+    assert!(entry.lines().any(|line| line == "Name=hello-world-test"), "{}", entry);
+    assert!(entry.lines().any(|line| line == "Exec=hello-world-test"), "{}", entry);
+}
+
+#[test]
+fn outdated_tool_is_reported() {
+    let fixture = Fixture::new();
+    fixture.fake_tool("strip", "#!/bin/sh\necho 'GNU strip (GNU Binutils) 2.20'\n");
+    let path = fixture.fake_path();
+
+    let result = fixture.rustpack().arg("--doctor").env("PATH", &path).output().unwrap();
+    assert_eq!(result.status.code(), Some(1));
+    let output = common::stdout(&result);
+    assert!(output.lines().any(|line| line.contains("outdated") && line.contains("strip 2.20.0 (need 2.26.0")), "{}", output);
+
+    let result = fixture.rustpack().args(["--dry-run", "--strip"]).env("PATH", &path).output().unwrap();
+    let stderr = common::stderr(&result);
+    assert!(stderr.contains("Warning: strip 2.20.0 is older than the required 2.26.0"), "{}", stderr);
+}
+
+#[test]
+fn env_file_has_output_and_checksum() {
+    use sha2::{Digest, Sha256};
+
+    let fixture = Fixture::new();
+    let package = fixture.pack("app.rpack", &["--env-file", "build.env"]);
+    let env = fs::read_to_string(fixture.path().join("build.env")).unwrap();
+    let vars: std::collections::HashMap<&str, &str> = env.lines().filter_map(|line| line.split_once('=')).collect();
+
+    assert_eq!(vars.get("RUSTPACK_OUTPUT"), Some(&"app.rpack"));
+    let checksum = format!("{:x}", Sha256::digest(fs::read(&package).unwrap()));
+    assert_eq!(vars.get("RUSTPACK_CHECKSUM"), Some(&checksum.as_str()));
+    assert_eq!(vars.get("RUSTPACK_VERSION"), Some(&"0.1.0"));
+}
+
+#[test]
+fn prefetch_runs_cargo_fetch_before_build() {
+    let fixture = Fixture::new();
+    let log = fixture.path().join("cargo.log");
+    fixture.fake_cargo(&log, None);
+    let path = fixture.fake_path();
+    let commands = |args: &[&str]| {
+        let _ = fs::remove_file(&log);
+        let result = fixture.rustpack().args(["-o", "app.rpack"]).args(args).env("PATH", &path).output().unwrap();
+        common::assert_success(&result);
+        fs::read_to_string(&log).unwrap().lines().map(str::to_string).collect::<Vec<_>>()
+    };
+
+    let with_fetch = commands(&["--prefetch-deps"]);
+    let fetch = with_fetch.iter().position(|c| c == "fetch").expect("cargo fetch was not run");
+    let build = with_fetch.iter().position(|c| c == "build").expect("cargo build was not run");
+    assert!(fetch < build, "{:?}", with_fetch);
+
+    let without = commands(&[]);
+    assert!(!without.iter().any(|c| c == "fetch"), "{:?}", without);
+}
+
+#[test]
+fn strip_rpath_removes_rpath_from_packed_binary() {
+    let fixture = Fixture::new();
+    fixture.write("RustPack.toml", "rustflags = [\"-C\", \"link-arg=-Wl,-rpath,/build/machine/lib\"]\n");
+    let binary = format!("rustpack/bin/{}/hello-world-test", rustpack::get_current_target());
+    let rpath = |package: &std::path::Path| {
+        let dir = tempfile::tempdir().unwrap();
+        Package::open(package).unwrap().extract(dir.path()).unwrap();
+        let output = std::process::Command::new("readelf").arg("-d").arg(dir.path().join(&binary)).output().ok()?;
+        Some(common::stdout(&output).contains("/build/machine/lib"))
+    };
+
+    let package = fixture.pack("with-rpath.rpack", &[]);
+    if rpath(&package).is_none() {
+        eprintln!("readelf not found, skipping");
+        return;
+    }
+    assert_eq!(rpath(&package), Some(true));
+    let package = fixture.pack("without-rpath.rpack", &["--strip-rpath"]);
+    assert_eq!(rpath(&package), Some(false));
+    assert!(fixture.run(&package).output().unwrap().status.success());
+}
+
+#[test]
+fn identical_builds_are_byte_identical() {
+    let fixture = Fixture::new();
+    fixture.write("assets/a.txt", "a");
+    fixture.write("assets/b.txt", "b");
+    let build = |output: &str| {
+        let result = fixture
+            .rustpack()
+            .args(["-o", output, "--assets", "assets/", "--sign-hmac", "secret"])
+            .env("SOURCE_DATE_EPOCH", "1700000000")
+            .output()
+            .unwrap();
+        common::assert_success(&result);
+        fs::read(fixture.path().join(output)).unwrap()
+    };
+
+    let first = build("first.rpack");
+    // New mtimes must not change the package.
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    fixture.write("assets/a.txt", "a");
+    let second = build("second.rpack");
+    assert!(first == second, "the two packages differ");
+}
+
+#[test]
+fn one_build_writes_every_format() {
+    let fixture = Fixture::new();
+    let log = fixture.path().join("cargo.log");
+    fixture.fake_cargo(&log, None);
+    let result = fixture
+        .rustpack()
+        .args(["-o", "app.rpack", "--format", "rpack,zip"])
+        .env("PATH", fixture.fake_path())
+        .output()
+        .unwrap();
+    common::assert_success(&result);
+
+    let builds = fs::read_to_string(&log).unwrap().lines().filter(|c| *c == "build").count();
+    assert_eq!(builds, 1);
+    let binary = format!("rustpack/bin/{}/hello-world-test", rustpack::get_current_target());
+    for output in ["app.rpack", "app.zip"] {
+        let package = Package::open(&fixture.path().join(output)).unwrap();
+        assert!(package.entries().any(|e| e.path == binary), "{} has no binary", output);
+    }
+}
+
+#[test]
+fn list_contents_shows_binaries_assets_and_info() {
+    let fixture = Fixture::new();
+    fixture.write("config.txt", "debug = false\n");
+    let binary = format!("rustpack/bin/{}/hello-world-test", rustpack::get_current_target());
+    for (output, format) in [("app.rpack", "rpack"), ("app.zip", "zip")] {
+        fixture.pack(output, &["--format", format, "--assets", "config.txt"]);
+        let listing = |args: &[&str]| {
+            let result = fixture.rustpack().arg("--list-contents").arg(output).args(args).output().unwrap();
+            common::assert_success(&result);
+            common::stdout(&result)
+        };
+
+        let short = listing(&[]);
+        let size = |path: &str| {
+            let line = short.lines().find(|l| l.ends_with(&format!(" {}", path)))?;
+            line.split_whitespace().next().map(str::to_string)
+        };
+        assert!(size(&binary).is_some(), "{}", short);
+        assert!(size("rustpack/info.json").is_some(), "{}", short);
+        assert_eq!(size("rustpack/assets/config.txt").as_deref(), Some("14"), "{}", short);
+
+        let long = listing(&["--long"]);
+        let line = long.lines().find(|l| l.ends_with(&binary)).unwrap();
+        assert!(line.starts_with("-rwxr-xr-x"), "{}", line);
+    }
+}
+
+#[test]
+fn unknown_feature_fails_before_cargo_runs() {
+    let fixture = Fixture::new();
+    fixture.write(
+        "Cargo.toml",
+        "[package]\nname = \"hello-world-test\"\nversion = \"0.1.0\"\nedition = \"2024\"\n\n[features]\nfancy = []\nplain = []\n",
+    );
+    let log = fixture.path().join("cargo.log");
+    fixture.fake_cargo(&log, Some(1));
+    let pack = |command: &mut std::process::Command| {
+        let result = command.args(["-o", "app.rpack"]).env("PATH", fixture.fake_path()).output().unwrap();
+        assert!(!result.status.success());
+        common::stderr(&result)
+    };
+
+    let error = pack(fixture.rustpack().args(["--features", "fancy,fnacy"]));
+    assert!(error.contains("Unknown feature(s) fnacy"), "{}", error);
+    assert!(error.contains("available: fancy, plain"), "{}", error);
+    fixture.write("RustPack.toml", "features = [\"plian\"]\n");
+    let error = pack(&mut fixture.rustpack());
+    assert!(error.contains("Unknown feature(s) plian"), "{}", error);
+    fs::remove_file(fixture.path().join("RustPack.toml")).unwrap();
+    let error = pack(fixture.rustpack().env("RUSTPACK_FEATURES", "bogus"));
+    assert!(error.contains("Unknown feature(s) bogus"), "{}", error);
+    assert!(!fs::read_to_string(&log).unwrap_or_default().lines().any(|c| c == "build"));
+}
+
+#[test]
+fn deb_installs_cleanly() {
+    let fixture = Fixture::new();
+    fixture.write("config.txt", "debug = false\n");
+    let deb = fixture.pack("app.deb", &["--format", "deb", "--assets", "config.txt"]);
+    let field = |name: &str| {
+        let output = std::process::Command::new("dpkg-deb").arg("-f").arg(&deb).arg(name).output().ok()?;
+        Some(common::stdout(&output).trim().to_string())
+    };
+    if field("Package").is_none() {
+        eprintln!("dpkg-deb not found, skipping");
+        return;
+    }
+    assert_eq!(field("Package").as_deref(), Some("hello-world-test"));
+    assert_eq!(field("Version").as_deref(), Some("0.1.0"));
+    assert!(field("Depends").unwrap().starts_with("libc6"));
+
+    // Install into a private root, without libc6 in its database.
+    let root = fixture.path().join("root");
+    fs::create_dir_all(root.join("var/lib/dpkg/info")).unwrap();
+    fs::create_dir_all(root.join("var/lib/dpkg/updates")).unwrap();
+    fs::write(root.join("var/lib/dpkg/status"), "").unwrap();
+    let result = std::process::Command::new("dpkg")
+        .arg(format!("--root={}", root.display()))
+        .args(["--force-not-root", "--force-depends", "--log=/dev/null", "-i"])
+        .arg(&deb)
+        .output()
+        .unwrap();
+    common::assert_success(&result);
+
+    let binary = root.join("usr/bin/hello-world-test");
+    assert_eq!(common::stdout(&std::process::Command::new(binary).output().unwrap()), "Hello, world!\n");
+    assert_eq!(fs::read_to_string(root.join("usr/share/hello-world-test/config.txt")).unwrap(), "debug = false\n");
+    assert!(root.join("usr/share/doc/hello-world-test/copyright").is_file());
+}
+
+#[test]
+fn each_failure_has_its_exit_code() {
+    let fixture = Fixture::new();
+    let package = fixture.pack("app.rpack", &["--sign-hmac", "secret"]);
+    // A patch made for some other file.
+    fixture.write("bad.patch", format!("RUSTPACK-PATCH 5 {} {}\n", "0".repeat(64), "0".repeat(64)));
+    let empty = tempfile::tempdir().unwrap();
+    let code = |args: &[&str]| fixture.rustpack().args(args).output().unwrap().status.code();
+
+    assert_eq!(code(&["--no-such-flag"]), Some(1));
+    assert_eq!(code(&["-o", "app.rpack", "--assets", "missing.txt"]), Some(3));
+    assert_eq!(code(&["-o", "app.rpack", "--strip", "--strip-tool", "rustpack-no-such-strip"]), Some(4));
+    assert_eq!(code(&["--verify", package.to_str().unwrap(), "--sign-hmac", "wrong"]), Some(5));
+    assert_eq!(code(&["-i", empty.path().to_str().unwrap(), "-o", "app.rpack"]), Some(6));
+    assert_eq!(code(&["--apply-patch", "--patch-file", "bad.patch", "-i", "app.rpack", "-o", "patched"]), Some(7));
+    fixture.write("src/main.rs", "fn main() { not rust }\n");
+    assert_eq!(code(&["-o", "app.rpack"]), Some(2));
+}
+
+#[test]
+fn missing_or_malformed_manifest_fails_cleanly() {
+    let empty = tempfile::tempdir().unwrap();
+    let run = |args: &[&str]| {
+        let result = std::process::Command::new(env!("CARGO_BIN_EXE_rustpack"))
+            .current_dir(empty.path())
+            .args(args)
+            .output()
+            .unwrap();
+        let stderr = common::stderr(&result);
+        assert!(!result.status.success());
+        assert!(!stderr.contains("panicked"), "{}", stderr);
+        stderr
+    };
+
+    let error = run(&["-o", "app.rpack"]);
+    assert!(error.contains("No Cargo.toml found in ."), "{}", error);
+    let error = run(&["-i", empty.path().to_str().unwrap(), "-o", "app.rpack", "--dry-run"]);
+    assert!(error.contains(&format!("No Cargo.toml found in {}", empty.path().display())), "{}", error);
+    fs::write(empty.path().join("Cargo.toml"), "[package\n").unwrap();
+    let error = run(&["-o", "app.rpack"]);
+    assert!(error.contains("Invalid ./Cargo.toml"), "{}", error);
+}
+
+#[test]
+fn workspace_member_is_picked_with_package() {
+    let fixture = Fixture::new();
+    fixture.write("Cargo.toml", "[workspace]\nmembers = [\"app\", \"tool\"]\nresolver = \"2\"\n");
+    for member in ["app", "tool"] {
+        fixture.write(
+            &format!("{}/Cargo.toml", member),
+            format!("[package]\nname = \"{}\"\nversion = \"0.3.0\"\nedition = \"2024\"\n", member),
+        );
+        fixture.write(&format!("{}/src/main.rs", member), format!("fn main() {{\n    println!(\"{}\");\n}}\n", member));
+    }
+
+    let result = fixture.rustpack().args(["-o", "app.rpack"]).output().unwrap();
+    assert!(!result.status.success());
+    let error = common::stderr(&result);
+    assert!(error.contains("app") && error.contains("tool") && error.contains("--package"), "{}", error);
+
+    let package = fixture.pack("tool.rpack", &["--package", "tool"]);
+    let info = Package::open(&package).unwrap().info().unwrap();
+    assert_eq!((info.name.as_str(), info.version.as_str()), ("tool", "0.3.0"));
+    assert_eq!(common::stdout(&fixture.run(&package).output().unwrap()), "tool\n");
+    let result = fixture.rustpack().args(["-o", "app.rpack"]).env("RUSTPACK_PACKAGE", "app").output().unwrap();
+    common::assert_success(&result);
+    assert_eq!(common::stdout(&fixture.run(&fixture.path().join("app.rpack")).output().unwrap()), "app\n");
+}
+
+#[test]
+fn custom_profile_is_built_and_packed() {
+    let fixture = Fixture::new();
+    fixture.write(
+        "Cargo.toml",
+        "[package]\nname = \"hello-world-test\"\nversion = \"0.1.0\"\nedition = \"2024\"\n\n\
+         [profile.dist]\ninherits = \"release\"\ndebug-assertions = true\n",
+    );
+    fixture.write("src/main.rs", "fn main() {\n    println!(\"debug assertions: {}\", cfg!(debug_assertions));\n}\n");
+    let run = |package: &std::path::Path| common::stdout(&fixture.run(package).output().unwrap());
+
+    assert_eq!(run(&fixture.pack("release.rpack", &[])), "debug assertions: false\n");
+    let package = fixture.pack("dist.rpack", &["--profile", "dist"]);
+    assert_eq!(run(&package), "debug assertions: true\n");
+    let target = rustpack::get_current_target();
+    let built = ["target/dist/hello-world-test", &format!("target/{}/dist/hello-world-test", target)];
+    assert!(built.iter().any(|path| fixture.path().join(path).is_file()));
+
+    let result = fixture.rustpack().args(["-o", "app.rpack", "--profile", "dsit"]).output().unwrap();
+    assert!(!result.status.success());
+    assert!(common::stderr(&result).contains("dsit"), "{}", common::stderr(&result));
+}
+
+#[test]
+fn musl_target_builds_static_binary() {
+    let target = "x86_64-unknown-linux-musl";
+    let libdir = std::process::Command::new("rustc").args(["--print", "target-libdir", "--target", target]).output().unwrap();
+    if !std::path::Path::new(common::stdout(&libdir).trim()).is_dir() {
+        eprintln!("{} is not installed, skipping", target);
+        return;
+    }
+    let fixture = Fixture::new();
+    let package = Package::open(&fixture.pack("app.rpack", &["-t", target])).unwrap();
+    assert!(package.info().unwrap().targets[0].compatibility.contains(&"static".to_string()));
+
+    let extracted = tempfile::tempdir().unwrap();
+    package.extract(extracted.path()).unwrap();
+    let binary = extracted.path().join(format!("rustpack/bin/{}/hello-world-test", target));
+    let ldd = std::process::Command::new("ldd").arg(&binary).output().unwrap();
+    let report = format!("{}{}", common::stdout(&ldd), common::stderr(&ldd));
+    assert!(report.contains("not a dynamic executable") || report.contains("statically linked"), "{}", report);
+}
+
+#[test]
+fn config_values_expand_environment_variables() {
+    let fixture = Fixture::new();
+    fixture.write("RustPack.toml", "output = \"dist/${RUSTPACK_TEST_VERSION}/${RUSTPACK_TEST_NAME:-app}.rpack\"\n");
+    let result = fixture.rustpack().env("RUSTPACK_TEST_VERSION", "1.2.3").output().unwrap();
+    common::assert_success(&result);
+    assert!(fixture.path().join("dist/1.2.3/app.rpack").is_file());
+
+    let result = fixture.rustpack().env_remove("RUSTPACK_TEST_VERSION").output().unwrap();
+    assert_eq!(result.status.code(), Some(1));
+    assert!(common::stderr(&result).contains("environment variable RUSTPACK_TEST_VERSION is not set"), "{}", common::stderr(&result));
+}
+
+#[test]
+fn quiet_build_prints_nothing() {
+    let fixture = Fixture::new();
+    let result = fixture.rustpack().args(["-o", "app.rpack", "--quiet"]).output().unwrap();
+    common::assert_success(&result);
+    assert_eq!(common::stdout(&result), "");
+    assert!(fixture.path().join("app.rpack").is_file());
+
+    // Errors still show, uncolored with NO_COLOR.
+    let result = fixture.rustpack().args(["-o", "app.rpack", "-q", "--assets", "missing.txt"]).env("NO_COLOR", "1").output().unwrap();
+    assert_eq!(common::stdout(&result), "");
+    assert!(common::stderr(&result).contains("missing.txt"));
+    assert!(!common::stderr(&result).contains('\u{1b}'), "{:?}", common::stderr(&result));
+}
+
+#[test]
+fn binaries_are_found_in_custom_target_dir() {
+    let fixture = Fixture::new();
+    let shared = tempfile::tempdir().unwrap();
+    let env_dir = shared.path().join("from-env");
+    let result = fixture.rustpack().args(["-o", "env.rpack"]).env("CARGO_TARGET_DIR", &env_dir).output().unwrap();
+    common::assert_success(&result);
+    assert!(env_dir.is_dir());
+    assert!(!fixture.path().join("target").exists());
+
+    let flag_dir = shared.path().join("from-flag");
+    let package = fixture.pack("flag.rpack", &["--target-dir", flag_dir.to_str().unwrap()]);
+    assert!(flag_dir.is_dir());
+    assert!(!fixture.path().join("target").exists());
+    for package in [fixture.path().join("env.rpack"), package] {
+        assert_eq!(common::stdout(&fixture.run(&package).output().unwrap()), "Hello, world!\n");
+    }
+
+    // The multi-call wrapper crate goes there too.
+    fixture.write("src/lib.rs", "pub fn foo(_args: Vec<String>) -> i32 {\n    println!(\"foo\");\n    0\n}\n");
+    fixture.write("RustPack.toml", "[multicall]\nfoo = \"hello_world_test::foo\"\n");
+    let package = fixture.pack("tools.rpack", &["--target-dir", flag_dir.to_str().unwrap()]);
+    assert!(flag_dir.join("rustpack-multicall").is_dir());
+    assert!(!fixture.path().join("target").exists());
+    assert_eq!(common::stdout(&fixture.run(&package).arg("foo").output().unwrap()), "foo\n");
+}
+
+#[test]
+fn deb_desktop_entry_is_valid() {
+    let fixture = Fixture::new();
+    fixture.write("RustPack.toml", "[desktop]\nTerminal = \"true\"\nKeywords = \"hello;greeting;\"\n");
+    let desktop_entry = |output: &str, args: &[&str]| {
+        let deb = fixture.pack(output, &[&["--format", "deb", "--desktop-entry"], args].concat());
+        let root = fixture.path().join(format!("{}-root", output));
+        let result = std::process::Command::new("dpkg-deb").arg("-x").arg(&deb).arg(&root).output().ok()?;
+        common::assert_success(&result);
+        Some(root.join("usr/share/applications/hello-world-test.desktop"))
+    };
+
+    let Some(path) = desktop_entry("app.deb", &["--categories", "Development", "--comment", "Says hello"]) else {
+        eprintln!("dpkg-deb not found, skipping");
+        return;
+    };
+    let entry = fs::read_to_string(&path).unwrap();
+    for line in ["Name=hello-world-test", "Exec=hello-world-test", "Comment=Says hello", "Categories=Development;", "Terminal=true", "Keywords=hello;greeting;"] {
+        assert!(entry.lines().any(|l| l == line), "no {} in\n{}", line, entry);
+    }
+    match std::process::Command::new("desktop-file-validate").arg(&path).output() {
+        Ok(result) => {
+            common::assert_success(&result);
+            assert_eq!(common::stdout(&result), "", "{}", entry);
+        }
+        Err(_) => eprintln!("desktop-file-validate not found, not validating"),
+    }
+
+    // A desktop file in the config replaces the generated entry.
+    let custom = "[Desktop Entry]\nType=Application\nName=Hello\nExec=hello-world-test --gui\n";
+    fixture.write("hello.desktop", custom);
+    fixture.write("RustPack.toml", "desktop = \"hello.desktop\"\n");
+    let path = desktop_entry("custom.deb", &[]).unwrap();
+    assert_eq!(fs::read_to_string(path).unwrap(), custom);
+}
+
+#[test]
+fn source_date_epoch_pins_every_timestamp() {
+    let fixture = Fixture::new();
+    fixture.write("config.txt", "debug = false\n");
+    let build = |output: &str| {
+        let result = fixture
+            .rustpack()
+            .args(["-o", output, "--format", "rpack,zip", "--assets", "config.txt"])
+            .env("SOURCE_DATE_EPOCH", "1700000000")
+            .output()
+            .unwrap();
+        common::assert_success(&result);
+        let path = |extension: &str| fixture.path().join(output).with_extension(extension);
+        (Package::open(&path("rpack")).unwrap(), Package::open(&path("zip")).unwrap())
+    };
+
+    let first = build("first.rpack");
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    let second = build("second.rpack");
+    for package in [&first.0, &first.1, &second.0, &second.1] {
+        assert_eq!(package.info().unwrap().created_at.as_deref(), Some("2023-11-14T22:13:20Z"));
+        for entry in package.entries() {
+            // ZIP times have a resolution of two seconds.
+            assert!(entry.mtime.abs_diff(1_700_000_000) < 2, "{} is dated {}", entry.path, entry.mtime);
+        }
+    }
+    let times = |package: &Package| package.entries().map(|e| (e.path.clone(), e.mtime)).collect::<Vec<_>>();
+    assert_eq!(times(&first.0), times(&second.0));
+    assert_eq!(times(&first.1), times(&second.1));
+}
+
+#[test]
+fn identical_files_are_stored_once() {
+    let fixture = Fixture::new();
+    let content = "the same bytes\n".repeat(100);
+    fixture.write("assets/a.txt", &content);
+    fixture.write("assets/b.txt", &content);
+    let package = fixture.pack("app.rpack", &["--assets", "assets/", "--compression", "none", "--report", "report.json"]);
+    let report: serde_json::Value = serde_json::from_slice(&fs::read(fixture.path().join("report.json")).unwrap()).unwrap();
+    assert_eq!(report["outputs"][0]["deduplicated_bytes"], content.len());
+
+    // The second copy is a hard link to the first in the payload.
+    let bytes = fs::read(&package).unwrap();
+    let marker = b"\n__PAYLOAD_BEGINS__\n";
+    let start = bytes.windows(marker.len()).rposition(|w| w == marker).unwrap() + marker.len();
+    let mut archive = tar::Archive::new(&bytes[start..]);
+    let links: Vec<_> = archive
+        .entries()
+        .unwrap()
+        .map(|e| e.unwrap())
+        .filter(|e| e.header().entry_type().is_hard_link())
+        .map(|e| (e.path().unwrap().display().to_string(), e.link_name().unwrap().unwrap().display().to_string()))
+        .collect();
+    assert_eq!(links, [("rustpack/assets/assets/b.txt".to_string(), "rustpack/assets/assets/a.txt".to_string())]);
+
+    let extracted = tempfile::tempdir().unwrap();
+    rustpack::extract_package(&package, extracted.path()).unwrap();
+    for name in ["a.txt", "b.txt"] {
+        assert_eq!(fs::read_to_string(extracted.path().join("rustpack/assets/assets").join(name)).unwrap(), content);
+    }
+}
+
+#[test]
+fn invalid_environment_value_is_an_error() {
+    let fixture = Fixture::new();
+    let result = fixture.rustpack().args(["-o", "app.rpack"]).env("RUSTPACK_MAX_PACKAGE_SIZE", "lots").output().unwrap();
+    assert_eq!(result.status.code(), Some(1));
+    let stderr = common::stderr(&result);
+    assert!(stderr.contains("RUSTPACK_MAX_PACKAGE_SIZE must be a size such as 10M, not \"lots\""), "{}", stderr);
+    assert!(!fixture.path().join("app.rpack").exists());
+
+    let result = fixture.rustpack().args(["-o", "app.rpack"]).env("RUSTPACK_MAX_SIZE", "8X").output().unwrap();
+    assert_eq!(result.status.code(), Some(1));
+    let stderr = common::stderr(&result);
+    assert!(stderr.contains("RUSTPACK_MAX_SIZE must be a size such as 10M, not \"8X\""), "{}", stderr);
+
+    let result = fixture.rustpack().args(["-o", "app.rpack"]).env("RUSTPACK_COMPRESSION_LEVEL", "12").output().unwrap();
+    assert_eq!(result.status.code(), Some(1));
+    let stderr = common::stderr(&result);
+    assert!(stderr.contains("RUSTPACK_COMPRESSION_LEVEL must be between 0 and 9, not \"12\""), "{}", stderr);
+}
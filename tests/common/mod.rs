@@ -0,0 +1,104 @@
+//! Shared setup for the integration tests: a copy of the hello-world-test
+//! crate in a temporary directory, and the rustpack binary to run on it.
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use tempfile::TempDir;
+
+pub struct Fixture {
+    pub dir: TempDir,
+}
+
+impl Fixture {
+    /// A fresh copy of hello-world-test.
+    pub fn new() -> Fixture {
+        let dir = tempfile::tempdir().unwrap();
+        let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("hello-world-test");
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::copy(source.join("Cargo.toml"), dir.path().join("Cargo.toml")).unwrap();
+        fs::copy(source.join("src/main.rs"), dir.path().join("src/main.rs")).unwrap();
+        Fixture { dir }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub fn write(&self, name: &str, content: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.path().join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    /// Writes a script into `fake-bin/`, for tests to put ahead of the real
+    /// tools on `PATH`.
+    pub fn fake_tool(&self, name: &str, script: &str) -> PathBuf {
+        let path = self.write(&format!("fake-bin/{}", name), script);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        path
+    }
+
+    /// A `cargo` that appends its subcommand to `log`, then exits with
+    /// `exit_code` or, without one, runs the real cargo.
+    pub fn fake_cargo(&self, log: &Path, exit_code: Option<i32>) -> PathBuf {
+        let then = match exit_code {
+            Some(code) => format!("exit {}", code),
+            None => format!("exec '{}' \"$@\"", std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string())),
+        };
+        self.fake_tool("cargo", &format!("#!/bin/sh\necho \"$1\" >> '{}'\n{}\n", log.display(), then))
+    }
+
+    /// `PATH` with the `fake_tool` scripts first.
+    pub fn fake_path(&self) -> String {
+        format!("{}:{}", self.path().join("fake-bin").display(), std::env::var("PATH").unwrap_or_default())
+    }
+
+    /// rustpack, run in the fixture with a private bootstrap cache.
+    pub fn rustpack(&self) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_rustpack"));
+        command.current_dir(self.path()).env("XDG_CACHE_HOME", self.path().join("cache"));
+        command
+    }
+
+    /// Packs the fixture into `output` with `args`, failing the test if
+    /// rustpack does.
+    pub fn pack(&self, output: &str, args: &[&str]) -> PathBuf {
+        let result = self.rustpack().arg("-o").arg(output).args(args).output().unwrap();
+        assert_success(&result);
+        self.path().join(output)
+    }
+
+    /// Runs a self-extracting package the way a user would.
+    pub fn run(&self, package: &Path) -> Command {
+        let mut command = Command::new(package);
+        command.current_dir(self.path()).env("XDG_CACHE_HOME", self.path().join("cache"));
+        command
+    }
+}
+
+pub fn assert_success(output: &Output) {
+    assert!(
+        output.status.success(),
+        "exited with {}\nstdout:\n{}\nstderr:\n{}",
+        output.status,
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
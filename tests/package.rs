@@ -0,0 +1,34 @@
+//! Opens packages built from the fixture crate with the `Package` API.
+mod common;
+
+use common::Fixture;
+use rustpack::package::{Package, PackageFormat};
+use rustpack::verify_package;
+
+#[test]
+fn replaced_asset_persists_after_rewrite() {
+    let fixture = Fixture::new();
+    fixture.write("config.txt", "debug = false\n");
+    for (output, format, name) in [("app.rpack", PackageFormat::SelfExtracting, "rpack"), ("app.zip", PackageFormat::Zip, "zip")] {
+        let original = fixture.pack(output, &["--format", name, "--assets", "config.txt", "--sign-hmac", "secret"]);
+        let mut package = Package::open(&original).unwrap();
+        assert_eq!(package.format(), format);
+        assert_eq!(package.entry("rustpack/assets/config.txt").unwrap().data, b"debug = false\n");
+
+        package.replace_asset("config.txt", b"debug = true\n".to_vec());
+        let rewritten = fixture.path().join(format!("patched-{}", output));
+        package.write(&rewritten, Some("secret")).unwrap();
+
+        let package = Package::open(&rewritten).unwrap();
+        assert_eq!(package.format(), format);
+        assert_eq!(package.entry("rustpack/assets/config.txt").unwrap().data, b"debug = true\n");
+        assert_eq!(package.info().unwrap().name, "hello-world-test");
+        verify_package(&rewritten, "secret").unwrap();
+        assert!(verify_package(&rewritten, "wrong").is_err());
+
+        let extracted = tempfile::tempdir().unwrap();
+        package.extract(extracted.path()).unwrap();
+        let config = std::fs::read_to_string(extracted.path().join("rustpack/assets/config.txt")).unwrap();
+        assert_eq!(config, "debug = true\n");
+    }
+}
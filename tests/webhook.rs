@@ -0,0 +1,78 @@
+#![cfg(feature = "webhook")]
+
+mod common;
+
+use common::Fixture;
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+
+/// Accepts POSTs on a local port and keeps each JSON body, in order.
+fn mock_endpoint() -> (String, Arc<Mutex<Vec<Value>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let updates = Arc::new(Mutex::new(Vec::new()));
+    let received = Arc::clone(&updates);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let mut reader = BufReader::new(&stream);
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':')
+                    && name.eq_ignore_ascii_case("content-length")
+                {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            received.lock().unwrap().push(serde_json::from_slice(&body).unwrap());
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+        }
+    });
+    (url, updates)
+}
+
+#[test]
+fn build_posts_progress_and_completed() {
+    let fixture = Fixture::new();
+    let (url, updates) = mock_endpoint();
+    fixture.pack("app.rpack", &["--progress-webhook", &url]);
+
+    // rustpack waits for the last update before exiting, so it is here.
+    let updates = updates.lock().unwrap();
+    assert!(
+        updates.iter().any(|u| u["event"] == "progress" && u["phase"] == "build"),
+        "{:?}",
+        updates
+    );
+    let last = updates.last().unwrap();
+    assert_eq!(last["event"], "completed");
+    assert_eq!(last["level"], "info");
+    assert!(last["message"].as_str().unwrap().starts_with("Package created"));
+}
+
+#[test]
+fn failed_build_posts_failed() {
+    let fixture = Fixture::new();
+    fixture.write("src/main.rs", "fn main() { not rust }\n");
+    let (url, updates) = mock_endpoint();
+    let output = fixture
+        .rustpack()
+        .args(["-o", "app.rpack", "--progress-webhook", &url])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+
+    let updates = updates.lock().unwrap();
+    let last = updates.last().unwrap();
+    assert_eq!(last["event"], "failed");
+    assert_eq!(last["level"], "error");
+    assert!(last["message"].as_str().unwrap().starts_with("Build failed"));
+}